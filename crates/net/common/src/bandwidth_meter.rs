@@ -38,7 +38,7 @@ use tokio::{
     net::TcpStream,
 };
 
-use crate::stream::HasRemoteAddr;
+use crate::stream::{HasNodelay, HasRemoteAddr};
 
 /// Meters bandwidth usage of streams
 #[derive(Debug)]
@@ -167,6 +167,16 @@ impl HasRemoteAddr for MeteredStream<TcpStream> {
     }
 }
 
+impl<S: HasNodelay> HasNodelay for MeteredStream<S> {
+    fn nodelay(&self) -> io::Result<Option<bool>> {
+        self.inner.nodelay()
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.inner.set_nodelay(nodelay)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
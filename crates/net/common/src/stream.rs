@@ -1,5 +1,5 @@
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::{io, net::SocketAddr};
+use tokio::{io::DuplexStream, net::TcpStream};
 /// This trait is for instrumenting a TCPStream with a socket addr
 pub trait HasRemoteAddr {
     /// Maybe returns a [`SocketAddr`]
@@ -11,3 +11,37 @@ impl HasRemoteAddr for TcpStream {
         self.peer_addr().ok()
     }
 }
+
+/// This trait is for toggling Nagle's algorithm on the TCP socket underlying a stream.
+///
+/// Transports that aren't backed by a TCP socket keep the default no-op implementations.
+pub trait HasNodelay {
+    /// Returns whether `TCP_NODELAY` is set, `None` if the transport isn't a TCP socket.
+    fn nodelay(&self) -> io::Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// Sets `TCP_NODELAY`, doing nothing if the transport isn't a TCP socket.
+    fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HasNodelay for TcpStream {
+    fn nodelay(&self) -> io::Result<Option<bool>> {
+        TcpStream::nodelay(self).map(Some)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+}
+
+/// In-memory transports have no remote address.
+impl HasRemoteAddr for DuplexStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl HasNodelay for DuplexStream {}
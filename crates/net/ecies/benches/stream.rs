@@ -0,0 +1,66 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::{SinkExt, StreamExt};
+use reth_ecies::{stream::ECIESStream, util::pk2id};
+use reth_primitives::bytes::Bytes;
+use secp256k1::{rand, SecretKey, SECP256K1};
+use tokio::{io::DuplexStream, runtime::Runtime};
+
+/// Payload sizes of the benchmarked messages.
+const MESSAGE_SIZES: [usize; 4] = [64, 1024, 64 * 1024, 1024 * 1024];
+
+/// Capacity of each direction of the in-memory transport.
+const DUPLEX_CAPACITY: usize = 1024 * 1024;
+
+/// Returns a client and server stream connected over an in-memory transport.
+fn connected(rt: &Runtime) -> (ECIESStream<DuplexStream>, ECIESStream<DuplexStream>) {
+    let (client, server) = tokio::io::duplex(DUPLEX_CAPACITY);
+    let server_key = SecretKey::new(&mut rand::thread_rng());
+    let client_key = SecretKey::new(&mut rand::thread_rng());
+    let server_id = pk2id(&server_key.public_key(SECP256K1));
+
+    rt.block_on(async {
+        let (client, server) = tokio::join!(
+            ECIESStream::connect(client, client_key, server_id),
+            ECIESStream::incoming(server, server_key)
+        );
+        (client.unwrap(), server.unwrap())
+    })
+}
+
+/// Measures the throughput of sending and of receiving messages of different sizes.
+///
+/// Every iteration transfers a single message, so the time per iteration is the inverse of the
+/// messages per second, while the reported throughput is in bytes per second. The opposite end of
+/// the connection is driven by a background task, so only one direction is measured at a time.
+pub fn stream(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("ecies stream");
+
+    for size in MESSAGE_SIZES {
+        let message = Bytes::from(vec![0xab; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("send", size), &message, |b, message| {
+            let (mut client, mut server) = connected(&rt);
+            let receiver = rt.spawn(async move { while let Some(Ok(_)) = server.next().await {} });
+            b.iter(|| rt.block_on(client.send(message.clone())).unwrap());
+            drop(client);
+            rt.block_on(receiver).unwrap();
+        });
+
+        group.bench_with_input(BenchmarkId::new("receive", size), &message, |b, message| {
+            let (mut client, mut server) = connected(&rt);
+            let message = message.clone();
+            let sender =
+                rt.spawn(async move { while server.send(message.clone()).await.is_ok() {} });
+            b.iter(|| rt.block_on(client.next()).unwrap().unwrap());
+            drop(client);
+            rt.block_on(sender).unwrap();
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, stream);
+criterion_main!(benches);
@@ -2,10 +2,11 @@
 use crate::{
     error::ECIESErrorImpl,
     mac::{HeaderBytes, MAC},
+    params::HandshakeParams,
     util::{hmac_sha256, id2pk, pk2id, sha256},
     ECIESError,
 };
-use aes::{cipher::StreamCipher, Aes128, Aes256};
+use aes::{cipher::StreamCipher, Aes256};
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use ctr::Ctr64BE;
 use digest::{crypto_common::KeyIvInit, Digest};
@@ -74,17 +75,104 @@ pub struct ECIES {
     nonce: H256,
     remote_nonce: Option<H256>,
 
+    /// Parameters of the encryption of the auth and ack messages
+    handshake_params: HandshakeParams,
+
     #[educe(Debug(ignore))]
     ingress_aes: Option<Ctr64BE<Aes256>>,
     #[educe(Debug(ignore))]
     egress_aes: Option<Ctr64BE<Aes256>>,
     ingress_mac: Option<MAC>,
     egress_mac: Option<MAC>,
+    /// The secrets of the current ingress keys, the next ones are derived from on rekeying
+    #[educe(Debug(ignore))]
+    ingress_secrets: Option<FrameSecrets>,
+    /// The secrets of the current egress keys, the next ones are derived from on rekeying
+    #[educe(Debug(ignore))]
+    egress_secrets: Option<FrameSecrets>,
 
     init_msg: Option<Bytes>,
     remote_init_msg: Option<Bytes>,
 
     body_size: Option<usize>,
+    /// Whether the frame whose header was read last is a rekey frame
+    rekey_frame: bool,
+
+    #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+    #[educe(Debug(ignore))]
+    session_keys: Option<SessionKeys>,
+}
+
+/// The symmetric secrets of an established session.
+///
+/// **INSECURE**: anyone holding these can decrypt and forge all frames of the session. This only
+/// exists to decrypt one's own packet captures while developing the protocol, and is only
+/// available in debug builds with the `insecure-session-key-export` feature enabled.
+#[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionKeys {
+    /// The AES-256-CTR key of both directions, with an all zero IV.
+    pub aes_secret: H256,
+    /// The secret the ingress and egress MACs are keyed with.
+    pub mac_secret: H256,
+    /// The local nonce, seeding the ingress MAC.
+    pub nonce: H256,
+    /// The remote nonce, seeding the egress MAC.
+    pub remote_nonce: H256,
+}
+
+/// The header-data of rekey frames, an RLP list of a zero capability-id and a context-id of `1`.
+///
+/// Regular frames carry a zero context-id, which devp2p peers always send since they don't use
+/// frame contexts.
+const REKEY_HEADER_DATA: [u8; 3] = [194, 128, 1];
+
+/// The body size of rekey frames, the fresh ephemeral public key of the sender in the 64 byte
+/// [`PeerId`] encoding.
+const REKEY_BODY_SIZE: usize = 64;
+
+/// The header-data of regular frames, an RLP list of a zero capability-id and context-id.
+const FRAME_HEADER_DATA: [u8; 3] = [194, 128, 128];
+
+/// The secrets the frame keys of one direction are derived from.
+#[derive(Clone, Copy)]
+struct FrameSecrets {
+    aes_secret: H256,
+    mac_secret: H256,
+}
+
+// === impl FrameSecrets ===
+
+impl FrameSecrets {
+    /// Derives the secrets replacing these on rekeying, mixing in the ECDH secret of the rekey
+    /// frame.
+    ///
+    /// The derivation is one-way, so the new secrets don't reveal any earlier ones. Without the
+    /// shared secret, the current secrets don't reveal the new ones either.
+    fn next(&self, shared_secret: &H256) -> Self {
+        let derive = |secret: &H256, label: &[u8]| {
+            let mut hasher = Keccak256::new();
+            hasher.update(secret.as_ref());
+            hasher.update(shared_secret.as_ref());
+            hasher.update(label);
+            H256::from(hasher.finalize().as_ref())
+        };
+        Self {
+            aes_secret: derive(&self.aes_secret, b"rekey aes"),
+            mac_secret: derive(&self.mac_secret, b"rekey mac"),
+        }
+    }
+
+    fn aes(&self) -> Ctr64BE<Aes256> {
+        Ctr64BE::<Aes256>::new(self.aes_secret.0.as_ref().into(), H128::default().as_ref().into())
+    }
+
+    /// Creates the MAC of the new keys, continuing the transcript of the `previous` one.
+    fn mac(&self, previous: &MAC) -> MAC {
+        let mut mac = MAC::new(self.mac_secret);
+        mac.update(previous.digest().as_ref());
+        mac
+    }
 }
 
 fn split_at_mut<T>(arr: &mut [T], idx: usize) -> Result<(&mut [T], &mut [T]), ECIESError> {
@@ -122,12 +210,19 @@ impl ECIES {
             remote_init_msg: None,
 
             remote_id: Some(remote_id),
+            handshake_params: HandshakeParams::default(),
 
             body_size: None,
+            rekey_frame: false,
             egress_aes: None,
             ingress_aes: None,
             egress_mac: None,
             ingress_mac: None,
+            egress_secrets: None,
+            ingress_secrets: None,
+
+            #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+            session_keys: None,
         })
     }
 
@@ -164,12 +259,19 @@ impl ECIES {
             remote_init_msg: None,
 
             remote_id: None,
+            handshake_params: HandshakeParams::default(),
 
             body_size: None,
+            rekey_frame: false,
             egress_aes: None,
             ingress_aes: None,
             egress_mac: None,
             ingress_mac: None,
+            egress_secrets: None,
+            ingress_secrets: None,
+
+            #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+            session_keys: None,
         })
     }
 
@@ -181,11 +283,58 @@ impl ECIES {
         Self::new_static_server(secret_key, nonce, ephemeral_secret_key)
     }
 
+    /// Sets the parameters of the encryption of the auth and ack messages.
+    ///
+    /// **For interop testing only**, see [`HandshakeParams`].
+    pub fn with_handshake_params(mut self, handshake_params: HandshakeParams) -> Self {
+        self.handshake_params = handshake_params;
+        self
+    }
+
+    /// Returns the peer ID of the local node.
+    pub fn local_id(&self) -> PeerId {
+        pk2id(&self.public_key)
+    }
+
+    /// Switches to the first of the `secret_keys` the auth message was encrypted to, so it's
+    /// read with that key.
+    ///
+    /// Returns an error if it wasn't encrypted to any of them, the key is left unchanged then.
+    pub fn select_server_key(
+        &mut self,
+        auth: &[u8],
+        secret_keys: &[SecretKey],
+    ) -> Result<(), ECIESError> {
+        let secret_key = self.secret_key;
+        for candidate in secret_keys {
+            self.secret_key = *candidate;
+            self.public_key = PublicKey::from_secret_key(SECP256K1, candidate);
+            if self.is_handshake_message(auth) {
+                return Ok(())
+            }
+        }
+
+        self.secret_key = secret_key;
+        self.public_key = PublicKey::from_secret_key(SECP256K1, &secret_key);
+        Err(ECIESErrorImpl::TagCheckDecryptFailed.into())
+    }
+
     /// Return the contained remote peer ID.
     pub fn remote_id(&self) -> PeerId {
         self.remote_id.unwrap()
     }
 
+    /// Derives the cipher key and the MAC key of a handshake message from the shared secret.
+    fn handshake_keys(&self, shared_secret: H256) -> (Vec<u8>, H256) {
+        let key_len = self.handshake_params.cipher.key_len();
+        let mut key = vec![0u8; 2 * key_len];
+        kdf(shared_secret, &self.handshake_params.kdf_shared_info, &mut key);
+
+        let mac_key = sha256(&key[key_len..]);
+        key.truncate(key_len);
+        (key, mac_key)
+    }
+
     fn encrypt_message(&self, data: &[u8], out: &mut BytesMut) {
         out.reserve(secp256k1::constants::UNCOMPRESSED_PUBLIC_KEY_SIZE + 16 + data.len() + 32);
 
@@ -195,17 +344,11 @@ impl ECIES {
         );
 
         let x = ecdh_x(&self.remote_public_key.unwrap(), &secret_key);
-        let mut key = [0u8; 32];
-        kdf(x, &[], &mut key);
-
-        let enc_key = H128::from_slice(&key[..16]);
-        let mac_key = sha256(&key[16..32]);
+        let (enc_key, mac_key) = self.handshake_keys(x);
 
         let iv = H128::random();
-        let mut encryptor = Ctr64BE::<Aes128>::new(enc_key.as_ref().into(), iv.as_ref().into());
-
         let mut encrypted = data.to_vec();
-        encryptor.apply_keystream(&mut encrypted);
+        self.handshake_params.cipher.apply_keystream(&enc_key, iv.as_bytes(), &mut encrypted);
 
         let total_size: u16 = u16::try_from(65 + 16 + data.len() + 32).unwrap();
 
@@ -226,10 +369,7 @@ impl ECIES {
         let tag = H256::from_slice(tag_bytes);
 
         let x = ecdh_x(&public_key, &self.secret_key);
-        let mut key = [0u8; 32];
-        kdf(x, &[], &mut key);
-        let enc_key = H128::from_slice(&key[..16]);
-        let mac_key = sha256(&key[16..32]);
+        let (enc_key, mac_key) = self.handshake_keys(x);
 
         let check_tag = hmac_sha256(mac_key.as_ref(), &[iv, encrypted_data], auth_data);
         if check_tag != tag {
@@ -237,9 +377,7 @@ impl ECIES {
         }
 
         let decrypted_data = encrypted_data;
-
-        let mut decryptor = Ctr64BE::<Aes128>::new(enc_key.as_ref().into(), (*iv).into());
-        decryptor.apply_keystream(decrypted_data);
+        self.handshake_params.cipher.apply_keystream(&enc_key, iv, decrypted_data);
 
         Ok(decrypted_data)
     }
@@ -332,6 +470,11 @@ impl ECIES {
         Ok(())
     }
 
+    /// Returns the nonce of the remote, available once its auth or ack was read.
+    pub(crate) fn remote_nonce(&self) -> Option<H256> {
+        self.remote_nonce
+    }
+
     /// Read and verify an auth message from the input data.
     #[tracing::instrument(skip_all)]
     pub fn read_auth(&mut self, data: &mut [u8]) -> Result<(), ECIESError> {
@@ -340,6 +483,13 @@ impl ECIES {
         self.parse_auth_unencrypted(unencrypted)
     }
 
+    /// Returns `true` if the data is an auth or ack message encrypted to the local node's key.
+    ///
+    /// The data is left unchanged.
+    pub fn is_handshake_message(&self, data: &[u8]) -> bool {
+        self.decrypt_message(&mut data.to_vec()).is_ok()
+    }
+
     /// Create an `ack` message using the internal nonce, local ephemeral public key, and RLPx
     /// ECIES protocol version.
     fn create_ack_unencrypted(&self) -> impl AsRef<[u8]> {
@@ -461,6 +611,30 @@ impl ECIES {
             .unwrap()
             .update((mac_secret ^ self.remote_nonce.unwrap()).as_ref());
         self.egress_mac.as_mut().unwrap().update(self.init_msg.as_ref().unwrap());
+
+        self.ingress_secrets = Some(FrameSecrets { aes_secret, mac_secret });
+        self.egress_secrets = self.ingress_secrets;
+
+        #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+        {
+            self.session_keys = Some(SessionKeys {
+                aes_secret,
+                mac_secret,
+                nonce: self.nonce,
+                remote_nonce: self.remote_nonce.unwrap(),
+            });
+        }
+    }
+
+    /// Returns the symmetric secrets of the session, `None` until the handshake completed.
+    ///
+    /// These are the secrets the session started with, frames following a rekey frame are
+    /// encrypted with keys derived from them.
+    ///
+    /// **INSECURE**, see [`SessionKeys`].
+    #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+    pub fn session_keys(&self) -> Option<SessionKeys> {
+        self.session_keys
     }
 
     #[cfg(test)]
@@ -471,11 +645,15 @@ impl ECIES {
     }
 
     pub fn write_header(&mut self, out: &mut BytesMut, size: usize) {
+        self.write_header_with_data(out, size, FRAME_HEADER_DATA)
+    }
+
+    fn write_header_with_data(&mut self, out: &mut BytesMut, size: usize, header_data: [u8; 3]) {
         let mut buf = [0u8; 8];
         BigEndian::write_uint(&mut buf, size as u64, 3);
         let mut header = [0u8; 16];
         header[..3].copy_from_slice(&buf[..3]);
-        header[3..6].copy_from_slice(&[194, 128, 128]);
+        header[3..6].copy_from_slice(&header_data);
 
         let mut header = HeaderBytes::from(header);
         self.egress_aes.as_mut().unwrap().apply_keystream(&mut header);
@@ -505,11 +683,51 @@ impl ECIES {
 
         let body_size = usize::try_from(header.as_slice().read_uint::<BigEndian>(3)?)?;
 
+        self.rekey_frame = header[3..6] == REKEY_HEADER_DATA;
+        if self.rekey_frame && body_size != REKEY_BODY_SIZE {
+            return Err(ECIESErrorImpl::InvalidHeader.into())
+        }
+
         self.body_size = Some(body_size);
 
         Ok(self.body_size.unwrap())
     }
 
+    /// Returns `true` if the frame whose header was read last is a rekey frame.
+    ///
+    /// Rekey frames carry no data, once the body is read the ingress keys are replaced, see
+    /// [`ECIES::write_rekey`].
+    pub fn is_rekey_frame(&self) -> bool {
+        self.rekey_frame
+    }
+
+    /// Writes a rekey frame and replaces the egress keys, all subsequent frames are encrypted with
+    /// the new keys.
+    ///
+    /// The rekey frame carries a fresh ephemeral public key, the new keys are derived from the
+    /// current ones and the ECDH secret of this key and the remote's ephemeral key of the
+    /// handshake. The remote derives the same keys for its ingress frames once it read the rekey
+    /// frame. A leaked key only exposes the frames sent until the next rekey: the earlier keys
+    /// can't be derived from it, and the following keys also require the remote's ephemeral
+    /// secret key.
+    ///
+    /// **Non-standard**: devp2p has no rekeying, only peers supporting it can read the frames
+    /// following a rekey frame.
+    pub fn write_rekey(&mut self, out: &mut BytesMut) {
+        let ephemeral_secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        let ephemeral_public_key = PublicKey::from_secret_key(SECP256K1, &ephemeral_secret_key);
+        let shared_secret =
+            ecdh_x(self.remote_ephemeral_public_key.as_ref().unwrap(), &ephemeral_secret_key);
+
+        self.write_header_with_data(out, REKEY_BODY_SIZE, REKEY_HEADER_DATA);
+        self.write_body(out, pk2id(&ephemeral_public_key).as_bytes());
+
+        let secrets = self.egress_secrets.as_ref().unwrap().next(&shared_secret);
+        self.egress_aes = Some(secrets.aes());
+        self.egress_mac = Some(secrets.mac(self.egress_mac.as_ref().unwrap()));
+        self.egress_secrets = Some(secrets);
+    }
+
     pub const fn header_len() -> usize {
         32
     }
@@ -552,15 +770,29 @@ impl ECIES {
 
         let size = self.body_size.unwrap();
         self.body_size = None;
-        let ret = body;
-        self.ingress_aes.as_mut().unwrap().apply_keystream(ret);
-        Ok(split_at_mut(ret, size)?.0)
+        self.ingress_aes.as_mut().unwrap().apply_keystream(body);
+        let ret = split_at_mut(body, size)?.0;
+
+        // the rekey frame itself is still encrypted with the old keys
+        if self.rekey_frame {
+            self.rekey_frame = false;
+            let remote_key = id2pk(PeerId::from_slice(ret))?;
+            let shared_secret = ecdh_x(&remote_key, &self.ephemeral_secret_key);
+            let secrets = self.ingress_secrets.as_ref().unwrap().next(&shared_secret);
+            self.ingress_aes = Some(secrets.aes());
+            self.ingress_mac = Some(secrets.mac(self.ingress_mac.as_ref().unwrap()));
+            self.ingress_secrets = Some(secrets);
+            return Ok(&mut ret[..0])
+        }
+
+        Ok(ret)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::params::HandshakeCipher;
     use hex_literal::hex;
 
     #[test]
@@ -648,6 +880,138 @@ mod tests {
         assert_eq!(ret, client_to_server_data);
     }
 
+    fn ecies_pair(
+        client_params: HandshakeParams,
+        server_params: HandshakeParams,
+    ) -> (ECIES, ECIES) {
+        let server_secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        let server_public_key = PublicKey::from_secret_key(SECP256K1, &server_secret_key);
+        let client_secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+
+        let client = ECIES::new_client(client_secret_key, pk2id(&server_public_key))
+            .unwrap()
+            .with_handshake_params(client_params);
+        let server =
+            ECIES::new_server(server_secret_key).unwrap().with_handshake_params(server_params);
+        (client, server)
+    }
+
+    #[test]
+    fn handshake_with_default_params() {
+        assert!(HandshakeParams::default().is_standard());
+
+        // the default parameters decrypt the handshakes of other clients, see `eip8_test`
+        let (mut client, mut server) =
+            ecies_pair(HandshakeParams::default(), HandshakeParams::default());
+
+        let mut auth = client.create_auth();
+        server.read_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack();
+        client.read_ack(&mut ack).unwrap();
+    }
+
+    #[test]
+    fn handshake_with_custom_params() {
+        let params = HandshakeParams::default()
+            .with_cipher(HandshakeCipher::Aes256Ctr)
+            .with_kdf_shared_info(&b"interop"[..]);
+        assert!(!params.is_standard());
+        let (mut client, mut server) = ecies_pair(params.clone(), params);
+
+        let mut auth = client.create_auth();
+        server.read_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack();
+        client.read_ack(&mut ack).unwrap();
+        assert_eq!(server.remote_id(), pk2id(&client.public_key));
+
+        // the frames of the session are unaffected
+        let mut header = client.create_header(3);
+        server.read_header(&mut header).unwrap();
+        let mut body = client.create_body(&[1, 2, 3]);
+        assert_eq!(server.read_body(&mut body).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn mismatched_handshake_params_fail() {
+        let mismatches = [
+            HandshakeParams::default().with_cipher(HandshakeCipher::Aes256Ctr),
+            HandshakeParams::default().with_kdf_shared_info(&b"interop"[..]),
+        ];
+
+        for params in mismatches {
+            for (client_params, server_params) in
+                [(params.clone(), HandshakeParams::default()), (HandshakeParams::default(), params)]
+            {
+                let (mut client, mut server) = ecies_pair(client_params, server_params);
+
+                let mut auth = client.create_auth();
+                let err = server.read_auth(&mut auth).unwrap_err();
+                assert!(err.to_string().contains("different ECIES parameters"), "{err}");
+                assert!(matches!(err.into_inner(), ECIESErrorImpl::TagCheckDecryptFailed));
+            }
+        }
+    }
+
+    #[test]
+    fn rekeying_replaces_frame_keys() {
+        let (mut client, mut server) =
+            ecies_pair(HandshakeParams::default(), HandshakeParams::default());
+        let mut auth = client.create_auth();
+        server.read_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack();
+        client.read_ack(&mut ack).unwrap();
+
+        for round in 0..3u8 {
+            let mut rekey = BytesMut::new();
+            client.write_rekey(&mut rekey);
+            let mut body = rekey.split_off(ECIES::header_len());
+            assert_eq!(server.read_header(&mut rekey).unwrap(), REKEY_BODY_SIZE);
+            assert!(server.is_rekey_frame());
+            assert!(server.read_body(&mut body).unwrap().is_empty());
+
+            let mut header = client.create_header(1);
+            server.read_header(&mut header).unwrap();
+            assert!(!server.is_rekey_frame());
+            let mut body = client.create_body(&[round]);
+            assert_eq!(server.read_body(&mut body).unwrap(), [round]);
+        }
+
+        // frames following a skipped rekey frame can't be read with the old keys
+        client.write_rekey(&mut BytesMut::new());
+        let mut header = client.create_header(1);
+        let err = server.read_header(&mut header).unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::TagCheckHeaderFailed));
+    }
+
+    #[test]
+    fn leaked_keys_dont_expose_frames_after_rekey() {
+        let (mut client, mut server) =
+            ecies_pair(HandshakeParams::default(), HandshakeParams::default());
+        let mut auth = client.create_auth();
+        server.read_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack();
+        client.read_ack(&mut ack).unwrap();
+
+        // an eavesdropper holding all current keys of the server, but not its ephemeral secret key
+        let mut eavesdropper = server;
+        eavesdropper.ephemeral_secret_key = SecretKey::new(&mut secp256k1::rand::thread_rng());
+
+        let mut header = client.create_header(1);
+        eavesdropper.read_header(&mut header).unwrap();
+        let mut body = client.create_body(&[1]);
+        assert_eq!(eavesdropper.read_body(&mut body).unwrap(), [1]);
+
+        let mut rekey = BytesMut::new();
+        client.write_rekey(&mut rekey);
+        let mut body = rekey.split_off(ECIES::header_len());
+        eavesdropper.read_header(&mut rekey).unwrap();
+        assert!(eavesdropper.read_body(&mut body).unwrap().is_empty());
+
+        let mut header = client.create_header(1);
+        let err = eavesdropper.read_header(&mut header).unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::TagCheckHeaderFailed));
+    }
+
     fn eip8_test_server_key() -> SecretKey {
         SecretKey::from_slice(&hex!(
             "b71c71a67e1177ad4e901695e1b4b9ee17ae16c6668d313eac2f96dbcda3f291"
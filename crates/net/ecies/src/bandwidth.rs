@@ -0,0 +1,278 @@
+//! A bandwidth budget shared fairly by many connections.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+
+/// The virtual time a byte costs a connection of weight one, see [`BandwidthShare::request`].
+const WEIGHT_UNIT: u128 = 1 << 20;
+
+/// A global bandwidth budget, shared by all [`ECIESStream`](crate::stream::ECIESStream)s
+/// registered with it, see
+/// [`ECIESStream::with_bandwidth_limiter`](crate::stream::ECIESStream::with_bandwidth_limiter).
+///
+/// The budget is a token bucket refilling at `bytes_per_second`, holding at most `burst` bytes.
+/// Every message body sent or received by a registered stream is paid from it, ingress and egress
+/// alike. A stream pays for a message after transferring it: the next message in the same
+/// direction waits until the payment was granted. So a stream over budget stops writing, and stops
+/// reading from the socket, which throttles the peer through TCP flow control.
+///
+/// Payments waiting for the budget are granted in the order of self-clocked fair queuing: each
+/// payment is tagged with the virtual time its stream would finish at if it got its weighted share
+/// of the bandwidth, and the payment with the earliest tag goes first. A stream that sends a flood
+/// of messages is therefore queued behind the others instead of monopolizing the budget, and
+/// streams backlogged at the same time get bandwidth in proportion to their weights. Streams that
+/// are idle don't accumulate any credit.
+///
+/// The limiter is cheap to clone and meant to be shared by all connections of a node.
+#[derive(Debug, Clone)]
+pub struct BandwidthLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+// === impl BandwidthLimiter ===
+
+impl BandwidthLimiter {
+    /// Creates a limiter with the given budget, which allows bursts of up to `burst` bytes.
+    ///
+    /// Zero rates and bursts are treated as one byte.
+    pub fn new(bytes_per_second: u64, burst: u64) -> Self {
+        let burst = burst.max(1);
+        let bucket = Bucket {
+            bytes_per_second: bytes_per_second.max(1) as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled_at: Instant::now(),
+            virtual_time: 0,
+            next_seq: 0,
+            waiting: BTreeMap::new(),
+        };
+        Self { bucket: Arc::new(Mutex::new(bucket)) }
+    }
+
+    /// Returns the number of payments of the registered streams waiting for the budget.
+    pub fn waiting(&self) -> usize {
+        self.bucket.lock().expect("not poisoned").waiting.len()
+    }
+
+    /// Registers a stream with the given weight, treating zero as one.
+    pub(crate) fn register(&self, weight: u32) -> PeerBandwidth {
+        PeerBandwidth {
+            egress: BandwidthShare::new(self.bucket.clone(), weight),
+            ingress: BandwidthShare::new(self.bucket.clone(), weight),
+        }
+    }
+}
+
+/// The token bucket of a [`BandwidthLimiter`], with the payments waiting for it.
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_second: f64,
+    burst: f64,
+    /// The available bytes, negative while paying off a payment larger than the burst.
+    tokens: f64,
+    refilled_at: Instant,
+    /// The tag of the last granted payment.
+    virtual_time: u128,
+    /// Tells apart payments with the same tag.
+    next_seq: u64,
+    /// The payments waiting for the budget, by tag.
+    waiting: BTreeMap<(u128, u64), Payment>,
+}
+
+#[derive(Debug)]
+struct Payment {
+    bytes: u64,
+    waker: Option<Waker>,
+}
+
+// === impl Bucket ===
+
+impl Bucket {
+    /// Refills the bucket and grants the waiting payments the budget allows, in order.
+    fn grant(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second).min(self.burst);
+        self.refilled_at = now;
+
+        while let Some(entry) = self.waiting.first_entry() {
+            // payments larger than the burst go into debt once the bucket is full
+            if self.tokens < (entry.get().bytes as f64).min(self.burst) {
+                break
+            }
+            self.virtual_time = entry.key().0;
+            let payment = entry.remove();
+            self.tokens -= payment.bytes as f64;
+            if let Some(waker) = payment.waker {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Returns how long it takes until the first waiting payment can be granted.
+    fn next_grant_in(&self) -> Option<Duration> {
+        let (_, payment) = self.waiting.first_key_value()?;
+        let missing = (payment.bytes as f64).min(self.burst) - self.tokens;
+        Some(Duration::from_secs_f64(missing.max(0.) / self.bytes_per_second))
+    }
+}
+
+/// The shares of a registered stream, paying for each direction separately.
+#[derive(Debug)]
+pub(crate) struct PeerBandwidth {
+    pub(crate) egress: BandwidthShare,
+    pub(crate) ingress: BandwidthShare,
+}
+
+/// Pays the transferred bytes of one direction of a stream from the shared budget.
+#[derive(Debug)]
+pub(crate) struct BandwidthShare {
+    bucket: Arc<Mutex<Bucket>>,
+    weight: u128,
+    /// The tag of the last payment.
+    last_tag: u128,
+    /// The payment waiting for the budget, if any.
+    pending: Option<(u128, u64)>,
+    /// Fires once the first waiting payment can be granted.
+    timer: Pin<Box<Sleep>>,
+}
+
+// === impl BandwidthShare ===
+
+impl BandwidthShare {
+    fn new(bucket: Arc<Mutex<Bucket>>, weight: u32) -> Self {
+        Self {
+            bucket,
+            weight: weight.max(1) as u128,
+            last_tag: 0,
+            pending: None,
+            timer: Box::pin(tokio::time::sleep(Duration::ZERO)),
+        }
+    }
+
+    /// Pays for `bytes` transferred bytes, to be granted before the next transfer.
+    ///
+    /// Must only be called once [`BandwidthShare::poll_granted`] returned `Ready`.
+    pub(crate) fn request(&mut self, bytes: usize) {
+        debug_assert!(self.pending.is_none(), "previous payment not granted yet");
+        let mut bucket = self.bucket.lock().expect("not poisoned");
+        let tag =
+            bucket.virtual_time.max(self.last_tag) + bytes as u128 * WEIGHT_UNIT / self.weight;
+        let key = (tag, bucket.next_seq);
+        bucket.next_seq += 1;
+        bucket.waiting.insert(key, Payment { bytes: bytes as u64, waker: None });
+        self.last_tag = tag;
+        self.pending = Some(key);
+        bucket.grant();
+        if !bucket.waiting.contains_key(&key) {
+            self.pending = None;
+        }
+    }
+
+    /// Returns `Ready` once the last payment was granted.
+    ///
+    /// While waiting, this also grants the payments of other streams, so the budget is handed out
+    /// as long as any of the waiting streams is polled.
+    pub(crate) fn poll_granted(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(key) = self.pending else { return Poll::Ready(()) };
+        loop {
+            let mut bucket = self.bucket.lock().expect("not poisoned");
+            bucket.grant();
+            let Some(payment) = bucket.waiting.get_mut(&key) else {
+                self.pending = None;
+                return Poll::Ready(())
+            };
+            payment.waker = Some(cx.waker().clone());
+            let next_grant_in = bucket.next_grant_in().expect("payment is waiting");
+            drop(bucket);
+
+            self.timer.as_mut().reset(Instant::now() + next_grant_in);
+            if self.timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for BandwidthShare {
+    fn drop(&mut self) {
+        if let Some(key) = self.pending.take() {
+            let mut bucket = self.bucket.lock().expect("not poisoned");
+            bucket.waiting.remove(&key);
+            // the new first payment may be grantable already
+            bucket.grant();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{stream::ECIESStream, util::pk2id};
+    use futures::SinkExt;
+    use reth_primitives::bytes::Bytes;
+    use secp256k1::{rand, SecretKey, SECP256K1};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn shares_bandwidth_fairly() {
+        const MESSAGE_SIZE: usize = 256;
+        let bytes_per_second = 32 * 1024;
+        let limiter = BandwidthLimiter::new(bytes_per_second, 1024);
+        let weights = [1, 1, 2];
+
+        let mut senders = Vec::new();
+        let mut receivers = Vec::new();
+        let mut sent = Vec::new();
+        for weight in weights {
+            let (client, server) = tokio::io::duplex(1024 * 1024);
+            let server_key = SecretKey::new(&mut rand::thread_rng());
+            let server_id = pk2id(&server_key.public_key(SECP256K1));
+            let client_key = SecretKey::new(&mut rand::thread_rng());
+            let (client_stream, server_stream) = tokio::join!(
+                ECIESStream::connect(client, client_key, server_id),
+                ECIESStream::incoming(server, server_key)
+            );
+            let mut client_stream = client_stream.unwrap().with_bandwidth_limiter(&limiter, weight);
+            receivers.push(server_stream.unwrap());
+
+            let count = Arc::new(AtomicUsize::new(0));
+            sent.push(count.clone());
+            senders.push(tokio::spawn(async move {
+                loop {
+                    client_stream.send(Bytes::from(vec![0; MESSAGE_SIZE])).await.unwrap();
+                    count.fetch_add(1, Ordering::Relaxed);
+                }
+            }));
+        }
+
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let sent = sent.iter().map(|count| count.load(Ordering::Relaxed)).collect::<Vec<_>>();
+        let elapsed = start.elapsed().as_secs_f64();
+        for sender in senders {
+            sender.abort();
+        }
+
+        // the budget is enforced, each stream pays its last message after sending it
+        let budget = (elapsed * bytes_per_second as f64 + 1024.) / MESSAGE_SIZE as f64;
+        let total = sent.iter().sum::<usize>();
+        assert!(total as f64 <= budget + weights.len() as f64, "{sent:?} exceeds {budget}");
+        assert!(total as f64 >= budget * 0.8, "{sent:?} falls short of {budget}");
+
+        // and shared in proportion to the weights
+        let unit = total as f64 / weights.iter().sum::<u32>() as f64;
+        for (sent, weight) in sent.iter().zip(weights) {
+            let share = *sent as f64 / unit;
+            assert!((share - weight as f64).abs() <= 0.25 * weight as f64, "{sent} of {total}");
+        }
+        drop(receivers);
+    }
+}
@@ -0,0 +1,155 @@
+//! An [`ECIESStream`] adapter that yields all readily available messages at once.
+
+use crate::stream::ECIESStream;
+use futures::Sink;
+use reth_primitives::bytes::Bytes;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_stream::Stream;
+
+/// Wraps an [`ECIESStream`] to receive messages in batches.
+///
+/// The [`Stream`] impl of [`ECIESStream`] yields a single message per poll, so a task consuming a
+/// flood of tiny messages is polled once per message. This adapter instead yields all messages
+/// that can be decoded without waiting for the transport, up to `max_batch_size` of them, in a
+/// single poll. A batch is never empty, the stream waits for at least one message.
+///
+/// If reading fails or the peer closes the connection after some messages of a batch were
+/// decoded, the batch is yielded first and the error or the end of the stream on the next poll.
+///
+/// Sending is unaffected, messages are sent one at a time like with the wrapped stream.
+#[derive(Debug)]
+#[pin_project::pin_project]
+pub struct BatchedECIESStream<Io, Ctx = ()> {
+    #[pin]
+    inner: ECIESStream<Io, Ctx>,
+    max_batch_size: usize,
+    /// The error or end of the stream that followed the last yielded batch, if any.
+    deferred: Option<Option<io::Error>>,
+}
+
+// === impl BatchedECIESStream ===
+
+impl<Io, Ctx> BatchedECIESStream<Io, Ctx> {
+    /// Wraps the given stream, yielding at most `max_batch_size` messages per batch.
+    ///
+    /// A `max_batch_size` of zero is treated as one.
+    pub fn new(inner: ECIESStream<Io, Ctx>, max_batch_size: usize) -> Self {
+        Self { inner, max_batch_size: max_batch_size.max(1), deferred: None }
+    }
+
+    /// Returns a reference to the wrapped stream.
+    pub fn inner(&self) -> &ECIESStream<Io, Ctx> {
+        &self.inner
+    }
+
+    /// Returns the wrapped stream, which yields single messages again.
+    ///
+    /// An error or the end of the stream that was deferred until after the last batch is lost.
+    pub fn into_inner(self) -> ECIESStream<Io, Ctx> {
+        self.inner
+    }
+}
+
+impl<Io, Ctx> Stream for BatchedECIESStream<Io, Ctx>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<Vec<Bytes>, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if let Some(deferred) = this.deferred.take() {
+            return Poll::Ready(deferred.map(Err))
+        }
+
+        let mut batch = Vec::new();
+        while batch.len() < *this.max_batch_size {
+            let next = match this.inner.as_mut().poll_next(cx) {
+                Poll::Ready(next) => next,
+                Poll::Pending if batch.is_empty() => return Poll::Pending,
+                Poll::Pending => break,
+            };
+            match next {
+                Some(Ok(message)) => batch.push(message.freeze()),
+                Some(Err(err)) if batch.is_empty() => return Poll::Ready(Some(Err(err))),
+                None if batch.is_empty() => return Poll::Ready(None),
+                end => {
+                    *this.deferred = Some(end.and_then(Result::err));
+                    break
+                }
+            }
+        }
+        Poll::Ready(Some(Ok(batch)))
+    }
+}
+
+impl<Io, Ctx> Sink<Bytes> for BatchedECIESStream<Io, Ctx>
+where
+    Io: AsyncWrite + Unpin,
+{
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        self.project().inner.start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::pk2id;
+    use futures::{FutureExt, SinkExt};
+    use secp256k1::{rand, SecretKey, SECP256K1};
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn yields_buffered_messages_in_one_batch() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let (client_stream, server_stream) = tokio::join!(
+            ECIESStream::connect(client, client_key, server_id),
+            ECIESStream::incoming(server, server_key)
+        );
+        let mut client_stream = client_stream.unwrap();
+        let mut server_stream = server_stream.unwrap().into_batched(4);
+
+        let messages = (0..6u8).map(|i| Bytes::from(vec![i; 8])).collect::<Vec<_>>();
+        for message in &messages {
+            client_stream.feed(message.clone()).await.unwrap();
+        }
+        client_stream.flush().await.unwrap();
+
+        // everything is buffered by the transport, so batches are yielded without waiting
+        let batch = server_stream.next().now_or_never().unwrap().unwrap().unwrap();
+        assert_eq!(batch, messages[..4]);
+        let batch = server_stream.next().now_or_never().unwrap().unwrap().unwrap();
+        assert_eq!(batch, messages[4..]);
+        assert!(server_stream.next().now_or_never().is_none());
+
+        // the end of the stream is deferred until after the batch preceding it
+        client_stream.send(Bytes::from("last")).await.unwrap();
+        client_stream.close().await.unwrap();
+        drop(client_stream);
+        assert_eq!(server_stream.next().await.unwrap().unwrap(), vec![Bytes::from("last")]);
+        assert!(server_stream.next().await.is_none());
+    }
+}
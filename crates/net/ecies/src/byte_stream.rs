@@ -0,0 +1,190 @@
+//! An [`AsyncRead`] + [`AsyncWrite`] adapter for [`ECIESStream`].
+
+use crate::stream::ECIESStream;
+use futures::{ready, Sink};
+use reth_primitives::bytes::{Buf, Bytes, BytesMut};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_stream::Stream;
+
+/// Default maximum number of bytes sent as a single message by [`ECIESByteStream`].
+pub const DEFAULT_MAX_WRITE_SIZE: usize = 64 * 1024;
+
+/// Exposes the decrypted messages of an [`ECIESStream`] as a continuous byte stream.
+///
+/// This allows layering any framing, e.g. a [`tokio_util::codec`] codec, on top of the ECIES
+/// transport.
+///
+/// Message boundaries are not preserved: reads return the bytes of the received messages in order,
+/// possibly spanning several messages or only part of one. Each write is sent as a single message
+/// of at most `max_write_size` bytes, so larger writes are only partially accepted. As usual,
+/// writes are buffered until the stream is flushed.
+#[derive(Debug)]
+#[pin_project::pin_project]
+pub struct ECIESByteStream<Io> {
+    #[pin]
+    inner: ECIESStream<Io>,
+    /// The unread remainder of the last received message.
+    read_buf: BytesMut,
+    /// Writes larger than this are split into several messages.
+    max_write_size: usize,
+}
+
+// === impl ECIESByteStream ===
+
+impl<Io> ECIESByteStream<Io> {
+    /// Wraps the given stream.
+    pub fn new(inner: ECIESStream<Io>) -> Self {
+        Self { inner, read_buf: BytesMut::new(), max_write_size: DEFAULT_MAX_WRITE_SIZE }
+    }
+
+    /// Sets the maximum number of bytes sent as a single message.
+    ///
+    /// Must be non-zero and not exceed the maximum frame size of the stream.
+    pub fn with_max_write_size(mut self, max_write_size: usize) -> Self {
+        self.max_write_size = max_write_size.max(1);
+        self
+    }
+
+    /// Returns a reference to the wrapped stream.
+    pub fn inner(&self) -> &ECIESStream<Io> {
+        &self.inner
+    }
+
+    /// Returns the wrapped stream.
+    ///
+    /// Any bytes that were received but not read yet are discarded.
+    pub fn into_inner(self) -> ECIESStream<Io> {
+        self.inner
+    }
+}
+
+impl<Io> AsyncRead for ECIESByteStream<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+
+        while this.read_buf.is_empty() {
+            match ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(message)) => *this.read_buf = message,
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                // end of stream
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let len = this.read_buf.len().min(buf.remaining());
+        buf.put_slice(&this.read_buf[..len]);
+        this.read_buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<Io> AsyncWrite for ECIESByteStream<Io>
+where
+    Io: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0))
+        }
+
+        let mut this = self.project();
+        ready!(this.inner.as_mut().poll_ready(cx))?;
+        let len = buf.len().min(*this.max_write_size);
+        this.inner.start_send(Bytes::copy_from_slice(&buf[..len]))?;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::pk2id;
+    use futures::SinkExt;
+    use secp256k1::{rand, SecretKey, SECP256K1};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::{Framed, LinesCodec};
+
+    async fn connected_pair() -> (ECIESStream<TcpStream>, ECIESStream<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            ECIESStream::incoming(incoming, server_key).await.unwrap()
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let client = ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        (client, handle.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn reads_across_message_boundaries() {
+        let (client, mut server) = connected_pair().await;
+        let mut client = ECIESByteStream::new(client).with_max_write_size(4);
+
+        // split into the messages "hell", "o wo", "rld"
+        client.write_all(b"hello world").await.unwrap();
+        client.flush().await.unwrap();
+        for expected in ["hell", "o wo", "rld"] {
+            assert_eq!(server.next().await.unwrap().unwrap(), Bytes::from(expected));
+        }
+
+        server.send(Bytes::from("abc")).await.unwrap();
+        server.send(Bytes::new()).await.unwrap();
+        server.send(Bytes::from("defgh")).await.unwrap();
+        drop(server);
+
+        // reads span and split the messages, empty messages are skipped
+        let mut buf = [0u8; 2];
+        for expected in [b"ab", b"cd", b"ef", b"gh"] {
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, expected);
+        }
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn composes_with_codecs() {
+        let (client, server) = connected_pair().await;
+        let mut client = Framed::new(ECIESByteStream::new(client), LinesCodec::new());
+        let mut server = Framed::new(ECIESByteStream::new(server), LinesCodec::new());
+
+        client.send("hello").await.unwrap();
+        client.send("world").await.unwrap();
+
+        assert_eq!(server.next().await.unwrap().unwrap(), "hello");
+        assert_eq!(server.next().await.unwrap().unwrap(), "world");
+    }
+}
@@ -1,7 +1,14 @@
-use crate::{algorithm::ECIES, ECIESError, EgressECIESValue, IngressECIESValue};
+use crate::{
+    algorithm::ECIES,
+    config::{Rekeying, MAX_FRAME_SIZE},
+    error::ECIESErrorImpl,
+    params::HandshakeParams,
+    replay::HandshakeReplayGuard,
+    ECIESError, EgressECIESValue, IngressECIESValue,
+};
 use reth_primitives::{bytes::BytesMut, H512 as PeerId};
 use secp256k1::SecretKey;
-use std::{fmt::Debug, io};
+use std::{fmt::Debug, io, time::Instant};
 use tokio_util::codec::{Decoder, Encoder};
 use tracing::{instrument, trace};
 
@@ -10,6 +17,46 @@ use tracing::{instrument, trace};
 pub(crate) struct ECIESCodec {
     ecies: ECIES,
     state: ECIESState,
+    /// The maximum size of a frame body in both directions
+    max_frame_size: usize,
+    /// Rejects auth messages with an already seen nonce, if set
+    replay_guard: Option<HandshakeReplayGuard>,
+    /// Tracks when egress frames are due for rekeying, if enabled
+    rekeying: Option<RekeyingState>,
+    /// The error of the frame header that failed the tag check
+    unreadable_frame: Option<ECIESError>,
+    /// The keys of the identities served by the listener, the auth selects one, if set
+    server_keys: Option<Vec<SecretKey>>,
+}
+
+/// Usage of the current egress keys.
+#[derive(Debug)]
+struct RekeyingState {
+    thresholds: Rekeying,
+    /// Message bytes sent with the current keys
+    bytes: u64,
+    /// When the current keys were taken into use
+    since: Instant,
+}
+
+// === impl RekeyingState ===
+
+impl RekeyingState {
+    fn new(thresholds: Rekeying) -> Self {
+        Self { thresholds, bytes: 0, since: Instant::now() }
+    }
+
+    /// Records a sent message, returning `true` if the keys are due for rekeying.
+    fn on_message(&mut self, len: usize) -> bool {
+        self.bytes = self.bytes.saturating_add(len as u64);
+        let due = self.thresholds.max_bytes.map_or(false, |max| self.bytes >= max) ||
+            self.thresholds.max_age.map_or(false, |max| self.since.elapsed() >= max);
+        if due {
+            self.bytes = 0;
+            self.since = Instant::now();
+        }
+        due
+    }
 }
 
 /// Current ECIES state of a connection
@@ -25,17 +72,90 @@ enum ECIESState {
     Ack,
     Header,
     Body,
+
+    /// A frame header failed the tag check. The frame is held back until it's known whether the
+    /// peer sent a handshake message instead, the session can't continue either way.
+    UnreadableFrame,
 }
 
 impl ECIESCodec {
     /// Create a new server codec using the given secret key
     pub(crate) fn new_server(secret_key: SecretKey) -> Result<Self, ECIESError> {
-        Ok(Self { ecies: ECIES::new_server(secret_key)?, state: ECIESState::Auth })
+        Ok(Self {
+            ecies: ECIES::new_server(secret_key)?,
+            state: ECIESState::Auth,
+            max_frame_size: MAX_FRAME_SIZE,
+            replay_guard: None,
+            rekeying: None,
+            unreadable_frame: None,
+            server_keys: None,
+        })
     }
 
     /// Create a new client codec using the given secret key and the server's public id
     pub(crate) fn new_client(secret_key: SecretKey, remote_id: PeerId) -> Result<Self, ECIESError> {
-        Ok(Self { ecies: ECIES::new_client(secret_key, remote_id)?, state: ECIESState::Auth })
+        Ok(Self {
+            ecies: ECIES::new_client(secret_key, remote_id)?,
+            state: ECIESState::Auth,
+            max_frame_size: MAX_FRAME_SIZE,
+            replay_guard: None,
+            rekeying: None,
+            unreadable_frame: None,
+            server_keys: None,
+        })
+    }
+
+    /// Returns the peer ID of the local node.
+    pub(crate) fn local_id(&self) -> PeerId {
+        self.ecies.local_id()
+    }
+
+    /// Returns the symmetric secrets of the session, see [`ECIES::session_keys`].
+    #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+    pub(crate) fn session_keys(&self) -> Option<crate::algorithm::SessionKeys> {
+        self.ecies.session_keys()
+    }
+
+    /// Sets the maximum size of a frame body, frames exceeding it are rejected.
+    pub(crate) fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Returns an error if a message of the given size exceeds the maximum frame size.
+    pub(crate) fn check_frame_size(&self, len: usize) -> io::Result<()> {
+        if len > self.max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("ecies frame of {} bytes exceeds maximum of {}", len, self.max_frame_size),
+            ))
+        }
+        Ok(())
+    }
+
+    /// Sets the parameters of the encryption of the auth and ack messages.
+    pub(crate) fn with_handshake_params(mut self, handshake_params: HandshakeParams) -> Self {
+        self.ecies = self.ecies.with_handshake_params(handshake_params);
+        self
+    }
+
+    /// Enables rekeying of egress frames once any of the thresholds is reached.
+    pub(crate) fn with_rekeying(mut self, rekeying: Option<Rekeying>) -> Self {
+        self.rekeying = rekeying.map(RekeyingState::new);
+        self
+    }
+
+    /// Reads the auth with the first of the keys it was encrypted to, instead of the key the codec
+    /// was created with.
+    pub(crate) fn with_server_keys(mut self, server_keys: Vec<SecretKey>) -> Self {
+        self.server_keys = Some(server_keys);
+        self
+    }
+
+    /// Sets the guard that rejects replayed auth messages.
+    pub(crate) fn with_replay_guard(mut self, replay_guard: Option<HandshakeReplayGuard>) -> Self {
+        self.replay_guard = replay_guard;
+        self
     }
 }
 
@@ -61,8 +181,19 @@ impl Decoder for ECIESCodec {
                         return Ok(None)
                     }
 
+                    if let Some(server_keys) = &self.server_keys {
+                        self.ecies.select_server_key(&buf[..total_size], server_keys)?;
+                    }
                     self.ecies.read_auth(&mut buf.split_to(total_size))?;
 
+                    if let (Some(guard), Some(nonce)) =
+                        (&self.replay_guard, self.ecies.remote_nonce())
+                    {
+                        if !guard.insert(nonce) {
+                            return Err(ECIESErrorImpl::ReplayedHandshake.into())
+                        }
+                    }
+
                     self.state = ECIESState::Header;
                     return Ok(Some(IngressECIESValue::AuthReceive(self.ecies.remote_id())))
                 }
@@ -91,7 +222,28 @@ impl Decoder for ECIESCodec {
                         return Ok(None)
                     }
 
-                    self.ecies.read_header(&mut buf.split_to(ECIES::header_len()))?;
+                    let mut header = buf.split_to(ECIES::header_len());
+                    let body_size = match self.ecies.read_header(&mut header) {
+                        Ok(body_size) => body_size,
+                        Err(err) => match err.into_inner() {
+                            err @ ECIESErrorImpl::TagCheckHeaderFailed => {
+                                // the header is left unchanged if the tag check fails
+                                header.unsplit(buf.split());
+                                *buf = header;
+                                self.unreadable_frame = Some(err.into());
+                                self.state = ECIESState::UnreadableFrame;
+                                continue
+                            }
+                            err => return Err(err.into()),
+                        },
+                    };
+                    if body_size > self.max_frame_size {
+                        return Err(ECIESErrorImpl::FrameTooLarge {
+                            size: body_size,
+                            max: self.max_frame_size,
+                        }
+                        .into())
+                    }
 
                     self.state = ECIESState::Body;
                 }
@@ -100,16 +252,58 @@ impl Decoder for ECIESCodec {
                         return Ok(None)
                     }
 
+                    let rekey = self.ecies.is_rekey_frame();
                     let mut data = buf.split_to(self.ecies.body_len());
                     let mut ret = BytesMut::new();
                     ret.extend_from_slice(self.ecies.read_body(&mut data)?);
 
                     self.state = ECIESState::Header;
+                    if rekey {
+                        // not a message, the following frames use the new keys
+                        trace!("rekeyed ingress frames");
+                        continue
+                    }
                     return Ok(Some(IngressECIESValue::Message(ret)))
                 }
+                ECIESState::UnreadableFrame => {
+                    // handshake messages are prefixed by their size and start with the
+                    // uncompressed ephemeral public key
+                    if buf.len() < 3 {
+                        return Ok(None)
+                    }
+                    let total_size = u16::from_be_bytes([buf[0], buf[1]]) as usize + 2;
+                    if buf[2] == 0x04 && buf.len() < total_size {
+                        trace!("current len {}, need {}", buf.len(), total_size);
+                        return Ok(None)
+                    }
+
+                    if buf[2] == 0x04 && self.ecies.is_handshake_message(&buf[..total_size]) {
+                        return Err(ECIESErrorImpl::HandshakeAfterEstablished.into())
+                    }
+                    return Err(self
+                        .unreadable_frame
+                        .take()
+                        .unwrap_or_else(|| ECIESErrorImpl::TagCheckHeaderFailed.into()))
+                }
             }
         }
     }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(item) = self.decode(buf)? {
+            return Ok(Some(item))
+        }
+        match self.state {
+            ECIESState::UnreadableFrame => Err(self
+                .unreadable_frame
+                .take()
+                .unwrap_or_else(|| ECIESErrorImpl::TagCheckHeaderFailed.into())),
+            // the header of the frame was already consumed
+            ECIESState::Body => Err(ECIESErrorImpl::TruncatedFrame.into()),
+            _ if buf.is_empty() => Ok(None),
+            _ => Err(ECIESErrorImpl::TruncatedFrame.into()),
+        }
+    }
 }
 
 impl Encoder<EgressECIESValue> for ECIESCodec {
@@ -129,8 +323,13 @@ impl Encoder<EgressECIESValue> for ECIESCodec {
                 Ok(())
             }
             EgressECIESValue::Message(data) => {
+                self.check_frame_size(data.len())?;
                 self.ecies.write_header(buf, data.len());
                 self.ecies.write_body(buf, &data);
+                if self.rekeying.as_mut().map_or(false, |state| state.on_message(data.len())) {
+                    self.ecies.write_rekey(buf);
+                    trace!("rekeyed egress frames");
+                }
                 Ok(())
             }
         }
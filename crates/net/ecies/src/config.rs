@@ -0,0 +1,200 @@
+//! Configuration for [`ECIESStream`](crate::stream::ECIESStream).
+
+use crate::{
+    in_flight::InFlightWindow,
+    limiter::ConnectionLimiter,
+    params::HandshakeParams,
+    replay::HandshakeReplayGuard,
+    stream::{EgressPolicy, FlushBatching},
+};
+use reth_net_common::ratelimit::Rate;
+use std::time::Duration;
+
+/// Default time to wait for the auth/ack handshake to complete.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum size of a frame body, bounded by the 3 byte size field of the frame header.
+pub const MAX_FRAME_SIZE: usize = (1 << 24) - 1;
+
+/// All tunables of an [`ECIESStream`](crate::stream::ECIESStream), passed once at construction.
+#[derive(Debug, Clone)]
+pub struct ECIESConfig {
+    /// How long the auth/ack handshake may take before it's aborted.
+    ///
+    /// `None` waits indefinitely.
+    pub handshake_timeout: Option<Duration>,
+    /// How long the stream may go without receiving a frame before it yields a
+    /// [TimedOut](std::io::ErrorKind::TimedOut) error.
+    ///
+    /// `None` disables the idle timeout.
+    pub idle_timeout: Option<Duration>,
+    /// The maximum size of a frame body, in both directions.
+    ///
+    /// Must not exceed [`MAX_FRAME_SIZE`].
+    pub max_frame_size: usize,
+    /// The maximum rate of egress messages.
+    ///
+    /// `None` sends messages as fast as the transport accepts them.
+    pub egress_rate_limit: Option<Rate>,
+    /// Coalesces egress frames into fewer writes, see [`FlushBatching`].
+    pub flush_batching: Option<FlushBatching>,
+    /// How to handle a peer that doesn't keep up with the egress messages, see [`EgressPolicy`].
+    pub egress_policy: EgressPolicy,
+    /// The maximum number of sent messages the caller didn't acknowledge yet, see
+    /// [`InFlightWindow`].
+    ///
+    /// `None` doesn't track messages in flight.
+    pub max_in_flight: Option<usize>,
+    /// Rejects incoming handshakes replaying the auth of an earlier one.
+    ///
+    /// Share the same guard between all connections accepted by a listener.
+    pub replay_guard: Option<HandshakeReplayGuard>,
+    /// Caps the number of concurrent incoming connections.
+    ///
+    /// Share the same limiter between all connections accepted by a listener.
+    pub connection_limiter: Option<ConnectionLimiter>,
+    /// The parameters of the encryption of the auth and ack messages.
+    ///
+    /// **For interop testing only**, anything but the default can't connect to other devp2p
+    /// peers, see [`HandshakeParams`].
+    pub handshake_params: HandshakeParams,
+    /// When to renegotiate the keys encrypting egress frames, see [`Rekeying`].
+    ///
+    /// `None` keeps the keys of the handshake for the whole session.
+    pub rekeying: Option<Rekeying>,
+}
+
+// === impl ECIESConfig ===
+
+impl ECIESConfig {
+    /// Sets the handshake timeout.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the idle timeout.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum frame size, capped at [`MAX_FRAME_SIZE`].
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size.min(MAX_FRAME_SIZE);
+        self
+    }
+
+    /// Sets the egress rate limit.
+    pub fn with_egress_rate_limit(mut self, rate: Rate) -> Self {
+        self.egress_rate_limit = Some(rate);
+        self
+    }
+
+    /// Enables batched flushing of egress frames.
+    pub fn with_flush_batching(mut self, batching: FlushBatching) -> Self {
+        self.flush_batching = Some(batching);
+        self
+    }
+
+    /// Sets how to handle a peer that doesn't keep up with the egress messages.
+    pub fn with_egress_policy(mut self, policy: EgressPolicy) -> Self {
+        self.egress_policy = policy;
+        self
+    }
+
+    /// Bounds the number of messages in flight, see [`InFlightWindow`].
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Sets the guard rejecting replayed incoming handshakes.
+    pub fn with_replay_guard(mut self, guard: HandshakeReplayGuard) -> Self {
+        self.replay_guard = Some(guard);
+        self
+    }
+
+    /// Sets the limiter of concurrent incoming connections.
+    pub fn with_connection_limiter(mut self, limiter: ConnectionLimiter) -> Self {
+        self.connection_limiter = Some(limiter);
+        self
+    }
+
+    /// Sets the parameters of the encryption of the auth and ack messages.
+    ///
+    /// **For interop testing only**, see [`HandshakeParams`].
+    pub fn with_handshake_params(mut self, params: HandshakeParams) -> Self {
+        self.handshake_params = params;
+        self
+    }
+
+    /// Enables rekeying of egress frames.
+    ///
+    /// **Non-standard**, see [`Rekeying`].
+    pub fn with_rekeying(mut self, rekeying: Rekeying) -> Self {
+        self.rekeying = Some(rekeying);
+        self
+    }
+}
+
+impl Default for ECIESConfig {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+            idle_timeout: None,
+            max_frame_size: MAX_FRAME_SIZE,
+            egress_rate_limit: None,
+            flush_batching: None,
+            egress_policy: EgressPolicy::default(),
+            max_in_flight: None,
+            replay_guard: None,
+            connection_limiter: None,
+            handshake_params: HandshakeParams::default(),
+            rekeying: None,
+        }
+    }
+}
+
+/// Thresholds for replacing the keys encrypting the egress frames of an established session.
+///
+/// Once a threshold is reached, the stream sends a rekey frame after the current message and
+/// encrypts all subsequent frames with new keys. These are derived from the current keys and an
+/// ECDH secret of a fresh ephemeral key, carried in the rekey frame, and the remote's ephemeral
+/// key of the handshake. The remote derives the same keys when it reads the rekey frame, so the
+/// session continues without interruption and rekeying is transparent to the users of the stream.
+/// Each direction is rekeyed independently.
+///
+/// A leaked key only exposes the frames sent until the next rekey: the derivation is one-way, and
+/// the following keys can't be derived without the ephemeral secret key of the remote.
+///
+/// **Non-standard**: devp2p has no rekeying, only enable this for connections to peers that are
+/// known to support it. Other peers fail to read the frames following the first rekey frame.
+/// Every [`ECIESStream`](crate::stream::ECIESStream) accepts rekey frames, regardless of whether
+/// it rekeys its own frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rekeying {
+    /// Rekeys once this many message bytes were sent with the current keys.
+    pub max_bytes: Option<u64>,
+    /// Rekeys once the current keys are in use for this long.
+    ///
+    /// This is checked whenever a message is sent, an idle session isn't rekeyed until it sends
+    /// the next message.
+    pub max_age: Option<Duration>,
+}
+
+// === impl Rekeying ===
+
+impl Rekeying {
+    /// Sets the number of bytes after which the keys are replaced.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Sets the time after which the keys are replaced.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+}
@@ -0,0 +1,134 @@
+//! Coordinated draining of many connections, e.g. on node shutdown.
+
+use futures::task::AtomicWaker;
+use reth_primitives::bytes::Bytes;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::Context,
+};
+use tokio::sync::Notify;
+
+/// Drains all [`ECIESStream`](crate::stream::ECIESStream)s registered with it at once, see
+/// [`ECIESStream::with_drain`](crate::stream::ECIESStream::with_drain).
+///
+/// Once [triggered](DrainSignal::drain), every registered stream stops accepting new messages,
+/// writes out the frames it still buffers, sends the farewell message, if any, and closes its side
+/// of the connection. ECIES doesn't know about the protocols it carries, so the farewell is the
+/// already encoded message to notify the peer with, e.g. an RLPx `Disconnect`.
+///
+/// A stream only drains while it's polled, like it only does any other I/O then. Registered
+/// streams are woken when the drain is triggered, so connections whose tasks keep polling them
+/// drain without further coordination. [`DrainSignal::drained`] resolves once all of them are
+/// done, for a graceful shutdown to wait on, usually with a timeout.
+///
+/// The signal is cheap to clone and meant to be shared by all connections of a node.
+#[derive(Debug, Clone, Default)]
+pub struct DrainSignal {
+    inner: Arc<DrainInner>,
+}
+
+#[derive(Debug, Default)]
+struct DrainInner {
+    /// Set once the drain was triggered.
+    triggered: AtomicBool,
+    /// The message sent by the streams before closing.
+    farewell: Mutex<Option<Bytes>>,
+    /// The wakers of the registered streams, dead once a stream drained or was dropped.
+    streams: Mutex<Vec<Weak<AtomicWaker>>>,
+    /// Notified whenever a stream drained or was dropped.
+    stream_done: Notify,
+}
+
+// === impl DrainSignal ===
+
+impl DrainSignal {
+    /// Creates a signal without any registered streams.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Triggers the drain of all registered streams, which send the `farewell` message before
+    /// closing.
+    ///
+    /// Streams registered afterwards drain right away. Only the first call has an effect.
+    pub fn drain(&self, farewell: Option<Bytes>) {
+        {
+            let mut current = self.inner.farewell.lock().expect("not poisoned");
+            if self.inner.triggered.load(Ordering::Acquire) {
+                return
+            }
+            *current = farewell;
+            self.inner.triggered.store(true, Ordering::Release);
+        }
+
+        let streams = self.inner.streams.lock().expect("not poisoned");
+        for waker in streams.iter().filter_map(Weak::upgrade) {
+            waker.wake();
+        }
+    }
+
+    /// Returns `true` if the drain was triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of registered streams that are neither drained nor dropped.
+    pub fn active_streams(&self) -> usize {
+        let mut streams = self.inner.streams.lock().expect("not poisoned");
+        streams.retain(|waker| waker.strong_count() > 0);
+        streams.len()
+    }
+
+    /// Resolves once every registered stream drained or was dropped.
+    ///
+    /// This doesn't trigger the drain, see [`DrainSignal::drain`].
+    pub async fn drained(&self) {
+        loop {
+            let stream_done = self.inner.stream_done.notified();
+            if self.active_streams() == 0 {
+                return
+            }
+            stream_done.await;
+        }
+    }
+
+    /// Registers a stream, which drains once the signal is triggered.
+    pub(crate) fn register(&self) -> DrainRegistration {
+        let waker = Arc::new(AtomicWaker::new());
+        self.inner.streams.lock().expect("not poisoned").push(Arc::downgrade(&waker));
+        DrainRegistration { signal: self.inner.clone(), waker }
+    }
+}
+
+/// The registration of a stream with a [`DrainSignal`], released when dropped.
+#[derive(Debug)]
+pub(crate) struct DrainRegistration {
+    signal: Arc<DrainInner>,
+    waker: Arc<AtomicWaker>,
+}
+
+// === impl DrainRegistration ===
+
+impl DrainRegistration {
+    /// Returns `true` if the drain was triggered, otherwise the current task is woken once it is.
+    pub(crate) fn poll_triggered(&self, cx: &mut Context<'_>) -> bool {
+        self.waker.register(cx.waker());
+        self.signal.triggered.load(Ordering::Acquire)
+    }
+
+    /// Returns the farewell message of the triggered drain.
+    pub(crate) fn farewell(&self) -> Option<Bytes> {
+        self.signal.farewell.lock().expect("not poisoned").clone()
+    }
+}
+
+impl Drop for DrainRegistration {
+    fn drop(&mut self) {
+        // replace the waker, so the stream no longer counts as active before notifying
+        self.waker = Arc::new(AtomicWaker::new());
+        self.signal.stream_done.notify_waiters();
+    }
+}
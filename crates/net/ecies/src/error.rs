@@ -1,5 +1,5 @@
-use crate::IngressECIESValue;
-use std::fmt;
+use crate::{violation::ViolationKind, IngressECIESValue};
+use std::{fmt, io};
 use thiserror::Error;
 
 /// An error that occurs while reading or writing to an ECIES stream.
@@ -14,6 +14,46 @@ impl ECIESError {
     pub fn into_inner(self) -> ECIESErrorImpl {
         *self.inner
     }
+
+    /// Returns `true` if the peer closed the connection, see [`ECIESErrorImpl::PeerClosed`].
+    pub fn is_peer_closed(&self) -> bool {
+        matches!(*self.inner, ECIESErrorImpl::PeerClosed(_))
+    }
+
+    /// Returns `true` if the stream no longer accepts messages because it was drained, see
+    /// [`ECIESErrorImpl::Drained`].
+    pub fn is_drained(&self) -> bool {
+        matches!(*self.inner, ECIESErrorImpl::Drained)
+    }
+
+    /// Returns `true` if the peer sent a handshake message on an established session, see
+    /// [`ECIESErrorImpl::HandshakeAfterEstablished`].
+    pub fn is_handshake_after_established(&self) -> bool {
+        matches!(*self.inner, ECIESErrorImpl::HandshakeAfterEstablished)
+    }
+
+    /// Returns the protocol violation of the peer that caused this error, if any.
+    ///
+    /// Errors that aren't the peer's fault, e.g. I/O errors, aren't violations.
+    pub fn violation(&self) -> Option<ViolationKind> {
+        match &*self.inner {
+            ECIESErrorImpl::HandshakeAfterEstablished => Some(ViolationKind::UnexpectedMessage),
+            ECIESErrorImpl::FrameTooLarge { size, max } => {
+                Some(ViolationKind::OversizedFrame { size: *size, max: *max })
+            }
+            ECIESErrorImpl::TagCheckHeaderFailed |
+            ECIESErrorImpl::TagCheckBodyFailed |
+            ECIESErrorImpl::InvalidHeader => Some(ViolationKind::MalformedFrame),
+            ECIESErrorImpl::TruncatedFrame => Some(ViolationKind::TruncatedFrame),
+            _ => None,
+        }
+    }
+
+    /// Returns the error wrapped by an [`io::Error`] of an
+    /// [`ECIESStream`](crate::stream::ECIESStream), if any.
+    pub fn from_io_error(err: &io::Error) -> Option<&ECIESError> {
+        err.get_ref()?.downcast_ref()
+    }
 }
 
 impl fmt::Debug for ECIESError {
@@ -40,8 +80,11 @@ pub enum ECIESErrorImpl {
     /// Error during IO
     #[error("IO error")]
     IO(std::io::Error),
-    /// Error when checking the HMAC tag against the tag on the message being decrypted
-    #[error("tag check failure in read_header")]
+    /// Error when checking the HMAC tag against the tag on the handshake message being decrypted.
+    ///
+    /// Besides corrupted messages, this is the error of a peer using different
+    /// [`HandshakeParams`](crate::params::HandshakeParams).
+    #[error("tag check failure in handshake message, the peer may use different ECIES parameters")]
     TagCheckDecryptFailed,
     /// Error when checking the HMAC tag against the tag on the header
     #[error("tag check failure in read_header")]
@@ -91,6 +134,53 @@ pub enum ECIESErrorImpl {
     /// a message from the (partially filled) buffer.
     #[error("Stream closed due to not being readable.")]
     UnreadableStream,
+    /// Error when the remote peer id is the local node's own id, i.e. the node tried to connect
+    /// to itself.
+    #[error("attempted to establish a connection to self")]
+    SelfConnection,
+    /// Error when an incoming connection can't be accepted, because the
+    /// [`ServerKeyResolver`](crate::resolver::ServerKeyResolver) provided no keys.
+    #[error("no server keys to accept the connection with")]
+    NoServerKeys,
+    /// Error when a listener received an auth message with a nonce it has already seen, i.e. a
+    /// replayed handshake.
+    #[error("replayed ecies handshake")]
+    ReplayedHandshake,
+    /// Error when an incoming connection was rejected because the configured limit of concurrent
+    /// inbound connections is reached.
+    #[error("too many incoming connections")]
+    TooManyConnections,
+    /// Error when the auth/ack handshake didn't complete within the configured timeout.
+    #[error("ecies handshake timed out")]
+    HandshakeTimeout,
+    /// Error when writing to a connection the peer already closed.
+    ///
+    /// This is the expected end of a connection rather than a protocol error, so it's meant to
+    /// be handled by tearing the connection down. The underlying
+    /// [BrokenPipe](io::ErrorKind::BrokenPipe) or similar error is the source.
+    #[error("peer closed the connection")]
+    PeerClosed(#[source] io::Error),
+    /// Error when sending on a stream that was drained by a
+    /// [`DrainSignal`](crate::drain::DrainSignal).
+    #[error("ecies stream was drained")]
+    Drained,
+    /// Error when the peer sent an auth or ack message after the handshake completed.
+    ///
+    /// Unlike a corrupted frame, this is a deliberate violation of the protocol by the peer,
+    /// which should be banned rather than just disconnected.
+    #[error("peer sent a handshake message on an established session")]
+    HandshakeAfterEstablished,
+    /// Error when the peer closed the connection in the middle of a frame.
+    #[error("connection closed in the middle of a frame")]
+    TruncatedFrame,
+    /// Error when the peer announced a frame body larger than the configured maximum.
+    #[error("frame of {size} bytes exceeds maximum frame size of {max}")]
+    FrameTooLarge {
+        /// The announced size of the frame body
+        size: usize,
+        /// The configured maximum size of a frame body
+        max: usize,
+    },
 }
 
 impl From<ECIESErrorImpl> for ECIESError {
@@ -0,0 +1,150 @@
+//! Reading the capabilities a peer declares in the RLPx `Hello` message.
+
+use crate::stream::ECIESStream;
+use reth_primitives::bytes::BytesMut;
+use reth_rlp::{Decodable, DecodeError, Header, RlpDecodable, RlpEncodable};
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_stream::StreamExt;
+
+/// The id of the p2p `Hello` message, which is the first message of both peers after the
+/// handshake.
+const HELLO_MESSAGE_ID: u8 = 0x00;
+
+/// A subprotocol a peer supports, e.g. `eth/68`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, RlpEncodable, RlpDecodable)]
+pub struct PeerCapability {
+    /// The name of the subprotocol
+    pub name: String,
+    /// The version of the subprotocol
+    pub version: usize,
+}
+
+/// The `Hello` message of a peer, see [`ECIESStream::read_hello`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerHello {
+    /// The capabilities the peer advertised.
+    pub capabilities: Vec<PeerCapability>,
+    /// The message as received, to be handed on to the p2p layer.
+    pub message: BytesMut,
+}
+
+impl<Io, Ctx> ECIESStream<Io, Ctx>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Reads the first message after the handshake and parses it as the RLPx `Hello` of the peer,
+    /// so a caller can decide whether to keep the peer before setting up the p2p protocol.
+    ///
+    /// Only the capabilities are parsed, the returned message still has to be processed by the p2p
+    /// layer, which keeps using the stream afterwards. Any other message, e.g. a `Disconnect`, is
+    /// an [InvalidData](io::ErrorKind::InvalidData) error. If the connection is closed before the
+    /// message arrived, this fails with an [UnexpectedEof](io::ErrorKind::UnexpectedEof) error.
+    ///
+    /// The `Hello` is never snappy compressed, so this must be called before
+    /// [enabling](ECIESStream::enable_snappy) compression.
+    pub async fn read_hello(&mut self) -> io::Result<PeerHello> {
+        let message = self.next().await.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before Hello")
+        })??;
+        let capabilities = decode_hello_capabilities(&message)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(PeerHello { capabilities, message })
+    }
+}
+
+/// Decodes the capabilities of a `Hello` message, including its message id.
+///
+/// The `Hello` is the list `[protocolVersion, clientId, capabilities, listenPort, nodeId]`, the
+/// elements following the capabilities are ignored.
+fn decode_hello_capabilities(mut buf: &[u8]) -> Result<Vec<PeerCapability>, DecodeError> {
+    // message ids are RLP encoded, the one of the `Hello` is `0x80`
+    if u8::decode(&mut buf)? != HELLO_MESSAGE_ID {
+        return Err(DecodeError::Custom("not a Hello message"))
+    }
+
+    let header = Header::decode(&mut buf)?;
+    if !header.list {
+        return Err(DecodeError::UnexpectedString)
+    }
+    let mut payload = buf.get(..header.payload_length).ok_or(DecodeError::InputTooShort)?;
+    let _protocol_version = u8::decode(&mut payload)?;
+    let _client_version = String::decode(&mut payload)?;
+    Vec::decode(&mut payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::pk2id;
+    use futures::SinkExt;
+    use reth_primitives::{bytes::Bytes, H512 as PeerId};
+    use reth_rlp::Encodable;
+    use secp256k1::{rand, SecretKey, SECP256K1};
+
+    #[derive(RlpEncodable)]
+    struct Hello {
+        protocol_version: u8,
+        client_version: String,
+        capabilities: Vec<PeerCapability>,
+        port: u16,
+        id: PeerId,
+    }
+
+    fn capability(name: &str, version: usize) -> PeerCapability {
+        PeerCapability { name: name.to_string(), version }
+    }
+
+    #[tokio::test]
+    async fn reads_hello_capabilities() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let (client_stream, server_stream) = tokio::join!(
+            ECIESStream::connect(client, client_key, server_id),
+            ECIESStream::incoming(server, server_key)
+        );
+        let mut client_stream = client_stream.unwrap();
+        let mut server_stream = server_stream.unwrap();
+
+        let capabilities =
+            vec![capability("eth", 67), capability("eth", 68), capability("snap", 1)];
+        let hello = Hello {
+            protocol_version: 5,
+            client_version: "reth/v0.1.0".to_string(),
+            capabilities: capabilities.clone(),
+            port: 30303,
+            id: pk2id(&client_key.public_key(SECP256K1)),
+        };
+        let mut message = BytesMut::new();
+        HELLO_MESSAGE_ID.encode(&mut message);
+        hello.encode(&mut message);
+        assert_eq!(message[0], 0x80);
+        client_stream.send(message.clone().freeze()).await.unwrap();
+
+        let peer_hello = server_stream.read_hello().await.unwrap();
+        assert_eq!(peer_hello.capabilities, capabilities);
+        assert_eq!(peer_hello.message, message);
+
+        // the stream is still usable
+        client_stream.send(Bytes::from("status")).await.unwrap();
+        assert_eq!(server_stream.next().await.unwrap().unwrap(), Bytes::from("status"));
+
+        // a disconnect instead of the Hello
+        client_stream.send(Bytes::from_static(&[0x01, 0xc1, 0x04])).await.unwrap();
+        let err = server_stream.read_hello().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        drop(client_stream);
+        let err = server_stream.read_hello().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_truncated_hello() {
+        assert!(decode_hello_capabilities(&[0x80, 0xc5, 0x05]).is_err());
+        assert!(decode_hello_capabilities(&[0x80, 0x05]).is_err());
+        assert!(decode_hello_capabilities(&[]).is_err());
+    }
+}
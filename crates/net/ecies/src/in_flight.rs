@@ -0,0 +1,88 @@
+//! Flow control of request/response protocols, bounding the messages awaiting a response.
+
+use futures::task::AtomicWaker;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Context,
+};
+
+/// The window of in-flight messages of an [`ECIESStream`](crate::stream::ECIESStream), see
+/// [`ECIESConfig::max_in_flight`](crate::ECIESConfig::max_in_flight).
+///
+/// Every message sent counts as in flight until the caller acknowledges it, e.g. once the peer
+/// responded to the request. ECIES doesn't know about the protocols it carries, so it can't tell
+/// responses apart itself. Once the window is full, [`Sink::poll_ready`](futures::Sink::poll_ready)
+/// returns `Pending` until messages are acknowledged, so a fast sender can't pile up outstanding
+/// requests at a slow peer.
+///
+/// The window is cheap to clone, so e.g. the task reading the responses can acknowledge them
+/// while another one is waiting to send, see
+/// [`ECIESStream::in_flight_window`](crate::stream::ECIESStream::in_flight_window).
+#[derive(Debug, Clone)]
+pub struct InFlightWindow {
+    inner: Arc<WindowInner>,
+}
+
+#[derive(Debug)]
+struct WindowInner {
+    max_in_flight: usize,
+    /// The number of sent messages that weren't acknowledged yet.
+    in_flight: AtomicUsize,
+    /// The task of the stream waiting for the window to have room.
+    sender: AtomicWaker,
+}
+
+// === impl InFlightWindow ===
+
+impl InFlightWindow {
+    /// Creates a window of up to `max_in_flight` messages, treating zero as one.
+    pub(crate) fn new(max_in_flight: usize) -> Self {
+        Self {
+            inner: Arc::new(WindowInner {
+                max_in_flight: max_in_flight.max(1),
+                in_flight: AtomicUsize::new(0),
+                sender: AtomicWaker::new(),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of messages in flight.
+    pub fn max_in_flight(&self) -> usize {
+        self.inner.max_in_flight
+    }
+
+    /// Returns the number of sent messages that weren't acknowledged yet.
+    ///
+    /// This may exceed [`InFlightWindow::max_in_flight`] after an
+    /// [ordered send](crate::stream::ECIESStream::send_ordered).
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Acknowledges `count` messages, making room for as many new ones.
+    ///
+    /// Acknowledging more messages than are in flight only empties the window.
+    pub fn acknowledge(&self, count: usize) {
+        let _ =
+            self.inner.in_flight.fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                Some(in_flight.saturating_sub(count))
+            });
+        self.inner.sender.wake();
+    }
+
+    /// Returns `true` if another message can be sent, otherwise the current task is woken once
+    /// messages are acknowledged.
+    pub(crate) fn poll_has_room(&self, cx: &mut Context<'_>) -> bool {
+        // registered first, so an acknowledgement in between isn't missed
+        self.inner.sender.register(cx.waker());
+        self.in_flight() < self.inner.max_in_flight
+    }
+
+    /// Records `count` sent messages.
+    pub(crate) fn sent(&self, count: usize) {
+        self.inner.in_flight.fetch_add(count, Ordering::AcqRel);
+    }
+}
@@ -8,15 +8,35 @@
 //! RLPx ECIES framed transport protocol.
 
 pub mod algorithm;
+pub mod bandwidth;
+pub mod batched;
+pub mod byte_stream;
+pub mod config;
+pub mod drain;
+pub mod hello;
+pub mod in_flight;
+pub mod limiter;
 pub mod mac;
+pub mod params;
+pub mod replay;
+pub mod resolver;
+pub mod snappy;
 pub mod stream;
+pub mod tagged;
 pub mod util;
+pub mod violation;
 
 mod error;
 pub use error::ECIESError;
 
+pub use config::ECIESConfig;
+
 mod codec;
 
+// only used by the benchmarks
+#[cfg(test)]
+use criterion as _;
+
 use reth_primitives::{
     bytes::{Bytes, BytesMut},
     H512 as PeerId,
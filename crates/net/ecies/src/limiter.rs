@@ -0,0 +1,36 @@
+//! Limits the number of concurrent inbound connections.
+
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps the number of concurrently established inbound
+/// [`ECIESStream`](crate::stream::ECIESStream)s.
+///
+/// Each accepted stream holds a slot until it's dropped. Connections arriving while all slots are
+/// taken are rejected before the handshake, so floods don't cost any crypto work.
+///
+/// The limiter is cheap to clone and meant to be shared by all incoming connections of a
+/// listening socket.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimiter {
+    slots: Arc<Semaphore>,
+}
+
+// === impl ConnectionLimiter ===
+
+impl ConnectionLimiter {
+    /// Creates a limiter allowing up to `max_connections` concurrent connections.
+    pub fn new(max_connections: usize) -> Self {
+        Self { slots: Arc::new(Semaphore::new(max_connections)) }
+    }
+
+    /// Returns the number of connections that can still be accepted.
+    pub fn available(&self) -> usize {
+        self.slots.available_permits()
+    }
+
+    /// Takes a slot, returning `None` if the limit is reached.
+    pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+}
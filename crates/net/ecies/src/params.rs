@@ -0,0 +1,85 @@
+//! Parameters of the ECIES encryption of the auth and ack handshake messages.
+//!
+//! **For interop testing only**: all devp2p peers use the [default](HandshakeParams::default)
+//! parameters, a node using anything else can't connect to them. Overriding the parameters allows
+//! reproducing the exact handshake of another client that was configured the same way, e.g. to
+//! debug a cross-client test setup.
+//!
+//! Only the parameters of the SEC 1 ECIES scheme the handshake messages are encrypted with can be
+//! changed. The frames of an established session are always encrypted as specified by RLPx.
+
+use aes::{cipher::StreamCipher, Aes128, Aes256};
+use ctr::Ctr64BE;
+use digest::crypto_common::KeyIvInit;
+use reth_primitives::bytes::Bytes;
+
+/// The symmetric cipher encrypting the handshake messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum HandshakeCipher {
+    /// AES-128 in CTR mode, as specified by devp2p.
+    #[default]
+    Aes128Ctr,
+    /// AES-256 in CTR mode.
+    Aes256Ctr,
+}
+
+// === impl HandshakeCipher ===
+
+impl HandshakeCipher {
+    /// Returns the length of the cipher key in bytes.
+    pub fn key_len(&self) -> usize {
+        match self {
+            HandshakeCipher::Aes128Ctr => 16,
+            HandshakeCipher::Aes256Ctr => 32,
+        }
+    }
+
+    /// Encrypts or decrypts the data in place.
+    ///
+    /// `key` must be [key_len](Self::key_len) and `iv` 16 bytes long.
+    pub(crate) fn apply_keystream(&self, key: &[u8], iv: &[u8], data: &mut [u8]) {
+        match self {
+            HandshakeCipher::Aes128Ctr => {
+                Ctr64BE::<Aes128>::new(key.into(), iv.into()).apply_keystream(data)
+            }
+            HandshakeCipher::Aes256Ctr => {
+                Ctr64BE::<Aes256>::new(key.into(), iv.into()).apply_keystream(data)
+            }
+        }
+    }
+}
+
+/// The KDF and cipher parameters of the ECIES encryption of the handshake messages.
+///
+/// See the [module docs](self) before changing these.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakeParams {
+    /// The cipher encrypting the messages.
+    ///
+    /// The KDF derives a cipher key and a MAC key of [key_len](HandshakeCipher::key_len) bytes
+    /// each.
+    pub cipher: HandshakeCipher,
+    /// The shared information `S1` mixed into the key derivation, empty in devp2p.
+    pub kdf_shared_info: Bytes,
+}
+
+// === impl HandshakeParams ===
+
+impl HandshakeParams {
+    /// Sets the cipher encrypting the messages.
+    pub fn with_cipher(mut self, cipher: HandshakeCipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Sets the shared information mixed into the key derivation.
+    pub fn with_kdf_shared_info(mut self, kdf_shared_info: impl Into<Bytes>) -> Self {
+        self.kdf_shared_info = kdf_shared_info.into();
+        self
+    }
+
+    /// Returns `true` if these are the standard devp2p parameters.
+    pub fn is_standard(&self) -> bool {
+        *self == Self::default()
+    }
+}
@@ -0,0 +1,109 @@
+//! Protection against replayed handshakes.
+
+use reth_primitives::H256;
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Default number of handshake nonces a [`HandshakeReplayGuard`] remembers.
+pub const DEFAULT_REPLAY_GUARD_CAPACITY: usize = 4096;
+
+/// Remembers the nonces of recently received auth messages, so a captured auth can't be replayed
+/// to a listener.
+///
+/// Every dialer picks a fresh random nonce per handshake, hence a nonce that was already seen
+/// indicates a replay. Only the most recent `capacity` nonces are tracked.
+///
+/// The guard is cheap to clone and meant to be shared by all incoming connections of a listening
+/// socket.
+#[derive(Debug, Clone)]
+pub struct HandshakeReplayGuard {
+    capacity: usize,
+    seen: Arc<Mutex<SeenNonces>>,
+}
+
+// === impl HandshakeReplayGuard ===
+
+impl HandshakeReplayGuard {
+    /// Creates a guard that remembers up to `capacity` nonces.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), seen: Default::default() }
+    }
+
+    /// Records the nonce of a received auth message.
+    ///
+    /// Returns `false` if the nonce was already seen, i.e. the auth is a replay.
+    pub fn insert(&self, nonce: H256) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        if !seen.set.insert(nonce) {
+            return false
+        }
+
+        seen.order.push_back(nonce);
+        if seen.order.len() > self.capacity {
+            if let Some(evicted) = seen.order.pop_front() {
+                seen.set.remove(&evicted);
+            }
+        }
+        true
+    }
+}
+
+impl Default for HandshakeReplayGuard {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_GUARD_CAPACITY)
+    }
+}
+
+#[derive(Debug, Default)]
+struct SeenNonces {
+    set: HashSet<H256>,
+    /// Insertion order, oldest first
+    order: VecDeque<H256>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec::ECIESCodec, error::ECIESErrorImpl, util::pk2id, EgressECIESValue};
+    use reth_primitives::bytes::BytesMut;
+    use secp256k1::{rand, SecretKey, SECP256K1};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn evicts_oldest_nonce() {
+        let guard = HandshakeReplayGuard::new(2);
+        let nonces = [H256::random(), H256::random(), H256::random()];
+
+        assert!(guard.insert(nonces[0]));
+        assert!(guard.insert(nonces[1]));
+        assert!(!guard.insert(nonces[0]));
+
+        assert!(guard.insert(nonces[2]));
+        assert!(guard.insert(nonces[0]));
+    }
+
+    #[test]
+    fn rejects_replayed_auth() {
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+
+        let mut auth = BytesMut::new();
+        ECIESCodec::new_client(client_key, server_id)
+            .unwrap()
+            .encode(EgressECIESValue::Auth, &mut auth)
+            .unwrap();
+
+        let guard = HandshakeReplayGuard::default();
+        let mut server =
+            ECIESCodec::new_server(server_key).unwrap().with_replay_guard(Some(guard.clone()));
+        assert!(server.decode(&mut auth.clone()).unwrap().is_some());
+
+        // the same auth bytes are rejected by another connection of the listener
+        let mut server = ECIESCodec::new_server(server_key).unwrap().with_replay_guard(Some(guard));
+        let err = server.decode(&mut auth).unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::ReplayedHandshake));
+    }
+}
@@ -0,0 +1,27 @@
+//! Selection of the local identity of incoming connections, for listeners serving several nodes.
+
+use secp256k1::SecretKey;
+use std::fmt;
+
+/// Provides the secret keys of the node identities served on a single listener, see
+/// [`ECIESStream::incoming_with_resolver`](crate::stream::ECIESStream::incoming_with_resolver).
+///
+/// A dialer encrypts its auth message to the public key of the node it dials, so the dialed
+/// identity is the one whose key decrypts the auth. The keys are tried in order, a dialer
+/// targeting none of them fails the handshake like a dialer targeting the wrong node.
+pub trait ServerKeyResolver: Send + Sync + fmt::Debug {
+    /// Returns the secret keys of all identities a dialer may target.
+    fn secret_keys(&self) -> Vec<SecretKey>;
+}
+
+impl ServerKeyResolver for Vec<SecretKey> {
+    fn secret_keys(&self) -> Vec<SecretKey> {
+        self.clone()
+    }
+}
+
+impl ServerKeyResolver for SecretKey {
+    fn secret_keys(&self) -> Vec<SecretKey> {
+        vec![*self]
+    }
+}
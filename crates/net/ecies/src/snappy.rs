@@ -0,0 +1,107 @@
+//! Snappy compression of message bodies.
+
+use reth_primitives::bytes::{BufMut, Bytes, BytesMut};
+use std::io;
+
+/// Default limit of the decompressed size of a message, matching the RLPx payload limit.
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Compresses egress and decompresses ingress message bodies with snappy.
+///
+/// Like in RLPx, the leading message id byte is left uncompressed and only the rest of the message
+/// is compressed. Empty messages are passed through as is.
+///
+/// RLPx compresses all messages following the `p2p` handshake if both peers support it, so this is
+/// enabled per connection once that's negotiated, see
+/// [`ECIESStream::enable_snappy`](crate::stream::ECIESStream::enable_snappy).
+#[derive(Debug)]
+pub(crate) struct SnappyCodec {
+    encoder: snap::raw::Encoder,
+    decoder: snap::raw::Decoder,
+    /// Ingress messages decompressing to more bytes than this are rejected.
+    max_decompressed_size: usize,
+}
+
+// === impl SnappyCodec ===
+
+impl SnappyCodec {
+    pub(crate) fn new(max_decompressed_size: usize) -> Self {
+        Self {
+            encoder: snap::raw::Encoder::new(),
+            decoder: snap::raw::Decoder::new(),
+            max_decompressed_size,
+        }
+    }
+
+    /// Compresses an egress message.
+    pub(crate) fn compress(&mut self, msg: &[u8]) -> io::Result<Bytes> {
+        let Some((id, body)) = msg.split_first() else { return Ok(Bytes::new()) };
+
+        let mut compressed = BytesMut::zeroed(1 + snap::raw::max_compress_len(body.len()));
+        compressed[0] = *id;
+        let len = self.encoder.compress(body, &mut compressed[1..]).map_err(invalid_data)?;
+        compressed.truncate(1 + len);
+        Ok(compressed.freeze())
+    }
+
+    /// Decompresses an ingress message.
+    ///
+    /// The decompressed size is read from the message header and checked against the limit before
+    /// decompressing, so a small message can't make us allocate huge buffers.
+    pub(crate) fn decompress(&mut self, msg: &[u8]) -> io::Result<BytesMut> {
+        let Some((id, body)) = msg.split_first() else { return Ok(BytesMut::new()) };
+
+        let decompressed_len = snap::raw::decompress_len(body).map_err(invalid_data)?;
+        if decompressed_len > self.max_decompressed_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "decompressed message size {decompressed_len} exceeds limit {}",
+                    self.max_decompressed_size
+                ),
+            ))
+        }
+
+        let mut decompressed = BytesMut::with_capacity(1 + decompressed_len);
+        decompressed.put_u8(*id);
+        decompressed.resize(1 + decompressed_len, 0);
+        let len = self.decoder.decompress(body, &mut decompressed[1..]).map_err(invalid_data)?;
+        decompressed.truncate(1 + len);
+        Ok(decompressed)
+    }
+}
+
+fn invalid_data(err: snap::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_messages() {
+        let mut codec = SnappyCodec::new(DEFAULT_MAX_DECOMPRESSED_SIZE);
+        for msg in [&b""[..], b"\x01", b"\x10hello", &[7u8; 4096]] {
+            let compressed = codec.compress(msg).unwrap();
+            assert_eq!(compressed.first(), msg.first());
+            assert_eq!(codec.decompress(&compressed).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn rejects_decompression_bombs() {
+        let compressed = SnappyCodec::new(0).compress(&[0u8; 1024 * 1024]).unwrap();
+        assert!(compressed.len() < 1024 * 64);
+
+        let err = SnappyCodec::new(1024).decompress(&compressed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_corrupt_messages() {
+        let mut codec = SnappyCodec::new(DEFAULT_MAX_DECOMPRESSED_SIZE);
+        let err = codec.decompress(&[0x10, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,181 +1,2507 @@
 //! The ECIES Stream implementation which wraps over [`AsyncRead`] and [`AsyncWrite`].
+#[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+use crate::algorithm::SessionKeys;
 use crate::{
-    codec::ECIESCodec, error::ECIESErrorImpl, ECIESError, EgressECIESValue, IngressECIESValue,
+    bandwidth::{BandwidthLimiter, PeerBandwidth},
+    batched::BatchedECIESStream,
+    codec::ECIESCodec,
+    drain::{DrainRegistration, DrainSignal},
+    error::ECIESErrorImpl,
+    in_flight::InFlightWindow,
+    resolver::ServerKeyResolver,
+    snappy::SnappyCodec,
+    tagged::TaggedECIESStream,
+    util::pk2id,
+    violation::{ProtocolViolation, ViolationKind},
+    ECIESConfig, ECIESError, EgressECIESValue, IngressECIESValue,
 };
 use futures::{ready, Sink, SinkExt};
-use reth_net_common::stream::HasRemoteAddr;
+use reth_net_common::{
+    ratelimit::RateLimit,
+    stream::{HasNodelay, HasRemoteAddr},
+};
 use reth_primitives::{
     bytes::{Bytes, BytesMut},
     H512 as PeerId,
 };
-use secp256k1::SecretKey;
+use secp256k1::{SecretKey, SECP256K1};
 use std::{
+    collections::VecDeque,
     fmt::Debug,
+    future::{poll_fn, Future},
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{mpsc::UnboundedSender, OwnedSemaphorePermit},
+    time::{Instant, Sleep},
 };
-use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_stream::{Stream, StreamExt};
 use tokio_util::codec::{Decoder, Framed};
 use tracing::{debug, instrument, trace};
 
 /// `ECIES` stream over TCP exchanging raw bytes
+///
+/// The stream can carry arbitrary user data `Ctx`, e.g. the bookkeeping of the peer manager that
+/// initiated the connection, see [`ECIESStream::with_context`]. No context is attached by default.
+///
+/// Empty messages are valid, e.g. as keepalives: they're sent as a frame with an empty body and
+/// received as an empty message, also with snappy compression enabled. They're told apart from the
+/// frames used for [rekeying](crate::config::Rekeying), which are never yielded.
+///
+/// Egress is bounded: once the encoded frames buffered for writing reach the backpressure boundary
+/// of the underlying [`Framed`] transport, 8 KiB, [`Sink::poll_ready`] first writes them out and
+/// returns `Pending` while the transport doesn't accept them, e.g. because the socket's send
+/// buffer is full since the peer reads slowly. A single message larger than the boundary is still
+/// buffered as a whole. With [`EgressPolicy::DropOldest`], the stream drops stale messages instead
+/// of applying backpressure.
 #[derive(Debug)]
 #[pin_project::pin_project]
-pub struct ECIESStream<Io> {
+pub struct ECIESStream<Io, Ctx = ()> {
     #[pin]
     stream: Framed<Io, ECIESCodec>,
     remote_id: PeerId,
+    /// Set if egress frames are flushed in batches, see [`FlushBatching`].
+    batch: Option<FlushBatch>,
+    /// Set if the stream errors after not receiving anything for a while.
+    idle: Option<IdleTimeout>,
+    /// Set if egress messages are rate limited.
+    egress_rate_limit: Option<RateLimit>,
+    /// Set if the stream pays its traffic from a shared budget, see
+    /// [`ECIESStream::with_bandwidth_limiter`].
+    bandwidth: Option<PeerBandwidth>,
+    /// Set if egress messages are queued and dropped instead of applying backpressure, see
+    /// [`EgressPolicy`].
+    egress_queue: Option<EgressQueue>,
+    /// Set if the messages in flight are bounded, see [`InFlightWindow`].
+    in_flight: Option<InFlightWindow>,
+    /// Set if message bodies are snappy compressed, see [`ECIESStream::enable_snappy`].
+    snappy: Option<SnappyCodec>,
+    /// The slot of an incoming connection, released when the stream is dropped.
+    _connection_slot: Option<OwnedSemaphorePermit>,
+    /// Tracks the lifecycle of the connection, see [`ECIESStream::state`].
+    lifecycle: Lifecycle,
+    /// Set if the stream is registered with a [`DrainSignal`], see [`ECIESStream::with_drain`].
+    drain: Option<Drain>,
+    /// Set if protocol violations of the peer are published, see
+    /// [`ECIESStream::with_violation_events`].
+    violations: Option<UnboundedSender<ProtocolViolation>>,
+    /// User data attached to the connection.
+    context: Ctx,
 }
 
 impl<Io> ECIESStream<Io>
 where
     Io: AsyncRead + AsyncWrite + Unpin + HasRemoteAddr,
 {
-    /// Connect to an `ECIES` server
-    #[instrument(skip(transport, secret_key), fields(peer=&*format!("{:?}", transport.remote_addr())))]
+    /// Connect to an `ECIES` server using the default [`ECIESConfig`].
+    ///
+    /// See [`ECIESStream::connect_with_config`] for the cancel safety.
     pub async fn connect(
         transport: Io,
         secret_key: SecretKey,
         remote_id: PeerId,
     ) -> Result<Self, ECIESError> {
-        let ecies = ECIESCodec::new_client(secret_key, remote_id)
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid handshake"))?;
+        Self::connect_with_config(transport, secret_key, remote_id, ECIESConfig::default()).await
+    }
+
+    /// Connect to an `ECIES` server using the given [`ECIESConfig`].
+    ///
+    /// # Cancel safety
+    ///
+    /// The returned future owns the transport until the handshake completed. Dropping it at any
+    /// point, e.g. when it loses a `tokio::select!` race, drops the transport along with a
+    /// possibly partially written auth message, which closes the connection. So a cancelled
+    /// handshake never leaves a half-open connection behind, and there is no transport in an
+    /// undefined state that could be reused.
+    #[instrument(skip(transport, secret_key, config), fields(peer=&*format!("{:?}", transport.remote_addr())))]
+    pub async fn connect_with_config(
+        transport: Io,
+        secret_key: SecretKey,
+        remote_id: PeerId,
+        config: ECIESConfig,
+    ) -> Result<Self, ECIESError> {
+        Self::connect_pending(transport, secret_key, remote_id, config)?.finish_handshake().await
+    }
+
+    /// Prepares a connection to an `ECIES` server using the given [`ECIESConfig`], without
+    /// performing the handshake yet.
+    ///
+    /// This fails right away if the connection can't be established at all, e.g. because the
+    /// `remote_id` is the local node. The handshake is performed by
+    /// [`PendingECIESStream::finish_handshake`].
+    pub fn connect_pending(
+        transport: Io,
+        secret_key: SecretKey,
+        remote_id: PeerId,
+        config: ECIESConfig,
+    ) -> Result<PendingECIESStream<Io>, ECIESError> {
+        ensure_not_self(pk2id(&secret_key.public_key(SECP256K1)), remote_id)?;
 
-        let mut transport = ecies.framed(transport);
+        let ecies = ECIESCodec::new_client(secret_key, remote_id)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid handshake"))?
+            .with_max_frame_size(config.max_frame_size)
+            .with_handshake_params(config.handshake_params.clone())
+            .with_rekeying(config.rekeying);
 
-        trace!("sending ecies auth ...");
-        transport.send(EgressECIESValue::Auth).await?;
+        Ok(PendingECIESStream {
+            transport: ecies.framed(transport),
+            side: HandshakeSide::Client { remote_id },
+            config,
+        })
+    }
 
-        trace!("waiting for ecies ack ...");
+    /// Listen on a just connected ECIES client using the default [`ECIESConfig`].
+    ///
+    /// See [`ECIESStream::incoming_with_config`] for the cancel safety.
+    pub async fn incoming(transport: Io, secret_key: SecretKey) -> Result<Self, ECIESError> {
+        Self::incoming_with_config(transport, secret_key, ECIESConfig::default()).await
+    }
 
-        let msg = transport.try_next().await?;
+    /// Listen on a just connected ECIES client using the given [`ECIESConfig`].
+    ///
+    /// # Cancel safety
+    ///
+    /// Like [`ECIESStream::connect_with_config`], dropping the returned future before the
+    /// handshake completed closes the transport. The slot taken from the
+    /// [`ConnectionLimiter`](crate::limiter::ConnectionLimiter) is released as well.
+    pub async fn incoming_with_config(
+        transport: Io,
+        secret_key: SecretKey,
+        config: ECIESConfig,
+    ) -> Result<Self, ECIESError> {
+        Self::incoming_with_keys(transport, secret_key, None, config).await
+    }
 
-        // `Framed` returns `None` if the underlying stream is no longer readable, and the codec is
-        // unable to decode another message from the (partially filled) buffer. This usually happens
-        // if the remote drops the TcpStream.
-        let msg = msg.ok_or(ECIESErrorImpl::UnreadableStream)?;
+    /// Listen on a just connected ECIES client of a listener serving several node identities.
+    ///
+    /// The identity the client dialed is selected among the keys of the `resolver` while reading
+    /// its auth message, see [`ServerKeyResolver`]. The selected identity is
+    /// [`ECIESStream::local_id`]. Otherwise this behaves like
+    /// [`ECIESStream::incoming_with_config`].
+    pub async fn incoming_with_resolver(
+        transport: Io,
+        resolver: &dyn ServerKeyResolver,
+        config: ECIESConfig,
+    ) -> Result<Self, ECIESError> {
+        let secret_keys = resolver.secret_keys();
+        let secret_key = *secret_keys.first().ok_or(ECIESErrorImpl::NoServerKeys)?;
+        Self::incoming_with_keys(transport, secret_key, Some(secret_keys), config).await
+    }
 
-        trace!("parsing ecies ack ...");
-        if matches!(msg, IngressECIESValue::Ack) {
-            Ok(Self { stream: transport, remote_id })
-        } else {
-            Err(ECIESErrorImpl::InvalidHandshake {
-                expected: IngressECIESValue::Ack,
-                msg: Some(msg),
-            }
-            .into())
-        }
+    /// Prepares listening on a just connected ECIES client using the given [`ECIESConfig`],
+    /// without performing the handshake yet.
+    ///
+    /// This fails right away if the connection is rejected without reading anything, i.e. if the
+    /// [`ConnectionLimiter`](crate::limiter::ConnectionLimiter) has no slot left. The handshake is
+    /// performed by [`PendingECIESStream::finish_handshake`].
+    pub fn incoming_pending(
+        transport: Io,
+        secret_key: SecretKey,
+        config: ECIESConfig,
+    ) -> Result<PendingECIESStream<Io>, ECIESError> {
+        Self::pending_incoming_with_keys(transport, secret_key, None, config)
     }
 
-    /// Listen on a just connected ECIES client
+    /// Accepts the client with the given key, or the first of the `server_keys` its auth was
+    /// encrypted to, if set.
     #[instrument(skip_all, fields(peer=&*format!("{:?}", transport.remote_addr())))]
-    pub async fn incoming(transport: Io, secret_key: SecretKey) -> Result<Self, ECIESError> {
-        let ecies = ECIESCodec::new_server(secret_key)?;
+    async fn incoming_with_keys(
+        transport: Io,
+        secret_key: SecretKey,
+        server_keys: Option<Vec<SecretKey>>,
+        config: ECIESConfig,
+    ) -> Result<Self, ECIESError> {
+        Self::pending_incoming_with_keys(transport, secret_key, server_keys, config)?
+            .finish_handshake()
+            .await
+    }
+
+    /// Prepares accepting the client, see [`ECIESStream::incoming_with_keys`].
+    fn pending_incoming_with_keys(
+        transport: Io,
+        secret_key: SecretKey,
+        server_keys: Option<Vec<SecretKey>>,
+        config: ECIESConfig,
+    ) -> Result<PendingECIESStream<Io>, ECIESError> {
+        // reject before doing any handshake work
+        let connection_slot = match &config.connection_limiter {
+            Some(limiter) => Some(limiter.try_acquire().ok_or(ECIESErrorImpl::TooManyConnections)?),
+            None => None,
+        };
+
+        let mut ecies = ECIESCodec::new_server(secret_key)?
+            .with_max_frame_size(config.max_frame_size)
+            .with_replay_guard(config.replay_guard.clone())
+            .with_handshake_params(config.handshake_params.clone())
+            .with_rekeying(config.rekeying);
+        if let Some(server_keys) = server_keys {
+            ecies = ecies.with_server_keys(server_keys);
+        }
 
         debug!("incoming ecies stream ...");
-        let mut transport = ecies.framed(transport);
-        let msg = transport.try_next().await?;
-
-        debug!("receiving ecies auth");
-        let remote_id = match &msg {
-            Some(IngressECIESValue::AuthReceive(remote_id)) => *remote_id,
-            _ => {
-                return Err(ECIESErrorImpl::InvalidHandshake {
-                    expected: IngressECIESValue::AuthReceive(Default::default()),
-                    msg,
-                }
-                .into())
+        Ok(PendingECIESStream {
+            transport: ecies.framed(transport),
+            side: HandshakeSide::Server { connection_slot },
+            config,
+        })
+    }
+}
+
+/// An [`ECIESStream`] whose handshake wasn't performed yet, created by
+/// [`ECIESStream::connect_pending`] and [`ECIESStream::incoming_pending`].
+///
+/// This separates constructing a connection from its handshake, e.g. so a connection manager can
+/// register many pending connections first and then drive all of their handshakes concurrently.
+/// Nothing is sent or received until [`PendingECIESStream::finish_handshake`] is polled.
+#[derive(Debug)]
+pub struct PendingECIESStream<Io> {
+    transport: Framed<Io, ECIESCodec>,
+    side: HandshakeSide,
+    config: ECIESConfig,
+}
+
+/// The side of the handshake a [`PendingECIESStream`] performs.
+#[derive(Debug)]
+enum HandshakeSide {
+    /// Sends the auth message to the dialed node.
+    Client { remote_id: PeerId },
+    /// Waits for the auth message of the dialer.
+    Server {
+        /// The slot of the incoming connection, if limited.
+        connection_slot: Option<OwnedSemaphorePermit>,
+    },
+}
+
+// === impl PendingECIESStream ===
+
+impl<Io> PendingECIESStream<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Returns the id of the remote node, known upfront for outgoing connections only.
+    ///
+    /// The id of the dialer of an incoming connection is only known once its auth message was
+    /// received, see [`ECIESStream::remote_id`].
+    pub fn remote_id(&self) -> Option<PeerId> {
+        match self.side {
+            HandshakeSide::Client { remote_id } => Some(remote_id),
+            HandshakeSide::Server { .. } => None,
+        }
+    }
+
+    /// Performs the handshake, returning the established stream.
+    ///
+    /// The handshake timeout of the [`ECIESConfig`] starts once this is first polled, not when the
+    /// pending stream was created. The returned future is cancel safe like
+    /// [`ECIESStream::connect_with_config`]: dropping it closes the transport.
+    pub async fn finish_handshake(self) -> Result<ECIESStream<Io>, ECIESError> {
+        let Self { mut transport, side, config } = self;
+        match side {
+            HandshakeSide::Client { remote_id } => {
+                with_handshake_timeout(config.handshake_timeout, client_handshake(&mut transport))
+                    .await?;
+                Ok(ECIESStream::new(transport, remote_id, config))
             }
-        };
+            HandshakeSide::Server { connection_slot } => {
+                let remote_id = with_handshake_timeout(
+                    config.handshake_timeout,
+                    server_handshake(&mut transport),
+                )
+                .await?;
+                let mut stream = ECIESStream::new(transport, remote_id, config);
+                stream._connection_slot = connection_slot;
+                Ok(stream)
+            }
+        }
+    }
+}
+
+impl<Io> ECIESStream<Io> {
+    /// Wraps the handshaked transport.
+    fn new(stream: Framed<Io, ECIESCodec>, remote_id: PeerId, config: ECIESConfig) -> Self {
+        Self {
+            stream,
+            remote_id,
+            batch: config.flush_batching.map(FlushBatch::new),
+            idle: config.idle_timeout.map(IdleTimeout::new),
+            egress_rate_limit: config.egress_rate_limit.map(RateLimit::new),
+            bandwidth: None,
+            egress_queue: EgressQueue::new(config.egress_policy),
+            in_flight: config.max_in_flight.map(InFlightWindow::new),
+            snappy: None,
+            _connection_slot: None,
+            lifecycle: Lifecycle::default(),
+            drain: None,
+            violations: None,
+            context: (),
+        }
+    }
+
+    /// Converts the stream into one that exchanges `(message id, payload)` pairs.
+    ///
+    /// See [`TaggedECIESStream`].
+    pub fn into_tagged(self) -> TaggedECIESStream<Io> {
+        TaggedECIESStream::new(self)
+    }
+}
+
+impl<Io, Ctx> ECIESStream<Io, Ctx> {
+    /// Attaches the given context to the stream, replacing the current one.
+    pub fn with_context<C>(self, context: C) -> ECIESStream<Io, C> {
+        ECIESStream {
+            stream: self.stream,
+            remote_id: self.remote_id,
+            batch: self.batch,
+            idle: self.idle,
+            egress_rate_limit: self.egress_rate_limit,
+            bandwidth: self.bandwidth,
+            egress_queue: self.egress_queue,
+            in_flight: self.in_flight,
+            snappy: self.snappy,
+            _connection_slot: self._connection_slot,
+            lifecycle: self.lifecycle,
+            drain: self.drain,
+            violations: self.violations,
+            context,
+        }
+    }
+
+    /// Converts the stream into one that yields all readily available messages at once, up to
+    /// `max_batch_size` of them.
+    ///
+    /// See [`BatchedECIESStream`].
+    pub fn into_batched(self, max_batch_size: usize) -> BatchedECIESStream<Io, Ctx> {
+        BatchedECIESStream::new(self, max_batch_size)
+    }
 
-        debug!("sending ecies ack ...");
-        transport.send(EgressECIESValue::Ack).await?;
+    /// Returns a reference to the attached context.
+    pub fn context(&self) -> &Ctx {
+        &self.context
+    }
 
-        Ok(Self { stream: transport, remote_id })
+    /// Returns a mutable reference to the attached context.
+    pub fn context_mut(&mut self) -> &mut Ctx {
+        &mut self.context
     }
 
     /// Get the remote id
     pub fn remote_id(&self) -> PeerId {
         self.remote_id
     }
+
+    /// Returns the id of the local node identity the connection was established with.
+    pub fn local_id(&self) -> PeerId {
+        self.stream.codec().local_id()
+    }
+
+    /// Returns the state of the connection, as observed by the last reads and writes.
+    ///
+    /// This doesn't do any I/O, so the state only changes while the stream is driven, see
+    /// [`ConnectionState`].
+    pub fn state(&self) -> ConnectionState {
+        self.lifecycle.state()
+    }
+
+    /// Exports the symmetric secrets of the session, so captured traffic of this connection can
+    /// be decrypted offline.
+    ///
+    /// **INSECURE**: this hands out the keys protecting the connection. It's a debugging aid for
+    /// protocol development and only available in debug builds with the
+    /// `insecure-session-key-export` feature enabled.
+    #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+    pub fn session_keys(&self) -> SessionKeys {
+        self.stream.codec().session_keys().expect("handshake completed on construction")
+    }
+
+    /// Enables batched flushing of egress frames.
+    ///
+    /// See [`FlushBatching`] for the exact semantics.
+    pub fn with_flush_batching(mut self, batching: FlushBatching) -> Self {
+        self.batch = Some(FlushBatch::new(batching));
+        self
+    }
+
+    /// Sets how the stream handles a peer that doesn't keep up with the egress messages.
+    ///
+    /// See [`EgressPolicy`] for the exact semantics. Messages queued under a previous
+    /// [`EgressPolicy::DropOldest`] policy are dropped.
+    pub fn with_egress_policy(mut self, policy: EgressPolicy) -> Self {
+        self.egress_queue = EgressQueue::new(policy);
+        self
+    }
+
+    /// Returns the number of egress messages dropped because the peer didn't keep up, see
+    /// [`EgressPolicy::DropOldest`].
+    pub fn dropped_messages(&self) -> u64 {
+        self.egress_queue.as_ref().map(|queue| queue.dropped).unwrap_or_default()
+    }
+
+    /// Returns the window of messages in flight, if bounded with
+    /// [`ECIESConfig::max_in_flight`].
+    ///
+    /// Sent messages have to be acknowledged with [`InFlightWindow::acknowledge`], otherwise the
+    /// stream stops sending once the window is full.
+    pub fn in_flight_window(&self) -> Option<InFlightWindow> {
+        self.in_flight.clone()
+    }
+
+    /// Registers the stream with the given [`DrainSignal`], replacing any previous registration.
+    ///
+    /// Once the drain is triggered, sending fails with an [`ECIESErrorImpl::Drained`] error after
+    /// the stream wrote out its buffered frames and the farewell message, and closed its side of
+    /// the connection. The stream ends at that point, subsequent messages of the peer are not read
+    /// anymore. Draining is driven by polling the stream for reading or writing.
+    pub fn with_drain(mut self, signal: &DrainSignal) -> Self {
+        self.drain =
+            Some(Drain::Registered { registration: signal.register(), farewell_sent: false });
+        self
+    }
+
+    /// Pays the traffic of the stream from the given shared budget, replacing any previous
+    /// limiter.
+    ///
+    /// The bodies of all messages sent and received from then on are paid in proportion to the
+    /// `weight` of the stream, zero is treated as one. See [`BandwidthLimiter`] for how the budget
+    /// is shared. Frames already buffered aren't paid for.
+    pub fn with_bandwidth_limiter(mut self, limiter: &BandwidthLimiter, weight: u32) -> Self {
+        self.bandwidth = Some(limiter.register(weight));
+        self
+    }
+
+    /// Publishes the protocol violations of the peer to the given channel, e.g. for a central
+    /// reputation system.
+    ///
+    /// Violations are still returned as errors by the [`Stream`] impl, which publishes them
+    /// before returning them, see [`ECIESError::violation`] for which errors are violations.
+    /// Publishing never blocks, violations are dropped if the receiver is gone.
+    pub fn with_violation_events(mut self, events: UnboundedSender<ProtocolViolation>) -> Self {
+        self.violations = Some(events);
+        self
+    }
+
+    /// Enables snappy compression of all subsequent messages.
+    ///
+    /// Callers keep exchanging uncompressed messages, while their bodies are compressed on the
+    /// wire. As in RLPx, the leading message id byte is not compressed. Ingress messages
+    /// decompressing to more than `max_decompressed_size` bytes are rejected with an
+    /// [InvalidData](io::ErrorKind::InvalidData) error.
+    ///
+    /// Compression must be enabled by both peers, so this is meant to be called once the
+    /// connection negotiated it, see also
+    /// [`DEFAULT_MAX_DECOMPRESSED_SIZE`](crate::snappy::DEFAULT_MAX_DECOMPRESSED_SIZE).
+    pub fn enable_snappy(&mut self, max_decompressed_size: usize) {
+        self.snappy = Some(SnappyCodec::new(max_decompressed_size));
+    }
+
+    /// Returns `true` if message bodies are snappy compressed.
+    pub fn is_snappy_enabled(&self) -> bool {
+        self.snappy.is_some()
+    }
+
+    /// Returns a hex dump of the received bytes that weren't decoded yet, truncated to
+    /// [`MAX_FRAME_DUMP_LEN`] bytes.
+    ///
+    /// After a frame failed its header check, these start with the malformed frame. The dump is
+    /// also logged at debug level whenever a frame can't be decoded.
+    ///
+    /// Only available with the `frame-dump` feature, since the dump exposes raw connection
+    /// traffic.
+    #[cfg(feature = "frame-dump")]
+    pub fn read_buffer_dump(&self) -> String {
+        frame_dump(self.stream.read_buffer())
+    }
 }
 
-impl<Io> Stream for ECIESStream<Io>
+impl<Io, Ctx> ECIESStream<Io, Ctx>
 where
-    Io: AsyncRead + Unpin,
+    Io: HasNodelay,
 {
-    type Item = Result<BytesMut, io::Error>;
+    /// Returns whether Nagle's algorithm is disabled on the underlying socket, `None` if the
+    /// transport isn't a TCP socket.
+    pub fn nodelay(&self) -> io::Result<Option<bool>> {
+        self.stream.get_ref().nodelay()
+    }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match ready!(self.project().stream.poll_next(cx)) {
-            Some(Ok(IngressECIESValue::Message(body))) => Poll::Ready(Some(Ok(body))),
-            Some(other) => Poll::Ready(Some(Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("ECIES stream protocol error: expected message, received {other:?}"),
-            )))),
-            None => Poll::Ready(None),
-        }
+    /// Disables or enables Nagle's algorithm on the underlying socket, see
+    /// [`TcpStream::set_nodelay`](tokio::net::TcpStream::set_nodelay).
+    ///
+    /// Disabling it sends small frames right away instead of coalescing them, which lowers the
+    /// latency of e.g. block propagation. Does nothing if the transport isn't a TCP socket.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 }
 
-impl<Io> Sink<Bytes> for ECIESStream<Io>
+impl<Io, Ctx> ECIESStream<Io, Ctx>
 where
     Io: AsyncWrite + Unpin,
 {
-    type Error = io::Error;
+    /// Sends the message and flushes it, failing with a [TimedOut](io::ErrorKind::TimedOut) error
+    /// if that doesn't complete before the `deadline`.
+    ///
+    /// Frames are encoded into the write buffer as a whole, so a timed out send never leaves a
+    /// partial frame behind: either the message was not accepted at all, or it's fully buffered
+    /// and is written out by the next flush, ahead of any subsequent message.
+    pub async fn send_with_timeout(&mut self, msg: Bytes, deadline: Instant) -> io::Result<()> {
+        tokio::time::timeout_at(deadline, self.send(msg))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "ECIES send timed out"))?
+    }
 
-    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project().stream.poll_ready(cx)
+    /// Sends the messages in the given order with a single flush.
+    ///
+    /// The frames of the messages are written back to back, so the peer receives them in exactly
+    /// this order. Frames buffered by earlier sends are written out first.
+    ///
+    /// Messages are only sent if all of them can be encoded, i.e. none exceeds the maximum frame
+    /// size. If writing them fails, the error reports how many of the leading messages were fully
+    /// written to the transport before the failure, all later ones were not or only partially
+    /// sent.
+    ///
+    /// The messages count as a single message for the egress rate limit and bypass
+    /// [`FlushBatching`]. They're never dropped, regardless of the [`EgressPolicy`], and wait for
+    /// messages queued by earlier sends to be written out. With an [`InFlightWindow`], they wait
+    /// for room for a single message, but all of them count as in flight, which may overfill the
+    /// window.
+    pub async fn send_ordered(&mut self, messages: Vec<Bytes>) -> Result<(), OrderedSendError> {
+        let failed = |source: io::Error| OrderedSendError { sent: 0, source };
+
+        if matches!(self.drain, Some(Drain::Drained)) {
+            return Err(failed(drained_error()))
+        }
+
+        // the write buffer must only hold the messages afterwards, to tell which were written
+        if let Some(queue) = self.egress_queue.as_mut() {
+            poll_fn(|cx| queue.poll_write_out(Pin::new(&mut self.stream), cx))
+                .await
+                .map_err(classify_write_error)
+                .map_err(failed)?;
+        }
+        self.stream.flush().await.map_err(classify_write_error).map_err(failed)?;
+
+        let messages = messages
+            .into_iter()
+            .map(|message| match self.snappy.as_mut() {
+                Some(snappy) => snappy.compress(&message),
+                None => Ok(message),
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(failed)?;
+        for message in &messages {
+            self.stream.codec().check_frame_size(message.len()).map_err(failed)?;
+        }
+
+        if let Some(limit) = self.egress_rate_limit.as_mut() {
+            poll_fn(|cx| limit.poll_ready(cx)).await;
+            limit.tick();
+        }
+        if let Some(bandwidth) = self.bandwidth.as_mut() {
+            poll_fn(|cx| bandwidth.egress.poll_granted(cx)).await;
+            bandwidth.egress.request(messages.iter().map(Bytes::len).sum());
+        }
+        if let Some(window) = self.in_flight.as_ref() {
+            poll_fn(|cx| if window.poll_has_room(cx) { Poll::Ready(()) } else { Poll::Pending })
+                .await;
+            window.sent(messages.len());
+        }
+
+        // the end of each message's frame in the write buffer
+        let mut frame_ends = Vec::with_capacity(messages.len());
+        for message in messages {
+            self.stream.start_send_unpin(EgressECIESValue::Message(message)).map_err(failed)?;
+            frame_ends.push(self.stream.write_buffer().len());
+        }
+
+        if let Err(source) = self.stream.flush().await {
+            let buffered = frame_ends.last().copied().unwrap_or_default();
+            let written = buffered - self.stream.write_buffer().len();
+            let sent = frame_ends.iter().take_while(|end| **end <= written).count();
+            return Err(OrderedSendError { sent, source: classify_write_error(source) })
+        }
+        Ok(())
     }
+}
 
-    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
-        self.project().stream.start_send(EgressECIESValue::Message(item))?;
+/// Error of [`ECIESStream::send_ordered`].
+#[derive(Debug, thiserror::Error)]
+#[error("ordered ECIES send failed after {sent} messages: {source}")]
+pub struct OrderedSendError {
+    /// The number of leading messages that were fully written to the transport.
+    pub sent: usize,
+    /// The error that aborted the send.
+    #[source]
+    pub source: io::Error,
+}
+
+/// The lifecycle of an [`ECIESStream`] connection, see [`ECIESStream::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The handshake is in progress.
+    ///
+    /// Streams are only returned once the handshake completed, so this is never reported by
+    /// [`ECIESStream::state`]. It's the state of connections whose
+    /// [`ECIESStream::connect`] or [`ECIESStream::incoming`] future is still pending.
+    Handshaking,
+    /// The connection is open in both directions.
+    Established,
+    /// One direction of the connection is closed: either the peer closed its side, or
+    /// [`Sink::poll_close`] was called. The other direction remains usable.
+    Closing,
+    /// Both directions of the connection are closed, or reading or writing failed.
+    ///
+    /// Failures are terminal: the codec state may no longer match the peer's, so the connection is
+    /// expected to be dropped.
+    Closed,
+}
+
+/// Records which directions of an [`ECIESStream`] were closed.
+#[derive(Debug, Default)]
+struct Lifecycle {
+    /// Set once the peer closed its side of the connection.
+    read_closed: bool,
+    /// Set once closing the local side of the connection started.
+    write_closing: bool,
+    /// Set once the local side of the connection is closed.
+    write_closed: bool,
+    /// Set once reading or writing failed.
+    failed: bool,
+}
+
+impl Lifecycle {
+    fn state(&self) -> ConnectionState {
+        if self.failed || (self.read_closed && self.write_closed) {
+            return ConnectionState::Closed
+        }
+        if self.read_closed || self.write_closing {
+            return ConnectionState::Closing
+        }
+        ConnectionState::Established
+    }
+
+    /// Records the outcome of a read.
+    fn on_read<T>(&mut self, read: Poll<Option<io::Result<T>>>) -> Poll<Option<io::Result<T>>> {
+        match &read {
+            Poll::Ready(Some(Err(_))) => self.failed = true,
+            Poll::Ready(None) => self.read_closed = true,
+            _ => {}
+        }
+        read
+    }
+
+    /// Records the outcome of a write.
+    fn on_write<T>(&mut self, write: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if let Poll::Ready(Err(_)) = &write {
+            self.failed = true;
+        }
+        write
+    }
+}
+
+/// Max number of bytes included in a [`ECIESStream::read_buffer_dump`].
+#[cfg(feature = "frame-dump")]
+pub const MAX_FRAME_DUMP_LEN: usize = 128;
+
+/// Hex encodes the first [`MAX_FRAME_DUMP_LEN`] bytes of the buffer, noting how many were left
+/// out.
+#[cfg(feature = "frame-dump")]
+fn frame_dump(buf: &[u8]) -> String {
+    let len = buf.len().min(MAX_FRAME_DUMP_LEN);
+    let dump = reth_primitives::hex::encode(&buf[..len]);
+    if len < buf.len() {
+        return format!("{dump}... ({} more bytes)", buf.len() - len)
+    }
+    dump
+}
+
+/// Sends the auth message and waits for the remote's ack.
+async fn client_handshake<Io>(transport: &mut Framed<Io, ECIESCodec>) -> Result<(), ECIESError>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    trace!("sending ecies auth ...");
+    transport.send(EgressECIESValue::Auth).await?;
+
+    trace!("waiting for ecies ack ...");
+
+    let msg = transport.try_next().await?;
+
+    // `Framed` returns `None` if the underlying stream is no longer readable, and the codec is
+    // unable to decode another message from the (partially filled) buffer. This usually happens
+    // if the remote drops the TcpStream.
+    let msg = msg.ok_or(ECIESErrorImpl::UnreadableStream)?;
+
+    trace!("parsing ecies ack ...");
+    if matches!(msg, IngressECIESValue::Ack) {
         Ok(())
+    } else {
+        Err(ECIESErrorImpl::InvalidHandshake { expected: IngressECIESValue::Ack, msg: Some(msg) }
+            .into())
     }
+}
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project().stream.poll_flush(cx)
+/// Waits for the remote's auth message and acknowledges it, returning the remote's id.
+async fn server_handshake<Io>(transport: &mut Framed<Io, ECIESCodec>) -> Result<PeerId, ECIESError>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    let msg = transport.try_next().await?;
+
+    debug!("receiving ecies auth");
+    let remote_id = match &msg {
+        Some(IngressECIESValue::AuthReceive(remote_id)) => *remote_id,
+        _ => {
+            return Err(ECIESErrorImpl::InvalidHandshake {
+                expected: IngressECIESValue::AuthReceive(Default::default()),
+                msg,
+            }
+            .into())
+        }
+    };
+
+    ensure_not_self(transport.codec().local_id(), remote_id)?;
+
+    debug!("sending ecies ack ...");
+    transport.send(EgressECIESValue::Ack).await?;
+
+    Ok(remote_id)
+}
+
+/// Runs the handshake future, aborting it with [`ECIESErrorImpl::HandshakeTimeout`] if it doesn't
+/// complete within `timeout`.
+async fn with_handshake_timeout<T>(
+    timeout: Option<Duration>,
+    handshake: impl Future<Output = Result<T, ECIESError>>,
+) -> Result<T, ECIESError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, handshake)
+            .await
+            .map_err(|_| ECIESErrorImpl::HandshakeTimeout)?,
+        None => handshake.await,
     }
+}
 
-    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.project().stream.poll_close(cx)
+/// Returns an error if the `remote_id` is the `local_id`.
+///
+/// Such a connection would be a useless loopback peer wasting a connection slot.
+fn ensure_not_self(local_id: PeerId, remote_id: PeerId) -> Result<(), ECIESError> {
+    if local_id == remote_id {
+        return Err(ECIESErrorImpl::SelfConnection.into())
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::util::pk2id;
-    use secp256k1::{rand, SECP256K1};
-    use tokio::net::{TcpListener, TcpStream};
+/// Configures an [`ECIESStream`] to coalesce small egress frames into fewer writes.
+///
+/// With batching enabled, [`Sink::poll_flush`] only writes the buffered frames to the socket once
+/// the `interval` elapsed since the first frame of the batch was buffered, or once at least
+/// `max_buffered_bytes` are buffered. Until then, flushing completes immediately and the frames
+/// stay buffered. The deferred write is driven by the stream itself: the internal timer wakes the
+/// task, which writes the batch the next time the stream is polled for reading or writing.
+///
+/// [`Sink::poll_close`] always writes out any residual frames before shutting down the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushBatching {
+    /// How long encoded frames may be buffered before they are written out.
+    pub interval: Duration,
+    /// The number of buffered bytes that triggers an immediate write.
+    pub max_buffered_bytes: usize,
+}
 
-    #[tokio::test]
-    async fn can_write_and_read() {
-        let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
-        let server_key = SecretKey::new(&mut rand::thread_rng());
+/// Tracks the batch that is currently buffered.
+#[derive(Debug)]
+struct FlushBatch {
+    config: FlushBatching,
+    /// Fires when the current batch is due.
+    timer: Pin<Box<Sleep>>,
+    /// Whether the timer was started for the current batch.
+    armed: bool,
+}
 
-        let handle = tokio::spawn(async move {
-            // roughly based off of the design of tokio::net::TcpListener
-            let (incoming, _) = listener.accept().await.unwrap();
-            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+// === impl FlushBatch ===
 
-            // use the stream to get the next message
-            let message = stream.next().await.unwrap().unwrap();
-            assert_eq!(message, Bytes::from("hello"));
-        });
+impl FlushBatch {
+    fn new(config: FlushBatching) -> Self {
+        Self { config, timer: Box::pin(tokio::time::sleep(config.interval)), armed: false }
+    }
 
-        // create the server pubkey
-        let server_id = pk2id(&server_key.public_key(SECP256K1));
+    /// Writes out the buffered frames if the batch is due.
+    ///
+    /// Returns `Poll::Ready(Ok(()))` if the batch is not yet due, after registering the timer with
+    /// the current task.
+    fn poll_flush_due<Io>(
+        &mut self,
+        mut stream: Pin<&mut Framed<Io, ECIESCodec>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>>
+    where
+        Io: AsyncWrite + Unpin,
+    {
+        let buffered = stream.write_buffer().len();
+        if buffered == 0 {
+            self.armed = false;
+            return Poll::Ready(Ok(()))
+        }
 
-        let client_key = SecretKey::new(&mut rand::thread_rng());
-        let outgoing = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-        let mut client_stream =
-            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
-        client_stream.send(Bytes::from("hello")).await.unwrap();
+        if buffered < self.config.max_buffered_bytes {
+            if !self.armed {
+                self.timer.as_mut().reset(Instant::now() + self.config.interval);
+                self.armed = true;
+            }
+            if self.timer.as_mut().poll(cx).is_pending() {
+                return Poll::Ready(Ok(()))
+            }
+        }
 
-        // make sure the server receives the message and asserts before ending the test
-        handle.await.unwrap();
+        trace!(buffered, "flushing batched ecies frames");
+        ready!(stream.as_mut().poll_flush(cx))?;
+        self.armed = false;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Errors the stream if no frame was received for a while.
+#[derive(Debug)]
+struct IdleTimeout {
+    timeout: Duration,
+    /// Fires once the stream has been idle for `timeout`.
+    timer: Pin<Box<Sleep>>,
+}
+
+// === impl IdleTimeout ===
+
+impl IdleTimeout {
+    fn new(timeout: Duration) -> Self {
+        Self { timeout, timer: Box::pin(tokio::time::sleep(timeout)) }
+    }
+
+    /// Restarts the timer, called whenever a frame is received.
+    fn reset(&mut self) {
+        self.timer.as_mut().reset(Instant::now() + self.timeout);
+    }
+
+    /// Returns `Poll::Ready` once the stream has been idle for too long.
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.timer.as_mut().poll(cx)
+    }
+}
+
+/// How an [`ECIESStream`] handles a peer that doesn't keep up with the egress messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EgressPolicy {
+    /// Sending waits until the transport accepts more frames, see [`ECIESStream`].
+    #[default]
+    Backpressure,
+    /// Sending never waits for the transport: messages the transport doesn't accept yet are
+    /// queued, and once `capacity` messages are queued, the oldest is dropped for each new one.
+    ///
+    /// Meant for low latency gossip, e.g. block announcements, where stale messages are worthless
+    /// and a slow peer must not stall the sender. Dropped messages are counted by
+    /// [`ECIESStream::dropped_messages`].
+    ///
+    /// Messages are only encrypted when they're handed to the transport, since frames can't be
+    /// removed from the encrypted stream. So only queued messages are dropped, never frames that
+    /// are already buffered by the transport, up to its 8 KiB backpressure boundary. Like with
+    /// [`FlushBatching`], flushing completes without waiting for the transport, the queue is
+    /// written out whenever the stream is polled for reading or writing. Only
+    /// [`Sink::poll_close`] and a drain, see [`ECIESStream::with_drain`], wait for all queued
+    /// messages to be written.
+    DropOldest {
+        /// The maximum number of queued messages, at least 1.
+        capacity: usize,
+    },
+}
+
+/// The egress messages of a stream with the [`EgressPolicy::DropOldest`] policy that were not yet
+/// handed to the transport.
+#[derive(Debug)]
+struct EgressQueue {
+    capacity: usize,
+    /// The compressed messages, oldest first.
+    queue: VecDeque<Bytes>,
+    /// The number of messages dropped so far.
+    dropped: u64,
+}
+
+// === impl EgressQueue ===
+
+impl EgressQueue {
+    /// Returns the queue for the given policy, if it queues messages.
+    fn new(policy: EgressPolicy) -> Option<Self> {
+        match policy {
+            EgressPolicy::Backpressure => None,
+            EgressPolicy::DropOldest { capacity } => {
+                let capacity = capacity.max(1);
+                Some(Self { capacity, queue: VecDeque::with_capacity(capacity), dropped: 0 })
+            }
+        }
+    }
+
+    /// Queues the message, dropping the oldest queued message if the queue is full.
+    fn push(&mut self, message: Bytes) {
+        if self.queue.len() == self.capacity {
+            self.queue.pop_front();
+            self.dropped += 1;
+            trace!(dropped = self.dropped, "dropped stale ecies message");
+        }
+        self.queue.push_back(message);
+    }
+
+    /// Hands the queued messages to the transport, resolving once all of them were accepted.
+    fn poll_write_out<Io>(
+        &mut self,
+        mut stream: Pin<&mut Framed<Io, ECIESCodec>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>>
+    where
+        Io: AsyncWrite + Unpin,
+    {
+        while !self.queue.is_empty() {
+            ready!(stream.as_mut().poll_ready(cx))?;
+            let message = self.queue.pop_front().expect("not empty");
+            stream.as_mut().start_send(EgressECIESValue::Message(message))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The state of a stream registered with a [`DrainSignal`].
+#[derive(Debug)]
+enum Drain {
+    /// Waiting for the drain to be triggered, or draining.
+    Registered {
+        registration: DrainRegistration,
+        /// Whether the farewell message was buffered.
+        farewell_sent: bool,
+    },
+    /// The stream drained and released its registration.
+    Drained,
+}
+
+// === impl Drain ===
+
+impl Drain {
+    /// Drives a triggered drain, resolving to `true` once the stream is drained.
+    ///
+    /// Resolves to `false` right away if the drain wasn't triggered yet, after registering the
+    /// current task to be woken once it is.
+    fn poll_drained<Io>(
+        &mut self,
+        mut stream: Pin<&mut Framed<Io, ECIESCodec>>,
+        egress_queue: Option<&mut EgressQueue>,
+        snappy: Option<&mut SnappyCodec>,
+        lifecycle: &mut Lifecycle,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<bool>>
+    where
+        Io: AsyncWrite + Unpin,
+    {
+        let Drain::Registered { registration, farewell_sent } = self else {
+            return Poll::Ready(Ok(true))
+        };
+        if !registration.poll_triggered(cx) {
+            return Poll::Ready(Ok(false))
+        }
+
+        lifecycle.write_closing = true;
+        let closed = match egress_queue {
+            Some(queue) => {
+                ready!(queue.poll_write_out(stream.as_mut(), cx)).map_err(classify_write_error)
+            }
+            None => Ok(()),
+        };
+        let closed = match closed {
+            Ok(()) => {
+                ready!(poll_farewell_and_close(stream, registration, farewell_sent, snappy, cx))
+            }
+            Err(err) => Err(err),
+        };
+        trace!(ok = closed.is_ok(), "drained ecies stream");
+        // failed drains are done as well, the connection is unusable afterwards
+        *self = Drain::Drained;
+        if closed.is_ok() {
+            lifecycle.write_closed = true;
+        }
+        lifecycle.on_write(Poll::Ready(closed.map(|()| true)))
+    }
+}
+
+/// Buffers the farewell message of the drain, if any, and closes the stream, which writes out all
+/// buffered frames first.
+fn poll_farewell_and_close<Io>(
+    mut stream: Pin<&mut Framed<Io, ECIESCodec>>,
+    registration: &DrainRegistration,
+    farewell_sent: &mut bool,
+    snappy: Option<&mut SnappyCodec>,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>>
+where
+    Io: AsyncWrite + Unpin,
+{
+    if !*farewell_sent {
+        if let Some(farewell) = registration.farewell() {
+            ready!(stream.as_mut().poll_ready(cx)).map_err(classify_write_error)?;
+            let farewell = match snappy {
+                Some(snappy) => snappy.compress(&farewell)?,
+                None => farewell,
+            };
+            stream
+                .as_mut()
+                .start_send(EgressECIESValue::Message(farewell))
+                .map_err(classify_write_error)?;
+        }
+        *farewell_sent = true;
+    }
+    stream.poll_close(cx).map_err(classify_write_error)
+}
+
+/// Returns the error of sending on a drained stream.
+fn drained_error() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, ECIESError::from(ECIESErrorImpl::Drained))
+}
+
+impl<Io, Ctx> Stream for ECIESStream<Io, Ctx>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<BytesMut, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        if let Some(drain) = this.drain.as_mut() {
+            let drained = drain.poll_drained(
+                this.stream.as_mut(),
+                this.egress_queue.as_mut(),
+                this.snappy.as_mut(),
+                this.lifecycle,
+                cx,
+            );
+            match drained {
+                Poll::Ready(Ok(false)) => {}
+                // nothing is read anymore once the stream is drained
+                Poll::Ready(Ok(true)) => return this.lifecycle.on_read(Poll::Ready(None)),
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // drive a pending batch, so buffered frames are written even if only the read half is
+        // polled
+        if let Some(batch) = this.batch.as_mut() {
+            if let Poll::Ready(Err(err)) = batch.poll_flush_due(this.stream.as_mut(), cx) {
+                return this.lifecycle.on_read(Poll::Ready(Some(Err(err))))
+            }
+        }
+        // likewise for queued messages
+        if let Some(queue) = this.egress_queue.as_mut() {
+            if let Poll::Ready(Err(err)) = queue.poll_write_out(this.stream.as_mut(), cx) {
+                return this.lifecycle.on_read(Poll::Ready(Some(Err(err))))
+            }
+        }
+
+        // don't read any further until the last message is paid for
+        if let Some(bandwidth) = this.bandwidth.as_mut() {
+            ready!(bandwidth.ingress.poll_granted(cx));
+        }
+
+        let next = match this.stream.poll_next(cx) {
+            Poll::Ready(next) => next,
+            Poll::Pending => {
+                if let Some(idle) = this.idle.as_mut() {
+                    if idle.poll_expired(cx).is_ready() {
+                        return this.lifecycle.on_read(Poll::Ready(Some(Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "ECIES stream idle timeout",
+                        )))))
+                    }
+                }
+                return Poll::Pending
+            }
+        };
+
+        if let Some(idle) = this.idle.as_mut() {
+            idle.reset();
+        }
+
+        let read = match next {
+            Some(Ok(IngressECIESValue::Message(body))) => {
+                if let Some(bandwidth) = this.bandwidth.as_mut() {
+                    bandwidth.ingress.request(body.len());
+                }
+                match this.snappy.as_mut() {
+                    Some(snappy) => Poll::Ready(Some(snappy.decompress(&body))),
+                    None => Poll::Ready(Some(Ok(body))),
+                }
+            }
+            Some(Err(err)) => {
+                if let (Some(events), Some(kind)) = (this.violations.as_ref(), err.violation()) {
+                    let _ = events.send(ProtocolViolation { peer_id: *this.remote_id, kind });
+                }
+                #[cfg(feature = "frame-dump")]
+                debug!(
+                    %err,
+                    buffer = %frame_dump(this.stream.read_buffer()),
+                    "failed to decode ecies frame"
+                );
+                // keep the error, so it can be classified with `ECIESError::from_io_error`
+                Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, err))))
+            }
+            Some(other) => {
+                if let Some(events) = this.violations.as_ref() {
+                    let kind = ViolationKind::UnexpectedMessage;
+                    let _ = events.send(ProtocolViolation { peer_id: *this.remote_id, kind });
+                }
+                Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("ECIES stream protocol error: expected message, received {other:?}"),
+                ))))
+            }
+            None => Poll::Ready(None),
+        };
+        this.lifecycle.on_read(read)
+    }
+}
+
+impl<Io, Ctx> Sink<Bytes> for ECIESStream<Io, Ctx>
+where
+    Io: AsyncWrite + Unpin,
+{
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut this = self.project();
+        if let Some(drain) = this.drain.as_mut() {
+            let drained = drain.poll_drained(
+                this.stream.as_mut(),
+                this.egress_queue.as_mut(),
+                this.snappy.as_mut(),
+                this.lifecycle,
+                cx,
+            );
+            if ready!(drained)? {
+                return Poll::Ready(Err(drained_error()))
+            }
+        }
+        if let Some(limit) = this.egress_rate_limit.as_mut() {
+            ready!(limit.poll_ready(cx));
+        }
+        if let Some(bandwidth) = this.bandwidth.as_mut() {
+            ready!(bandwidth.egress.poll_granted(cx));
+        }
+        if let Some(window) = this.in_flight.as_ref() {
+            if !window.poll_has_room(cx) {
+                return Poll::Pending
+            }
+        }
+        if let Some(queue) = this.egress_queue.as_mut() {
+            // never wait for the transport, the message is queued instead
+            if let Poll::Ready(Err(err)) = queue.poll_write_out(this.stream.as_mut(), cx) {
+                return this.lifecycle.on_write(Poll::Ready(Err(classify_write_error(err))))
+            }
+            return Poll::Ready(Ok(()))
+        }
+        if let Some(batch) = this.batch.as_mut() {
+            let flushed = batch.poll_flush_due(this.stream.as_mut(), cx);
+            ready!(this.lifecycle.on_write(flushed.map_err(classify_write_error)))?;
+        }
+        this.lifecycle.on_write(this.stream.poll_ready(cx).map_err(classify_write_error))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.project();
+        let item = match this.snappy.as_mut() {
+            Some(snappy) => snappy.compress(&item)?,
+            None => item,
+        };
+        let len = item.len();
+        if let Some(queue) = this.egress_queue.as_mut() {
+            // reject oversized messages now, rather than when they're written out
+            this.stream.codec().check_frame_size(item.len())?;
+            queue.push(item);
+        } else if let Err(err) = this.stream.start_send(EgressECIESValue::Message(item)) {
+            this.lifecycle.failed = true;
+            return Err(classify_write_error(err))
+        }
+        if let Some(limit) = this.egress_rate_limit.as_mut() {
+            limit.tick();
+        }
+        if let Some(bandwidth) = this.bandwidth.as_mut() {
+            bandwidth.egress.request(len);
+        }
+        if let Some(window) = this.in_flight.as_ref() {
+            window.sent(1);
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut this = self.project();
+        let mut flushed = Poll::Ready(Ok(()));
+        if let Some(queue) = this.egress_queue.as_mut() {
+            flushed = queue.poll_write_out(this.stream.as_mut(), cx);
+        }
+        if let Poll::Ready(Ok(())) = flushed {
+            flushed = match this.batch.as_mut() {
+                Some(batch) => batch.poll_flush_due(this.stream.as_mut(), cx),
+                None => this.stream.poll_flush(cx),
+            };
+        }
+        if flushed.is_pending() && this.egress_queue.is_some() {
+            // the rest is written out whenever the stream is polled next
+            return Poll::Ready(Ok(()))
+        }
+        this.lifecycle.on_write(flushed.map_err(classify_write_error))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let mut this = self.project();
+        this.lifecycle.write_closing = true;
+        if let Some(queue) = this.egress_queue.as_mut() {
+            let written = queue.poll_write_out(this.stream.as_mut(), cx);
+            ready!(this.lifecycle.on_write(written.map_err(classify_write_error)))?;
+        }
+        let closed = this.stream.poll_close(cx).map_err(classify_write_error);
+        if let Poll::Ready(Ok(())) = closed {
+            this.lifecycle.write_closed = true;
+        }
+        this.lifecycle.on_write(closed)
+    }
+}
+
+/// Wraps write errors caused by the peer closing the connection into
+/// [`ECIESErrorImpl::PeerClosed`], keeping their [kind](io::Error::kind).
+///
+/// See [`ECIESError::from_io_error`] for unwrapping them.
+fn classify_write_error(err: io::Error) -> io::Error {
+    let peer_closed = matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe |
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted
+    );
+    if !peer_closed || ECIESError::from_io_error(&err).is_some() {
+        return err
+    }
+    io::Error::new(err.kind(), ECIESError::from(ECIESErrorImpl::PeerClosed(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Rekeying,
+        drain::DrainSignal,
+        limiter::ConnectionLimiter,
+        params::{HandshakeCipher, HandshakeParams},
+        snappy::DEFAULT_MAX_DECOMPRESSED_SIZE,
+    };
+    use futures::FutureExt;
+    use secp256k1::rand;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_util::codec::Encoder;
+
+    #[tokio::test]
+    async fn can_write_and_read() {
+        let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            // roughly based off of the design of tokio::net::TcpListener
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+
+            // use the stream to get the next message
+            let message = stream.next().await.unwrap().unwrap();
+            assert_eq!(message, Bytes::from("hello"));
+        });
+
+        // create the server pubkey
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect("127.0.0.1:8080").await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        client_stream.send(Bytes::from("hello")).await.unwrap();
+
+        // make sure the server receives the message and asserts before ending the test
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_self_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+
+        let handle = tokio::spawn(async move {
+            // the first dialer drops the connection before sending its auth
+            let (incoming, _) = listener.accept().await.unwrap();
+            assert!(ECIESStream::incoming(incoming, server_key).await.is_err());
+
+            let (incoming, _) = listener.accept().await.unwrap();
+            let err = ECIESStream::incoming(incoming, server_key).await.unwrap_err();
+            assert!(matches!(err.into_inner(), ECIESErrorImpl::SelfConnection));
+        });
+
+        // dialing with the server's own key is rejected before anything is sent
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let err = ECIESStream::connect(outgoing, server_key, server_id).await.unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::SelfConnection));
+
+        // a dialer that skips the check is rejected by the listener
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut transport = ECIESCodec::new_client(server_key, server_id).unwrap().framed(outgoing);
+        transport.send(EgressECIESValue::Auth).await.unwrap();
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn batched_frames_arrive_after_interval() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let interval = Duration::from_millis(200);
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            let start = Instant::now();
+
+            for i in 0..3u8 {
+                let message = stream.next().await.unwrap().unwrap();
+                assert_eq!(message, Bytes::from(vec![i]));
+            }
+            assert!(start.elapsed() >= interval);
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream = ECIESStream::connect(outgoing, client_key, server_id)
+            .await
+            .unwrap()
+            .with_flush_batching(FlushBatching { interval, max_buffered_bytes: 1024 });
+
+        // all frames are buffered, sending completes without writing to the socket
+        for i in 0..3u8 {
+            client_stream.send(Bytes::from(vec![i])).await.unwrap();
+        }
+        assert!(!client_stream.stream.write_buffer().is_empty());
+
+        // polling the stream drives the batch timer until the server closes the connection
+        let _ = tokio::time::timeout(interval * 5, client_stream.next()).await;
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_with_timeout_keeps_frames_intact() {
+        const MESSAGE_SIZE: usize = 1024 * 1024;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let (start_reading, reading) = tokio::sync::oneshot::channel::<()>();
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+
+            // stall until the client's sends time out
+            reading.await.unwrap();
+
+            loop {
+                let message = stream.next().await.unwrap().unwrap();
+                if message == Bytes::from("done") {
+                    break
+                }
+                assert_eq!(message.len(), MESSAGE_SIZE);
+                assert!(message.iter().all(|b| *b == message[0]));
+            }
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+
+        let mut timed_out = false;
+        for i in 0..64u8 {
+            let deadline = Instant::now() + Duration::from_millis(100);
+            let message = Bytes::from(vec![i; MESSAGE_SIZE]);
+            if let Err(err) = client_stream.send_with_timeout(message, deadline).await {
+                assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+                timed_out = true;
+                break
+            }
+        }
+        assert!(timed_out);
+
+        // the stream is still usable after the timeout
+        start_reading.send(()).unwrap();
+        client_stream.send(Bytes::from("done")).await.unwrap();
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn classifies_writes_after_peer_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            // close the connection right after the handshake
+            drop(ECIESStream::incoming(incoming, server_key).await.unwrap());
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        handle.await.unwrap();
+
+        // the first writes may still be accepted by the socket, until the peer's reset arrives
+        let mut err = None;
+        for _ in 0..100 {
+            if let Err(e) = client_stream.send(Bytes::from("hello")).await {
+                err = Some(e);
+                break
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let err = err.expect("writes fail once the peer closed the connection");
+        assert!(matches!(err.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset));
+        assert!(ECIESError::from_io_error(&err).unwrap().is_peer_closed());
+    }
+
+    #[tokio::test]
+    async fn sends_ordered_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let messages: Vec<_> = (0..32u8).map(|i| Bytes::from(vec![i; i as usize + 1])).collect();
+
+        let expected = messages.clone();
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("first"));
+            for message in expected {
+                assert_eq!(stream.next().await.unwrap().unwrap(), message);
+            }
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream = ECIESStream::connect(outgoing, client_key, server_id)
+            .await
+            .unwrap()
+            .with_flush_batching(FlushBatching {
+                interval: Duration::from_secs(60),
+                max_buffered_bytes: 1024 * 1024,
+            });
+
+        // buffered by the batching, must arrive ahead of the ordered messages
+        client_stream.feed(Bytes::from("first")).await.unwrap();
+        client_stream.send_ordered(messages).await.unwrap();
+
+        handle.await.unwrap();
+    }
+
+    /// A transport that fails all writes once the given number of bytes were written.
+    #[derive(Debug)]
+    struct WriteBudget {
+        inner: TcpStream,
+        remaining: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl AsyncRead for WriteBudget {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for WriteBudget {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            use std::sync::atomic::Ordering;
+
+            let remaining = self.remaining.load(Ordering::SeqCst);
+            if remaining == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()))
+            }
+            let len = buf.len().min(remaining);
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[..len]))?;
+            self.remaining.fetch_sub(written, Ordering::SeqCst);
+            Poll::Ready(Ok(written))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    impl HasRemoteAddr for WriteBudget {
+        fn remote_addr(&self) -> Option<std::net::SocketAddr> {
+            self.inner.remote_addr()
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_sent_messages_on_ordered_send_failure() {
+        // header and header mac, body padded to 16 bytes and body mac
+        const FRAME_SIZE: usize = 32 + 16 + 16;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("one"));
+            // the second frame is incomplete
+            assert!(stream.next().await.map_or(true, |res| res.is_err()));
+        });
+
+        let remaining = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(usize::MAX));
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = WriteBudget {
+            inner: TcpStream::connect(addr).await.unwrap(),
+            remaining: remaining.clone(),
+        };
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+
+        // oversized messages abort the send before anything is written
+        let oversized = Bytes::from(vec![0; crate::config::MAX_FRAME_SIZE + 1]);
+        let err =
+            client_stream.send_ordered(vec![Bytes::from("one"), oversized]).await.unwrap_err();
+        assert_eq!(err.sent, 0);
+        assert_eq!(err.source.kind(), io::ErrorKind::InvalidInput);
+
+        remaining.store(FRAME_SIZE + FRAME_SIZE / 2, std::sync::atomic::Ordering::SeqCst);
+        let messages = ["one", "two", "three"].into_iter().map(Bytes::from).collect();
+        let err = client_stream.send_ordered(messages).await.unwrap_err();
+        assert_eq!(err.sent, 1);
+        assert_eq!(err.source.kind(), io::ErrorKind::WriteZero);
+
+        drop(client_stream);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn traffic_continues_across_rekeying() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        // the server rekeys after every message, the client after every few
+        let server_config =
+            ECIESConfig::default().with_rekeying(Rekeying::default().with_max_age(Duration::ZERO));
+        let client_config =
+            ECIESConfig::default().with_rekeying(Rekeying::default().with_max_bytes(100));
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming_with_config(incoming, server_key, server_config)
+                .await
+                .unwrap();
+            for i in 0..32u8 {
+                assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from(vec![i; 40]));
+                stream.send(Bytes::from(vec![i; 3])).await.unwrap();
+            }
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect_with_config(outgoing, client_key, server_id, client_config)
+                .await
+                .unwrap();
+
+        for i in 0..32u8 {
+            client_stream.send(Bytes::from(vec![i; 40])).await.unwrap();
+            assert_eq!(client_stream.next().await.unwrap().unwrap(), Bytes::from(vec![i; 3]));
+        }
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn toggles_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            ECIESStream::incoming(incoming, server_key).await.unwrap()
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let client_stream = ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+
+        for nodelay in [true, false] {
+            client_stream.set_nodelay(nodelay).unwrap();
+            assert_eq!(client_stream.nodelay().unwrap(), Some(nodelay));
+            assert_eq!(client_stream.stream.get_ref().nodelay().unwrap(), nodelay);
+        }
+
+        let _server_stream = handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ignores_nodelay_of_non_tcp_transports() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+
+        let (client_stream, _server_stream) = tokio::join!(
+            ECIESStream::connect(client, client_key, server_id),
+            ECIESStream::incoming(server, server_key)
+        );
+        let client_stream = client_stream.unwrap();
+
+        client_stream.set_nodelay(true).unwrap();
+        assert_eq!(client_stream.nodelay().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn round_trips_empty_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        // interleaved with rekey frames, which carry no message either
+        let config =
+            ECIESConfig::default().with_rekeying(Rekeying::default().with_max_age(Duration::ZERO));
+        let messages = [Bytes::new(), Bytes::from("hello"), Bytes::new()];
+
+        let expected = messages.clone();
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            for snappy in [false, true] {
+                if snappy {
+                    stream.enable_snappy(DEFAULT_MAX_DECOMPRESSED_SIZE);
+                }
+                for message in &expected {
+                    assert_eq!(stream.next().await.unwrap().unwrap(), *message);
+                }
+            }
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect_with_config(outgoing, client_key, server_id, config)
+                .await
+                .unwrap();
+        for snappy in [false, true] {
+            if snappy {
+                client_stream.enable_snappy(DEFAULT_MAX_DECOMPRESSED_SIZE);
+            }
+            for message in &messages {
+                client_stream.send(message.clone()).await.unwrap();
+            }
+        }
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_handshake_after_established() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("hello"));
+
+            let err = stream.next().await.unwrap().unwrap_err();
+            let err = ECIESError::from_io_error(&err).unwrap();
+            assert!(err.is_handshake_after_established(), "{err:?}");
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        client_stream.send(Bytes::from("hello")).await.unwrap();
+
+        // a second auth on the established session
+        client_stream.stream.send(EgressECIESValue::Auth).await.unwrap();
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tracks_half_closed_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert_eq!(stream.state(), ConnectionState::Established);
+
+            // the client closed its side
+            assert!(stream.next().await.is_none());
+            assert_eq!(stream.state(), ConnectionState::Closing);
+
+            // while the other direction is still usable
+            stream.send(Bytes::from("bye")).await.unwrap();
+            stream.close().await.unwrap();
+            assert_eq!(stream.state(), ConnectionState::Closed);
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        assert_eq!(client_stream.state(), ConnectionState::Established);
+
+        client_stream.close().await.unwrap();
+        assert_eq!(client_stream.state(), ConnectionState::Closing);
+        assert_eq!(client_stream.next().await.unwrap().unwrap(), Bytes::from("bye"));
+        assert_eq!(client_stream.state(), ConnectionState::Closing);
+        assert!(client_stream.next().await.is_none());
+        assert_eq!(client_stream.state(), ConnectionState::Closed);
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn applies_backpressure_from_slow_readers() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let (client_stream, server_stream) = tokio::join!(
+            ECIESStream::connect(client, client_key, server_id),
+            ECIESStream::incoming(server, server_key)
+        );
+        let (mut client_stream, mut server_stream) =
+            (client_stream.unwrap(), server_stream.unwrap());
+        let message = Bytes::from(vec![0xab; 1024]);
+
+        // the server doesn't read, so the transport fills up
+        let mut sent = 0;
+        loop {
+            let ready = poll_fn(|cx| Poll::Ready(client_stream.poll_ready_unpin(cx))).await;
+            match ready {
+                Poll::Ready(Ok(())) => {
+                    client_stream.start_send_unpin(message.clone()).unwrap();
+                    sent += 1;
+                }
+                Poll::Ready(Err(err)) => panic!("unexpected error: {err}"),
+                Poll::Pending => break,
+            }
+            assert!(sent < 64, "sender never observed backpressure");
+        }
+        // at most the backpressure boundary plus the frame that crossed it are buffered
+        assert!(client_stream.stream.write_buffer().len() < 8 * 1024 + 2 * message.len());
+
+        // the sender becomes ready again once the reader catches up
+        let reader = tokio::spawn(async move {
+            for _ in 0..sent {
+                assert_eq!(server_stream.next().await.unwrap().unwrap(), message);
+            }
+        });
+        poll_fn(|cx| client_stream.poll_ready_unpin(cx)).await.unwrap();
+        client_stream.flush().await.unwrap();
+        reader.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_messages_of_slow_readers() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let config =
+            ECIESConfig::default().with_egress_policy(EgressPolicy::DropOldest { capacity: 4 });
+        let (client_stream, server_stream) = tokio::join!(
+            ECIESStream::connect_with_config(client, client_key, server_id, config),
+            ECIESStream::incoming(server, server_key)
+        );
+        let (mut client_stream, mut server_stream) =
+            (client_stream.unwrap(), server_stream.unwrap());
+
+        // the server doesn't read, but sending never waits for it
+        let messages = 64u8;
+        for i in 0..messages {
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                client_stream.send(Bytes::from(vec![i; 1024])),
+            )
+            .await
+            .expect("sending doesn't apply backpressure")
+            .unwrap();
+        }
+        let dropped = client_stream.dropped_messages();
+        assert!(dropped > 0);
+
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(message) = server_stream.next().await {
+                received.push(message.unwrap()[0]);
+            }
+            received
+        });
+        // closing writes out the queued messages
+        client_stream.close().await.unwrap();
+        assert_eq!(client_stream.dropped_messages(), dropped);
+
+        let received = reader.await.unwrap();
+        assert_eq!(received.len() as u64, messages as u64 - dropped);
+        // the oldest queued messages were dropped, the newest always arrive
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(received[received.len() - 4..], [60, 61, 62, 63]);
+    }
+
+    #[tokio::test]
+    async fn bounds_messages_in_flight() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let config = ECIESConfig::default().with_max_in_flight(2);
+        let (client_stream, server_stream) = tokio::join!(
+            ECIESStream::connect_with_config(client, client_key, server_id, config),
+            ECIESStream::incoming(server, server_key)
+        );
+        let (mut client_stream, mut server_stream) =
+            (client_stream.unwrap(), server_stream.unwrap());
+        let window = client_stream.in_flight_window().unwrap();
+        assert!(server_stream.in_flight_window().is_none());
+        assert_eq!(window.max_in_flight(), 2);
+
+        client_stream.send(Bytes::from("request 1")).await.unwrap();
+        client_stream.send(Bytes::from("request 2")).await.unwrap();
+        assert_eq!(window.in_flight(), 2);
+
+        // the window is full, sending waits for an acknowledgement
+        assert!(client_stream.send(Bytes::from("request 3")).now_or_never().is_none());
+        assert_eq!(window.in_flight(), 2);
+
+        // the sender is woken once the response was acknowledged by another task
+        let responder = tokio::spawn(async move {
+            let request = server_stream.next().await.unwrap().unwrap();
+            assert_eq!(request, Bytes::from("request 1"));
+            window.acknowledge(1);
+            (server_stream, window)
+        });
+        tokio::time::timeout(Duration::from_secs(5), client_stream.send(Bytes::from("request 3")))
+            .await
+            .expect("acknowledgement resumes sending")
+            .unwrap();
+        let (mut server_stream, window) = responder.await.unwrap();
+        assert_eq!(window.in_flight(), 2);
+        for expected in ["request 2", "request 3"] {
+            assert_eq!(server_stream.next().await.unwrap().unwrap(), Bytes::from(expected));
+        }
+
+        // acknowledging more than is in flight empties the window
+        window.acknowledge(5);
+        assert_eq!(window.in_flight(), 0);
+        client_stream.send(Bytes::from("request 4")).await.unwrap();
+        assert_eq!(window.in_flight(), 1);
+    }
+
+    #[tokio::test]
+    async fn drains_registered_streams() {
+        let signal = DrainSignal::new();
+        let farewell = Bytes::from("disconnect");
+        // keeps egress frames buffered until they're written out by the drain
+        let batching =
+            FlushBatching { interval: Duration::from_secs(3600), max_buffered_bytes: usize::MAX };
+
+        let mut clients = Vec::new();
+        let mut servers = Vec::new();
+        for _ in 0..3 {
+            let (client, server) = tokio::io::duplex(1024);
+            let server_key = SecretKey::new(&mut rand::thread_rng());
+            let server_id = pk2id(&server_key.public_key(SECP256K1));
+            let client_key = SecretKey::new(&mut rand::thread_rng());
+            let (client_stream, server_stream) = tokio::join!(
+                ECIESStream::connect(client, client_key, server_id),
+                ECIESStream::incoming(server, server_key)
+            );
+            let mut server_stream =
+                server_stream.unwrap().with_flush_batching(batching).with_drain(&signal);
+            server_stream.send(Bytes::from("pending")).await.unwrap();
+
+            clients.push(client_stream.unwrap());
+            servers.push(tokio::spawn(async move {
+                // the stream ends once drained
+                assert!(server_stream.next().await.is_none());
+                assert_eq!(server_stream.state(), ConnectionState::Closed);
+                let err = server_stream.send(Bytes::from("late")).await.unwrap_err();
+                assert!(ECIESError::from_io_error(&err).unwrap().is_drained());
+                server_stream
+            }));
+        }
+        assert_eq!(signal.active_streams(), 3);
+
+        signal.drain(Some(farewell.clone()));
+        tokio::time::timeout(Duration::from_secs(5), signal.drained()).await.unwrap();
+        assert_eq!(signal.active_streams(), 0);
+
+        for mut client in clients {
+            assert_eq!(client.next().await.unwrap().unwrap(), Bytes::from("pending"));
+            assert_eq!(client.next().await.unwrap().unwrap(), farewell);
+            assert!(client.next().await.is_none());
+        }
+        for server in servers {
+            server.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn closed_after_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert!(stream.next().await.unwrap().is_err());
+            assert_eq!(stream.state(), ConnectionState::Closed);
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        client_stream.stream.send(EgressECIESValue::Auth).await.unwrap();
+
+        handle.await.unwrap();
+    }
+
+    #[cfg(feature = "frame-dump")]
+    #[tokio::test]
+    async fn dumps_malformed_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let garbage = [0xab; 32];
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert!(stream.next().await.unwrap().is_err());
+            assert_eq!(stream.read_buffer_dump(), "ab".repeat(32));
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+
+        // bytes that fail the header check of the session
+        client_stream.stream.get_mut().write_all(&garbage).await.unwrap();
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn publishes_protocol_violations() {
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let (events, mut violations) = tokio::sync::mpsc::unbounded_channel();
+
+        // bytes that fail the header check, and bytes cut off in the middle of a header
+        let cases = [
+            (vec![0xab; 32], ViolationKind::MalformedFrame),
+            (vec![0xab; 10], ViolationKind::TruncatedFrame),
+        ];
+        for (bytes, kind) in cases {
+            let (client, server) = tokio::io::duplex(1024);
+            let client_key = SecretKey::new(&mut rand::thread_rng());
+            let (client_stream, server_stream) = tokio::join!(
+                ECIESStream::connect(client, client_key, server_id),
+                ECIESStream::incoming(server, server_key)
+            );
+            let mut client_stream = client_stream.unwrap();
+            let mut server_stream = server_stream.unwrap().with_violation_events(events.clone());
+
+            client_stream.stream.get_mut().write_all(&bytes).await.unwrap();
+            client_stream.stream.get_mut().shutdown().await.unwrap();
+
+            let err = server_stream.next().await.unwrap().unwrap_err();
+            let violation = violations.try_recv().unwrap();
+            assert_eq!(violation.peer_id, pk2id(&client_key.public_key(SECP256K1)));
+            assert_eq!(violation.kind, kind);
+            assert_eq!(ECIESError::from_io_error(&err).and_then(ECIESError::violation), Some(kind));
+        }
+
+        // a peer closing the connection between frames is not a violation
+        let (client, server) = tokio::io::duplex(1024);
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let (client_stream, server_stream) = tokio::join!(
+            ECIESStream::connect(client, client_key, server_id),
+            ECIESStream::incoming(server, server_key)
+        );
+        let mut server_stream = server_stream.unwrap().with_violation_events(events);
+        drop(client_stream);
+        assert!(server_stream.next().await.is_none());
+        assert!(violations.try_recv().is_err());
+    }
+
+    #[cfg(feature = "frame-dump")]
+    #[test]
+    fn truncates_frame_dumps() {
+        assert_eq!(frame_dump(&[]), "");
+        assert_eq!(frame_dump(&[0x01, 0xff]), "01ff");
+
+        let dump = frame_dump(&[0x10; MAX_FRAME_DUMP_LEN + 3]);
+        assert_eq!(dump, format!("{}... (3 more bytes)", "10".repeat(MAX_FRAME_DUMP_LEN)));
+    }
+
+    #[test]
+    fn unreadable_frames_are_not_handshakes() {
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let mut client =
+            ECIESCodec::new_client(SecretKey::new(&mut rand::thread_rng()), server_id).unwrap();
+        let mut server = ECIESCodec::new_server(server_key).unwrap();
+
+        let mut buf = BytesMut::new();
+        client.encode(EgressECIESValue::Auth, &mut buf).unwrap();
+        assert!(matches!(server.decode(&mut buf), Ok(Some(IngressECIESValue::AuthReceive(_)))));
+        server.encode(EgressECIESValue::Ack, &mut buf).unwrap();
+        assert!(matches!(client.decode(&mut buf), Ok(Some(IngressECIESValue::Ack))));
+
+        let mut garbage = BytesMut::from(&[0x01; 64][..]);
+        let err = server.decode(&mut garbage).unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::TagCheckHeaderFailed));
+    }
+
+    #[tokio::test]
+    async fn rejects_incoming_connections_above_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let limiter = ConnectionLimiter::new(1);
+        let config = ECIESConfig::default().with_connection_limiter(limiter.clone());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let stream = ECIESStream::incoming_with_config(incoming, server_key, config.clone())
+                .await
+                .unwrap();
+            assert_eq!(limiter.available(), 0);
+
+            let (incoming, _) = listener.accept().await.unwrap();
+            let err =
+                ECIESStream::incoming_with_config(incoming, server_key, config).await.unwrap_err();
+            assert!(matches!(err.into_inner(), ECIESErrorImpl::TooManyConnections));
+
+            // closing the connection frees its slot
+            drop(stream);
+            assert_eq!(limiter.available(), 1);
+        });
+
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let _client_stream = ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+
+        // the second connection is dropped by the server without answering the auth
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        assert!(ECIESStream::connect(outgoing, client_key, server_id).await.is_err());
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_connect_closes_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+
+        // receive the auth but never answer it
+        let handle = tokio::spawn(async move {
+            let (mut incoming, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            incoming.read_to_end(&mut received).await.unwrap();
+            received
+        });
+
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        tokio::select! {
+            _ = ECIESStream::connect(outgoing, client_key, server_id) => {
+                panic!("handshake completed without an ack")
+            }
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+        }
+
+        // the server reaches the end of the stream, so the dropped handshake closed the socket
+        let received = handle.await.unwrap();
+        assert!(!received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_incoming_closes_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let limiter = ConnectionLimiter::new(1);
+        let config = ECIESConfig::default().with_connection_limiter(limiter.clone());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            tokio::select! {
+                _ = ECIESStream::incoming_with_config(incoming, server_key, config) => {
+                    panic!("handshake completed without an auth")
+                }
+                _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+            }
+            assert_eq!(limiter.available(), 1);
+        });
+
+        // send part of an auth message, then wait for the server to give up
+        let mut outgoing = TcpStream::connect(addr).await.unwrap();
+        outgoing.write_all(&[0x01, 0x00, 0x04]).await.unwrap();
+        let mut received = Vec::new();
+        outgoing.read_to_end(&mut received).await.unwrap();
+        assert!(received.is_empty());
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn selects_dialed_server_identity() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let keys =
+            vec![SecretKey::new(&mut rand::thread_rng()), SecretKey::new(&mut rand::thread_rng())];
+        let ids = keys.iter().map(|key| pk2id(&key.public_key(SECP256K1))).collect::<Vec<_>>();
+
+        let expected = ids.clone();
+        let handle = tokio::spawn(async move {
+            for id in [expected[1], expected[0]] {
+                let (incoming, _) = listener.accept().await.unwrap();
+                let mut stream =
+                    ECIESStream::incoming_with_resolver(incoming, &keys, ECIESConfig::default())
+                        .await
+                        .unwrap();
+                assert_eq!(stream.local_id(), id);
+                assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from(id.to_string()));
+            }
+
+            // dialers of an identity that isn't served are rejected
+            let (incoming, _) = listener.accept().await.unwrap();
+            let err = ECIESStream::incoming_with_resolver(incoming, &keys, ECIESConfig::default())
+                .await
+                .unwrap_err();
+            assert!(matches!(err.into_inner(), ECIESErrorImpl::TagCheckDecryptFailed));
+        });
+
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        for id in [ids[1], ids[0]] {
+            let outgoing = TcpStream::connect(addr).await.unwrap();
+            let mut client_stream = ECIESStream::connect(outgoing, client_key, id).await.unwrap();
+            client_stream.send(Bytes::from(id.to_string())).await.unwrap();
+        }
+
+        let unknown = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let unknown_id = pk2id(&unknown.public_key(SECP256K1));
+        assert!(ECIESStream::connect(outgoing, client_key, unknown_id).await.is_err());
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_resolver_without_keys() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _outgoing = TcpStream::connect(addr).await.unwrap();
+        let (incoming, _) = listener.accept().await.unwrap();
+
+        let err = ECIESStream::incoming_with_resolver(
+            incoming,
+            &Vec::<SecretKey>::new(),
+            ECIESConfig::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::NoServerKeys));
+    }
+
+    #[tokio::test]
+    async fn handshake_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+
+        // accept the connection but never answer the auth
+        let handle = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let config = ECIESConfig::default().with_handshake_timeout(Duration::from_millis(100));
+        let err = ECIESStream::connect_with_config(outgoing, client_key, server_id, config)
+            .await
+            .unwrap_err();
+        assert!(matches!(err.into_inner(), ECIESErrorImpl::HandshakeTimeout));
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn drives_pending_handshakes_concurrently() {
+        let mut clients = Vec::new();
+        let mut servers = Vec::new();
+        let mut server_ids = Vec::new();
+        for _ in 0..4 {
+            let (client, server) = tokio::io::duplex(1024);
+            let server_key = SecretKey::new(&mut rand::thread_rng());
+            let server_id = pk2id(&server_key.public_key(SECP256K1));
+            let client_key = SecretKey::new(&mut rand::thread_rng());
+
+            // nothing is exchanged yet, so neither side waits for the other
+            let client =
+                ECIESStream::connect_pending(client, client_key, server_id, ECIESConfig::default())
+                    .unwrap();
+            assert_eq!(client.remote_id(), Some(server_id));
+            let server =
+                ECIESStream::incoming_pending(server, server_key, ECIESConfig::default()).unwrap();
+            assert_eq!(server.remote_id(), None);
+
+            clients.push(client);
+            servers.push(server);
+            server_ids.push(server_id);
+        }
+
+        // a single task drives all handshakes
+        let (clients, servers) = tokio::join!(
+            futures::future::join_all(clients.into_iter().map(|c| c.finish_handshake())),
+            futures::future::join_all(servers.into_iter().map(|s| s.finish_handshake()))
+        );
+
+        for ((client, server), server_id) in clients.into_iter().zip(servers).zip(server_ids) {
+            let (mut client, mut server) = (client.unwrap(), server.unwrap());
+            assert_eq!(client.remote_id(), server_id);
+            assert_eq!(server.remote_id(), client.local_id());
+
+            client.send(Bytes::from("hello")).await.unwrap();
+            assert_eq!(server.next().await.unwrap().unwrap(), Bytes::from("hello"));
+        }
+    }
+
+    #[tokio::test]
+    async fn snappy_compressed_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let large = Bytes::from(vec![0x10; 64 * 1024]);
+
+        let (expected, large_len) = (large.clone(), large.len());
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            stream.enable_snappy(1024);
+
+            assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("\x01hello"));
+
+            // the large message is rejected without being decompressed
+            let err = stream.next().await.unwrap().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+            stream.enable_snappy(large_len);
+            assert_eq!(stream.next().await.unwrap().unwrap(), expected);
+
+            // the wire carries the compressed body behind the uncompressed message id
+            stream.snappy = None;
+            let raw = stream.next().await.unwrap().unwrap();
+            assert_eq!(raw[0], 0x10);
+            assert!(raw.len() < large_len);
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        client_stream.enable_snappy(crate::snappy::DEFAULT_MAX_DECOMPRESSED_SIZE);
+        assert!(client_stream.is_snappy_enabled());
+
+        client_stream.send(Bytes::from("\x01hello")).await.unwrap();
+        for _ in 0..3 {
+            client_stream.send(large.clone()).await.unwrap();
+        }
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let config = ECIESConfig::default().with_max_frame_size(8);
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream =
+                ECIESStream::incoming_with_config(incoming, server_key, config).await.unwrap();
+
+            let message = stream.next().await.unwrap().unwrap();
+            assert_eq!(message, Bytes::from("hello"));
+
+            // the oversized frame is rejected
+            assert!(stream.next().await.unwrap().is_err());
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream =
+            ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        client_stream.send(Bytes::from("hello")).await.unwrap();
+        client_stream.send(Bytes::from("hello world")).await.unwrap();
+
+        // the limit applies to egress frames as well
+        let mut limited =
+            ECIESCodec::new_client(client_key, server_id).unwrap().with_max_frame_size(8);
+        let mut buf = BytesMut::new();
+        assert!(limited
+            .encode(EgressECIESValue::Message(Bytes::from("hello world")), &mut buf)
+            .is_err());
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_mismatched_handshake_params() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let err = ECIESStream::incoming(incoming, server_key).await.unwrap_err();
+            assert!(matches!(err.into_inner(), ECIESErrorImpl::TagCheckDecryptFailed));
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let config = ECIESConfig::default().with_handshake_params(
+            HandshakeParams::default().with_cipher(HandshakeCipher::Aes256Ctr),
+        );
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+
+        // the server drops the connection instead of acknowledging the auth
+        let result =
+            ECIESStream::connect_with_config(outgoing, client_key, server_id, config).await;
+        assert!(result.is_err());
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn carries_context() {
+        #[derive(Debug, PartialEq)]
+        struct PeerRecord {
+            discovered: bool,
+            reputation: i32,
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let mut stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("hello"));
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let mut client_stream = ECIESStream::connect(outgoing, client_key, server_id)
+            .await
+            .unwrap()
+            .with_context(PeerRecord { discovered: true, reputation: 0 });
+
+        client_stream.context_mut().reputation += 1;
+        assert_eq!(client_stream.context(), &PeerRecord { discovered: true, reputation: 1 });
+        assert_eq!(client_stream.remote_id(), server_id);
+
+        // the stream is still usable with a context attached
+        client_stream.send(Bytes::from("hello")).await.unwrap();
+        handle.await.unwrap();
+    }
+
+    #[cfg(all(feature = "insecure-session-key-export", debug_assertions))]
+    #[tokio::test]
+    async fn exports_matching_session_keys() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            ECIESStream::incoming(incoming, server_key).await.unwrap().session_keys()
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let client_stream = ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+
+        let client_keys = client_stream.session_keys();
+        let server_keys = handle.await.unwrap();
+        assert_eq!(client_keys.aes_secret, server_keys.aes_secret);
+        assert_eq!(client_keys.mac_secret, server_keys.mac_secret);
+        assert_eq!(client_keys.nonce, server_keys.remote_nonce);
+        assert_eq!(client_keys.remote_nonce, server_keys.nonce);
     }
 }
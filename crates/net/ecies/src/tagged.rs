@@ -0,0 +1,157 @@
+//! An [`ECIESStream`] adapter that splits off the leading message id of every message.
+
+use crate::stream::ECIESStream;
+use futures::{ready, Sink};
+use reth_primitives::bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_stream::Stream;
+
+/// Wraps an [`ECIESStream`] to exchange messages as `(message id, payload)` pairs.
+///
+/// Upper layer protocols prefix every message with a single message id byte. This adapter splits
+/// it off ingress messages and prepends it to egress messages, so callers don't have to slice the
+/// raw bytes themselves.
+///
+/// Receiving an empty message is an [InvalidData](io::ErrorKind::InvalidData) error, since it has
+/// no message id.
+#[derive(Debug)]
+#[pin_project::pin_project]
+pub struct TaggedECIESStream<Io> {
+    #[pin]
+    inner: ECIESStream<Io>,
+}
+
+// === impl TaggedECIESStream ===
+
+impl<Io> TaggedECIESStream<Io> {
+    /// Wraps the given stream.
+    pub fn new(inner: ECIESStream<Io>) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the wrapped stream.
+    pub fn inner(&self) -> &ECIESStream<Io> {
+        &self.inner
+    }
+
+    /// Returns the wrapped stream, which exchanges raw messages again.
+    pub fn into_inner(self) -> ECIESStream<Io> {
+        self.inner
+    }
+}
+
+impl<Io> Stream for TaggedECIESStream<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<(u8, Bytes), io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(message) = ready!(self.project().inner.poll_next(cx)) else {
+            return Poll::Ready(None)
+        };
+        Poll::Ready(Some(message.and_then(split_message_id)))
+    }
+}
+
+impl<Io> Sink<(u8, Bytes)> for TaggedECIESStream<Io>
+where
+    Io: AsyncWrite + Unpin,
+{
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: (u8, Bytes)) -> Result<(), Self::Error> {
+        let (id, payload) = item;
+        let mut message = BytesMut::with_capacity(1 + payload.len());
+        message.put_u8(id);
+        message.extend_from_slice(&payload);
+        self.project().inner.start_send(message.freeze())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+/// Splits the leading message id off the message.
+fn split_message_id(mut message: BytesMut) -> io::Result<(u8, Bytes)> {
+    if message.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "ECIES message without message id"))
+    }
+    let id = message.get_u8();
+    Ok((id, message.freeze()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::pk2id;
+    use futures::SinkExt;
+    use secp256k1::{rand, SecretKey, SECP256K1};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn splits_message_id() {
+        let (id, payload) = split_message_id(BytesMut::from(&[0x10, 1, 2][..])).unwrap();
+        assert_eq!(id, 0x10);
+        assert_eq!(payload, Bytes::from_static(&[1, 2]));
+
+        let (id, payload) = split_message_id(BytesMut::from(&[0x02][..])).unwrap();
+        assert_eq!(id, 0x02);
+        assert!(payload.is_empty());
+
+        let err = split_message_id(BytesMut::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn round_trips_tagged_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_key = SecretKey::new(&mut rand::thread_rng());
+        let messages =
+            [(0x00, Bytes::from("hello")), (0x10, Bytes::new()), (0xff, Bytes::from("world"))];
+
+        let expected = messages.clone();
+        let handle = tokio::spawn(async move {
+            let (incoming, _) = listener.accept().await.unwrap();
+            let stream = ECIESStream::incoming(incoming, server_key).await.unwrap();
+            let mut stream = stream.into_tagged();
+
+            for message in expected {
+                assert_eq!(stream.next().await.unwrap().unwrap(), message);
+            }
+
+            // raw messages carry the id as their first byte
+            let mut stream = stream.into_inner();
+            assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from_static(&[0x01, 0xaa]));
+        });
+
+        let server_id = pk2id(&server_key.public_key(SECP256K1));
+        let client_key = SecretKey::new(&mut rand::thread_rng());
+        let outgoing = TcpStream::connect(addr).await.unwrap();
+        let client_stream = ECIESStream::connect(outgoing, client_key, server_id).await.unwrap();
+        let mut client_stream = TaggedECIESStream::new(client_stream);
+
+        for message in messages {
+            client_stream.send(message).await.unwrap();
+        }
+        client_stream.send((0x01, Bytes::from_static(&[0xaa]))).await.unwrap();
+
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+//! Protocol violations of peers, published for a central consumer like a reputation system.
+
+use reth_primitives::H512 as PeerId;
+
+/// A protocol violation of the peer of an [`ECIESStream`](crate::stream::ECIESStream), see
+/// [`ECIESStream::with_violation_events`](crate::stream::ECIESStream::with_violation_events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolViolation {
+    /// The id of the peer that violated the protocol.
+    pub peer_id: PeerId,
+    /// What the peer did wrong.
+    pub kind: ViolationKind,
+}
+
+/// The kind of a [`ProtocolViolation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The peer sent a handshake message on an established session.
+    UnexpectedMessage,
+    /// The peer announced a frame body larger than the maximum frame size.
+    OversizedFrame {
+        /// The announced size of the frame body
+        size: usize,
+        /// The configured maximum size of a frame body
+        max: usize,
+    },
+    /// A frame failed its integrity check or couldn't be decoded.
+    MalformedFrame,
+    /// The peer closed the connection in the middle of a frame.
+    TruncatedFrame,
+}
@@ -178,9 +178,10 @@ where
 }
 
 #[async_trait::async_trait]
-impl<S> CanDisconnect<Bytes> for ECIESStream<S>
+impl<S, Ctx> CanDisconnect<Bytes> for ECIESStream<S, Ctx>
 where
     S: AsyncWrite + Unpin + Send,
+    Ctx: Send,
 {
     async fn disconnect(&mut self, _reason: DisconnectReason) -> Result<(), std::io::Error> {
         self.close().await
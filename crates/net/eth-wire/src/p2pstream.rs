@@ -238,6 +238,11 @@ impl<S> P2PStream<S> {
         &self.shared_capability
     }
 
+    /// Returns the smoothed round-trip time of the peer's pongs, `None` if none was received yet.
+    pub fn latency(&self) -> Option<Duration> {
+        self.pinger.latency()
+    }
+
     /// Returns `true` if the connection is about to disconnect.
     pub fn is_disconnecting(&self) -> bool {
         self.disconnecting
@@ -7,6 +7,10 @@ use std::{
 use tokio::time::{Instant, Interval, Sleep};
 use tokio_stream::Stream;
 
+/// Weight of the previous estimate in the smoothed latency, i.e. each new round-trip time
+/// contributes `1 / LATENCY_SMOOTHING` to the estimate.
+const LATENCY_SMOOTHING: u32 = 8;
+
 /// The pinger is a state machine that is created with a maximum number of pongs that can be
 /// missed.
 #[derive(Debug)]
@@ -19,6 +23,10 @@ pub(crate) struct Pinger {
     timeout: Duration,
     /// Keeps track of the state
     state: PingState,
+    /// When the ping that's awaiting a pong was sent.
+    ping_sent_at: Instant,
+    /// Smoothed round-trip time of answered pings, `None` until the first pong.
+    latency: Option<Duration>,
 }
 
 // === impl Pinger ===
@@ -34,15 +42,36 @@ impl Pinger {
             ping_interval: tokio::time::interval_at(now + ping_interval, ping_interval),
             timeout_timer: Box::pin(timeout_timer),
             timeout: timeout_duration,
+            ping_sent_at: now,
+            latency: None,
         }
     }
 
+    /// Returns the smoothed round-trip time of answered pings.
+    ///
+    /// Only one ping is in flight at a time, so a pong always answers the most recent ping. Pongs
+    /// arriving after the ping timed out are not accounted for.
+    pub(crate) fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Records the round-trip time of an answered ping.
+    fn record_round_trip(&mut self, rtt: Duration) {
+        // exponentially weighted moving average as used for TCP's smoothed RTT, so a single slow
+        // pong doesn't dominate the estimate
+        self.latency = Some(match self.latency {
+            Some(latency) => (latency * (LATENCY_SMOOTHING - 1) + rtt) / LATENCY_SMOOTHING,
+            None => rtt,
+        });
+    }
+
     /// Mark a pong as received, and transition the pinger to the `Ready` state if it was in the
     /// `WaitingForPong` state. Unsets the sleep timer.
     pub(crate) fn on_pong(&mut self) -> Result<(), PingerError> {
         match self.state {
             PingState::Ready => Err(PingerError::UnexpectedPong),
             PingState::WaitingForPong => {
+                self.record_round_trip(self.ping_sent_at.elapsed());
                 self.state = PingState::Ready;
                 self.ping_interval.reset();
                 Ok(())
@@ -71,7 +100,9 @@ impl Pinger {
         match self.state() {
             PingState::Ready => {
                 if self.ping_interval.poll_tick(cx).is_ready() {
-                    self.timeout_timer.as_mut().reset(Instant::now() + self.timeout);
+                    let now = Instant::now();
+                    self.ping_sent_at = now;
+                    self.timeout_timer.as_mut().reset(now + self.timeout);
                     self.state = PingState::WaitingForPong;
                     return Poll::Ready(Ok(PingerEvent::Ping))
                 }
@@ -144,4 +175,35 @@ mod tests {
 
         assert_eq!(pinger.next().await.unwrap().unwrap(), PingerEvent::Ping);
     }
+
+    #[tokio::test]
+    async fn test_ping_latency() {
+        let delay = Duration::from_millis(50);
+        let mut pinger = Pinger::new(Duration::from_millis(10), delay * 2);
+        assert_eq!(pinger.latency(), None);
+
+        // the pong arrives after the injected delay
+        assert_eq!(pinger.next().await.unwrap().unwrap(), PingerEvent::Ping);
+        tokio::time::sleep(delay).await;
+        pinger.on_pong().unwrap();
+        let latency = pinger.latency().unwrap();
+        assert!(latency >= delay && latency < delay * 2);
+
+        // an unsolicited pong is rejected and leaves the estimate untouched
+        assert!(pinger.on_pong().is_err());
+        assert_eq!(pinger.latency(), Some(latency));
+
+        // a pong arriving after the timeout isn't accounted for
+        assert_eq!(pinger.next().await.unwrap().unwrap(), PingerEvent::Ping);
+        tokio::time::sleep(delay * 3).await;
+        assert_eq!(pinger.next().await.unwrap().unwrap(), PingerEvent::Timeout);
+        pinger.on_pong().unwrap();
+        assert_eq!(pinger.latency(), Some(latency));
+
+        // a fast pong only moves the estimate partially
+        assert_eq!(pinger.next().await.unwrap().unwrap(), PingerEvent::Ping);
+        pinger.on_pong().unwrap();
+        let smoothed = pinger.latency().unwrap();
+        assert!(smoothed < latency && smoothed > latency / 2);
+    }
 }
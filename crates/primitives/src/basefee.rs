@@ -0,0 +1,178 @@
+//! Helpers for calculating the base fee of the next block, see
+//! [EIP-1559](https://eips.ethereum.org/EIPS/eip-1559) and
+//! [EIP-4844](https://eips.ethereum.org/EIPS/eip-4844).
+
+use crate::{
+    constants::{
+        DATA_GASPRICE_UPDATE_FRACTION, EIP1559_BASE_FEE_MAX_CHANGE_DENOMINATOR,
+        EIP1559_ELASTICITY_MULTIPLIER, EIP1559_INITIAL_BASE_FEE, MIN_DATA_GASPRICE,
+        TARGET_DATA_GAS_PER_BLOCK,
+    },
+    Header,
+};
+
+/// Calculate base fee for next block. EIP-1559 spec
+pub fn calculate_next_block_base_fee(gas_used: u64, gas_limit: u64, base_fee: u64) -> u64 {
+    let gas_target = gas_limit / EIP1559_ELASTICITY_MULTIPLIER;
+
+    if gas_used == gas_target {
+        return base_fee
+    }
+    if gas_used > gas_target {
+        let gas_used_delta = gas_used - gas_target;
+        let base_fee_delta = std::cmp::max(
+            1,
+            base_fee as u128 * gas_used_delta as u128 /
+                gas_target as u128 /
+                EIP1559_BASE_FEE_MAX_CHANGE_DENOMINATOR as u128,
+        );
+        base_fee + (base_fee_delta as u64)
+    } else {
+        let gas_used_delta = gas_target - gas_used;
+        let base_fee_per_gas_delta = base_fee as u128 * gas_used_delta as u128 /
+            gas_target as u128 /
+            EIP1559_BASE_FEE_MAX_CHANGE_DENOMINATOR as u128;
+
+        base_fee.saturating_sub(base_fee_per_gas_delta as u64)
+    }
+}
+
+/// Returns the base fee of the block following `parent`.
+///
+/// If `parent` predates EIP-1559, the child is the fork block and
+/// [EIP1559_INITIAL_BASE_FEE] is returned.
+pub fn next_base_fee(parent: &Header) -> u64 {
+    match parent.base_fee_per_gas {
+        Some(base_fee) => {
+            calculate_next_block_base_fee(parent.gas_used, parent.gas_limit, base_fee)
+        }
+        None => EIP1559_INITIAL_BASE_FEE,
+    }
+}
+
+/// Calculates the excess data gas of the next block from its parent's excess data gas and the data
+/// gas used by the parent's blob transactions. EIP-4844 spec
+pub fn calculate_excess_data_gas(parent_excess_data_gas: u64, parent_data_gas_used: u64) -> u64 {
+    (parent_excess_data_gas + parent_data_gas_used).saturating_sub(TARGET_DATA_GAS_PER_BLOCK)
+}
+
+/// Calculates the data gas price of a block from its excess data gas. EIP-4844 spec
+pub fn calculate_data_gasprice(excess_data_gas: u64) -> u128 {
+    fake_exponential(MIN_DATA_GASPRICE, excess_data_gas, DATA_GASPRICE_UPDATE_FRACTION)
+}
+
+/// Returns the data gas price of the next block, the blob counterpart of [next_base_fee].
+pub fn next_data_gasprice(parent_excess_data_gas: u64, parent_data_gas_used: u64) -> u128 {
+    calculate_data_gasprice(calculate_excess_data_gas(parent_excess_data_gas, parent_data_gas_used))
+}
+
+/// Approximates `factor * e ** (numerator / denominator)` using Taylor expansion, as defined in
+/// EIP-4844.
+fn fake_exponential(factor: u64, numerator: u64, denominator: u64) -> u128 {
+    let (factor, numerator, denominator) = (factor as u128, numerator as u128, denominator as u128);
+
+    let mut i = 1;
+    let mut output = 0;
+    let mut numerator_accum = factor * denominator;
+    while numerator_accum > 0 {
+        output += numerator_accum;
+        numerator_accum = numerator_accum * numerator / (denominator * i);
+        i += 1;
+    }
+    output / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DATA_GAS_PER_BLOB;
+
+    #[test]
+    fn calculate_base_fee_success() {
+        let base_fee = [
+            1000000000, 1000000000, 1000000000, 1072671875, 1059263476, 1049238967, 1049238967, 0,
+            1, 2,
+        ];
+        let gas_used = [
+            10000000, 10000000, 10000000, 9000000, 10001000, 0, 10000000, 10000000, 10000000,
+            10000000,
+        ];
+        let gas_limit = [
+            10000000, 12000000, 14000000, 10000000, 14000000, 2000000, 18000000, 18000000,
+            18000000, 18000000,
+        ];
+        let next_base_fee = [
+            1125000000, 1083333333, 1053571428, 1179939062, 1116028649, 918084097, 1063811730, 1,
+            2, 3,
+        ];
+
+        for i in 0..base_fee.len() {
+            assert_eq!(
+                next_base_fee[i],
+                calculate_next_block_base_fee(gas_used[i], gas_limit[i], base_fee[i])
+            );
+        }
+    }
+
+    #[test]
+    fn next_base_fee_of_header() {
+        let parent = Header {
+            gas_used: 10000000,
+            gas_limit: 10000000,
+            base_fee_per_gas: Some(1000000000),
+            ..Default::default()
+        };
+        assert_eq!(next_base_fee(&parent), 1125000000);
+
+        let pre_london = Header { base_fee_per_gas: None, ..parent };
+        assert_eq!(next_base_fee(&pre_london), EIP1559_INITIAL_BASE_FEE);
+    }
+
+    #[test]
+    fn fake_exponential_vectors() {
+        let vectors = [
+            (1, 0, 1, 1),
+            (38493, 0, 1000, 38493),
+            (0, 1234, 2345, 0),
+            (1, 2, 1, 6),
+            (1, 4, 2, 6),
+            (1, 3, 1, 16),
+            (1, 6, 2, 18),
+            (1, 4, 1, 49),
+            (1, 8, 2, 50),
+            (10, 8, 2, 542),
+            (11, 8, 2, 596),
+            (1, 5, 1, 136),
+            (1, 5, 2, 11),
+            (2, 5, 2, 23),
+            (1, 50000000, 2225652, 5709098764),
+        ];
+
+        for (factor, numerator, denominator, expected) in vectors {
+            assert_eq!(fake_exponential(factor, numerator, denominator), expected);
+        }
+    }
+
+    #[test]
+    fn calculate_excess_data_gas_success() {
+        // below target, excess is drained
+        assert_eq!(calculate_excess_data_gas(0, DATA_GAS_PER_BLOB), 0);
+        assert_eq!(calculate_excess_data_gas(0, TARGET_DATA_GAS_PER_BLOCK), 0);
+        // above target, the surplus carries over
+        assert_eq!(
+            calculate_excess_data_gas(DATA_GAS_PER_BLOB, TARGET_DATA_GAS_PER_BLOCK),
+            DATA_GAS_PER_BLOB
+        );
+        assert_eq!(
+            calculate_excess_data_gas(0, TARGET_DATA_GAS_PER_BLOCK + 2 * DATA_GAS_PER_BLOB),
+            2 * DATA_GAS_PER_BLOB
+        );
+    }
+
+    #[test]
+    fn next_data_gasprice_success() {
+        assert_eq!(next_data_gasprice(0, 0), MIN_DATA_GASPRICE as u128);
+        assert_eq!(next_data_gasprice(0, TARGET_DATA_GAS_PER_BLOCK), MIN_DATA_GASPRICE as u128);
+        assert_eq!(calculate_data_gasprice(DATA_GASPRICE_UPDATE_FRACTION * 20), 485165195);
+    }
+}
@@ -15,6 +15,18 @@ pub const EIP1559_BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
 /// Elasticity multiplier as defined in [EIP-1559](https://eips.ethereum.org/EIPS/eip-1559)
 pub const EIP1559_ELASTICITY_MULTIPLIER: u64 = 2;
 
+/// Data gas consumed by a single blob as defined in [EIP-4844](https://eips.ethereum.org/EIPS/eip-4844)
+pub const DATA_GAS_PER_BLOB: u64 = 131_072;
+
+/// Target data gas per block as defined in [EIP-4844](https://eips.ethereum.org/EIPS/eip-4844)
+pub const TARGET_DATA_GAS_PER_BLOCK: u64 = 3 * DATA_GAS_PER_BLOB;
+
+/// Minimum data gas price as defined in [EIP-4844](https://eips.ethereum.org/EIPS/eip-4844)
+pub const MIN_DATA_GASPRICE: u64 = 1;
+
+/// Data gas price update fraction as defined in [EIP-4844](https://eips.ethereum.org/EIPS/eip-4844)
+pub const DATA_GASPRICE_UPDATE_FRACTION: u64 = 3_338_477;
+
 /// Multiplier for converting gwei to wei.
 pub const GWEI_TO_WEI: u64 = 1_000_000_000;
 
@@ -274,6 +274,28 @@ impl Transaction {
         }
     }
 
+    /// Returns the priority fee per gas for eip1559 transactions, and the gas price for legacy and
+    /// eip2930 transactions.
+    pub fn priority_fee_or_price(&self) -> u128 {
+        match self {
+            Transaction::Legacy(TxLegacy { gas_price, .. }) |
+            Transaction::Eip2930(TxEip2930 { gas_price, .. }) => *gas_price,
+            Transaction::Eip1559(TxEip1559 { max_priority_fee_per_gas, .. }) => {
+                *max_priority_fee_per_gas
+            }
+        }
+    }
+
+    /// Returns the effective tip per gas the transaction pays to the block producer, given the
+    /// base fee of the block it's included in.
+    ///
+    /// Returns `None` if the transaction's max fee per gas is below the base fee.
+    pub fn effective_gas_tip(&self, base_fee: Option<u64>) -> Option<u128> {
+        let Some(base_fee) = base_fee else { return Some(self.priority_fee_or_price()) };
+        let max_tip = self.max_fee_per_gas().checked_sub(base_fee as u128)?;
+        Some(max_tip.min(self.priority_fee_or_price()))
+    }
+
     /// Get the transaction's input field.
     pub fn input(&self) -> &Bytes {
         match self {
@@ -950,6 +972,24 @@ mod tests {
     use reth_rlp::{Decodable, DecodeError, Encodable};
     use std::str::FromStr;
 
+    #[test]
+    fn test_effective_gas_tip() {
+        let legacy = Transaction::Legacy(TxLegacy { gas_price: 30, ..Default::default() });
+        assert_eq!(legacy.effective_gas_tip(None), Some(30));
+        assert_eq!(legacy.effective_gas_tip(Some(10)), Some(20));
+        assert_eq!(legacy.effective_gas_tip(Some(31)), None);
+
+        let eip1559 = Transaction::Eip1559(TxEip1559 {
+            max_fee_per_gas: 30,
+            max_priority_fee_per_gas: 5,
+            ..Default::default()
+        });
+        assert_eq!(eip1559.effective_gas_tip(None), Some(5));
+        assert_eq!(eip1559.effective_gas_tip(Some(10)), Some(5));
+        assert_eq!(eip1559.effective_gas_tip(Some(27)), Some(3));
+        assert_eq!(eip1559.effective_gas_tip(Some(31)), None);
+    }
+
     #[test]
     fn test_decode_empty_typed_tx() {
         let input = [0x80u8];
@@ -2,7 +2,7 @@ use jsonrpsee::{core::RpcResult as Result, proc_macros::rpc};
 use reth_primitives::{BlockId, BlockNumberOrTag, Bytes, H256};
 use reth_rpc_types::{
     trace::geth::{BlockTraceResult, GethDebugTracingOptions, GethTraceFrame, TraceResult},
-    CallRequest, RichBlock,
+    CallRequest, RichBlock, TransactionGasAndReward,
 };
 
 /// Debug rpc interface.
@@ -25,6 +25,16 @@ pub trait DebugApi {
     #[method(name = "debug_getRawReceipts")]
     async fn raw_receipts(&self, block_id: BlockId) -> Result<Vec<Bytes>>;
 
+    /// Returns the gas used and effective priority fee per gas of every transaction of the block,
+    /// sorted by fee in ascending order.
+    ///
+    /// This is the distribution the `eth_feeHistory` reward percentiles are sampled from.
+    #[method(name = "debug_blockRewardDistribution")]
+    async fn block_reward_distribution(
+        &self,
+        block_id: BlockId,
+    ) -> Result<Vec<TransactionGasAndReward>>;
+
     /// Returns an array of recent bad blocks that the client has seen on the network.
     #[method(name = "debug_getBadBlocks")]
     async fn bad_blocks(&self) -> Result<Vec<RichBlock>>;
@@ -1,5 +1,9 @@
 use reth_rpc::{
-    eth::cache::{EthStateCache, EthStateCacheConfig},
+    eth::{
+        cache::{EthStateCache, EthStateCacheConfig},
+        DEFAULT_MAX_FEE_HISTORY_REWARDS, DEFAULT_MAX_FEE_HISTORY_SUBSCRIPTIONS,
+        DEFAULT_MAX_REWARD_PERCENTILES,
+    },
     EthApi, EthFilter, EthPubSub,
 };
 use serde::{Deserialize, Serialize};
@@ -18,17 +22,55 @@ pub struct EthHandlers<Client, Pool, Network, Events> {
 }
 
 /// Additional config values for the eth namespace
+///
+/// The fee history circuit breaker and response cache aren't configurable here, they can only be
+/// enabled on an [EthApi] that's created directly.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EthConfig {
     /// Settings for the caching layer
     pub cache: EthStateCacheConfig,
 
     /// The maximum number of tracing calls that can be executed in concurrently.
     pub max_tracing_requests: usize,
+
+    /// Maximum number of blocks in an `eth_feeHistory` response, if limited.
+    pub max_fee_history_blocks: Option<u64>,
+
+    /// Maximum number of rewards (blocks × percentiles) in an `eth_feeHistory` response, if
+    /// limited.
+    pub max_fee_history_rewards: Option<u64>,
+
+    /// Maximum number of reward percentiles of an `eth_feeHistory` request, if limited.
+    pub max_reward_percentiles: Option<usize>,
+
+    /// Maximum number of blocks the chain may have advanced since a fee history cache entry was
+    /// queried for it to be served, if limited.
+    pub max_fee_history_cache_age: Option<u64>,
+
+    /// Number of most requested reward percentile sets whose rewards are precomputed for cached
+    /// blocks, disabled if `None`.
+    pub popular_reward_percentiles: Option<usize>,
+
+    /// The number of wei in one unit of the chain's fees.
+    pub wei_per_fee_unit: u64,
+
+    /// The maximum number of concurrent fee history subscriptions.
+    pub max_fee_history_subscriptions: usize,
 }
 
 impl Default for EthConfig {
     fn default() -> Self {
-        Self { cache: EthStateCacheConfig::default(), max_tracing_requests: 10 }
+        Self {
+            cache: EthStateCacheConfig::default(),
+            max_tracing_requests: 10,
+            max_fee_history_blocks: None,
+            max_fee_history_rewards: Some(DEFAULT_MAX_FEE_HISTORY_REWARDS),
+            max_reward_percentiles: Some(DEFAULT_MAX_REWARD_PERCENTILES),
+            max_fee_history_cache_age: None,
+            popular_reward_percentiles: None,
+            wei_per_fee_unit: 1,
+            max_fee_history_subscriptions: DEFAULT_MAX_FEE_HISTORY_SUBSCRIPTIONS,
+        }
     }
 }
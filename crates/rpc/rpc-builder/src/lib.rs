@@ -648,12 +648,19 @@ where
                 self.config.eth.cache.clone(),
                 self.executor.clone(),
             );
+            let eth_config = &self.config.eth;
             let api = EthApi::new(
                 self.client.clone(),
                 self.pool.clone(),
                 self.network.clone(),
                 cache.clone(),
-            );
+            )
+            .with_max_fee_history_blocks(eth_config.max_fee_history_blocks)
+            .with_max_fee_history_rewards(eth_config.max_fee_history_rewards)
+            .with_max_reward_percentiles(eth_config.max_reward_percentiles)
+            .with_max_fee_history_cache_age(eth_config.max_fee_history_cache_age)
+            .with_popular_reward_percentiles(eth_config.popular_reward_percentiles)
+            .with_wei_per_fee_unit(eth_config.wei_per_fee_unit);
             let filter = EthFilter::new(self.client.clone(), self.pool.clone());
 
             let pubsub = EthPubSub::new(
@@ -662,7 +669,9 @@ where
                 self.events.clone(),
                 self.network.clone(),
                 cache.clone(),
-            );
+            )
+            .with_fee_unit_of(&api)
+            .with_max_fee_history_subscriptions(eth_config.max_fee_history_subscriptions);
 
             let eth = EthHandlers { api, cache, filter, pubsub };
             self.eth = Some(eth);
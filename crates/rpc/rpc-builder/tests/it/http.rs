@@ -131,6 +131,8 @@ where
     DebugApiClient::raw_block(client, block_id).await.unwrap();
     DebugApiClient::raw_transaction(client, H256::default()).await.unwrap();
     DebugApiClient::raw_receipts(client, block_id).await.unwrap();
+    // the noop provider has no blocks
+    DebugApiClient::block_reward_distribution(client, block_id).await.unwrap_err();
     assert!(is_unimplemented(DebugApiClient::bad_blocks(client).await.err().unwrap()));
 }
 
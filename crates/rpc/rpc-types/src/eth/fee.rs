@@ -1,11 +1,11 @@
 use lru::LruCache;
-use reth_primitives::{BlockNumber, H256, U256};
+use reth_primitives::{BlockNumber, H256, U256, U64};
 use serde::{Deserialize, Serialize};
-use std::{num::NonZeroUsize, sync::Arc};
+use std::{num::NonZeroUsize, ops::RangeInclusive, sync::Arc};
 use tokio::sync::Mutex;
 
 /// Response type for `eth_feeHistory`
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FeeHistory {
     /// An array of block base fees per gas.
@@ -22,11 +22,295 @@ pub struct FeeHistory {
     /// block. All zeroes are returned if the block is empty.
     #[serde(default)]
     pub reward: Option<Vec<Vec<U256>>>,
+    /// An (optional) array of block gas limits. Non-standard, only returned if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_limit: Option<Vec<U64>>,
+    /// An (optional) array of block timestamps. Non-standard, only returned if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<Vec<U64>>,
+    /// An (optional) array of the number of transactions of each block. Non-standard, only
+    /// returned if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_count: Option<Vec<U64>>,
+    /// An (optional) array of the gas-weighted median effective priority fee per gas of each
+    /// block. Non-standard, only returned if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub median_reward: Option<Vec<U256>>,
+    /// Whether the rewards of some blocks are approximate, because their receipts were pruned and
+    /// all of their transactions were weighted equally instead of by gas used. Non-standard, only
+    /// returned if set.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate_reward: bool,
 }
 
+// === impl FeeHistory ===
+
+impl FeeHistory {
+    /// Returns the numbers of the first and last block the response covers, `None` if it covers no
+    /// blocks.
+    ///
+    /// The range may differ from the requested one, e.g. if it was trimmed to the blocks that
+    /// exist. The per block arrays have an entry for every block of the range, `gas_used_ratio[i]`
+    /// is the ratio of block `start + i` in ascending order, or of block `end - i` in descending
+    /// order. `base_fee_per_gas` has an additional entry for the block after the range.
+    pub fn block_range(&self) -> Option<RangeInclusive<u64>> {
+        let block_count = self.gas_used_ratio.len() as u64;
+        if block_count == 0 {
+            return None
+        }
+        let first_block = self.oldest_block.to::<u64>();
+        Some(first_block..=first_block + block_count - 1)
+    }
+}
+
+/// Response type for a fee history query that only reports base fees.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseFeeHistory {
+    /// Lowest number block of the returned range.
+    pub oldest_block: U256,
+    /// An array of block base fees per gas.
+    /// This includes the next block after the newest of the returned range,
+    /// because this value can be derived from the newest block. Zeroes are
+    /// returned for pre-EIP-1559 blocks.
+    pub base_fee_per_gas: Vec<U256>,
+}
+
+/// Response type for a tip threshold query over a fee history window.
+///
+/// Instead of the tips paid at certain percentiles of a block's gas, this reports which share of
+/// a block's gas paid at least certain absolute tips.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeHistoryTipThresholds {
+    /// Lowest number block of the returned range.
+    pub oldest_block: U256,
+    /// For each block, the fraction of the block's gas used by transactions whose effective
+    /// priority fee per gas is at least the requested threshold, one entry per threshold.
+    /// All zeroes are returned if the block is empty.
+    pub gas_used_fraction: Vec<Vec<f64>>,
+    /// Whether the fractions of any block are approximate, because its receipts were pruned and
+    /// all of its transactions were weighted equally instead of by gas used. Non-standard, only
+    /// returned if set.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate: bool,
+}
+
+/// Gas used and effective priority fee per gas of a single transaction, see
+/// `debug_blockRewardDistribution`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionGasAndReward {
+    /// Gas used by the transaction.
+    pub gas_used: U64,
+    /// Effective priority fee per gas paid by the transaction.
+    pub reward: U256,
+}
+
+/// Suggested fees for a transaction to be included within a target number of blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionFeeEstimate {
+    /// Suggested max priority fee per gas.
+    pub max_priority_fee_per_gas: U256,
+    /// Suggested max fee per gas, covering the base fee rising over the target blocks.
+    pub max_fee_per_gas: U256,
+}
+
+/// Suggested fees of an EIP-1559 transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eip1559Fees {
+    /// Base fee per gas of the next block.
+    pub base_fee_per_gas: U256,
+    /// Suggested max priority fee per gas.
+    pub max_priority_fee_per_gas: U256,
+    /// Suggested max fee per gas, at least the base fee per gas plus the max priority fee per gas.
+    pub max_fee_per_gas: U256,
+}
+
+/// Fees of a transaction converted to another token with a caller supplied price of ether.
+///
+/// All values are in the smallest unit of the token.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenFeeEstimate {
+    /// Cost of the gas at the base fee per gas of the next block.
+    pub base_fee: U256,
+    /// Cost of the gas at the suggested max priority fee per gas.
+    pub priority_fee: U256,
+    /// Cost of the gas at both fees, converted as a whole, so it may differ from the sum of the
+    /// rounded parts.
+    pub total_fee: U256,
+}
+
+/// Current fees together with the fee history of the most recent blocks, meant to be fetched in
+/// a single call before sending a transaction.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasPriceAndFeeHistory {
+    /// Base fee per gas of the next block.
+    pub base_fee_per_gas: U256,
+    /// Suggested max priority fee per gas.
+    pub max_priority_fee_per_gas: U256,
+    /// Fee history of the most recent blocks, with the rewards the priority fee is derived from.
+    pub fee_history: FeeHistory,
+}
+
+/// Summary statistics of the fee history of a block range, without the per block values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeHistorySummary {
+    /// Number of blocks the statistics were computed over.
+    pub block_count: u64,
+    /// Lowest base fee per gas of the blocks. Zero for pre-EIP-1559 blocks.
+    pub min_base_fee_per_gas: U256,
+    /// Highest base fee per gas of the blocks.
+    pub max_base_fee_per_gas: U256,
+    /// Mean base fee per gas of the blocks, rounded down.
+    pub mean_base_fee_per_gas: U256,
+    /// Mean gas used ratio of the blocks.
+    pub mean_gas_used_ratio: f64,
+    /// Median of the blocks' rewards at the requested percentile, `None` if no percentile was
+    /// requested.
+    pub median_reward: Option<U256>,
+}
+
+/// Whether the base fee spiked relative to the base fees of the preceding blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSpike {
+    /// Whether the current base fee exceeds the baseline times the requested multiplier.
+    pub is_spike: bool,
+    /// Base fee per gas of the latest block.
+    pub current_base_fee: U256,
+    /// Median base fee per gas of the blocks preceding the latest block.
+    pub baseline_base_fee: U256,
+}
+
+/// A priority fee level for display, e.g. "low", "medium" or "high".
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TipBucket {
+    /// Label of the bucket.
+    pub label: String,
+    /// Percentile of the blocks' gas used the bucket's priority fee is sampled at.
+    pub percentile: f64,
+    /// Representative priority fee per gas of the bucket in gwei.
+    pub gwei: U256,
+    /// Representative priority fee per gas of the bucket in wei, a whole number of gwei.
+    pub max_priority_fee_per_gas: U256,
+}
+
+/// In how many of the most recent blocks a priority fee would have been high enough for inclusion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TipBacktest {
+    /// Number of blocks the tip was tested against.
+    pub block_count: u64,
+    /// Number of blocks whose inclusion threshold the tip meets.
+    pub included_blocks: u64,
+    /// Fraction of the blocks whose inclusion threshold the tip meets, zero without any blocks.
+    pub inclusion_rate: f64,
+}
+
+/// Fee data of a single block, see [FeeHistory] for the fields.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockFees {
+    /// Number of the block.
+    pub number: U64,
+    /// Base fee per gas of the block. Zero for pre-EIP-1559 blocks.
+    pub base_fee_per_gas: U256,
+    /// Gas used ratio of the block.
+    pub gas_used_ratio: f64,
+    /// Effective priority fee per gas at the requested percentiles, only returned if requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reward: Option<Vec<U256>>,
+}
+
+/// How the reward at a percentile of a block's gas used is determined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PercentileInterpolation {
+    /// The reward of the first transaction at which the cumulative gas used reaches the
+    /// percentile, as specified for `eth_feeHistory`.
+    #[default]
+    Step,
+    /// The reward linearly interpolated between the two transactions whose cumulative gas used
+    /// brackets the percentile, for analyses that want a continuous distribution.
+    ///
+    /// Each transaction's reward is placed at the cumulative gas used up to and including it, so
+    /// the result equals [PercentileInterpolation::Step] at these points and is at most that
+    /// in between. Fractions of a wei are rounded down.
+    Linear,
+}
+
+/// What the reward percentiles of a block are weighted by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PercentileWeight {
+    /// The gas used by the transactions, as specified for `eth_feeHistory`.
+    #[default]
+    Gas,
+    /// The number of transactions regardless of their size, for analyses interested in e.g. the
+    /// tip of the median transaction.
+    ///
+    /// The percentiles refer to the transactions sorted by reward, so the 50th percentile is the
+    /// reward of the transaction at half of the transaction count.
+    TransactionCount,
+}
+
+/// How the rewards of a [FeeHistoryCacheItem] were calculated.
+///
+/// Cached rewards are only served to requests with the same mode, see [RewardMode::new].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RewardMode {
+    /// The rewards at the percentiles of the gas used by all transactions, as specified for
+    /// `eth_feeHistory`.
+    #[default]
+    Standard,
+    /// Like [RewardMode::Standard], but excluding transactions without an effective priority fee.
+    ZeroTipsExcluded,
+    /// Rewards with a non-standard interpolation or weight.
+    Custom {
+        /// How the rewards at the percentiles were determined.
+        interpolation: PercentileInterpolation,
+        /// What the percentiles were weighted by.
+        weight: PercentileWeight,
+        /// Whether transactions without an effective priority fee were excluded.
+        exclude_zero_tips: bool,
+    },
+}
+
+// === impl RewardMode ===
+
+impl RewardMode {
+    /// Returns the mode of rewards calculated with the given options.
+    ///
+    /// The step function by gas is always one of the standard modes, so that equal options map to
+    /// equal modes.
+    pub fn new(
+        exclude_zero_tips: bool,
+        interpolation: PercentileInterpolation,
+        weight: PercentileWeight,
+    ) -> Self {
+        match (interpolation, weight, exclude_zero_tips) {
+            (PercentileInterpolation::Step, PercentileWeight::Gas, false) => RewardMode::Standard,
+            (PercentileInterpolation::Step, PercentileWeight::Gas, true) => {
+                RewardMode::ZeroTipsExcluded
+            }
+            (interpolation, weight, exclude_zero_tips) => {
+                RewardMode::Custom { interpolation, weight, exclude_zero_tips }
+            }
+        }
+    }
+}
+
+/// Entries of the [FeeHistoryCache]. Block Number => Fee History.
+pub type FeeHistoryCacheEntries = LruCache<BlockNumber, FeeHistoryCacheItem>;
+
 /// LRU cache for `eth_feeHistory` RPC method. Block Number => Fee History.
 #[derive(Clone, Debug)]
-pub struct FeeHistoryCache(pub Arc<Mutex<LruCache<BlockNumber, FeeHistoryCacheItem>>>);
+pub struct FeeHistoryCache(pub Arc<Mutex<FeeHistoryCacheEntries>>);
 
 impl FeeHistoryCache {
     /// Creates a new LRU Cache that holds at most cap items.
@@ -43,9 +327,42 @@ pub struct FeeHistoryCacheItem {
     pub hash: Option<H256>,
     /// Block base fee per gas. Zero for pre-EIP-1559 blocks.
     pub base_fee_per_gas: U256,
+    /// Base fee per gas of the following block, projected from this block's header.
+    pub next_base_fee_per_gas: U256,
     /// Block gas used ratio. Calculated as the ratio of `gasUsed` and `gasLimit`.
     pub gas_used_ratio: f64,
+    /// Block gas limit.
+    pub gas_limit: u64,
+    /// Block timestamp.
+    pub timestamp: u64,
+    /// Number of transactions of the block, `None` if it wasn't requested along.
+    pub transaction_count: Option<u64>,
+    /// Gas-weighted median effective priority fee per gas of the block, `None` if its
+    /// transactions weren't fetched along.
+    pub median_reward: Option<U256>,
     /// An (optional) array of effective priority fee per gas data points for a
     /// block. All zeroes are returned if the block is empty.
     pub reward: Option<Vec<U256>>,
+    /// The percentiles `reward` was calculated for, `None` if `reward` wasn't calculated.
+    pub reward_percentiles: Option<Vec<f64>>,
+    /// How `reward` was calculated, [RewardMode::Standard] if it wasn't calculated.
+    pub reward_mode: RewardMode,
+    /// Whether `reward` and `median_reward` weight all transactions equally, because the block's
+    /// receipts were pruned.
+    pub approximate_reward: bool,
+    /// Rewards precomputed for the most requested percentiles, other than `reward_percentiles`.
+    pub popular_rewards: Vec<CachedRewards>,
+    /// The best block when the item was queried from the database.
+    pub cached_at: u64,
+}
+
+/// Rewards of a [FeeHistoryCacheItem] for a set of percentiles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedRewards {
+    /// The percentiles the rewards were calculated for.
+    pub percentiles: Vec<f64>,
+    /// How the rewards were calculated.
+    pub reward_mode: RewardMode,
+    /// The effective priority fee per gas at each of the percentiles.
+    pub reward: Vec<U256>,
 }
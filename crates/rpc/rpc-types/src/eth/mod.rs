@@ -19,7 +19,13 @@ mod work;
 pub use account::*;
 pub use block::*;
 pub use call::CallRequest;
-pub use fee::{FeeHistory, FeeHistoryCache, FeeHistoryCacheItem};
+pub use fee::{
+    BaseFeeHistory, BlockFees, CachedRewards, Eip1559Fees, FeeHistory, FeeHistoryCache,
+    FeeHistoryCacheEntries, FeeHistoryCacheItem, FeeHistorySummary, FeeHistoryTipThresholds,
+    FeeSpike, GasPriceAndFeeHistory, InclusionFeeEstimate, PercentileInterpolation,
+    PercentileWeight, RewardMode, TipBacktest, TipBucket, TokenFeeEstimate,
+    TransactionGasAndReward,
+};
 pub use filter::*;
 pub use index::Index;
 pub use log::Log;
@@ -1,11 +1,11 @@
 //! Ethereum types for pub-sub
 
-use crate::{Log, RichHeader};
-use reth_primitives::{filter::Filter, H256};
+use crate::{FeeHistory, Log, RichHeader};
+use reth_primitives::{filter::Filter, H256, U256};
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
 /// Subscription result.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(untagged)]
 pub enum SubscriptionResult {
     /// New block header.
@@ -16,6 +16,10 @@ pub enum SubscriptionResult {
     TransactionHash(H256),
     /// SyncStatus
     SyncState(PubSubSyncStatus),
+    /// Fee history of a single new block
+    FeeHistory(Box<FeeHistory>),
+    /// Base fee crossing a threshold
+    BaseFeeThreshold(BaseFeeThresholdCrossing),
 }
 
 /// Response type for a SyncStatus subscription
@@ -50,6 +54,8 @@ impl Serialize for SubscriptionResult {
             SubscriptionResult::Log(ref log) => log.serialize(serializer),
             SubscriptionResult::TransactionHash(ref hash) => hash.serialize(serializer),
             SubscriptionResult::SyncState(ref sync) => sync.serialize(serializer),
+            SubscriptionResult::FeeHistory(ref fee_history) => fee_history.serialize(serializer),
+            SubscriptionResult::BaseFeeThreshold(ref crossing) => crossing.serialize(serializer),
         }
     }
 }
@@ -87,16 +93,80 @@ pub enum SubscriptionKind {
     /// indicating that the synchronization has started (true), finished (false) or an object with
     /// various progress indicators.
     Syncing,
+    /// Fee history subscription.
+    ///
+    /// Emits the fee history of every new canonical block, as a single block `eth_feeHistory`
+    /// response. Rewards are included for the requested percentiles, if any.
+    FeeHistory,
+    /// Base fee threshold subscription.
+    ///
+    /// Emits whenever the base fee of the new canonical blocks drops below or rises above the
+    /// requested thresholds. A crossing is only reported once the base fee stayed across the
+    /// threshold for a few consecutive blocks, so a base fee hovering around the threshold doesn't
+    /// trigger repeated notifications.
+    BaseFeeThreshold,
 }
 
 /// Subscription kind.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum Params {
     /// No parameters passed.
     #[default]
     None,
     /// Log parameters.
     Logs(Box<Filter>),
+    /// Fee history parameters.
+    FeeHistory(FeeHistoryParams),
+    /// Base fee threshold parameters.
+    BaseFeeThreshold(BaseFeeThresholdParams),
+}
+
+/// Parameters of the fee history subscription.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeHistoryParams {
+    /// Percentiles of the block's gas used to sample the rewards at, like the
+    /// `reward_percentiles` of `eth_feeHistory`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percentiles: Option<Vec<f64>>,
+}
+
+/// Parameters of the base fee threshold subscription, at least one threshold is required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BaseFeeThresholdParams {
+    /// Reports the base fee dropping below this base fee per gas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below: Option<U256>,
+    /// Reports the base fee rising above this base fee per gas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub above: Option<U256>,
+}
+
+/// Direction in which the base fee crossed a threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CrossingDirection {
+    /// The base fee dropped below the threshold.
+    Below,
+    /// The base fee rose above the threshold.
+    Above,
+}
+
+/// Response type of the base fee threshold subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseFeeThresholdCrossing {
+    /// Direction in which the base fee crossed the threshold.
+    pub direction: CrossingDirection,
+    /// The threshold that was crossed.
+    pub threshold: U256,
+    /// Base fee per gas of the block that confirmed the crossing.
+    pub base_fee_per_gas: U256,
+    /// Number of the block that confirmed the crossing.
+    pub block_number: U256,
+    /// Hash of the block that confirmed the crossing.
+    pub block_hash: H256,
 }
 
 impl Serialize for Params {
@@ -107,6 +177,8 @@ impl Serialize for Params {
         match self {
             Params::None => (&[] as &[serde_json::Value]).serialize(serializer),
             Params::Logs(logs) => logs.serialize(serializer),
+            Params::FeeHistory(params) => params.serialize(serializer),
+            Params::BaseFeeThreshold(params) => params.serialize(serializer),
         }
     }
 }
@@ -122,6 +194,20 @@ impl<'a> Deserialize<'a> for Params {
             return Ok(Params::None)
         }
 
+        // log filters don't have a `percentiles` field
+        if v.get("percentiles").is_some() {
+            return serde_json::from_value(v)
+                .map(Params::FeeHistory)
+                .map_err(|e| D::Error::custom(format!("Invalid Pub-Sub parameters: {e}")))
+        }
+
+        // neither have log filters `below` or `above` fields
+        if v.get("below").is_some() || v.get("above").is_some() {
+            return serde_json::from_value(v)
+                .map(Params::BaseFeeThreshold)
+                .map_err(|e| D::Error::custom(format!("Invalid Pub-Sub parameters: {e}")))
+        }
+
         serde_json::from_value(v)
             .map(|f| Params::Logs(Box::new(f)))
             .map_err(|e| D::Error::custom(format!("Invalid Pub-Sub parameters: {e}")))
@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use reth_network_api::test_utils::NoopNetwork;
+use reth_provider::test_utils::{MockFeeProvider, NoopProvider};
+use reth_rpc::{eth::cache::EthStateCache, EthApi};
+use reth_transaction_pool::test_utils::{testing_pool, TestPool};
+
+/// Number of blocks a single query covers.
+const BLOCK_COUNT: u64 = 256;
+/// Number of transactions per block.
+const TRANSACTIONS_PER_BLOCK: u64 = 50;
+
+/// Returns a provider with [BLOCK_COUNT] + 1 blocks full of legacy transactions.
+fn provider() -> MockFeeProvider {
+    let provider = MockFeeProvider::default();
+    let tips = (0..TRANSACTIONS_PER_BLOCK as u128).collect::<Vec<_>>();
+    for number in 0..=BLOCK_COUNT {
+        provider.add_block_with_tips(number, 1_000_000_000, &tips);
+    }
+    provider
+}
+
+fn eth_api(provider: MockFeeProvider) -> EthApi<MockFeeProvider, TestPool, NoopNetwork> {
+    EthApi::new(
+        provider,
+        testing_pool(),
+        NoopNetwork::default(),
+        EthStateCache::spawn(NoopProvider::default(), Default::default()),
+    )
+}
+
+/// Compares the lean base fee history against a full, uncached fee history with rewards.
+pub fn fee_history(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let _guard = rt.enter();
+    let provider = provider();
+
+    let mut group = c.benchmark_group("fee history");
+
+    group.bench_function("base fees only", |b| {
+        let eth_api = eth_api(provider.clone());
+        b.iter(|| eth_api.base_fee_history(BLOCK_COUNT, BLOCK_COUNT.into()).unwrap())
+    });
+
+    group.bench_function("with rewards", |b| {
+        b.iter_batched(
+            || eth_api(provider.clone()),
+            |eth_api| {
+                rt.block_on(eth_api.fee_history(
+                    BLOCK_COUNT,
+                    BLOCK_COUNT.into(),
+                    Some(vec![10.0, 50.0, 90.0]),
+                ))
+                .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, fee_history);
+criterion_main!(benches);
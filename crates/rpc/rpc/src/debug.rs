@@ -1,15 +1,16 @@
 use crate::{
     eth::{
         error::{EthApiError, EthResult},
+        fee_in_wei,
         revm_utils::inspect,
-        EthTransactions, TransactionSource,
+        sorted_tx_gas_and_rewards, EthTransactions, TransactionSource,
     },
     result::{internal_rpc_err, ToRpcResult},
     EthApiSpec, TracingCallGuard,
 };
 use async_trait::async_trait;
 use jsonrpsee::core::RpcResult;
-use reth_primitives::{BlockId, BlockNumberOrTag, Bytes, H256, U256};
+use reth_primitives::{BlockId, BlockNumberOrTag, Bytes, H256, U256, U64};
 use reth_provider::{BlockProvider, HeaderProvider};
 use reth_revm::{
     database::{State, SubState},
@@ -22,7 +23,7 @@ use reth_rpc_types::{
         BlockTraceResult, GethDebugBuiltInTracerType, GethDebugTracerType, GethDebugTracingOptions,
         GethTraceFrame, NoopFrame, TraceResult,
     },
-    CallRequest, RichBlock,
+    CallRequest, RichBlock, TransactionGasAndReward,
 };
 use revm::primitives::Env;
 
@@ -49,6 +50,35 @@ impl<Client, Eth> DebugApi<Client, Eth> {
     }
 }
 
+impl<Client, Eth> DebugApi<Client, Eth>
+where
+    Client: BlockProvider + 'static,
+    Eth: EthApiSpec + 'static,
+{
+    /// Returns the gas used and effective tip of every transaction of the block, sorted by tip in
+    /// ascending order.
+    ///
+    /// The tips are in wei, converted from the chain's fee unit like all fees of the `eth` API.
+    pub fn block_reward_distribution(
+        &self,
+        block_id: BlockId,
+    ) -> EthResult<Vec<TransactionGasAndReward>> {
+        let block = self.client.block(block_id)?.ok_or(EthApiError::UnknownBlockNumber)?;
+        let receipts =
+            self.client.receipts_by_block(block_id)?.ok_or(EthApiError::UnknownBlockNumber)?;
+
+        let sorted = sorted_tx_gas_and_rewards(&block.header, &block.body, &receipts)?;
+        let wei_per_fee_unit = self.eth_api.wei_per_fee_unit();
+        Ok(sorted
+            .into_iter()
+            .map(|tx| TransactionGasAndReward {
+                gas_used: U64::from(tx.gas_used),
+                reward: fee_in_wei(U256::from(tx.reward), wei_per_fee_unit),
+            })
+            .collect())
+    }
+}
+
 // === impl DebugApi ===
 
 impl<Client, Eth> DebugApi<Client, Eth>
@@ -197,6 +227,14 @@ where
         Ok(all_receipts)
     }
 
+    /// Handler for `debug_blockRewardDistribution`
+    async fn block_reward_distribution(
+        &self,
+        block_id: BlockId,
+    ) -> RpcResult<Vec<TransactionGasAndReward>> {
+        Ok(DebugApi::block_reward_distribution(self, block_id)?)
+    }
+
     /// Handler for `debug_getBadBlocks`
     async fn bad_blocks(&self) -> RpcResult<Vec<RichBlock>> {
         Err(internal_rpc_err("unimplemented"))
@@ -263,3 +301,35 @@ impl<Client, Eth> std::fmt::Debug for DebugApi<Client, Eth> {
         f.debug_struct("DebugApi").finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::{cache::EthStateCache, EthApi};
+    use reth_network_api::test_utils::NoopNetwork;
+    use reth_primitives::constants::GWEI_TO_WEI;
+    use reth_provider::test_utils::{MockFeeProvider, NoopProvider};
+    use reth_transaction_pool::test_utils::testing_pool;
+
+    #[tokio::test]
+    async fn block_reward_distribution_in_wei() {
+        let provider = MockFeeProvider::default();
+        provider.add_block_with_tips(0, 10, &[3, 1]);
+        let eth_api = EthApi::new(
+            provider.clone(),
+            testing_pool(),
+            NoopNetwork::default(),
+            EthStateCache::spawn(NoopProvider::default(), Default::default()),
+        )
+        .with_wei_per_fee_unit(GWEI_TO_WEI);
+        let debug_api = DebugApi::new(provider, eth_api, TracingCallGuard::new(1));
+
+        let rewards = debug_api
+            .block_reward_distribution(0u64.into())
+            .unwrap()
+            .into_iter()
+            .map(|tx| tx.reward)
+            .collect::<Vec<_>>();
+        assert_eq!(rewards, [GWEI_TO_WEI, 3 * GWEI_TO_WEI].map(U256::from));
+    }
+}
@@ -0,0 +1,4592 @@
+//! Contains RPC handler implementations for fee history.
+
+use crate::{
+    eth::{
+        circuit_breaker::CircuitBreaker,
+        error::{EthApiError, EthResult, InvalidTransactionError},
+        fee_history_response_cache::FeeHistoryResponseKey,
+        popular_percentiles::PercentileSet,
+        units::{wei_to_gwei, EthPrice, FeeUnit, Rounding},
+    },
+    EthApi,
+};
+use futures::{
+    future::{select, Either},
+    pin_mut,
+};
+use reth_primitives::{
+    basefee::next_base_fee, BlockId, BlockNumberOrTag, Header, Receipt, TransactionSigned, U256,
+    U64,
+};
+use reth_provider::{BlockProvider, EvmEnvProvider, StateProviderFactory};
+use reth_rpc_types::{
+    BaseFeeHistory, BlockFees, CachedRewards, Eip1559Fees, FeeHistory, FeeHistoryCacheEntries,
+    FeeHistoryCacheItem, FeeHistorySummary, FeeHistoryTipThresholds, FeeSpike,
+    GasPriceAndFeeHistory, InclusionFeeEstimate, PercentileInterpolation, PercentileWeight,
+    RewardMode, TipBacktest, TipBucket, TokenFeeEstimate,
+};
+use reth_tasks::TaskSpawner;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::Write,
+    ops::{Range, RangeInclusive},
+    time::{Duration, Instant},
+};
+use tokio::{sync::MutexGuard, task::JoinHandle};
+use tracing::{debug, trace, trace_span, warn};
+
+/// Number of most recent blocks [EthApi::median_reward] and [EthApi::tip_buckets] are calculated
+/// over.
+const MEDIAN_REWARD_BLOCK_COUNT: u64 = 20;
+
+/// Number of most recent blocks included in [EthApi::gas_price_and_fee_history].
+const GAS_PRICE_FEE_HISTORY_BLOCK_COUNT: u64 = 5;
+
+/// Number of most recent blocks [EthApi::estimate_inclusion_fee] samples.
+const INCLUSION_FEE_BLOCK_COUNT: u64 = 20;
+
+/// Percentile of a block's gas used whose reward is taken as the tip that was needed for inclusion
+/// in the block.
+const INCLUSION_PERCENTILE: f64 = 10.0;
+
+/// Precision of the multiplier of [EthApi::detect_fee_spike].
+const FEE_SPIKE_MULTIPLIER_SCALE: u64 = 1_000;
+
+/// Precision of the base fee multiplier of [EthApi::suggest_eip1559_fees].
+const BASE_FEE_MULTIPLIER_SCALE: u64 = 1_000;
+
+/// Precision of the decay of [EthApi::median_reward_ema].
+const REWARD_DECAY_SCALE: u64 = 1_000;
+
+/// The gas each transaction counts as with [PercentileWeight::TransactionCount], large enough
+/// that the thresholds of the percentiles aren't rounded to whole transactions.
+const TX_COUNT_WEIGHT: u64 = 1 << 20;
+
+/// Non-standard options of [EthApi::fee_history_with_options].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FeeHistoryOptions {
+    /// Excludes transactions paying no effective priority fee from the reward percentiles.
+    ///
+    /// This diverges from `eth_feeHistory`, which accounts for all transactions of a block, and
+    /// is meant for analytics that aren't interested in zero tip (e.g. builder) transactions.
+    pub exclude_zero_tips: bool,
+    /// Order of the blocks in the response.
+    pub order: FeeHistoryOrder,
+    /// The value reported at each reward percentile.
+    pub reward: FeeHistoryReward,
+    /// Includes the gas limit of each block in the response, see [FeeHistory::gas_limit].
+    pub include_gas_limit: bool,
+    /// Includes the timestamp of each block in the response, see [FeeHistory::timestamps].
+    pub include_timestamps: bool,
+    /// How the rewards at the reward percentiles are determined.
+    pub interpolation: PercentileInterpolation,
+    /// What the reward percentiles are weighted by.
+    pub weight: PercentileWeight,
+    /// Includes the number of transactions of each block in the response, see
+    /// [FeeHistory::transaction_count].
+    ///
+    /// Without reward percentiles, this costs an additional query for the counts, which doesn't
+    /// fetch the transactions themselves.
+    pub include_transaction_count: bool,
+    /// Includes the gas-weighted median reward of each block in the response, see
+    /// [FeeHistory::median_reward].
+    ///
+    /// Unlike the reward at the 50th percentile, this is the reward of the transaction at half of
+    /// the gas used by all transactions of the block, rather than of the block's gas used. It's
+    /// always the effective priority fee, regardless of [FeeHistoryOptions::reward]. Like the
+    /// rewards, it requires fetching the transactions and receipts of the blocks.
+    pub include_median_reward: bool,
+}
+
+// === impl FeeHistoryOptions ===
+
+impl FeeHistoryOptions {
+    /// Returns the mode the reward percentiles are calculated with.
+    pub fn reward_mode(&self) -> RewardMode {
+        RewardMode::new(self.exclude_zero_tips, self.interpolation, self.weight)
+    }
+}
+
+/// Order of the blocks in a [FeeHistory] response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FeeHistoryOrder {
+    /// Oldest block first, as specified for `eth_feeHistory`.
+    #[default]
+    Ascending,
+    /// Newest block first, e.g. for displaying the most recent fees at the top.
+    ///
+    /// `base_fee_per_gas`, `gas_used_ratio` and the optional per block arrays are reversed
+    /// together, so their entries stay aligned. `base_fee_per_gas` still ends with the base fee
+    /// of the block following the range. `oldest_block` still refers to the oldest block, which is
+    /// now the last entry.
+    Descending,
+}
+
+// === impl FeeHistoryOrder ===
+
+impl FeeHistoryOrder {
+    /// Arranges the blocks of the ascending `fee_history` in this order.
+    fn apply(&self, fee_history: &mut FeeHistory) {
+        if *self == FeeHistoryOrder::Descending {
+            let block_count =
+                fee_history.gas_used_ratio.len().min(fee_history.base_fee_per_gas.len());
+            fee_history.base_fee_per_gas[..block_count].reverse();
+            fee_history.gas_used_ratio.reverse();
+            if let Some(reward) = fee_history.reward.as_mut() {
+                reward.reverse();
+            }
+            if let Some(gas_limit) = fee_history.gas_limit.as_mut() {
+                gas_limit.reverse();
+            }
+            if let Some(timestamps) = fee_history.timestamps.as_mut() {
+                timestamps.reverse();
+            }
+            if let Some(transaction_count) = fee_history.transaction_count.as_mut() {
+                transaction_count.reverse();
+            }
+            if let Some(median_reward) = fee_history.median_reward.as_mut() {
+                median_reward.reverse();
+            }
+        }
+    }
+}
+
+/// The value reported at the reward percentiles of a [FeeHistory] response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FeeHistoryReward {
+    /// The effective priority fee per gas, as specified for `eth_feeHistory`.
+    #[default]
+    PriorityFee,
+    /// The effective gas price, i.e. the effective priority fee plus the base fee of the block.
+    ///
+    /// Some legacy tools expect this from older, non-compliant endpoints. The percentiles still
+    /// refer to the same transactions, since all transactions of a block pay the same base fee.
+    /// Empty blocks report the base fee, which a transaction without a tip would have paid.
+    EffectiveGasPrice,
+}
+
+// === impl FeeHistoryReward ===
+
+impl FeeHistoryReward {
+    /// Converts the priority fee `rewards` of a block with the given base fee to this value.
+    fn apply(&self, mut rewards: Vec<U256>, base_fee_per_gas: U256) -> Vec<U256> {
+        if *self == FeeHistoryReward::EffectiveGasPrice {
+            for reward in rewards.iter_mut() {
+                *reward = reward.saturating_add(base_fee_per_gas);
+            }
+        }
+        rewards
+    }
+}
+
+/// Settings of the task compacting the fee history cache, see
+/// [EthApi::spawn_fee_history_cache_compaction].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeHistoryCompactionConfig {
+    /// How often the cache is compacted.
+    ///
+    /// Default is 60 seconds
+    pub interval: Duration,
+    /// Number of most recent blocks, up to and including the chain tip, whose entries are kept.
+    ///
+    /// Default is 1024
+    pub window: u64,
+}
+
+impl Default for FeeHistoryCompactionConfig {
+    fn default() -> Self {
+        Self { interval: Duration::from_secs(60), window: 1024 }
+    }
+}
+
+/// A priority fee level of [EthApi::tip_buckets].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TipBucketConfig {
+    /// Label of the bucket, e.g. "low".
+    pub label: String,
+    /// Percentile of the blocks' gas used the bucket's priority fee is sampled at.
+    pub percentile: f64,
+}
+
+// === impl TipBucketConfig ===
+
+impl TipBucketConfig {
+    /// Creates a bucket with the given label, sampled at the given percentile.
+    pub fn new(label: impl Into<String>, percentile: f64) -> Self {
+        Self { label: label.into(), percentile }
+    }
+
+    /// Returns the "low", "medium" and "high" buckets, sampled at the 10th, 50th and 90th
+    /// percentile.
+    pub fn defaults() -> Vec<Self> {
+        vec![Self::new("low", 10.0), Self::new("medium", 50.0), Self::new("high", 90.0)]
+    }
+}
+
+/// The slot timing of the consensus layer of a post-Merge chain, see
+/// [EthApi::fee_history_for_epoch].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotSchedule {
+    /// Unix timestamp of slot 0, the genesis of the beacon chain.
+    pub genesis_time: u64,
+    /// Duration of a slot.
+    pub seconds_per_slot: u64,
+    /// Number of slots in an epoch.
+    pub slots_per_epoch: u64,
+}
+
+// === impl SlotSchedule ===
+
+impl SlotSchedule {
+    /// The slot schedule of the Ethereum mainnet beacon chain.
+    pub const MAINNET: Self =
+        Self { genesis_time: 1_606_824_023, seconds_per_slot: 12, slots_per_epoch: 32 };
+
+    /// Returns the timestamp of the given slot, which is the timestamp of its execution block.
+    pub fn slot_timestamp(&self, slot: u64) -> u64 {
+        self.genesis_time.saturating_add(slot.saturating_mul(self.seconds_per_slot))
+    }
+
+    /// Returns the slots of the given epoch.
+    pub fn epoch_slots(&self, epoch: u64) -> Range<u64> {
+        let start = epoch.saturating_mul(self.slots_per_epoch);
+        start..start.saturating_add(self.slots_per_epoch)
+    }
+}
+
+/// A single fee history query of [EthApi::fee_history_batch].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeHistoryRequest {
+    /// Number of blocks in the requested range.
+    pub block_count: u64,
+    /// Highest block of the requested range.
+    pub newest_block: BlockId,
+    /// Percentiles of the gas used to sample the rewards at, if any.
+    pub reward_percentiles: Option<Vec<f64>>,
+    /// Non-standard options of the query.
+    pub options: FeeHistoryOptions,
+}
+
+/// A validated fee history request, resolved to its block range.
+#[derive(Debug)]
+struct FeeHistoryRange {
+    start_block: u64,
+    end_block: u64,
+    /// The requested percentiles, `None` if no rewards are requested
+    reward_percentiles: Option<Vec<f64>>,
+    options: FeeHistoryOptions,
+    /// The best block at the time of the request, only set if the response cache is enabled
+    best_block: Option<u64>,
+}
+
+// === impl FeeHistoryRange ===
+
+impl FeeHistoryRange {
+    fn response_key(&self) -> FeeHistoryResponseKey {
+        FeeHistoryResponseKey::new(
+            self.start_block,
+            self.end_block,
+            &self.reward_percentiles,
+            self.options,
+        )
+    }
+}
+
+impl<Client, Pool, Network> EthApi<Client, Pool, Network>
+where
+    Client: BlockProvider + StateProviderFactory + EvmEnvProvider + 'static,
+{
+    // FeeHistory is calculated based on lazy evaluation of fees for historical blocks, and further
+    // caching of it in the LRU cache.
+    // When new RPC call is executed, the cache gets locked, we check it for the historical fees
+    // according to the requested block range, and fill any cache misses (in both RPC response
+    // and cache itself) with the actual data queried from the database.
+    // To minimize the number of database seeks required to query the missing data, we calculate the
+    // first non-cached block number and last non-cached block number. After that, we query this
+    // range of consecutive blocks from the database.
+    /// Returns the fee history of the given block range, see `eth_feeHistory`.
+    ///
+    /// Like geth, the range covers `block_count` blocks up to and including `newest_block`, and
+    /// `base_fee_per_gas` additionally contains the base fee of the block following it.
+    ///
+    /// Rewards are only calculated if any `reward_percentiles` are requested. Cached rewards are
+    /// only reused if they were calculated for the same percentiles.
+    pub async fn fee_history(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> EthResult<FeeHistory> {
+        self.fee_history_with_options(
+            block_count,
+            newest_block,
+            reward_percentiles,
+            FeeHistoryOptions::default(),
+        )
+        .await
+    }
+
+    /// Same as [EthApi::fee_history], but with non-standard [FeeHistoryOptions].
+    pub async fn fee_history_with_options(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        reward_percentiles: Option<Vec<f64>>,
+        options: FeeHistoryOptions,
+    ) -> EthResult<FeeHistory> {
+        let request = FeeHistoryRequest { block_count, newest_block, reward_percentiles, options };
+        let Some(range) = self.resolve_fee_history_range(request)? else {
+            return Ok(FeeHistory::default())
+        };
+        self.fee_history_of_range(range).await
+    }
+
+    /// Returns the fee history of the blocks of the given consensus layer epoch, see
+    /// [EthApi::fee_history_for_slots].
+    pub async fn fee_history_for_epoch(
+        &self,
+        epoch: u64,
+        schedule: &SlotSchedule,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> EthResult<FeeHistory> {
+        self.fee_history_for_slots(schedule.epoch_slots(epoch), schedule, reward_percentiles).await
+    }
+
+    /// Returns the fee history of the execution blocks proposed in the given consensus layer
+    /// slots.
+    ///
+    /// After the Merge, every execution block belongs to the slot it was proposed in and its
+    /// timestamp is the start of that slot, as given by the `schedule`. So the blocks of the slots
+    /// are the ones with a timestamp within the slots, which are looked up by binary search over
+    /// the headers. Missed slots have no block, so the number of blocks may be smaller than the
+    /// number of slots, and slots that didn't happen yet have no blocks either. Slots without any
+    /// blocks return an empty fee history.
+    ///
+    /// Blocks before the Merge don't belong to any slot, slots before the Merge block are rejected
+    /// with [EthApiError::InvalidBlockRange]. Post-Merge blocks are told apart by their zero
+    /// difficulty.
+    pub async fn fee_history_for_slots(
+        &self,
+        slots: Range<u64>,
+        schedule: &SlotSchedule,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> EthResult<FeeHistory> {
+        let reward_percentiles = reward_percentiles.filter(|percentiles| !percentiles.is_empty());
+        if let Some(percentiles) = &reward_percentiles {
+            self.validate_requested_percentiles(percentiles)?;
+        }
+
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let earliest = self.client().earliest_block_number()?;
+        let start_block =
+            self.first_block_at_or_after(schedule.slot_timestamp(slots.start), earliest, latest)?;
+        let end_block =
+            self.first_block_at_or_after(schedule.slot_timestamp(slots.end), start_block, latest)?;
+        if start_block == end_block {
+            return Ok(FeeHistory::default())
+        }
+
+        let first_header =
+            self.client().header_by_number(start_block)?.ok_or(EthApiError::UnknownBlockNumber)?;
+        if first_header.difficulty != U256::ZERO {
+            return Err(EthApiError::InvalidBlockRange)
+        }
+
+        let range = self.fee_history_range(
+            start_block,
+            end_block - 1,
+            reward_percentiles,
+            FeeHistoryOptions::default(),
+        )?;
+        self.fee_history_of_range(range).await
+    }
+
+    /// Returns the lowest block number within `earliest..=latest` whose block has a timestamp of
+    /// at least `timestamp`, or `latest + 1` if there is none.
+    fn first_block_at_or_after(
+        &self,
+        timestamp: u64,
+        earliest: u64,
+        latest: u64,
+    ) -> EthResult<u64> {
+        let (mut low, mut high) = (earliest, latest + 1);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let header =
+                self.client().header_by_number(mid)?.ok_or(EthApiError::UnknownBlockNumber)?;
+            if header.timestamp < timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Serves the fee history of the resolved range from the caches and the database.
+    async fn fee_history_of_range(&self, range: FeeHistoryRange) -> EthResult<FeeHistory> {
+        // Identical requests are served from the response cache until the chain advances
+        if let Some(response) = self.cached_fee_history_response(&range) {
+            return Ok(self.fee_history_in_wei(response))
+        }
+
+        let Some(mut fee_history_cache) = self.lock_fee_history_cache().await else {
+            return Err(EthApiError::Cancelled)
+        };
+        let fee_history = self.fee_history_for_range(&mut fee_history_cache, range)?;
+        Ok(self.fee_history_in_wei(fee_history))
+    }
+
+    /// Returns the fee histories of all given requests, in the same order.
+    ///
+    /// This is meant for fetching several, possibly overlapping, windows at once: the fee history
+    /// cache is only locked once for the whole batch, and blocks shared by requests with the same
+    /// percentiles and options are only queried from the database once.
+    pub async fn fee_history_batch(
+        &self,
+        requests: Vec<FeeHistoryRequest>,
+    ) -> Vec<EthResult<FeeHistory>> {
+        let mut results = Vec::with_capacity(requests.len());
+        let mut pending = Vec::new();
+        for (index, request) in requests.into_iter().enumerate() {
+            let result = match self.resolve_fee_history_range(request) {
+                Ok(Some(range)) => match self.cached_fee_history_response(&range) {
+                    Some(response) => Ok(response),
+                    None => {
+                        pending.push((index, range));
+                        results.push(None);
+                        continue
+                    }
+                },
+                Ok(None) => Ok(FeeHistory::default()),
+                Err(err) => Err(err),
+            };
+            results.push(Some(result));
+        }
+
+        if !pending.is_empty() {
+            let mut fee_history_cache = self.lock_fee_history_cache().await;
+            for (index, range) in pending {
+                results[index] = Some(match &mut fee_history_cache {
+                    Some(fee_history_cache) => self.fee_history_for_range(fee_history_cache, range),
+                    None => Err(EthApiError::Cancelled),
+                });
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("all requests are answered"))
+            .map(|result| result.map(|fee_history| self.fee_history_in_wei(fee_history)))
+            .collect()
+    }
+
+    /// Converts the base fees and rewards of the response from the chain's fee unit to wei.
+    ///
+    /// Responses are cached in the chain's unit, so this must be applied once, right before
+    /// returning them.
+    fn fee_history_in_wei(&self, fee_history: FeeHistory) -> FeeHistory {
+        fee_history_in_wei(fee_history, self.wei_per_fee_unit)
+    }
+
+    /// Converts a fee from the chain's fee unit to wei.
+    fn fee_in_wei(&self, fee: U256) -> U256 {
+        fee_in_wei(fee, self.wei_per_fee_unit)
+    }
+
+    /// Validates the requested reward percentiles, including their number.
+    fn validate_requested_percentiles(&self, percentiles: &[f64]) -> EthResult<()> {
+        if let Some(max) = self.max_reward_percentiles {
+            if percentiles.len() > max {
+                return Err(EthApiError::TooManyRewardPercentiles {
+                    requested: percentiles.len(),
+                    max,
+                })
+            }
+        }
+        validate_reward_percentiles(percentiles)
+    }
+
+    /// Validates the request and resolves the block range it covers.
+    ///
+    /// Returns `None` if the request covers no blocks.
+    fn resolve_fee_history_range(
+        &self,
+        request: FeeHistoryRequest,
+    ) -> EthResult<Option<FeeHistoryRange>> {
+        let FeeHistoryRequest { block_count, newest_block, reward_percentiles, options } = request;
+        if block_count == 0 {
+            return Ok(None)
+        }
+
+        let reward_percentiles = reward_percentiles.filter(|percentiles| !percentiles.is_empty());
+        if let Some(percentiles) = &reward_percentiles {
+            self.validate_requested_percentiles(percentiles)?;
+        }
+
+        let (start_block, end_block) = self.resolve_block_range(block_count, newest_block)?;
+        self.fee_history_range(start_block, end_block, reward_percentiles, options).map(Some)
+    }
+
+    /// Resolves the first and last block of the range of `block_count` blocks up to and including
+    /// `newest_block`, the way [EthApi::fee_history] does.
+    fn resolve_block_range(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+    ) -> EthResult<(u64, u64)> {
+        let Some(end_block) = self.client().block_number_for_id(newest_block)? else {
+            return Err(EthApiError::UnknownBlockNumber)
+        };
+
+        // The range starts at genesis at the earliest, ranges reaching below it are rejected
+        // rather than truncated
+        if block_count > end_block + 1 {
+            return Err(EthApiError::InvalidBlockRange)
+        }
+
+        Ok((end_block + 1 - block_count, end_block))
+    }
+
+    /// Checks the limits of the range with the already validated percentiles.
+    fn fee_history_range(
+        &self,
+        start_block: u64,
+        end_block: u64,
+        reward_percentiles: Option<Vec<f64>>,
+        options: FeeHistoryOptions,
+    ) -> EthResult<FeeHistoryRange> {
+        self.ensure_not_pruned(start_block)?;
+
+        self.ensure_fee_history_limits(end_block - start_block + 1, reward_percentiles.as_deref())?;
+
+        // The response cache is invalidated by the best block at the time of the request
+        let best_block = match self.fee_history_response_cache {
+            Some(_) => Some(self.client().chain_info()?.best_number),
+            None => None,
+        };
+
+        Ok(FeeHistoryRange { start_block, end_block, reward_percentiles, options, best_block })
+    }
+
+    /// Returns the response for the range from the response cache, if enabled and cached.
+    fn cached_fee_history_response(&self, range: &FeeHistoryRange) -> Option<FeeHistory> {
+        let (Some(cache), Some(best_block)) = (&self.fee_history_response_cache, range.best_block)
+        else {
+            return None
+        };
+        cache.get(&range.response_key(), best_block)
+    }
+
+    /// Returns the oldest best block a fee history cache entry may have been queried at to be
+    /// served, see [EthApi::with_max_fee_history_cache_age].
+    fn min_fee_history_cached_at(&self) -> EthResult<u64> {
+        let Some(max_age) = self.max_fee_history_cache_age else { return Ok(0) };
+        Ok(self.client().chain_info()?.best_number.saturating_sub(max_age))
+    }
+
+    /// Returns `true` if the fee history circuit breaker is enabled and open.
+    fn fee_history_circuit_open(&self) -> bool {
+        self.fee_history_circuit_breaker.as_ref().map_or(false, CircuitBreaker::is_open)
+    }
+
+    /// Records the outcome of a fee history database query with the circuit breaker, if enabled.
+    ///
+    /// Only provider errors count as failures, invalid requests don't.
+    fn record_fee_history_query<T>(&self, queried: &EthResult<T>, elapsed: Duration) {
+        let Some(circuit_breaker) = &self.fee_history_circuit_breaker else { return };
+        match queried {
+            Ok(_) => circuit_breaker.record(true, elapsed),
+            Err(EthApiError::Internal(_)) => circuit_breaker.record_failure(),
+            Err(_) => {}
+        }
+    }
+
+    /// Adds an item queried from the database to the fee history cache.
+    ///
+    /// The rewards of popular percentiles cached for the block before are merged into the item,
+    /// so re-querying a block for other percentiles doesn't discard them.
+    fn cache_fee_history_item(
+        &self,
+        fee_history_cache: &mut FeeHistoryCacheEntries,
+        block: u64,
+        mut item: FeeHistoryCacheItem,
+    ) {
+        if let (Some(popular_percentiles), Some(cached)) =
+            (&self.popular_percentiles, fee_history_cache.peek(&block))
+        {
+            // the cached rewards are only kept if the entry is for the same block
+            if cached.base_fee_per_gas == item.base_fee_per_gas &&
+                cached.gas_used_ratio == item.gas_used_ratio
+            {
+                for rewards in &cached.popular_rewards {
+                    if item.popular_rewards.len() >= popular_percentiles.max_cached_sets() {
+                        break
+                    }
+                    let computed = item.popular_rewards.iter().any(|computed| {
+                        computed.percentiles == rewards.percentiles &&
+                            computed.reward_mode == rewards.reward_mode
+                    });
+                    if !computed {
+                        item.popular_rewards.push(rewards.clone());
+                    }
+                }
+            }
+        }
+        fee_history_cache.push(block, item);
+    }
+
+    /// Locks the fee history cache.
+    ///
+    /// Returns `None` if the node started shutting down while waiting for the lock: waiting for
+    /// concurrent queries to release the cache must not delay a shutdown.
+    async fn lock_fee_history_cache(&self) -> Option<MutexGuard<'_, FeeHistoryCacheEntries>> {
+        match &self.shutdown {
+            Some(shutdown) => {
+                let lock = self.fee_history_cache.0.lock();
+                pin_mut!(lock);
+                match select(lock, shutdown.clone()).await {
+                    Either::Left((fee_history_cache, _)) => Some(fee_history_cache),
+                    Either::Right(_) => None,
+                }
+            }
+            None => Some(self.fee_history_cache.0.lock().await),
+        }
+    }
+
+    /// Returns the fee history of the range, serving cached blocks from the locked fee history
+    /// cache and querying the remaining ones from the database.
+    fn fee_history_for_range(
+        &self,
+        fee_history_cache: &mut FeeHistoryCacheEntries,
+        range: FeeHistoryRange,
+    ) -> EthResult<FeeHistory> {
+        // Only needed if the response cache is enabled
+        let response_key = range.best_block.map(|best_block| (range.response_key(), best_block));
+        let FeeHistoryRange { start_block, end_block, reward_percentiles, options, .. } = range;
+        if let (Some(popular_percentiles), Some(percentiles)) =
+            (&self.popular_percentiles, &reward_percentiles)
+        {
+            popular_percentiles.record(&PercentileSet::new(percentiles, options.exclude_zero_tips));
+        }
+        let min_cached_at = self.min_fee_history_cached_at()?;
+
+        // Sorted map that's populated in two rounds:
+        // 1. Cache entries until first non-cached block
+        // 2. Database query from the first non-cached block
+        let mut fee_history_cache_items = BTreeMap::new();
+
+        let mut first_non_cached_block = None;
+        let mut last_non_cached_block = None;
+        // Items queried from the database, only added to the cache once the response is complete
+        let mut new_cache_items = Vec::new();
+        {
+            let _span = trace_span!(target: "rpc::eth", "fee_history_cache").entered();
+            let started = Instant::now();
+
+            for block in start_block..=end_block {
+                // Check if block exists in cache, and move it to the head of the list if so.
+                // Entries without rewards for the requested percentiles count as cache misses, as
+                // do entries exceeding the maximum age.
+                let served = fee_history_cache.get(&block).and_then(|fee_history_cache_item| {
+                    let served = serve_cache_item(
+                        fee_history_cache_item,
+                        &reward_percentiles,
+                        options,
+                        min_cached_at,
+                    );
+                    if let (Some(popular_percentiles), Some(_)) =
+                        (&self.popular_percentiles, &reward_percentiles)
+                    {
+                        popular_percentiles.record_lookup(served.is_some());
+                    }
+                    served
+                });
+                match served {
+                    Some(fee_history_cache_item) => {
+                        fee_history_cache_items.insert(block, fee_history_cache_item);
+                    }
+                    None => {
+                        // If block doesn't exist in cache, set it as a first non-cached block to
+                        // query it from the database
+                        first_non_cached_block.get_or_insert(block);
+                        // And last non-cached block, so we could query the database until we reach
+                        // it
+                        last_non_cached_block = Some(block);
+                    }
+                }
+            }
+
+            trace!(target: "rpc::eth", cached = fee_history_cache_items.len(), elapsed = ?started.elapsed(), "Collected cached fee history");
+        }
+
+        // If we had any cache misses, query the database starting with the first non-cached block
+        // and ending with the last
+        if let (Some(start_block), Some(end_block)) =
+            (first_non_cached_block, last_non_cached_block)
+        {
+            // While the provider is degraded only fully cached ranges are served
+            if self.fee_history_circuit_open() {
+                return Err(EthApiError::ServiceUnavailable)
+            }
+
+            let _span = trace_span!(target: "rpc::eth", "fee_history_database").entered();
+            let started = Instant::now();
+
+            let queried = self.fee_history_from_database(
+                start_block,
+                end_block,
+                &reward_percentiles,
+                options,
+            );
+            self.record_fee_history_query(&queried, started.elapsed());
+
+            for (block, fee_history_cache_item) in queried? {
+                // Insert missing cache entries in the map for further response composition from it.
+                // Blocks in between the first and last non-cached block may already have been
+                // populated from the cache, in which case the freshly queried database value takes
+                // precedence over the possibly stale cache entry.
+                if let Some(cached) =
+                    fee_history_cache_items.insert(block, fee_history_cache_item.clone())
+                {
+                    if cached.base_fee_per_gas != fee_history_cache_item.base_fee_per_gas ||
+                        cached.gas_used_ratio != fee_history_cache_item.gas_used_ratio
+                    {
+                        debug!(
+                            target: "rpc::eth",
+                            block,
+                            ?cached,
+                            fresh = ?fee_history_cache_item,
+                            "Cached fee history conflicts with database, using database value"
+                        );
+                    }
+                }
+                new_cache_items.push((block, fee_history_cache_item));
+            }
+
+            trace!(target: "rpc::eth", queried = end_block - start_block + 1, elapsed = ?started.elapsed(), "Queried fee history from database");
+        }
+
+        let oldest_block_hash =
+            self.client().block_hash(start_block)?.ok_or(EthApiError::UnknownBlockNumber)?;
+
+        fee_history_cache_items.get_mut(&start_block).ok_or(EthApiError::InvalidBlockRange)?.hash =
+            Some(oldest_block_hash);
+
+        // Nothing can fail past this point, so the cache is populated with either all or none of
+        // the queried entries
+        for (block, fee_history_cache_item) in new_cache_items {
+            self.cache_fee_history_item(fee_history_cache, block, fee_history_cache_item);
+        }
+        if let Some(fee_history_cache_item) = fee_history_cache.get_mut(&start_block) {
+            fee_history_cache_item.hash = Some(oldest_block_hash);
+        }
+
+        // `fee_history_cache_items` now contains full requested block range (populated from both
+        // cache and database), so we can iterate over it in order and populate the response fields
+        let next_base_fee_per_gas =
+            fee_history_cache_items.values().next_back().map(|item| item.next_base_fee_per_gas);
+        let mut fee_history = FeeHistory {
+            base_fee_per_gas: fee_history_cache_items
+                .values()
+                .map(|item| item.base_fee_per_gas)
+                .chain(next_base_fee_per_gas)
+                .collect(),
+            gas_used_ratio: fee_history_cache_items
+                .values()
+                .map(|item| item.gas_used_ratio)
+                .collect(),
+            oldest_block: U256::from(start_block),
+            gas_limit: options.include_gas_limit.then(|| {
+                fee_history_cache_items.values().map(|item| U64::from(item.gas_limit)).collect()
+            }),
+            timestamps: options.include_timestamps.then(|| {
+                fee_history_cache_items.values().map(|item| U64::from(item.timestamp)).collect()
+            }),
+            transaction_count: options.include_transaction_count.then(|| {
+                fee_history_cache_items
+                    .values()
+                    .map(|item| U64::from(item.transaction_count.unwrap_or_default()))
+                    .collect()
+            }),
+            median_reward: options.include_median_reward.then(|| {
+                fee_history_cache_items
+                    .values()
+                    .map(|item| item.median_reward.unwrap_or_default())
+                    .collect()
+            }),
+            approximate_reward: (reward_percentiles.is_some() || options.include_median_reward) &&
+                fee_history_cache_items.values().any(|item| item.approximate_reward),
+            reward: reward_percentiles.map(|_| {
+                fee_history_cache_items
+                    .into_values()
+                    .map(|item| {
+                        options.reward.apply(item.reward.unwrap_or_default(), item.base_fee_per_gas)
+                    })
+                    .collect()
+            }),
+        };
+        options.order.apply(&mut fee_history);
+
+        if let (Some(cache), Some((key, best_block))) =
+            (&self.fee_history_response_cache, response_key)
+        {
+            cache.insert(key, best_block, fee_history.clone());
+        }
+
+        Ok(fee_history)
+    }
+
+    /// Queries the fee history cache items of the given range from the database.
+    fn fee_history_from_database(
+        &self,
+        start_block: u64,
+        end_block: u64,
+        reward_percentiles: &Option<Vec<f64>>,
+        options: FeeHistoryOptions,
+    ) -> EthResult<Vec<(u64, FeeHistoryCacheItem)>> {
+        let mut headers = self.client().headers_range(start_block..=end_block)?;
+        let best_block = self.client().chain_info()?.best_number;
+
+        // We should receive exactly the amount of blocks missing from the cache
+        let expected = (end_block - start_block + 1) as usize;
+        // Requests beyond the chain head never had headers, any other missing header disappeared
+        // after the request was resolved
+        if headers.len() < expected && end_block <= best_block {
+            let missing = missing_header(&headers, start_block, end_block);
+            return Err(EthApiError::HeaderNotFound(missing))
+        }
+        if headers.len() != expected {
+            return Err(EthApiError::InvalidBlockRange)
+        }
+        sort_range_headers(&mut headers, start_block)?;
+
+        self.ensure_not_shutting_down()?;
+
+        // The rewards of the most requested other percentiles are cached along, so requests for
+        // them are served from the cache
+        let popular = match (&self.popular_percentiles, reward_percentiles) {
+            (Some(popular_percentiles), Some(percentiles)) => {
+                let requested = PercentileSet::new(percentiles, options.exclude_zero_tips);
+                let mut popular = popular_percentiles.popular();
+                popular.retain(|set| *set != requested);
+                popular
+            }
+            _ => Vec::new(),
+        };
+
+        // Transaction bodies are solely required for rewards, so requests without
+        // `reward_percentiles` or the median reward never pay for a body fetch.
+        let fetch_transactions = reward_percentiles.is_some() || options.include_median_reward;
+        let mut transactions = if fetch_transactions {
+            // grouped per block, so there's exactly one entry per header
+            let transactions =
+                self.client().transactions_by_block_range(start_block..=end_block)?;
+            if transactions.len() != headers.len() {
+                return Err(EthApiError::InvalidBlockRange)
+            }
+            transactions.into_iter()
+        } else {
+            Vec::new().into_iter()
+        };
+
+        // Rewards fetch the transactions anyway, otherwise only their counts are queried
+        let mut transaction_counts = if !fetch_transactions && options.include_transaction_count {
+            let counts =
+                self.client().transaction_counts_by_block_range(start_block..=end_block)?;
+            if counts.len() != headers.len() {
+                return Err(EthApiError::InvalidBlockRange)
+            }
+            counts.into_iter()
+        } else {
+            Vec::new().into_iter()
+        };
+
+        let mut items = Vec::with_capacity(headers.len());
+        for header in headers {
+            // Rewards require a receipts query per block, so large ranges are aborted in between
+            self.ensure_not_shutting_down()?;
+
+            // the median reward and transaction count come for free once the transactions are
+            // fetched, so they're always cached along
+            let (transaction_count, median_reward, sorted, approximate_reward) =
+                if fetch_transactions {
+                    let transactions = transactions.next().ok_or(EthApiError::InvalidBlockRange)?;
+                    let (sorted, approximate) =
+                        self.sorted_block_rewards(&header, &transactions)?;
+                    let median_reward = gas_weighted_median_reward(&sorted);
+                    (
+                        Some(transactions.len() as u64),
+                        Some(median_reward),
+                        Some(sorted),
+                        approximate,
+                    )
+                } else {
+                    (transaction_counts.next(), None, None, false)
+                };
+
+            let (reward, popular_rewards) = match (reward_percentiles, sorted) {
+                (Some(percentiles), Some(sorted)) => {
+                    let popular_rewards = popular
+                        .iter()
+                        .map(|set| {
+                            let percentiles = set.percentiles();
+                            let reward = rewards_at_percentiles(
+                                &header,
+                                &sorted,
+                                &percentiles,
+                                set.exclude_zero_tips(),
+                                PercentileInterpolation::Step,
+                                PercentileWeight::Gas,
+                            );
+                            CachedRewards {
+                                percentiles,
+                                reward_mode: RewardMode::new(
+                                    set.exclude_zero_tips(),
+                                    PercentileInterpolation::Step,
+                                    PercentileWeight::Gas,
+                                ),
+                                reward,
+                            }
+                        })
+                        .collect();
+                    let reward = rewards_at_percentiles(
+                        &header,
+                        &sorted,
+                        percentiles,
+                        options.exclude_zero_tips,
+                        options.interpolation,
+                        options.weight,
+                    );
+                    (Some(reward), popular_rewards)
+                }
+                _ => (None, Vec::new()),
+            };
+
+            items.push((
+                header.number,
+                FeeHistoryCacheItem {
+                    hash: None,
+                    base_fee_per_gas: base_fee_per_gas(&header),
+                    next_base_fee_per_gas: U256::from(next_base_fee(&header)),
+                    gas_used_ratio: gas_used_ratio(&header),
+                    gas_limit: header.gas_limit,
+                    timestamp: header.timestamp,
+                    transaction_count,
+                    median_reward,
+                    reward,
+                    reward_percentiles: reward_percentiles.clone(),
+                    reward_mode: if reward_percentiles.is_some() {
+                        options.reward_mode()
+                    } else {
+                        RewardMode::default()
+                    },
+                    approximate_reward,
+                    popular_rewards,
+                    cached_at: best_block,
+                },
+            ));
+        }
+
+        Ok(items)
+    }
+
+    /// Returns the fees of each of the given `blocks`, in the order of `blocks`.
+    ///
+    /// Unlike [EthApi::fee_history], the blocks don't have to be contiguous, e.g. to look up the
+    /// fees of the blocks a contract was touched in. Blocks are served from the fee history cache
+    /// where possible, the remaining ones are queried from the database with a single query per
+    /// run of consecutive missing blocks and added to the cache. Rewards are only returned if
+    /// `reward_percentiles` are given.
+    ///
+    /// The number of blocks is limited like the range of [EthApi::fee_history], see
+    /// [EthApi::with_max_fee_history_blocks].
+    pub async fn fee_history_for_blocks(
+        &self,
+        blocks: Vec<u64>,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> EthResult<Vec<BlockFees>> {
+        let reward_percentiles = reward_percentiles.filter(|percentiles| !percentiles.is_empty());
+        if let Some(percentiles) = &reward_percentiles {
+            self.validate_requested_percentiles(percentiles)?;
+        }
+        self.ensure_fee_history_limits(blocks.len() as u64, reward_percentiles.as_deref())?;
+
+        // sorted, so consecutive missing blocks can be queried together
+        let unique = blocks.iter().copied().collect::<BTreeSet<_>>();
+        let Some(oldest_block) = unique.iter().next() else { return Ok(Vec::new()) };
+        self.ensure_not_pruned(*oldest_block)?;
+
+        let options = FeeHistoryOptions::default();
+        let Some(mut fee_history_cache) = self.lock_fee_history_cache().await else {
+            return Err(EthApiError::Cancelled)
+        };
+        let min_cached_at = self.min_fee_history_cached_at()?;
+
+        let mut items = BTreeMap::new();
+        let mut missing = Vec::new();
+        for block in unique {
+            let served = fee_history_cache.get(&block).and_then(|item| {
+                serve_cache_item(item, &reward_percentiles, options, min_cached_at)
+            });
+            match served {
+                Some(item) => {
+                    items.insert(block, item);
+                }
+                None => missing.push(block),
+            }
+        }
+
+        if !missing.is_empty() {
+            // While the provider is degraded only fully cached blocks are served
+            if self.fee_history_circuit_open() {
+                return Err(EthApiError::ServiceUnavailable)
+            }
+
+            let started = Instant::now();
+            let queried = consecutive_runs(&missing)
+                .into_iter()
+                .map(|run| {
+                    self.fee_history_from_database(
+                        *run.start(),
+                        *run.end(),
+                        &reward_percentiles,
+                        options,
+                    )
+                })
+                .collect::<EthResult<Vec<_>>>();
+            self.record_fee_history_query(&queried, started.elapsed());
+
+            // Nothing can fail past this point, so the cache is populated with either all or none
+            // of the queried entries
+            for (block, item) in queried?.into_iter().flatten() {
+                items.insert(block, item.clone());
+                self.cache_fee_history_item(&mut fee_history_cache, block, item);
+            }
+        }
+
+        Ok(blocks
+            .into_iter()
+            .map(|block| {
+                let item = &items[&block];
+                BlockFees {
+                    number: U64::from(block),
+                    base_fee_per_gas: self.fee_in_wei(item.base_fee_per_gas),
+                    gas_used_ratio: item.gas_used_ratio,
+                    reward: reward_percentiles.as_ref().map(|_| {
+                        let reward = item.reward.clone().unwrap_or_default();
+                        reward.into_iter().map(|fee| self.fee_in_wei(fee)).collect()
+                    }),
+                }
+            })
+            .collect())
+    }
+
+    /// Returns the base fees [EthApi::fee_history] returns for the same `block_count` and
+    /// `newest_block`, i.e. those of its blocks followed by the projected base fee of the block
+    /// following `newest_block`.
+    ///
+    /// This is the lean alternative to [EthApi::fee_history] for callers that only need base fees:
+    /// only headers are queried, no transactions are fetched and no rewards are calculated.
+    pub fn base_fee_history(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+    ) -> EthResult<BaseFeeHistory> {
+        if block_count == 0 {
+            return Ok(BaseFeeHistory::default())
+        }
+
+        let (start_block, headers) = self.window_headers(block_count, newest_block)?;
+        let Some(newest_header) = headers.last() else {
+            return Err(EthApiError::InvalidBlockRange)
+        };
+
+        let next_base_fee = U256::from(next_base_fee(newest_header));
+        let base_fee_per_gas = headers
+            .iter()
+            .map(base_fee_per_gas)
+            .chain(std::iter::once(next_base_fee))
+            .map(|base_fee| self.fee_in_wei(base_fee))
+            .collect();
+
+        Ok(BaseFeeHistory { oldest_block: U256::from(start_block), base_fee_per_gas })
+    }
+
+    /// Returns the total base fee burned by the blocks [EthApi::fee_history] covers for the same
+    /// `block_count` and `newest_block`, in wei.
+    ///
+    /// Each block burns its base fee per gas times its gas used, pre-EIP-1559 blocks don't burn
+    /// anything. Like [EthApi::base_fee_history], only headers are queried.
+    pub fn burned_base_fees(&self, block_count: u64, newest_block: BlockId) -> EthResult<U256> {
+        if block_count == 0 {
+            return Ok(U256::ZERO)
+        }
+
+        let (_, headers) = self.window_headers(block_count, newest_block)?;
+        let burned = headers.iter().fold(U256::ZERO, |burned, header| {
+            burned.saturating_add(
+                base_fee_per_gas(header).saturating_mul(U256::from(header.gas_used)),
+            )
+        });
+        Ok(self.fee_in_wei(burned))
+    }
+
+    /// Returns the fraction of the blocks [EthApi::fee_history] covers for the same `block_count`
+    /// and `newest_block` that were full, within `[0, 1]`.
+    ///
+    /// A block counts as full if its gas used is at most `epsilon` gas below its gas limit, so
+    /// blocks that couldn't fit another transaction are full as well. Like
+    /// [EthApi::base_fee_history], only headers are queried.
+    pub fn full_block_fraction(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        epsilon: u64,
+    ) -> EthResult<f64> {
+        if block_count == 0 {
+            return Ok(0.0)
+        }
+
+        let (_, headers) = self.window_headers(block_count, newest_block)?;
+        let full_blocks = headers
+            .iter()
+            .filter(|header| header.gas_limit.saturating_sub(header.gas_used) <= epsilon)
+            .count();
+        Ok(full_blocks as f64 / headers.len() as f64)
+    }
+
+    /// Returns the number of the oldest block and the sorted headers of the range of
+    /// `block_count` blocks up to `newest_block`, see [EthApi::resolve_block_range].
+    fn window_headers(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+    ) -> EthResult<(u64, Vec<Header>)> {
+        let (start_block, end_block) = self.resolve_block_range(block_count, newest_block)?;
+        self.ensure_not_pruned(start_block)?;
+
+        let mut headers = self.client().headers_range(start_block..=end_block)?;
+        if headers.len() as u64 != end_block - start_block + 1 {
+            return Err(EthApiError::InvalidBlockRange)
+        }
+        sort_range_headers(&mut headers, start_block)?;
+        Ok((start_block, headers))
+    }
+
+    /// Returns the median of the 50th percentile rewards of the most recent
+    /// [MEDIAN_REWARD_BLOCK_COUNT] blocks.
+    ///
+    /// This is a single "typical tip" answer built on top of [EthApi::fee_history], so the per
+    /// block rewards are cached by the fee history cache.
+    pub async fn median_reward(&self) -> EthResult<U256> {
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let block_count = MEDIAN_REWARD_BLOCK_COUNT.min(latest + 1);
+
+        let fee_history = self.fee_history(block_count, latest.into(), Some(vec![50.0])).await?;
+        Ok(median_first_reward(&fee_history))
+    }
+
+    /// Returns the base fee of the next block, a suggested priority fee and the fee history of the
+    /// most recent [GAS_PRICE_FEE_HISTORY_BLOCK_COUNT] blocks in a single response.
+    ///
+    /// All values are derived from the same latest block: the base fee is projected from its
+    /// header and the priority fee is the median of the 50th percentile rewards of the returned
+    /// fee history, like [EthApi::median_reward].
+    pub async fn gas_price_and_fee_history(&self) -> EthResult<GasPriceAndFeeHistory> {
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let block_count = GAS_PRICE_FEE_HISTORY_BLOCK_COUNT.min(latest + 1);
+
+        let fee_history = self.fee_history(block_count, latest.into(), Some(vec![50.0])).await?;
+        let latest_header =
+            self.client().header_by_number(latest)?.ok_or(EthApiError::UnknownBlockNumber)?;
+
+        Ok(GasPriceAndFeeHistory {
+            base_fee_per_gas: self.fee_in_wei(U256::from(next_base_fee(&latest_header))),
+            max_priority_fee_per_gas: median_first_reward(&fee_history),
+            fee_history,
+        })
+    }
+
+    /// Suggests the fees of an EIP-1559 transaction, with headroom for the base fee to rise.
+    ///
+    /// The max priority fee is the median of the rewards at `reward_percentile` over the most
+    /// recent [MEDIAN_REWARD_BLOCK_COUNT] blocks, like [EthApi::median_reward]. The max fee is the
+    /// base fee of the next block times `base_fee_multiplier` plus the max priority fee, so it
+    /// always covers the base fee plus the tip. The base fee rises by at most 12.5% per block, so
+    /// e.g. a multiplier of 2 keeps paying the full tip for the next 6 blocks, even if all of them
+    /// are full.
+    ///
+    /// The multiplier must be at least 1 and is applied with a precision of
+    /// [BASE_FEE_MULTIPLIER_SCALE].
+    pub async fn suggest_eip1559_fees(
+        &self,
+        reward_percentile: f64,
+        base_fee_multiplier: f64,
+    ) -> EthResult<Eip1559Fees> {
+        if !(base_fee_multiplier >= 1.0 && base_fee_multiplier.is_finite()) {
+            return Err(EthApiError::InvalidBaseFeeMultiplier)
+        }
+
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let block_count = MEDIAN_REWARD_BLOCK_COUNT.min(latest + 1);
+
+        let fee_history =
+            self.fee_history(block_count, latest.into(), Some(vec![reward_percentile])).await?;
+        let latest_header =
+            self.client().header_by_number(latest)?.ok_or(EthApiError::UnknownBlockNumber)?;
+
+        let base_fee_per_gas = self.fee_in_wei(U256::from(next_base_fee(&latest_header)));
+        let max_priority_fee_per_gas = median_first_reward(&fee_history);
+        let scaled_multiplier =
+            U256::from((base_fee_multiplier * BASE_FEE_MULTIPLIER_SCALE as f64).round() as u64);
+        let max_fee_per_gas = (base_fee_per_gas.saturating_mul(scaled_multiplier) /
+            U256::from(BASE_FEE_MULTIPLIER_SCALE))
+        .saturating_add(max_priority_fee_per_gas);
+
+        Ok(Eip1559Fees { base_fee_per_gas, max_priority_fee_per_gas, max_fee_per_gas })
+    }
+
+    /// Returns the exponential moving average of the median rewards of the blocks of the range,
+    /// one for each block in ascending order.
+    ///
+    /// Each block's average keeps `decay` of the previous one and adds the rest from the block's
+    /// median reward, so older blocks fade out and a single block with outlying tips barely moves
+    /// the average. The average of the first block of the range is its median reward, the one of
+    /// the newest block is the suggested tip. A decay of zero yields the raw median rewards.
+    ///
+    /// The range is resolved like [EthApi::fee_history], so the per block rewards are cached by
+    /// the fee history cache. The decay must be in `[0, 1)` and is applied with a precision of
+    /// [REWARD_DECAY_SCALE].
+    pub async fn median_reward_ema(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        decay: f64,
+    ) -> EthResult<Vec<U256>> {
+        if !(0.0..1.0).contains(&decay) {
+            return Err(EthApiError::InvalidRewardDecay)
+        }
+
+        let fee_history = self.fee_history(block_count, newest_block, Some(vec![50.0])).await?;
+        let kept = U256::from((decay * REWARD_DECAY_SCALE as f64).round() as u64);
+        let added = U256::from(REWARD_DECAY_SCALE) - kept;
+
+        let mut average = None;
+        Ok(fee_history
+            .reward
+            .iter()
+            .flatten()
+            .map(|rewards| {
+                let median = rewards.first().copied().unwrap_or_default();
+                let next = match average {
+                    Some(average) => {
+                        (average * kept + median * added) / U256::from(REWARD_DECAY_SCALE)
+                    }
+                    None => median,
+                };
+                average = Some(next);
+                next
+            })
+            .collect())
+    }
+
+    /// Returns the fees of a transaction using `gas` gas, converted to another token with the
+    /// given `price` of ether.
+    ///
+    /// The fees per gas are the base fee and suggested priority fee of
+    /// [EthApi::gas_price_and_fee_history]. The costs are computed in wei and only converted at
+    /// the end, each with the given rounding.
+    pub async fn fee_estimate_in(
+        &self,
+        gas: u64,
+        price: EthPrice,
+        rounding: Rounding,
+    ) -> EthResult<TokenFeeEstimate> {
+        let GasPriceAndFeeHistory { base_fee_per_gas, max_priority_fee_per_gas, .. } =
+            self.gas_price_and_fee_history().await?;
+        let gas = U256::from(gas);
+        let base_fee = base_fee_per_gas.saturating_mul(gas);
+        let priority_fee = max_priority_fee_per_gas.saturating_mul(gas);
+
+        Ok(TokenFeeEstimate {
+            base_fee: price.from_wei(base_fee, rounding),
+            priority_fee: price.from_wei(priority_fee, rounding),
+            total_fee: price.from_wei(base_fee.saturating_add(priority_fee), rounding),
+        })
+    }
+
+    /// Returns summary statistics of the fee history of the given range instead of the per block
+    /// values, see [FeeHistorySummary].
+    ///
+    /// The range is resolved like [EthApi::fee_history], whose response the statistics are
+    /// derived from, so the per block values are cached by the fee history cache. The median
+    /// reward is only computed if a `reward_percentile` is given.
+    pub async fn fee_history_summary(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        reward_percentile: Option<f64>,
+    ) -> EthResult<FeeHistorySummary> {
+        let fee_history =
+            self.fee_history(block_count, newest_block, reward_percentile.map(|p| vec![p])).await?;
+        Ok(summarize_fee_history(&fee_history))
+    }
+
+    /// Returns the fee history of the given range as CSV, for exporting it to analysis tools.
+    ///
+    /// The first row is a header, followed by one row per block of the range in ascending order,
+    /// with the columns `number`, `base_fee_per_gas`, `gas_used_ratio` and a `reward_<percentile>`
+    /// column for each of the `reward_percentiles`. Fees are in wei.
+    ///
+    /// The range is resolved like [EthApi::fee_history], whose response is serialized.
+    pub async fn fee_history_csv(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> EthResult<String> {
+        let percentiles = reward_percentiles.clone().unwrap_or_default();
+        let fee_history = self.fee_history(block_count, newest_block, reward_percentiles).await?;
+        Ok(fee_history_csv(&fee_history, &percentiles))
+    }
+
+    /// Returns a representative priority fee for each of the `buckets`, rounded up to whole gwei.
+    ///
+    /// The priority fee of a bucket is the median of the rewards at its percentile over the most
+    /// recent [MEDIAN_REWARD_BLOCK_COUNT] blocks, like [EthApi::median_reward]. Rounding up keeps
+    /// it at or above the sampled rewards. The buckets must be ordered by increasing percentile.
+    pub async fn tip_buckets(&self, buckets: &[TipBucketConfig]) -> EthResult<Vec<TipBucket>> {
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let block_count = MEDIAN_REWARD_BLOCK_COUNT.min(latest + 1);
+
+        let percentiles = buckets.iter().map(|bucket| bucket.percentile).collect();
+        let fee_history = self.fee_history(block_count, latest.into(), Some(percentiles)).await?;
+
+        Ok(buckets
+            .iter()
+            .enumerate()
+            .map(|(index, bucket)| {
+                let gwei = wei_to_gwei(median_reward_at(&fee_history, index), Rounding::Up);
+                TipBucket {
+                    label: bucket.label.clone(),
+                    percentile: bucket.percentile,
+                    gwei,
+                    max_priority_fee_per_gas: FeeUnit::Gwei.to_wei(gwei).unwrap_or(U256::MAX),
+                }
+            })
+            .collect())
+    }
+
+    /// Returns the [EthApi::median_reward] converted to the given unit.
+    pub async fn median_reward_in(&self, unit: FeeUnit, rounding: Rounding) -> EthResult<U256> {
+        Ok(unit.from_wei(self.median_reward().await?, rounding))
+    }
+
+    /// Suggests fees for a transaction to be included within `target_blocks` blocks with the given
+    /// `confidence`, which must be within `(0, 1)`.
+    ///
+    /// This is a heuristic built on the reward percentiles of the most recent
+    /// [INCLUSION_FEE_BLOCK_COUNT] blocks, not a guarantee:
+    ///  - The tip needed for inclusion in a block is approximated by the reward at the
+    ///    [INCLUSION_PERCENTILE]th percentile of its gas used, i.e. the tip of the cheapest
+    ///    transactions that still made it into the block.
+    ///  - Upcoming blocks are assumed to look like the recent ones, independently of each other. So
+    ///    a tip that meets the threshold of a fraction `q` of the recent blocks is included within
+    ///    `n` blocks with a probability of `1 - (1 - q)^n`.
+    ///  - The max fee covers the base fee of the next block, projected from the latest header,
+    ///    rising by the maximum of 12.5% per block until the last of the target blocks.
+    pub async fn estimate_inclusion_fee(
+        &self,
+        target_blocks: u64,
+        confidence: f64,
+    ) -> EthResult<InclusionFeeEstimate> {
+        if target_blocks == 0 || !(confidence > 0.0 && confidence < 1.0) {
+            return Err(EthApiError::InvalidInclusionTarget)
+        }
+
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let block_count = INCLUSION_FEE_BLOCK_COUNT.min(latest + 1);
+
+        let fee_history =
+            self.fee_history(block_count, latest.into(), Some(vec![INCLUSION_PERCENTILE])).await?;
+
+        let mut thresholds = fee_history
+            .reward
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|rewards| rewards.first().copied())
+            .collect::<Vec<_>>();
+        let max_priority_fee_per_gas = inclusion_tip(&mut thresholds, target_blocks, confidence);
+
+        let latest_header =
+            self.client().header_by_number(latest)?.ok_or(EthApiError::UnknownBlockNumber)?;
+        let next_base_fee = self.fee_in_wei(U256::from(next_base_fee(&latest_header)));
+        let max_fee_per_gas = max_base_fee_within(next_base_fee, target_blocks)
+            .saturating_add(max_priority_fee_per_gas);
+
+        Ok(InclusionFeeEstimate { max_priority_fee_per_gas, max_fee_per_gas })
+    }
+
+    /// Returns in how many of the most recent `window` blocks a transaction paying a priority fee
+    /// of `tip` wei would have been included.
+    ///
+    /// A block's inclusion threshold is approximated like in [EthApi::estimate_inclusion_fee], by
+    /// its reward at the [INCLUSION_PERCENTILE]th percentile of its gas used. The window is
+    /// shortened if the chain has fewer blocks.
+    pub async fn backtest_tip(&self, tip: U256, window: u64) -> EthResult<TipBacktest> {
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+
+        let fee_history = self
+            .fee_history(window.min(latest + 1), latest.into(), Some(vec![INCLUSION_PERCENTILE]))
+            .await?;
+
+        let thresholds = fee_history
+            .reward
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|rewards| rewards.first().copied())
+            .collect::<Vec<_>>();
+
+        Ok(tip_backtest(&thresholds, tip))
+    }
+
+    /// Returns whether the base fee of the latest block exceeds `multiplier` times the median base
+    /// fee of the `window` blocks preceding it.
+    ///
+    /// The window is shortened if the chain has fewer blocks, on a chain without any preceding
+    /// blocks the baseline is the current base fee. The multiplier must be positive and is applied
+    /// with a precision of [FEE_SPIKE_MULTIPLIER_SCALE].
+    pub fn detect_fee_spike(&self, window: u64, multiplier: f64) -> EthResult<FeeSpike> {
+        if window == 0 || !(multiplier > 0.0 && multiplier.is_finite()) {
+            return Err(EthApiError::InvalidFeeSpikeParams)
+        }
+
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+        let window = window.min(latest);
+
+        // the base fees of the window followed by the latest block's
+        let (_, headers) = self.window_headers(window + 1, latest.into())?;
+        let mut base_fees = headers
+            .iter()
+            .map(|header| self.fee_in_wei(base_fee_per_gas(header)))
+            .collect::<Vec<_>>();
+        let current_base_fee = base_fees.pop().ok_or(EthApiError::InvalidBlockRange)?;
+
+        let baseline_base_fee = if base_fees.is_empty() {
+            current_base_fee
+        } else {
+            base_fees.sort_unstable();
+            base_fees[(base_fees.len() - 1) / 2]
+        };
+
+        let scaled_multiplier =
+            U256::from((multiplier * FEE_SPIKE_MULTIPLIER_SCALE as f64).round() as u64);
+        let threshold = baseline_base_fee.saturating_mul(scaled_multiplier) /
+            U256::from(FEE_SPIKE_MULTIPLIER_SCALE);
+
+        Ok(FeeSpike { is_spike: current_base_fee > threshold, current_base_fee, baseline_base_fee })
+    }
+
+    /// Returns the mean `gas_used_ratio` of the most recent `block_count` blocks, clamped to
+    /// `0.0..=1.0`.
+    ///
+    /// This is built on top of [EthApi::fee_history], so the per block ratios are cached by the
+    /// fee history cache.
+    pub async fn average_gas_used_ratio(&self, block_count: u64) -> EthResult<f64> {
+        let latest = self
+            .client()
+            .block_number_for_id(BlockNumberOrTag::Latest.into())?
+            .ok_or(EthApiError::UnknownBlockNumber)?;
+
+        let fee_history =
+            self.fee_history(block_count.min(latest + 1), latest.into(), None).await?;
+
+        let ratios = &fee_history.gas_used_ratio;
+        if ratios.is_empty() {
+            return Ok(0.0)
+        }
+
+        let average = ratios.iter().sum::<f64>() / ratios.len() as f64;
+        Ok(average.clamp(0.0, 1.0))
+    }
+
+    /// Removes the fee history cache entries of blocks below the earliest block that wasn't pruned,
+    /// returning the number of removed entries.
+    ///
+    /// Pruned blocks can't be served anymore, so their entries only waste memory. This is meant
+    /// to be called whenever the node pruned blocks.
+    pub async fn prune_fee_history_cache(&self) -> EthResult<usize> {
+        let earliest = self.client().earliest_block_number()?;
+        let Some(mut fee_history_cache) = self.lock_fee_history_cache().await else {
+            return Err(EthApiError::Cancelled)
+        };
+
+        let pruned = fee_history_cache
+            .iter()
+            .map(|(block, _)| *block)
+            .filter(|block| *block < earliest)
+            .collect::<Vec<_>>();
+        for block in &pruned {
+            fee_history_cache.pop(block);
+        }
+
+        trace!(target: "rpc::eth", earliest, pruned = pruned.len(), "Pruned fee history cache");
+        Ok(pruned.len())
+    }
+
+    /// Returns the fee history cache entry of the block, `None` if the block isn't cached.
+    ///
+    /// This is meant for inspecting the cache, e.g. while debugging: the block is never fetched
+    /// from the database, and looking it up doesn't affect which entries are evicted next.
+    pub async fn cached_fee_history_item(
+        &self,
+        block_number: u64,
+    ) -> EthResult<Option<FeeHistoryCacheItem>> {
+        let Some(fee_history_cache) = self.lock_fee_history_cache().await else {
+            return Err(EthApiError::Cancelled)
+        };
+        Ok(fee_history_cache.peek(&block_number).cloned())
+    }
+
+    /// Removes the fee history cache entries of blocks outside the `window` most recent blocks,
+    /// returning the number of removed entries.
+    ///
+    /// Queries of scattered old blocks leave isolated entries behind, which take the place of
+    /// recent blocks in the cache. Evicting them keeps the cache focused on a contiguous window up
+    /// to the chain tip, which most requests cover.
+    pub async fn compact_fee_history_cache(&self, window: u64) -> EthResult<usize> {
+        let best_block = self.client().chain_info()?.best_number;
+        let Some(mut fee_history_cache) = self.lock_fee_history_cache().await else {
+            return Err(EthApiError::Cancelled)
+        };
+
+        let evicted = fee_history_cache
+            .iter()
+            .map(|(block, _)| *block)
+            .filter(|block| *block > best_block || best_block - block >= window)
+            .collect::<Vec<_>>();
+        for block in &evicted {
+            fee_history_cache.pop(block);
+        }
+
+        trace!(target: "rpc::eth", best_block, window, evicted = evicted.len(), "Compacted fee history cache");
+        Ok(evicted.len())
+    }
+
+    /// Returns an error if the block range starting at `start_block` reaches below the earliest
+    /// block that wasn't pruned.
+    fn ensure_not_pruned(&self, start_block: u64) -> EthResult<()> {
+        let earliest = self.client().earliest_block_number()?;
+        if start_block < earliest {
+            return Err(EthApiError::BlockPruned { requested: start_block, earliest })
+        }
+        Ok(())
+    }
+
+    /// Returns an error if a fee history query of `block_count` blocks with the given reward
+    /// percentiles exceeds [EthApi::with_max_fee_history_blocks] or
+    /// [EthApi::with_max_fee_history_rewards].
+    fn ensure_fee_history_limits(
+        &self,
+        block_count: u64,
+        reward_percentiles: Option<&[f64]>,
+    ) -> EthResult<()> {
+        if let Some(max) = self.max_fee_history_blocks {
+            if block_count > max {
+                return Err(EthApiError::TooManyFeeHistoryBlocks { requested: block_count, max })
+            }
+        }
+
+        if let (Some(percentiles), Some(max)) = (reward_percentiles, self.max_fee_history_rewards) {
+            let requested = block_count.saturating_mul(percentiles.len() as u64);
+            if requested > max {
+                return Err(EthApiError::TooManyFeeHistoryRewards { requested, max })
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the gas used and reward of the block's transactions, sorted by reward, and whether
+    /// the gas used is approximate.
+    ///
+    /// The gas used by each transaction is only known from the receipts. If they were pruned, the
+    /// fee history is still served instead of failing: the block's gas used is split evenly among
+    /// its transactions, so the percentiles weight all transactions equally, see
+    /// [evenly_weighted_tx_rewards].
+    fn sorted_block_rewards(
+        &self,
+        header: &Header,
+        transactions: &[TransactionSigned],
+    ) -> EthResult<(Vec<TxGasAndReward>, bool)> {
+        match self.client().receipts_by_block(header.number.into())? {
+            Some(receipts) => {
+                Ok((sorted_tx_gas_and_rewards(header, transactions, &receipts)?, false))
+            }
+            None => {
+                debug!(target: "rpc::eth", block = header.number, "Approximating rewards of block without receipts");
+                Ok((evenly_weighted_tx_rewards(header, transactions)?, true))
+            }
+        }
+    }
+
+    /// Returns, for each block of the range [EthApi::fee_history] covers for the same `block_count`
+    /// and `newest_block`, the fraction of the block's gas that was used by transactions paying an
+    /// effective priority fee of at least each of the given `tip_thresholds`.
+    ///
+    /// This is the inverse of the `reward_percentiles` query of `eth_feeHistory`: rather than
+    /// asking which tip was paid at a certain share of the gas, it asks which share of the gas paid
+    /// at least a certain tip. Blocks whose receipts were pruned are approximated like the rewards
+    /// of the fee history, see [EthApi::sorted_block_rewards]. The thresholds are in wei, like the
+    /// rewards of the fee history.
+    pub fn fee_history_tip_thresholds(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        tip_thresholds: &[U256],
+    ) -> EthResult<FeeHistoryTipThresholds> {
+        if block_count == 0 {
+            return Ok(FeeHistoryTipThresholds::default())
+        }
+
+        let (start_block, end_block) = self.resolve_block_range(block_count, newest_block)?;
+        self.ensure_not_pruned(start_block)?;
+        self.ensure_fee_history_limits(end_block - start_block + 1, None)?;
+
+        let mut headers = self.client().headers_range(start_block..=end_block)?;
+        let transactions = self.client().transactions_by_block_range(start_block..=end_block)?;
+
+        // We should receive exactly the amount of blocks requested
+        let expected = (end_block - start_block + 1) as usize;
+        if headers.len() != expected || transactions.len() != expected {
+            return Err(EthApiError::InvalidBlockRange)
+        }
+        sort_range_headers(&mut headers, start_block)?;
+
+        let mut gas_used_fraction = Vec::with_capacity(expected);
+        let mut approximate = false;
+        for (header, transactions) in headers.iter().zip(transactions) {
+            let (sorted, approximate_block) = self.sorted_block_rewards(header, &transactions)?;
+            approximate |= approximate_block;
+            gas_used_fraction.push(gas_used_fraction_above(
+                &sorted,
+                tip_thresholds,
+                self.wei_per_fee_unit,
+            ));
+        }
+
+        Ok(FeeHistoryTipThresholds {
+            oldest_block: U256::from(start_block),
+            gas_used_fraction,
+            approximate,
+        })
+    }
+}
+
+impl<Client, Pool, Network> EthApi<Client, Pool, Network>
+where
+    Client: BlockProvider + StateProviderFactory + EvmEnvProvider + Clone + 'static,
+    Pool: Clone + Send + Sync + 'static,
+    Network: Clone + Send + Sync + 'static,
+{
+    /// Spawns a task that periodically compacts the fee history cache, see
+    /// [EthApi::compact_fee_history_cache].
+    ///
+    /// The task runs until the shutdown signal fires, see [EthApi::with_shutdown_signal], or the
+    /// returned handle is aborted.
+    pub fn spawn_fee_history_cache_compaction(
+        &self,
+        config: FeeHistoryCompactionConfig,
+        executor: impl TaskSpawner,
+    ) -> JoinHandle<()> {
+        let this = self.clone();
+        let shutdown = self.shutdown.clone();
+        let compaction = async move {
+            let mut interval = tokio::time::interval(config.interval);
+            loop {
+                interval.tick().await;
+                match this.compact_fee_history_cache(config.window).await {
+                    Ok(_) => {}
+                    Err(EthApiError::Cancelled) => return,
+                    Err(err) => {
+                        warn!(target: "rpc::eth", ?err, "Failed to compact fee history cache")
+                    }
+                }
+            }
+        };
+
+        executor.spawn(Box::pin(async move {
+            match shutdown {
+                Some(shutdown) => {
+                    pin_mut!(compaction);
+                    select(compaction, shutdown).await;
+                }
+                None => compaction.await,
+            }
+        }))
+    }
+}
+
+/// Returns the fee history of a single block, with the rewards at the given percentiles of its gas
+/// used.
+///
+/// This is the item emitted by the `feeHistory` subscription for every new block. The percentiles
+/// must have been validated already.
+pub(crate) fn single_block_fee_history(
+    header: &Header,
+    transactions: &[TransactionSigned],
+    receipts: &[Receipt],
+    reward_percentiles: Option<&[f64]>,
+) -> EthResult<FeeHistory> {
+    let reward = match reward_percentiles {
+        Some(percentiles) => {
+            let sorted = sorted_tx_gas_and_rewards(header, transactions, receipts)?;
+            Some(vec![compute_reward_percentiles(&sorted, header.gas_used, percentiles)])
+        }
+        None => None,
+    };
+
+    Ok(FeeHistory {
+        base_fee_per_gas: vec![base_fee_per_gas(header), U256::from(next_base_fee(header))],
+        gas_used_ratio: vec![gas_used_ratio(header)],
+        oldest_block: U256::from(header.number),
+        reward,
+        gas_limit: None,
+        timestamps: None,
+        transaction_count: None,
+        median_reward: None,
+        approximate_reward: false,
+    })
+}
+
+/// Converts the base fees and rewards of the fee history from the chain's fee unit to wei, given
+/// the number of wei in one unit.
+pub(crate) fn fee_history_in_wei(
+    mut fee_history: FeeHistory,
+    wei_per_fee_unit: U256,
+) -> FeeHistory {
+    if wei_per_fee_unit == U256::from(1u64) {
+        return fee_history
+    }
+
+    let rewards = fee_history.reward.iter_mut().flatten().flatten();
+    let median_rewards = fee_history.median_reward.iter_mut().flatten();
+    for fee in fee_history.base_fee_per_gas.iter_mut().chain(rewards).chain(median_rewards) {
+        *fee = fee_in_wei(*fee, wei_per_fee_unit);
+    }
+    fee_history
+}
+
+/// Converts a fee from the chain's fee unit to wei, given the number of wei in one unit.
+pub(crate) fn fee_in_wei(fee: U256, wei_per_fee_unit: U256) -> U256 {
+    fee.saturating_mul(wei_per_fee_unit)
+}
+
+/// Splits the sorted, deduplicated `blocks` into runs of consecutive blocks.
+fn consecutive_runs(blocks: &[u64]) -> Vec<RangeInclusive<u64>> {
+    let mut runs: Vec<RangeInclusive<u64>> = Vec::new();
+    for &block in blocks {
+        match runs.last_mut() {
+            Some(run) if *run.end() + 1 == block => *run = *run.start()..=block,
+            _ => runs.push(block..=block),
+        }
+    }
+    runs
+}
+
+/// Sorts the headers of a range query by number and ensures they are exactly the consecutive
+/// blocks starting at `start_block`.
+///
+/// Transactions and receipts are matched with the headers by position, so headers returned out of
+/// order or with gaps would otherwise silently misalign them.
+fn sort_range_headers(headers: &mut [Header], start_block: u64) -> EthResult<()> {
+    headers.sort_unstable_by_key(|header| header.number);
+    for (expected, header) in (start_block..).zip(headers.iter()) {
+        if header.number != expected {
+            return Err(EthApiError::ProviderRangeMismatch { expected, actual: header.number })
+        }
+    }
+    Ok(())
+}
+
+/// Returns the newest block of the range whose header is missing from `headers`.
+///
+/// Must only be called if fewer headers than blocks in the range were returned.
+fn missing_header(headers: &[Header], start_block: u64, end_block: u64) -> u64 {
+    let present = headers.iter().map(|header| header.number).collect::<HashSet<_>>();
+    (start_block..=end_block).rev().find(|number| !present.contains(number)).unwrap_or(end_block)
+}
+
+/// Returns the median of the rewards at the first requested percentile, zero if there are none.
+fn median_first_reward(fee_history: &FeeHistory) -> U256 {
+    median_reward_at(fee_history, 0)
+}
+
+/// Returns the median of the rewards at the requested percentile with the given index, zero if
+/// there are none.
+fn median_reward_at(fee_history: &FeeHistory, index: usize) -> U256 {
+    let mut rewards = fee_history
+        .reward
+        .iter()
+        .flatten()
+        .filter_map(|rewards| rewards.get(index).copied())
+        .collect::<Vec<_>>();
+    if rewards.is_empty() {
+        return U256::ZERO
+    }
+
+    rewards.sort_unstable();
+    rewards[(rewards.len() - 1) / 2]
+}
+
+/// Aggregates the fee history into its summary statistics.
+fn summarize_fee_history(fee_history: &FeeHistory) -> FeeHistorySummary {
+    let block_count = fee_history.gas_used_ratio.len();
+    if block_count == 0 {
+        return FeeHistorySummary {
+            median_reward: fee_history.reward.as_ref().map(|_| U256::ZERO),
+            ..Default::default()
+        }
+    }
+
+    // ignores the base fee of the next block, if included
+    let base_fees = fee_history.base_fee_per_gas.iter().take(block_count).copied();
+    let (min_base_fee, max_base_fee, sum_base_fees) = base_fees
+        .fold((U256::MAX, U256::ZERO, U256::ZERO), |(min, max, sum), fee| {
+            (min.min(fee), max.max(fee), sum.saturating_add(fee))
+        });
+
+    FeeHistorySummary {
+        block_count: block_count as u64,
+        min_base_fee_per_gas: min_base_fee,
+        max_base_fee_per_gas: max_base_fee,
+        mean_base_fee_per_gas: sum_base_fees / U256::from(block_count),
+        mean_gas_used_ratio: fee_history.gas_used_ratio.iter().sum::<f64>() / block_count as f64,
+        median_reward: fee_history.reward.as_ref().map(|_| median_first_reward(fee_history)),
+    }
+}
+
+/// Serializes the fee history as CSV, with a reward column for each of the `percentiles`.
+fn fee_history_csv(fee_history: &FeeHistory, percentiles: &[f64]) -> String {
+    let mut csv = String::from("number,base_fee_per_gas,gas_used_ratio");
+    for percentile in percentiles {
+        let _ = write!(csv, ",reward_{percentile}");
+    }
+    csv.push('\n');
+
+    let oldest_block = fee_history.oldest_block.to::<u64>();
+    for (index, gas_used_ratio) in fee_history.gas_used_ratio.iter().enumerate() {
+        let base_fee = fee_history.base_fee_per_gas.get(index).copied().unwrap_or_default();
+        let _ = write!(csv, "{},{base_fee},{gas_used_ratio}", oldest_block + index as u64);
+        let rewards = fee_history.reward.as_ref().and_then(|rewards| rewards.get(index));
+        for reward in rewards.into_iter().flatten() {
+            let _ = write!(csv, ",{reward}");
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Returns the base fee per gas of the header, zero for pre-EIP-1559 blocks.
+fn base_fee_per_gas(header: &Header) -> U256 {
+    U256::from(header.base_fee_per_gas.unwrap_or_default())
+}
+
+/// Returns the ratio of the header's gas used to its gas limit, in `0.0..=1.0`.
+///
+/// A header with a zero gas limit would yield `NaN` or infinity, which can't be serialized in the
+/// response, so `0.0` is returned instead. A valid block never uses more gas than its limit, so a
+/// header claiming otherwise is corrupt, its ratio is capped at `1.0` to not break clients
+/// charting the ratios.
+fn gas_used_ratio(header: &Header) -> f64 {
+    if header.gas_limit == 0 {
+        warn!(target: "rpc::eth", block = header.number, gas_used = header.gas_used, "Header with zero gas limit in fee history");
+        return 0.0
+    }
+    if header.gas_used > header.gas_limit {
+        warn!(target: "rpc::eth", block = header.number, gas_used = header.gas_used, gas_limit = header.gas_limit, "Header with gas used above the gas limit in fee history");
+        return 1.0
+    }
+    let ratio = header.gas_used as f64 / header.gas_limit as f64;
+    debug_assert!((0.0..=1.0).contains(&ratio), "gas used ratio {ratio} out of bounds");
+    ratio
+}
+
+/// Gas used and effective priority fee per gas of a single transaction in a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TxGasAndReward {
+    /// Gas used by the transaction.
+    pub(crate) gas_used: u64,
+    /// Effective priority fee per gas paid by the transaction.
+    pub(crate) reward: u128,
+}
+
+/// Returns the gas used and the effective tip of every transaction of the block, sorted by tip in
+/// ascending order.
+///
+/// The gas used by each transaction is derived from the cumulative gas used of its receipt.
+/// Transactions that didn't use any gas are not included.
+pub(crate) fn sorted_tx_gas_and_rewards(
+    header: &Header,
+    transactions: &[TransactionSigned],
+    receipts: &[Receipt],
+) -> EthResult<Vec<TxGasAndReward>> {
+    if transactions.len() != receipts.len() {
+        return Err(EthApiError::InvalidBlockRange)
+    }
+
+    let mut previous_cumulative_gas_used = 0;
+    let mut sorted = Vec::with_capacity(transactions.len());
+    for (transaction, receipt) in transactions.iter().zip(receipts) {
+        let gas_used = receipt.cumulative_gas_used.saturating_sub(previous_cumulative_gas_used);
+        previous_cumulative_gas_used = receipt.cumulative_gas_used;
+
+        // transactions without gas used, e.g. system transactions of some chains, have no weight
+        // in the percentiles, so they are skipped instead of taking the reward of a percentile
+        if gas_used == 0 {
+            debug!(target: "rpc::eth", block = header.number, tx = ?transaction.hash, "Skipping transaction without gas used");
+            continue
+        }
+
+        let reward = transaction
+            .effective_gas_tip(header.base_fee_per_gas)
+            .ok_or(InvalidTransactionError::FeeCapTooLow)?;
+
+        sorted.push(TxGasAndReward { gas_used, reward });
+    }
+
+    sorted.sort_by_key(|tx| tx.reward);
+    Ok(sorted)
+}
+
+/// Returns the effective tip of every transaction of the block, sorted by tip in ascending order,
+/// with the block's gas used split evenly among them.
+///
+/// This is the fallback of [sorted_tx_gas_and_rewards] for blocks whose receipts were pruned:
+/// without them the gas used by each transaction is unknown, so every transaction is weighted
+/// equally and the percentiles of the block's gas used become percentiles of its transaction count.
+/// The remainder of the split goes to the cheapest transactions.
+fn evenly_weighted_tx_rewards(
+    header: &Header,
+    transactions: &[TransactionSigned],
+) -> EthResult<Vec<TxGasAndReward>> {
+    let mut rewards = transactions
+        .iter()
+        .map(|transaction| {
+            transaction
+                .effective_gas_tip(header.base_fee_per_gas)
+                .ok_or(InvalidTransactionError::FeeCapTooLow)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    rewards.sort_unstable();
+
+    let count = rewards.len() as u64;
+    let (share, remainder) = match count {
+        0 => (0, 0),
+        count => (header.gas_used / count, header.gas_used % count),
+    };
+    Ok((0u64..)
+        .zip(rewards)
+        .map(|(index, reward)| TxGasAndReward {
+            // every transaction uses some gas, even if the header claims otherwise
+            gas_used: (share + u64::from(index < remainder)).max(1),
+            reward,
+        })
+        .collect())
+}
+
+/// Returns the rewards at the given percentiles of the block's gas used.
+///
+/// This is the reward percentile algorithm of `eth_feeHistory`, independent of how the block was
+/// fetched: for every percentile, the reward of the first transaction at which the cumulative gas
+/// used reaches that share of `block_gas_used`.
+///
+/// `sorted` must be sorted by reward in ascending order and `percentiles` must be validated, see
+/// [validate_reward_percentiles]. All zeroes are returned if `sorted` is empty.
+pub(crate) fn compute_reward_percentiles(
+    sorted: &[TxGasAndReward],
+    block_gas_used: u64,
+    percentiles: &[f64],
+) -> Vec<U256> {
+    let Some(first) = sorted.first() else { return vec![U256::ZERO; percentiles.len()] };
+
+    // the block's gas used can exceed the gas used by its transactions, so the threshold of a
+    // high percentile may never be reached and the index must stop at the last transaction
+    let last_index = sorted.len() - 1;
+    let mut tx_index = 0;
+    let mut cumulative_gas_used = first.gas_used;
+    percentiles
+        .iter()
+        .map(|percentile| {
+            // the 0th percentile has a zero threshold, which the gas used by the first
+            // transaction always reaches, so it's the reward of the cheapest transaction
+            let threshold = (block_gas_used as f64 * percentile / 100.0) as u64;
+            while cumulative_gas_used < threshold && tx_index < last_index {
+                tx_index += 1;
+                cumulative_gas_used = cumulative_gas_used.saturating_add(sorted[tx_index].gas_used);
+            }
+            // rewards are `u128`, so they always fit into the `U256` of the response
+            U256::from(sorted[tx_index.min(last_index)].reward)
+        })
+        .collect()
+}
+
+/// Returns the rewards at the given percentiles of the block's gas used, linearly interpolated
+/// between adjacent transactions, see [PercentileInterpolation::Linear].
+///
+/// Has the same requirements as [compute_reward_percentiles].
+fn compute_interpolated_reward_percentiles(
+    sorted: &[TxGasAndReward],
+    block_gas_used: u64,
+    percentiles: &[f64],
+) -> Vec<U256> {
+    if sorted.is_empty() {
+        return vec![U256::ZERO; percentiles.len()]
+    }
+
+    // the cumulative gas used up to and including each transaction
+    let cumulative = sorted
+        .iter()
+        .scan(0u64, |sum, tx| {
+            *sum = sum.saturating_add(tx.gas_used);
+            Some(*sum)
+        })
+        .collect::<Vec<_>>();
+
+    percentiles
+        .iter()
+        .map(|percentile| {
+            let threshold = (block_gas_used as f64 * percentile / 100.0) as u64;
+            // the first transaction at which the cumulative gas used reaches the threshold, as
+            // with the step function, or the last one if it's never reached
+            let index = cumulative.partition_point(|gas| *gas < threshold).min(sorted.len() - 1);
+            if index == 0 || cumulative[index] <= threshold {
+                return U256::from(sorted[index].reward)
+            }
+
+            let (lower, upper) = (&sorted[index - 1], &sorted[index]);
+            let span = cumulative[index] - cumulative[index - 1];
+            let offset = threshold - cumulative[index - 1];
+            // rewards are sorted, so the difference can't underflow
+            let increase =
+                U256::from(upper.reward - lower.reward) * U256::from(offset) / U256::from(span);
+            U256::from(lower.reward) + increase
+        })
+        .collect()
+}
+
+/// Returns the lowest of the blocks' inclusion `thresholds` that is met in enough blocks for a
+/// transaction to be included within `target_blocks` blocks with the given `confidence`, see
+/// [EthApi::estimate_inclusion_fee].
+fn inclusion_tip(thresholds: &mut [U256], target_blocks: u64, confidence: f64) -> U256 {
+    if thresholds.is_empty() {
+        return U256::ZERO
+    }
+
+    // the probability of inclusion per block that's needed to reach the confidence within the
+    // target blocks
+    let per_block = 1.0 - (1.0 - confidence).powf(1.0 / target_blocks as f64);
+    let required_blocks = (per_block * thresholds.len() as f64).ceil() as usize;
+
+    thresholds.sort_unstable();
+    thresholds[required_blocks.clamp(1, thresholds.len()) - 1]
+}
+
+/// Returns how many of the blocks' inclusion `thresholds` the `tip` meets, see
+/// [EthApi::backtest_tip].
+fn tip_backtest(thresholds: &[U256], tip: U256) -> TipBacktest {
+    let block_count = thresholds.len() as u64;
+    let included_blocks = thresholds.iter().filter(|threshold| tip >= **threshold).count() as u64;
+    let inclusion_rate =
+        if block_count == 0 { 0.0 } else { included_blocks as f64 / block_count as f64 };
+    TipBacktest { block_count, included_blocks, inclusion_rate }
+}
+
+/// Returns the base fee of the last of the `target_blocks` blocks starting with the block with
+/// `next_base_fee`, assuming it increases by the maximum of 12.5% per block.
+fn max_base_fee_within(next_base_fee: U256, target_blocks: u64) -> U256 {
+    let mut base_fee = next_base_fee;
+    for _ in 1..target_blocks {
+        let increased = base_fee.saturating_add(base_fee / U256::from(8));
+        if increased == base_fee {
+            // saturated, or too small to increase
+            break
+        }
+        base_fee = increased;
+    }
+    base_fee
+}
+
+/// Computes the rewards at the given percentiles with the given interpolation, see
+/// [compute_reward_percentiles] for the requirements.
+fn compute_reward_percentiles_with(
+    interpolation: PercentileInterpolation,
+    sorted: &[TxGasAndReward],
+    block_gas_used: u64,
+    percentiles: &[f64],
+) -> Vec<U256> {
+    match interpolation {
+        PercentileInterpolation::Step => {
+            compute_reward_percentiles(sorted, block_gas_used, percentiles)
+        }
+        PercentileInterpolation::Linear => {
+            compute_interpolated_reward_percentiles(sorted, block_gas_used, percentiles)
+        }
+    }
+}
+
+/// Returns the rewards of the block at the given percentiles of its gas used.
+///
+/// If `exclude_zero_tips` is set, the percentiles refer to the gas used by the transactions paying
+/// an effective priority fee only. With [PercentileWeight::TransactionCount], every transaction
+/// counts as the same amount of gas instead.
+fn rewards_at_percentiles(
+    header: &Header,
+    sorted: &[TxGasAndReward],
+    percentiles: &[f64],
+    exclude_zero_tips: bool,
+    interpolation: PercentileInterpolation,
+    weight: PercentileWeight,
+) -> Vec<U256> {
+    let paying: Vec<_>;
+    let (sorted, block_gas_used) = if exclude_zero_tips {
+        paying = sorted.iter().filter(|tx| tx.reward > 0).copied().collect();
+        (&paying[..], sum_gas_used(&paying))
+    } else {
+        (sorted, header.gas_used)
+    };
+
+    match weight {
+        PercentileWeight::Gas => {
+            compute_reward_percentiles_with(interpolation, sorted, block_gas_used, percentiles)
+        }
+        PercentileWeight::TransactionCount => {
+            let counted: Vec<_> = sorted
+                .iter()
+                .map(|tx| TxGasAndReward { gas_used: TX_COUNT_WEIGHT, ..*tx })
+                .collect();
+            let count_gas_used = TX_COUNT_WEIGHT.saturating_mul(counted.len() as u64);
+            compute_reward_percentiles_with(interpolation, &counted, count_gas_used, percentiles)
+        }
+    }
+}
+
+/// Returns the cache item with the rewards for the requested percentiles, `None` if they weren't
+/// cached for the block or the item was queried before the `min_cached_at` best block.
+fn serve_cache_item(
+    item: &FeeHistoryCacheItem,
+    reward_percentiles: &Option<Vec<f64>>,
+    options: FeeHistoryOptions,
+    min_cached_at: u64,
+) -> Option<FeeHistoryCacheItem> {
+    if item.cached_at < min_cached_at {
+        return None
+    }
+    // the count and the median reward are only cached if they were requested along
+    if options.include_transaction_count && item.transaction_count.is_none() ||
+        options.include_median_reward && item.median_reward.is_none()
+    {
+        return None
+    }
+    let Some(percentiles) = reward_percentiles else { return Some(item.clone()) };
+    let reward_mode = options.reward_mode();
+    if item.reward_percentiles.as_ref() == Some(percentiles) && item.reward_mode == reward_mode {
+        return Some(item.clone())
+    }
+
+    match reward_mode {
+        RewardMode::Standard | RewardMode::ZeroTipsExcluded => {}
+        // the rewards of the popular percentiles are computed with the step function by gas only
+        RewardMode::Custom { .. } => return None,
+    }
+    let cached = item
+        .popular_rewards
+        .iter()
+        .find(|cached| cached.percentiles == *percentiles && cached.reward_mode == reward_mode)?;
+    Some(FeeHistoryCacheItem {
+        reward: Some(cached.reward.clone()),
+        reward_percentiles: Some(percentiles.clone()),
+        reward_mode,
+        ..item.clone()
+    })
+}
+
+/// Returns the reward of the transaction at which the cumulative gas used reaches half of the gas
+/// used by all `sorted` transactions, zero if there are none.
+///
+/// `sorted` must be sorted by reward in ascending order. Unlike [compute_reward_percentiles], this
+/// is exact: it's weighted by the gas used of the transactions only and compared without rounding.
+fn gas_weighted_median_reward(sorted: &[TxGasAndReward]) -> U256 {
+    let total = sorted.iter().map(|tx| tx.gas_used as u128).sum::<u128>();
+    let mut cumulative_gas_used = 0u128;
+    for tx in sorted {
+        cumulative_gas_used += tx.gas_used as u128;
+        if 2 * cumulative_gas_used >= total {
+            return U256::from(tx.reward)
+        }
+    }
+    U256::ZERO
+}
+
+/// Sums up the gas used by the given transactions, saturating at `u64::MAX`.
+fn sum_gas_used(transactions: &[TxGasAndReward]) -> u64 {
+    transactions.iter().fold(0u64, |sum, tx| sum.saturating_add(tx.gas_used))
+}
+
+/// Ensures all percentiles are within `[0, 100]` and monotonically increasing.
+pub(crate) fn validate_reward_percentiles(percentiles: &[f64]) -> EthResult<()> {
+    let mut previous = 0.0;
+    for &percentile in percentiles {
+        if !(previous..=100.0).contains(&percentile) {
+            return Err(EthApiError::InvalidRewardPercentiles)
+        }
+        previous = percentile;
+    }
+    Ok(())
+}
+
+/// Returns the fraction of the total gas used by transactions paying at least each of the given
+/// thresholds.
+///
+/// `sorted` must be sorted by reward in ascending order.
+///
+/// The rewards are in the chain's fee unit and compared in wei with the thresholds.
+fn gas_used_fraction_above(
+    sorted: &[TxGasAndReward],
+    thresholds: &[U256],
+    wei_per_fee_unit: U256,
+) -> Vec<f64> {
+    let total_gas_used = sum_gas_used(sorted);
+    if total_gas_used == 0 {
+        return vec![0.0; thresholds.len()]
+    }
+
+    thresholds
+        .iter()
+        .map(|threshold| {
+            let below = sorted.partition_point(|tx| {
+                fee_in_wei(U256::from(tx.reward), wei_per_fee_unit) < *threshold
+            });
+            let gas_used_above = sum_gas_used(&sorted[below..]);
+            gas_used_above as f64 / total_gas_used as f64
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::{
+        cache::EthStateCache, circuit_breaker::CircuitBreakerConfig,
+        fee_history_response_cache::FeeHistoryResponseCacheConfig,
+    };
+    use futures::FutureExt;
+    use reth_db::{
+        database::Database,
+        mdbx::{test_utils::create_test_db, EnvKind, WriteMap},
+        models::StoredBlockBody,
+        tables,
+        transaction::DbTxMut,
+    };
+    use reth_network_api::test_utils::NoopNetwork;
+    use reth_primitives::{
+        basefee::calculate_next_block_base_fee,
+        constants::{ETH_TO_WEI, GWEI_TO_WEI},
+        ChainSpecBuilder, Transaction, TxEip1559, TxLegacy, TxType, H256,
+    };
+    use reth_provider::{
+        test_utils::{MockFeeProvider, NoopProvider, MOCK_TX_GAS_USED},
+        ShareableDatabase,
+    };
+    use reth_transaction_pool::test_utils::{testing_pool, TestPool};
+    use std::sync::Arc;
+
+    fn signed(transaction: Transaction) -> TransactionSigned {
+        TransactionSigned { hash: H256::random(), signature: Default::default(), transaction }
+    }
+
+    fn receipt(cumulative_gas_used: u64) -> Receipt {
+        Receipt { tx_type: TxType::Legacy, success: true, cumulative_gas_used, logs: vec![] }
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_tip_thresholds() {
+        let mock_provider = MockFeeProvider::default();
+
+        let number = 1337;
+        let header = Header { number, base_fee_per_gas: Some(10), ..Default::default() };
+        let body = vec![
+            // tip 1
+            signed(Transaction::Legacy(TxLegacy { gas_price: 11, ..Default::default() })),
+            // tip 5
+            signed(Transaction::Eip1559(TxEip1559 {
+                max_fee_per_gas: 100,
+                max_priority_fee_per_gas: 5,
+                ..Default::default()
+            })),
+            // tip 3, capped by the max fee
+            signed(Transaction::Eip1559(TxEip1559 {
+                max_fee_per_gas: 13,
+                max_priority_fee_per_gas: 5,
+                ..Default::default()
+            })),
+        ];
+        let parent =
+            Header { number: number - 1, base_fee_per_gas: Some(10), ..Default::default() };
+        mock_provider.add_block(parent, vec![], vec![]);
+        mock_provider.add_block(
+            header,
+            body,
+            vec![receipt(21000), receipt(71000), receipt(100000)],
+        );
+        let eth_api = eth_api(mock_provider);
+
+        let thresholds = [0u64, 3, 4, 6].map(U256::from);
+        let response = eth_api.fee_history_tip_thresholds(2, number.into(), &thresholds).unwrap();
+
+        assert_eq!(response.oldest_block, U256::from(number - 1));
+        assert_eq!(
+            response.gas_used_fraction,
+            vec![vec![0.0; thresholds.len()], vec![1.0, 0.79, 0.5, 0.0]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_tip_thresholds_range() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[1]);
+        }
+        let eth_api = eth_api(mock_provider).with_max_fee_history_blocks(Some(3));
+        let thresholds = [U256::from(1)];
+
+        // the same range as the fee history
+        let response = eth_api.fee_history_tip_thresholds(3, 3u64.into(), &thresholds).unwrap();
+        let fee_history = eth_api.fee_history(3, 3u64.into(), None).await.unwrap();
+        assert_eq!(response.oldest_block, fee_history.oldest_block);
+        assert_eq!(response.gas_used_fraction.len(), fee_history.gas_used_ratio.len());
+
+        // ranges reaching below genesis are rejected rather than truncated
+        assert!(matches!(
+            eth_api.fee_history_tip_thresholds(5, 3u64.into(), &thresholds),
+            Err(EthApiError::InvalidBlockRange)
+        ));
+        assert!(matches!(
+            eth_api.fee_history_tip_thresholds(4, 3u64.into(), &thresholds),
+            Err(EthApiError::TooManyFeeHistoryBlocks { requested: 4, max: 3 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_tip_thresholds_without_receipts() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        mock_provider.add_block_with_tips(1, 10, &[1, 2, 3, 4]);
+        let thresholds = [2u64, 4].map(U256::from);
+
+        let exact = eth_api(mock_provider.clone())
+            .fee_history_tip_thresholds(2, 1u64.into(), &thresholds)
+            .unwrap();
+        assert!(!exact.approximate);
+
+        // like the fee history, blocks without receipts weight all transactions equally
+        mock_provider.prune_receipts_below(2);
+        let approximate =
+            eth_api(mock_provider).fee_history_tip_thresholds(2, 1u64.into(), &thresholds).unwrap();
+        assert!(approximate.approximate);
+        assert_eq!(approximate.gas_used_fraction, exact.gas_used_fraction);
+        assert_eq!(approximate.gas_used_fraction[1], vec![0.75, 0.25]);
+    }
+
+    fn eth_api(provider: MockFeeProvider) -> EthApi<MockFeeProvider, TestPool, NoopNetwork> {
+        EthApi::new(
+            provider,
+            testing_pool(),
+            NoopNetwork::default(),
+            EthStateCache::spawn(NoopProvider::default(), Default::default()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_in_chain_fee_unit() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[3, 1, 2]);
+        mock_provider.add_block_with_tips(1, 12, &[4]);
+        let wei_api = eth_api(mock_provider.clone());
+        let gwei_api = eth_api(mock_provider).with_wei_per_fee_unit(GWEI_TO_WEI);
+
+        let in_wei = wei_api.fee_history(2, 1u64.into(), Some(vec![0.0, 100.0])).await.unwrap();
+        let converted = gwei_api.fee_history(2, 1u64.into(), Some(vec![0.0, 100.0])).await.unwrap();
+        let gwei = |fees: &[u64]| -> Vec<U256> {
+            fees.iter().map(|fee| U256::from(fee * GWEI_TO_WEI)).collect()
+        };
+
+        assert_eq!(in_wei.base_fee_per_gas[..2], [10u64, 12].map(U256::from));
+        assert_eq!(converted.base_fee_per_gas[..2], gwei(&[10, 12]));
+        assert_eq!(
+            converted.base_fee_per_gas[2],
+            in_wei.base_fee_per_gas[2] * U256::from(GWEI_TO_WEI)
+        );
+        assert_eq!(converted.reward, Some(vec![gwei(&[1, 3]), gwei(&[4, 4])]));
+        assert_eq!(converted.gas_used_ratio, in_wei.gas_used_ratio);
+
+        // responses are converted once, also when served from the cache
+        let cached = gwei_api.fee_history(2, 1u64.into(), Some(vec![0.0, 100.0])).await.unwrap();
+        assert_eq!(cached, converted);
+
+        let base_fees = gwei_api.base_fee_history(2, 1u64.into()).unwrap();
+        assert_eq!(base_fees.base_fee_per_gas[..2], gwei(&[10, 12]));
+
+        // thresholds are in wei as well
+        let thresholds = gwei(&[2, 4]);
+        let response = gwei_api.fee_history_tip_thresholds(2, 1u64.into(), &thresholds).unwrap();
+        assert_eq!(response.gas_used_fraction, vec![vec![2.0 / 3.0, 0.0], vec![1.0, 1.0]]);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_rewards() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[3, 1, 2]);
+        mock_provider.add_block_with_tips(1, 10, &[]);
+        let eth_api = eth_api(mock_provider.clone());
+
+        // no rewards are calculated without percentiles, so no bodies or receipts are read
+        let fee_history = eth_api.fee_history(2, 1u64.into(), Some(vec![])).await.unwrap();
+        assert_eq!(fee_history.reward, None);
+        let fee_history = eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        assert_eq!(fee_history.reward, None);
+        assert_eq!(mock_provider.transactions_read(), 0);
+        assert_eq!(mock_provider.receipts_read(), 0);
+
+        let fee_history =
+            eth_api.fee_history(2, 1u64.into(), Some(vec![0.0, 50.0, 100.0])).await.unwrap();
+        assert_eq!(
+            fee_history.reward,
+            Some(vec![[1u64, 2, 3].map(U256::from).to_vec(), vec![U256::ZERO; 3]])
+        );
+        assert_eq!(mock_provider.transactions_read(), 3);
+        assert_eq!(mock_provider.receipts_read(), 3);
+
+        let err = eth_api.fee_history(2, 1u64.into(), Some(vec![50.0, 10.0])).await.unwrap_err();
+        assert!(matches!(err, EthApiError::InvalidRewardPercentiles));
+        let err = eth_api.fee_history(2, 1u64.into(), Some(vec![101.0])).await.unwrap_err();
+        assert!(matches!(err, EthApiError::InvalidRewardPercentiles));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_rewards_of_multiple_transactions_per_block() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[4, 8, 6, 2]);
+        mock_provider.add_block_with_tips(1, 10, &[]);
+        mock_provider.add_block_with_tips(2, 10, &[30, 10, 20]);
+        mock_provider.add_block_with_tips(3, 10, &[7]);
+        let eth_api = eth_api(mock_provider);
+
+        // each block's rewards are only taken from its own transactions
+        let fee_history =
+            eth_api.fee_history(4, 3u64.into(), Some(vec![0.0, 100.0])).await.unwrap();
+        let expected = [[2u64, 8], [0, 0], [10, 30], [7, 7]];
+        assert_eq!(
+            fee_history.reward.unwrap(),
+            expected.map(|reward| reward.map(U256::from).to_vec()).to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_reward_rows_match_percentiles() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        mock_provider.add_block_with_tips(1, 10, &[5]);
+        mock_provider.add_block_with_tips(2, 10, &[3, 1]);
+        mock_provider.add_block_with_tips(3, 10, &[9, 4, 6, 2, 8]);
+        let eth_api = eth_api(mock_provider);
+
+        // more percentiles than transactions, and the same again from the cache
+        for percentiles in
+            [vec![50.0], vec![10.0, 50.0, 90.0], vec![0.0, 10.0, 25.0, 50.0, 75.0, 90.0, 100.0]]
+        {
+            for _ in 0..2 {
+                let fee_history =
+                    eth_api.fee_history(4, 3u64.into(), Some(percentiles.clone())).await.unwrap();
+                let rewards = fee_history.reward.unwrap();
+                assert_eq!(rewards.len(), fee_history.gas_used_ratio.len());
+                assert!(rewards.iter().all(|rewards| rewards.len() == percentiles.len()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_ignores_transactions_without_gas_used() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        let header = Header {
+            number: 1,
+            gas_used: 2 * MOCK_TX_GAS_USED,
+            base_fee_per_gas: Some(10),
+            ..Default::default()
+        };
+        let body = [11, 15, 19]
+            .map(|gas_price| {
+                signed(Transaction::Legacy(TxLegacy { gas_price, ..Default::default() }))
+            })
+            .to_vec();
+        // the cheapest transaction doesn't use any gas
+        let receipts = vec![receipt(0), receipt(MOCK_TX_GAS_USED), receipt(2 * MOCK_TX_GAS_USED)];
+        mock_provider.add_block(header, body, receipts);
+        let eth_api = eth_api(mock_provider);
+
+        let fee_history =
+            eth_api.fee_history(2, 1u64.into(), Some(vec![0.0, 50.0, 100.0])).await.unwrap();
+        assert_eq!(fee_history.reward.unwrap()[1], [5u64, 5, 9].map(U256::from).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_min_and_max_rewards() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[5, 9, 2, 7]);
+        mock_provider.add_block_with_tips(1, 10, &[4, 0, 11]);
+        mock_provider.add_block_with_tips(2, 10, &[6]);
+        let eth_api = eth_api(mock_provider);
+
+        // the 0th percentile is the cheapest and the 100th the most expensive tip of each block
+        let fee_history =
+            eth_api.fee_history(3, 2u64.into(), Some(vec![0.0, 100.0])).await.unwrap();
+        let expected = [[2u64, 9], [0, 11], [6, 6]];
+        assert_eq!(
+            fee_history.reward.unwrap(),
+            expected.map(|reward| reward.map(U256::from).to_vec()).to_vec()
+        );
+
+        // also when requested on its own
+        let fee_history = eth_api.fee_history(3, 2u64.into(), Some(vec![0.0])).await.unwrap();
+        let expected = [2u64, 0, 6].map(|reward| vec![U256::from(reward)]).to_vec();
+        assert_eq!(fee_history.reward.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_excluding_zero_tips() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[0, 7, 0, 5]);
+        mock_provider.add_block_with_tips(1, 10, &[3, 0]);
+        let eth_api = eth_api(mock_provider);
+        let options = FeeHistoryOptions { exclude_zero_tips: true, ..Default::default() };
+
+        let filtered = eth_api
+            .fee_history_with_options(2, 1u64.into(), Some(vec![50.0]), options)
+            .await
+            .unwrap();
+        assert_eq!(filtered.reward.unwrap(), vec![vec![U256::from(5)], vec![U256::from(3)]]);
+
+        // filtered rewards are not served from the cache to unfiltered requests
+        let unfiltered = eth_api.fee_history(2, 1u64.into(), Some(vec![50.0])).await.unwrap();
+        assert_eq!(unfiltered.reward.unwrap(), vec![vec![U256::ZERO], vec![U256::ZERO]]);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_effective_gas_price_rewards() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[3, 1, 2]);
+        mock_provider.add_block_with_tips(1, 20, &[]);
+        let eth_api = eth_api(mock_provider);
+        let percentiles = Some(vec![0.0, 50.0, 100.0]);
+
+        let priority_fees = eth_api.fee_history(2, 1u64.into(), percentiles.clone()).await.unwrap();
+        assert_eq!(
+            priority_fees.reward.unwrap(),
+            vec![[1u64, 2, 3].map(U256::from).to_vec(), vec![U256::ZERO; 3]]
+        );
+
+        // the same transactions, including the base fee, empty blocks report the base fee
+        let options =
+            FeeHistoryOptions { reward: FeeHistoryReward::EffectiveGasPrice, ..Default::default() };
+        let gas_prices =
+            eth_api.fee_history_with_options(2, 1u64.into(), percentiles, options).await.unwrap();
+        assert_eq!(
+            gas_prices.reward.unwrap(),
+            vec![[11u64, 12, 13].map(U256::from).to_vec(), vec![U256::from(20); 3]]
+        );
+        assert_eq!(gas_prices.base_fee_per_gas, priority_fees.base_fee_per_gas);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_descending_order() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..5u64 {
+            let tips = vec![number as u128 + 1; number as usize];
+            mock_provider.add_block_with_tips(number, 100 + number, &tips);
+        }
+        let eth_api = eth_api(mock_provider);
+        let options =
+            FeeHistoryOptions { order: FeeHistoryOrder::Descending, ..Default::default() };
+
+        let ascending = eth_api.fee_history(4, 4u64.into(), Some(vec![50.0])).await.unwrap();
+        let descending = eth_api
+            .fee_history_with_options(4, 4u64.into(), Some(vec![50.0]), options)
+            .await
+            .unwrap();
+
+        assert_eq!(descending.oldest_block, ascending.oldest_block);
+        assert_eq!(descending.base_fee_per_gas[0], U256::from(104));
+
+        // the entries of each block stay aligned
+        let blocks = |fee_history: FeeHistory| {
+            fee_history
+                .base_fee_per_gas
+                .into_iter()
+                .zip(fee_history.gas_used_ratio)
+                .zip(fee_history.reward.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let mut expected = blocks(ascending);
+        expected.reverse();
+        assert_eq!(blocks(descending), expected);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_gas_limit() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            let header = Header {
+                number,
+                gas_used: 15_000_000,
+                gas_limit: 30_000_000 + number * 1_000_000,
+                base_fee_per_gas: Some(100 + number),
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+        let options = FeeHistoryOptions { include_gas_limit: true, ..Default::default() };
+
+        assert_eq!(eth_api.fee_history(3, 3u64.into(), None).await.unwrap().gas_limit, None);
+
+        let fee_history =
+            eth_api.fee_history_with_options(3, 3u64.into(), None, options).await.unwrap();
+        let gas_limit = fee_history.gas_limit.unwrap();
+        assert_eq!(gas_limit, [31_000_000u64, 32_000_000, 33_000_000].map(U64::from).to_vec());
+        assert_eq!(gas_limit.len(), fee_history.gas_used_ratio.len());
+        for (gas_limit, ratio) in gas_limit.iter().zip(fee_history.gas_used_ratio) {
+            assert_eq!(ratio, 15_000_000.0 / gas_limit.as_u64() as f64);
+        }
+
+        // reversed along with the other per block arrays
+        let options = FeeHistoryOptions { order: FeeHistoryOrder::Descending, ..options };
+        let fee_history =
+            eth_api.fee_history_with_options(3, 3u64.into(), None, options).await.unwrap();
+        assert_eq!(
+            fee_history.gas_limit.unwrap(),
+            [33_000_000u64, 32_000_000, 31_000_000].map(U64::from).to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_caps_gas_used_ratio() {
+        let mock_provider = MockFeeProvider::default();
+        for (number, gas_used) in [(0, 50), (1, 100), (2, 250), (3, 10_000)] {
+            let header = Header { number, gas_used, gas_limit: 100, ..Default::default() };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        // from the database and from the cache
+        for _ in 0..2 {
+            let fee_history = eth_api.fee_history(4, 3u64.into(), None).await.unwrap();
+            assert_eq!(fee_history.gas_used_ratio, [0.5, 1.0, 1.0, 1.0]);
+        }
+        let header = Header { gas_used: 101, gas_limit: 100, ..Default::default() };
+        assert_eq!(gas_used_ratio(&header), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_timestamps() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..5u64 {
+            let header = Header {
+                number,
+                timestamp: 1_700_000_000 + number * 12,
+                gas_used: number * 1_000_000,
+                gas_limit: 30_000_000,
+                base_fee_per_gas: Some(100 + number),
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+        let options = FeeHistoryOptions { include_timestamps: true, ..Default::default() };
+
+        assert_eq!(eth_api.fee_history(4, 4u64.into(), None).await.unwrap().timestamps, None);
+
+        let fee_history =
+            eth_api.fee_history_with_options(4, 4u64.into(), None, options).await.unwrap();
+        let timestamps = fee_history.timestamps.unwrap();
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // aligned with the fees of the same blocks
+        assert_eq!(timestamps.len(), fee_history.gas_used_ratio.len());
+        for ((timestamp, base_fee), ratio) in
+            timestamps.iter().zip(fee_history.base_fee_per_gas).zip(fee_history.gas_used_ratio)
+        {
+            let number = (timestamp.as_u64() - 1_700_000_000) / 12;
+            assert_eq!(base_fee, U256::from(100 + number));
+            assert_eq!(ratio, number as f64 / 30.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_transaction_count() {
+        let mock_provider = MockFeeProvider::default();
+        let tips: [&[u128]; 5] = [&[1], &[], &[3, 1, 2], &[5, 4], &[9, 8, 7, 6]];
+        for (number, tips) in tips.iter().enumerate() {
+            mock_provider.add_block_with_tips(number as u64, 10 + number as u64, tips);
+        }
+        let eth_api = eth_api(mock_provider);
+        let options = FeeHistoryOptions { include_transaction_count: true, ..Default::default() };
+
+        let fee_history = eth_api.fee_history(4, 4u64.into(), None).await.unwrap();
+        assert_eq!(fee_history.transaction_count, None);
+
+        // counted without rewards, and along with them
+        for percentiles in [None, Some(vec![50.0])] {
+            let fee_history = eth_api
+                .fee_history_with_options(4, 4u64.into(), percentiles, options)
+                .await
+                .unwrap();
+            let counts = fee_history.transaction_count.unwrap();
+            assert_eq!(counts, [0u64, 3, 2, 4].map(U64::from).to_vec());
+
+            // aligned with the other per block values of the same blocks
+            assert_eq!(counts.len(), fee_history.gas_used_ratio.len());
+            for (count, ratio) in counts.iter().zip(fee_history.gas_used_ratio) {
+                let gas_used = count.as_u64() * MOCK_TX_GAS_USED;
+                assert_eq!(ratio, gas_used as f64 / 30_000_000.0);
+            }
+        }
+
+        let descending = FeeHistoryOptions { order: FeeHistoryOrder::Descending, ..options };
+        let fee_history =
+            eth_api.fee_history_with_options(4, 4u64.into(), None, descending).await.unwrap();
+        assert_eq!(fee_history.transaction_count.unwrap(), [4u64, 2, 3, 0].map(U64::from).to_vec());
+    }
+
+    /// Returns a provider with blocks `0..=5`, each with a distinct base fee and gas used ratio.
+    fn cache_boundary_provider() -> MockFeeProvider {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=5u64 {
+            let tips = vec![1; number as usize + 1];
+            mock_provider.add_block_with_tips(number, 100 + number, &tips);
+        }
+        mock_provider
+    }
+
+    /// Asserts the fee history contains exactly the blocks of the range in ascending order.
+    fn assert_fee_history_blocks(fee_history: &FeeHistory, blocks: RangeInclusive<u64>) {
+        let newest = *blocks.end();
+        let next_base_fee = calculate_next_block_base_fee(
+            (newest + 1) * MOCK_TX_GAS_USED,
+            30_000_000,
+            100 + newest,
+        );
+        let base_fee_per_gas = blocks
+            .clone()
+            .map(|number| U256::from(100 + number))
+            .chain([U256::from(next_base_fee)])
+            .collect::<Vec<_>>();
+        let gas_used_ratio = blocks
+            .map(|number| (number + 1) as f64 * MOCK_TX_GAS_USED as f64 / 30_000_000.0)
+            .collect::<Vec<_>>();
+        assert_eq!(fee_history.base_fee_per_gas, base_fee_per_gas);
+        assert_eq!(fee_history.gas_used_ratio, gas_used_ratio);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_fully_cached() {
+        let mock_provider = cache_boundary_provider();
+        let eth_api = eth_api(mock_provider.clone());
+
+        let fee_history = eth_api.fee_history(4, 4u64.into(), None).await.unwrap();
+        assert_fee_history_blocks(&fee_history, 1..=4);
+        assert_eq!(mock_provider.headers_read(), 4);
+
+        // served without querying the database again
+        let fee_history = eth_api.fee_history(4, 4u64.into(), None).await.unwrap();
+        assert_fee_history_blocks(&fee_history, 1..=4);
+        let fee_history = eth_api.fee_history(2, 3u64.into(), None).await.unwrap();
+        assert_fee_history_blocks(&fee_history, 2..=3);
+        assert_eq!(mock_provider.headers_read(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_fully_uncached() {
+        let mock_provider = cache_boundary_provider();
+        let eth_api = eth_api(mock_provider.clone());
+
+        let fee_history = eth_api.fee_history(6, 5u64.into(), None).await.unwrap();
+        assert_fee_history_blocks(&fee_history, 0..=5);
+        assert_eq!(mock_provider.headers_read(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_refreshes_stale_cache_entries() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 100, &[1]);
+        }
+        let eth_api = eth_api(mock_provider.clone()).with_max_fee_history_cache_age(Some(2));
+
+        let fee_history = eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        assert_eq!(mock_provider.headers_read(), 2);
+
+        // served from the cache while the chain advanced by at most two blocks
+        mock_provider.add_block_with_tips(4, 100, &[1]);
+        mock_provider.add_block_with_tips(5, 100, &[1]);
+        assert_eq!(eth_api.fee_history(2, 1u64.into(), None).await.unwrap(), fee_history);
+        assert_eq!(mock_provider.headers_read(), 2);
+
+        // queried again once it advanced further
+        mock_provider.add_block_with_tips(6, 100, &[1]);
+        assert_eq!(eth_api.fee_history(2, 1u64.into(), None).await.unwrap(), fee_history);
+        assert_eq!(mock_provider.headers_read(), 4);
+        let cached = eth_api.fee_history_cache.0.lock().await.peek(&1).unwrap().clone();
+        assert_eq!(cached.cached_at, 6);
+
+        // and the refreshed entries are served again
+        eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        assert_eq!(mock_provider.headers_read(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_cached_prefix() {
+        let mock_provider = cache_boundary_provider();
+        let eth_api = eth_api(mock_provider.clone());
+
+        eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        assert_eq!(mock_provider.headers_read(), 2);
+
+        // only the uncached suffix is queried
+        let fee_history = eth_api.fee_history(6, 5u64.into(), None).await.unwrap();
+        assert_fee_history_blocks(&fee_history, 0..=5);
+        assert_eq!(mock_provider.headers_read(), 2 + 4);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_uncached_block_between_cached() {
+        let mock_provider = cache_boundary_provider();
+        let eth_api = eth_api(mock_provider.clone());
+
+        eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        eth_api.fee_history(3, 5u64.into(), None).await.unwrap();
+        assert_eq!(mock_provider.headers_read(), 2 + 3);
+
+        // only the gap is queried and merged in order
+        let fee_history = eth_api.fee_history(6, 5u64.into(), None).await.unwrap();
+        assert_fee_history_blocks(&fee_history, 0..=5);
+        assert_eq!(mock_provider.headers_read(), 2 + 3 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_batch() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..6u64 {
+            mock_provider.add_block_with_tips(number, 10, &[number as u128 + 1]);
+        }
+        let eth_api = eth_api(mock_provider.clone());
+
+        let request = |block_count: u64, newest_block: u64| FeeHistoryRequest {
+            block_count,
+            newest_block: newest_block.into(),
+            reward_percentiles: Some(vec![50.0]),
+            options: Default::default(),
+        };
+        let mut results = eth_api
+            .fee_history_batch(vec![request(3, 3), request(3, 4), request(0, 4), request(3, 100)])
+            .await;
+
+        // blocks 2 and 3 are shared by both ranges, but only queried once
+        assert_eq!(mock_provider.headers_read(), 4);
+
+        assert!(matches!(results.pop().unwrap(), Err(EthApiError::InvalidBlockRange)));
+        assert!(results.pop().unwrap().unwrap().base_fee_per_gas.is_empty());
+        let second = results.pop().unwrap().unwrap();
+        let first = results.pop().unwrap().unwrap();
+
+        let rewards = |fee_history: FeeHistory| {
+            fee_history.reward.unwrap().into_iter().map(|rewards| rewards[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(rewards(first), [2u64, 3, 4].map(U256::from).to_vec());
+        assert_eq!(rewards(second), [3u64, 4, 5].map(U256::from).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_estimate_inclusion_fee() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..6u64 {
+            mock_provider.add_block_with_tips(number, 800, &[number as u128 + 1]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        // the blocks are far below their gas target, so the base fee falls
+        let next_base_fee =
+            U256::from(calculate_next_block_base_fee(MOCK_TX_GAS_USED, 30_000_000, 800));
+        assert!(next_base_fee < U256::from(800));
+
+        let estimate = eth_api.estimate_inclusion_fee(1, 0.5).await.unwrap();
+        assert_eq!(estimate.max_priority_fee_per_gas, U256::from(3));
+        assert_eq!(estimate.max_fee_per_gas, next_base_fee + U256::from(3));
+
+        let estimate = eth_api.estimate_inclusion_fee(2, 0.5).await.unwrap();
+        assert_eq!(estimate.max_priority_fee_per_gas, U256::from(2));
+        assert_eq!(
+            estimate.max_fee_per_gas,
+            next_base_fee + next_base_fee / U256::from(8) + U256::from(2)
+        );
+
+        assert!(matches!(
+            eth_api.estimate_inclusion_fee(0, 0.5).await,
+            Err(EthApiError::InvalidInclusionTarget)
+        ));
+        assert!(matches!(
+            eth_api.estimate_inclusion_fee(1, 1.0).await,
+            Err(EthApiError::InvalidInclusionTarget)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_eip1559_fees() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..6u64 {
+            mock_provider.add_block_with_tips(number, 800, &[number as u128 + 1]);
+        }
+        let eth_api = eth_api(mock_provider);
+        let next_base_fee = eth_api.gas_price_and_fee_history().await.unwrap().base_fee_per_gas;
+
+        // the median of the tips 1 to 6
+        let fees = eth_api.suggest_eip1559_fees(50.0, 2.0).await.unwrap();
+        assert_eq!(fees.base_fee_per_gas, next_base_fee);
+        assert_eq!(fees.max_priority_fee_per_gas, U256::from(3));
+        assert_eq!(fees.max_fee_per_gas, next_base_fee * U256::from(2) + U256::from(3));
+        // covers the base fee rising in 5 full blocks after the next one
+        let projected_base_fee = max_base_fee_within(next_base_fee, 6);
+        assert!(projected_base_fee > next_base_fee);
+        assert!(fees.max_fee_per_gas >= projected_base_fee + fees.max_priority_fee_per_gas);
+
+        let fees = eth_api.suggest_eip1559_fees(50.0, 1.0).await.unwrap();
+        assert_eq!(fees.max_fee_per_gas, next_base_fee + U256::from(3));
+        let fees = eth_api.suggest_eip1559_fees(100.0, 1.5).await.unwrap();
+        assert_eq!(fees.max_priority_fee_per_gas, U256::from(3));
+        assert_eq!(
+            fees.max_fee_per_gas,
+            next_base_fee * U256::from(3) / U256::from(2) + U256::from(3)
+        );
+
+        for multiplier in [0.5, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                eth_api.suggest_eip1559_fees(50.0, multiplier).await,
+                Err(EthApiError::InvalidBaseFeeMultiplier)
+            ));
+        }
+        assert!(matches!(
+            eth_api.suggest_eip1559_fees(101.0, 2.0).await,
+            Err(EthApiError::InvalidRewardPercentiles)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_median_reward_ema() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..10u64 {
+            let tip = if number % 2 == 0 { 10 } else { 100 };
+            mock_provider.add_block_with_tips(number, 800, &[tip]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let fee_history = eth_api.fee_history(10, 9u64.into(), Some(vec![50.0])).await.unwrap();
+        let medians =
+            fee_history.reward.unwrap().into_iter().map(|rewards| rewards[0]).collect::<Vec<_>>();
+        let ema = eth_api.median_reward_ema(10, 9u64.into(), 0.8).await.unwrap();
+        assert_eq!(ema.len(), medians.len());
+        assert_eq!(ema[0], medians[0]);
+        // 0.8 * 10 + 0.2 * 100
+        assert_eq!(ema[1], U256::from(28));
+
+        // the average swings a lot less than the medians
+        let jitter = |values: &[U256]| {
+            values
+                .windows(2)
+                .map(|pair| pair[0].max(pair[1]) - pair[0].min(pair[1]))
+                .fold(U256::ZERO, |sum, diff| sum + diff)
+        };
+        assert!(jitter(&ema) * U256::from(4) < jitter(&medians), "{ema:?}");
+        let suggested = *ema.last().unwrap();
+        assert!(suggested > U256::from(10) && suggested < U256::from(100));
+
+        // without decay it's the medians
+        assert_eq!(eth_api.median_reward_ema(10, 9u64.into(), 0.0).await.unwrap(), medians);
+        for decay in [-0.1, 1.0, f64::NAN] {
+            assert!(matches!(
+                eth_api.median_reward_ema(10, 9u64.into(), decay).await,
+                Err(EthApiError::InvalidRewardDecay)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backtest_tip() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..6u64 {
+            mock_provider.add_block_with_tips(number, 800, &[number as u128 + 1]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        // the inclusion thresholds of blocks 2..=5 are 3, 4, 5 and 6
+        let backtest = eth_api.backtest_tip(U256::from(4), 4).await.unwrap();
+        assert_eq!(
+            backtest,
+            TipBacktest { block_count: 4, included_blocks: 2, inclusion_rate: 0.5 }
+        );
+
+        let backtest = eth_api.backtest_tip(U256::from(2), 4).await.unwrap();
+        assert_eq!(backtest.included_blocks, 0);
+        assert_eq!(backtest.inclusion_rate, 0.0);
+
+        let backtest = eth_api.backtest_tip(U256::from(6), 1).await.unwrap();
+        assert_eq!(backtest.block_count, 1);
+        assert_eq!(backtest.inclusion_rate, 1.0);
+
+        // shortened to the blocks of the chain
+        let backtest = eth_api.backtest_tip(U256::from(3), 100).await.unwrap();
+        assert_eq!(backtest.block_count, 6);
+        assert_eq!(backtest.included_blocks, 3);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_with_shuffled_headers() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 10 + number, &[number as u128 + 1]);
+        }
+        mock_provider.set_headers_reversed(true);
+        let eth_api = eth_api(mock_provider);
+
+        let fee_history = eth_api.fee_history(4, 3u64.into(), Some(vec![50.0])).await.unwrap();
+        assert_eq!(fee_history.base_fee_per_gas[..4], [10u64, 11, 12, 13].map(U256::from));
+        assert_eq!(
+            fee_history.reward.unwrap(),
+            [1u64, 2, 3, 4].map(|tip| vec![U256::from(tip)]).to_vec()
+        );
+
+        let base_fee_history = eth_api.base_fee_history(4, 3u64.into()).unwrap();
+        assert_eq!(base_fee_history.base_fee_per_gas[..4], [10u64, 11, 12, 13].map(U256::from));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_with_gap_in_headers() {
+        let mock_provider = MockFeeProvider::default();
+        // block 2 is missing, block 1 is returned twice
+        for number in [0, 1, 1, 3] {
+            mock_provider.add_block_with_tips(number, 10, &[1]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let err = eth_api.fee_history(4, 3u64.into(), None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::ProviderRangeMismatch { expected: 2, actual: 1 }));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_header_missing_after_resolving() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=5 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let eth_api = eth_api(mock_provider.clone());
+
+        // `latest` still resolves to block 5, but its header is gone once the range is read
+        mock_provider.hide_headers_from(Some(5));
+        let err = eth_api.fee_history(4, BlockNumberOrTag::Latest.into(), None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::HeaderNotFound(5)), "{err:?}");
+        assert!(err.to_string().contains("retry with a lower newest block"));
+
+        // retrying with a lower newest block succeeds
+        let fee_history = eth_api.fee_history(4, 4u64.into(), None).await.unwrap();
+        assert_eq!(fee_history.gas_used_ratio.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_reward_limit() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[1]);
+        }
+        let eth_api = eth_api(mock_provider).with_max_fee_history_rewards(Some(4));
+
+        // 2 blocks with 2 rewards each
+        let fee_history = eth_api.fee_history(2, 3u64.into(), Some(vec![10.0, 90.0])).await;
+        assert_eq!(fee_history.unwrap().reward.unwrap().len(), 2);
+
+        let err = eth_api.fee_history(3, 3u64.into(), Some(vec![10.0, 90.0])).await.unwrap_err();
+        assert!(matches!(err, EthApiError::TooManyFeeHistoryRewards { requested: 6, max: 4 }));
+
+        // the limit only applies to rewards
+        assert!(eth_api.fee_history(4, 3u64.into(), None).await.is_ok());
+
+        let eth_api = eth_api.with_max_fee_history_rewards(None);
+        assert!(eth_api.fee_history(3, 3u64.into(), Some(vec![10.0, 90.0])).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_block_limit() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[1]);
+        }
+        let eth_api = eth_api(mock_provider).with_max_fee_history_blocks(Some(3));
+
+        assert_eq!(
+            eth_api.fee_history(3, 3u64.into(), None).await.unwrap().gas_used_ratio.len(),
+            3
+        );
+
+        let err = eth_api.fee_history(4, 3u64.into(), None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::TooManyFeeHistoryBlocks { requested: 4, max: 3 }));
+
+        let eth_api = eth_api.with_max_fee_history_blocks(None);
+        assert!(eth_api.fee_history(4, 3u64.into(), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_reward_percentile_limit() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[1]);
+        }
+        let eth_api = eth_api(mock_provider.clone()).with_max_reward_percentiles(Some(3));
+
+        assert!(eth_api.fee_history(2, 3u64.into(), Some(vec![10.0, 50.0, 90.0])).await.is_ok());
+        let headers_read = mock_provider.headers_read();
+
+        // rejected before any blocks are queried
+        let percentiles = (0..=100).map(|p| p as f64).collect::<Vec<_>>();
+        let err = eth_api.fee_history(2, 2u64.into(), Some(percentiles.clone())).await.unwrap_err();
+        assert!(matches!(err, EthApiError::TooManyRewardPercentiles { requested: 101, max: 3 }));
+        assert_eq!(mock_provider.headers_read(), headers_read);
+
+        // no limit
+        let eth_api = eth_api.with_max_reward_percentiles(None);
+        assert!(eth_api.fee_history(2, 3u64.into(), Some(percentiles)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_fee_history_leaves_cache_untouched() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[number as u128 + 1]);
+        }
+        let eth_api = eth_api(mock_provider.clone());
+
+        eth_api.fee_history(2, 1u64.into(), Some(vec![50.0])).await.unwrap();
+        assert_eq!(eth_api.fee_history_cache.0.lock().await.len(), 2);
+
+        // fail after the missing blocks were queried from the database
+        mock_provider.set_block_hashes_failing(true);
+        let err = eth_api.fee_history(4, 3u64.into(), Some(vec![50.0])).await.unwrap_err();
+        assert!(matches!(err, EthApiError::Internal(_)));
+        {
+            let fee_history_cache = eth_api.fee_history_cache.0.lock().await;
+            assert_eq!(fee_history_cache.len(), 2);
+            assert!(!fee_history_cache.contains(&2) && !fee_history_cache.contains(&3));
+        }
+
+        mock_provider.set_block_hashes_failing(false);
+        let fee_history = eth_api.fee_history(4, 3u64.into(), Some(vec![50.0])).await.unwrap();
+        assert_eq!(fee_history.gas_used_ratio.len(), 4);
+        let fee_history_cache = eth_api.fee_history_cache.0.lock().await;
+        assert_eq!(fee_history_cache.len(), 4);
+        assert!(fee_history_cache.peek(&0).unwrap().hash.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_gas_price_and_fee_history() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..10u64 {
+            let tip = number as u128 + 1;
+            mock_provider.add_block_with_tips(number, 1_000 + number, &[3 * tip, tip, 2 * tip]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let response = eth_api.gas_price_and_fee_history().await.unwrap();
+        let fee_history = &response.fee_history;
+
+        // the window covers the most recent blocks
+        let newest = 9u64;
+        let window = GAS_PRICE_FEE_HISTORY_BLOCK_COUNT;
+        assert_eq!(fee_history.gas_used_ratio.len() as u64, window);
+        assert_eq!(fee_history.oldest_block, U256::from(newest + 1 - window));
+
+        // the base fee is projected from the newest block of the window, like the last base fee
+        // of the fee history
+        assert_eq!(response.base_fee_per_gas, *fee_history.base_fee_per_gas.last().unwrap());
+
+        // the priority fee is the median of the reported rewards
+        let mut rewards = fee_history
+            .reward
+            .clone()
+            .unwrap()
+            .into_iter()
+            .map(|rewards| rewards[0])
+            .collect::<Vec<_>>();
+        rewards.sort_unstable();
+        assert_eq!(response.max_priority_fee_per_gas, rewards[(rewards.len() - 1) / 2]);
+        assert_eq!(response.max_priority_fee_per_gas, U256::from(16));
+
+        // the same values are served by the individual methods
+        let same_window = eth_api
+            .fee_history(GAS_PRICE_FEE_HISTORY_BLOCK_COUNT, newest.into(), Some(vec![50.0]))
+            .await
+            .unwrap();
+        assert_eq!(*fee_history, same_window);
+    }
+
+    #[tokio::test]
+    async fn test_fee_estimate_in() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..10u64 {
+            let tip = (number as u128 + 1) * GWEI_TO_WEI as u128;
+            mock_provider.add_block_with_tips(number, 30 * GWEI_TO_WEI + number, &[tip, 2 * tip]);
+        }
+        let eth_api = eth_api(mock_provider);
+        let GasPriceAndFeeHistory { base_fee_per_gas, max_priority_fee_per_gas, .. } =
+            eth_api.gas_price_and_fee_history().await.unwrap();
+
+        // one token unit per gwei, so the costs are whole gwei
+        let price = EthPrice::new(U256::from(GWEI_TO_WEI)).unwrap();
+        let estimate = eth_api.fee_estimate_in(21_000, price, Rounding::Down).await.unwrap();
+        let gas = U256::from(21_000);
+        assert_eq!(estimate.base_fee, wei_to_gwei(base_fee_per_gas * gas, Rounding::Down));
+        assert_eq!(
+            estimate.priority_fee,
+            wei_to_gwei(max_priority_fee_per_gas * gas, Rounding::Down)
+        );
+        assert_eq!(
+            estimate.total_fee,
+            wei_to_gwei((base_fee_per_gas + max_priority_fee_per_gas) * gas, Rounding::Down)
+        );
+        assert!(estimate.base_fee > U256::ZERO && estimate.priority_fee > U256::ZERO);
+
+        // 1834.56 USD in cents, the parts are rounded separately
+        let price = EthPrice::from_f64(1834.56, 2).unwrap();
+        let down = eth_api.fee_estimate_in(21_000, price, Rounding::Down).await.unwrap();
+        let up = eth_api.fee_estimate_in(21_000, price, Rounding::Up).await.unwrap();
+        let cents = |wei: U256| wei * U256::from(183_456) / U256::from(ETH_TO_WEI);
+        assert_eq!(down.base_fee, cents(base_fee_per_gas * gas));
+        assert_eq!(down.priority_fee, cents(max_priority_fee_per_gas * gas));
+        assert_eq!(up.base_fee, down.base_fee + U256::from(1));
+        assert!(down.total_fee >= down.base_fee + down.priority_fee);
+        assert!(up.total_fee <= up.base_fee + up.priority_fee);
+
+        assert_eq!(
+            eth_api.fee_estimate_in(0, price, Rounding::Up).await.unwrap(),
+            TokenFeeEstimate::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detect_fee_spike() {
+        let mock_provider = MockFeeProvider::default();
+        let base_fees = [100, 104, 96, 101, 99, 100, 98, 250];
+        for (number, base_fee) in base_fees.into_iter().enumerate() {
+            mock_provider.add_block_with_tips(number as u64, base_fee, &[]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let spike = eth_api.detect_fee_spike(5, 2.0).unwrap();
+        assert_eq!(
+            spike,
+            FeeSpike {
+                is_spike: true,
+                current_base_fee: U256::from(250),
+                baseline_base_fee: U256::from(99),
+            }
+        );
+        // the base fee must exceed the threshold
+        assert!(!eth_api.detect_fee_spike(5, 2.6).unwrap().is_spike);
+        assert!(eth_api.detect_fee_spike(5, 2.5).unwrap().is_spike);
+
+        // the window is limited to the available blocks
+        let spike = eth_api.detect_fee_spike(100, 2.0).unwrap();
+        assert_eq!(spike.baseline_base_fee, U256::from(100));
+        assert!(spike.is_spike);
+
+        for (window, multiplier) in [(0, 2.0), (5, 0.0), (5, -1.0), (5, f64::NAN)] {
+            assert!(matches!(
+                eth_api.detect_fee_spike(window, multiplier),
+                Err(EthApiError::InvalidFeeSpikeParams)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detect_no_fee_spike_on_stable_fees() {
+        // a chain without preceding blocks never spikes
+        let genesis_only = MockFeeProvider::default();
+        genesis_only.add_block_with_tips(0, 500, &[]);
+        let spike = eth_api(genesis_only).detect_fee_spike(5, 1.0).unwrap();
+        assert_eq!(spike.baseline_base_fee, spike.current_base_fee);
+        assert!(!spike.is_spike);
+
+        let mock_provider = MockFeeProvider::default();
+        let base_fees = [100, 112, 99, 124, 110, 118, 121, 130];
+        for (number, base_fee) in base_fees.into_iter().enumerate() {
+            mock_provider.add_block_with_tips(number as u64, base_fee, &[]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let spike = eth_api.detect_fee_spike(7, 1.5).unwrap();
+        assert!(!spike.is_spike);
+        assert_eq!(spike.current_base_fee, U256::from(130));
+        assert_eq!(spike.baseline_base_fee, U256::from(112));
+    }
+
+    #[tokio::test]
+    async fn test_median_reward() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..5u64 {
+            let tip = number as u128 + 1;
+            mock_provider.add_block_with_tips(number, 10, &[3 * tip, tip, 2 * tip]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let median = eth_api.median_reward().await.unwrap();
+
+        let fee_history = eth_api.fee_history(5, 4u64.into(), Some(vec![50.0])).await.unwrap();
+        let mut rewards =
+            fee_history.reward.unwrap().into_iter().map(|rewards| rewards[0]).collect::<Vec<_>>();
+        rewards.sort_unstable();
+        assert_eq!(median, rewards[rewards.len() / 2]);
+        assert_eq!(median, U256::from(6));
+        assert_eq!(
+            eth_api.median_reward_in(FeeUnit::Gwei, Rounding::Up).await.unwrap(),
+            U256::from(1)
+        );
+        assert_eq!(
+            eth_api.median_reward_in(FeeUnit::Gwei, Rounding::Down).await.unwrap(),
+            U256::ZERO
+        );
+
+        // the rewards were cached for the requested percentile
+        let cached = eth_api.fee_history_cache.0.lock().await.get(&4).cloned().unwrap();
+        assert_eq!(cached.reward_percentiles, Some(vec![50.0]));
+    }
+
+    #[tokio::test]
+    async fn test_tip_buckets() {
+        let mock_provider = MockFeeProvider::default();
+        let gwei = |value: u128| value * GWEI_TO_WEI as u128;
+        for number in 0..4u64 {
+            // tips of 0.5, 1.2 and 3 gwei, slightly higher in later blocks
+            let tips = [gwei(1) / 2, gwei(6) / 5, gwei(3)].map(|tip| tip + number as u128);
+            mock_provider.add_block_with_tips(number, 10, &tips);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let buckets = eth_api.tip_buckets(&TipBucketConfig::defaults()).await.unwrap();
+        let labels = buckets.iter().map(|bucket| bucket.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["low", "medium", "high"]);
+        let levels = buckets.iter().map(|bucket| bucket.gwei).collect::<Vec<_>>();
+        assert_eq!(levels, [1u64, 2, 4].map(U256::from));
+        assert!(
+            buckets
+                .iter()
+                .all(|bucket| bucket.max_priority_fee_per_gas ==
+                    bucket.gwei * U256::from(GWEI_TO_WEI))
+        );
+
+        let buckets = eth_api
+            .tip_buckets(&[
+                TipBucketConfig::new("cheapest", 0.0),
+                TipBucketConfig::new("fast", 100.0),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(buckets[0].percentile, 0.0);
+        assert_eq!(buckets[0].gwei, U256::from(1));
+        assert_eq!(buckets[1].gwei, U256::from(4));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_for_blocks() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..10 {
+            mock_provider.add_block_with_tips(number, 10 + number, &[number as u128, 1, 2]);
+        }
+        let eth_api = eth_api(mock_provider.clone());
+        let percentiles = vec![25.0, 75.0];
+
+        // caches blocks 2 and 3
+        eth_api.fee_history(2, 3u64.into(), Some(percentiles.clone())).await.unwrap();
+        let headers_read = mock_provider.headers_read();
+
+        let blocks = vec![7, 2, 5, 3, 6, 2];
+        let fees = eth_api
+            .fee_history_for_blocks(blocks.clone(), Some(percentiles.clone()))
+            .await
+            .unwrap();
+
+        // only the missing blocks 5 to 7 are queried, as a single range
+        assert_eq!(mock_provider.headers_read() - headers_read, 3);
+        assert_eq!(fees.iter().map(|fees| fees.number.as_u64()).collect::<Vec<_>>(), blocks);
+        for fees in &fees {
+            let block = fees.number.as_u64();
+            let expected =
+                eth_api.fee_history(2, block.into(), Some(percentiles.clone())).await.unwrap();
+            assert_eq!(fees.base_fee_per_gas, expected.base_fee_per_gas[1]);
+            assert_eq!(fees.gas_used_ratio, expected.gas_used_ratio[1]);
+            assert_eq!(fees.reward.as_ref(), Some(&expected.reward.unwrap()[1]));
+        }
+
+        // the queried blocks were cached along
+        let headers_read = mock_provider.headers_read();
+        eth_api.fee_history_for_blocks(vec![6, 5], Some(percentiles)).await.unwrap();
+        assert_eq!(mock_provider.headers_read(), headers_read);
+
+        let fees = eth_api.fee_history_for_blocks(vec![9], None).await.unwrap();
+        assert_eq!(fees[0].reward, None);
+        assert!(eth_api.fee_history_for_blocks(vec![], None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_for_blocks_limits() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..10 {
+            mock_provider.add_block_with_tips(number, 10, &[1]);
+        }
+        let eth_api = eth_api(mock_provider.clone())
+            .with_max_fee_history_blocks(Some(3))
+            .with_max_fee_history_rewards(Some(4));
+
+        assert_eq!(eth_api.fee_history_for_blocks(vec![1, 5, 9], None).await.unwrap().len(), 3);
+
+        let headers_read = mock_provider.headers_read();
+        let err = eth_api.fee_history_for_blocks(vec![1, 3, 5, 7], None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::TooManyFeeHistoryBlocks { requested: 4, max: 3 }));
+
+        let percentiles = Some(vec![10.0, 90.0]);
+        let err = eth_api.fee_history_for_blocks(vec![1, 5, 9], percentiles).await.unwrap_err();
+        assert!(matches!(err, EthApiError::TooManyFeeHistoryRewards { requested: 6, max: 4 }));
+
+        // rejected before any blocks are queried
+        assert_eq!(mock_provider.headers_read(), headers_read);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_csv() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 7, &[1]);
+        mock_provider.add_block_with_tips(1, 10, &[3, 1, 2]);
+        mock_provider.add_block_with_tips(2, 14, &[]);
+        let eth_api = eth_api(mock_provider);
+        let percentiles = vec![12.5, 50.0];
+
+        let csv = eth_api.fee_history_csv(3, 2u64.into(), Some(percentiles.clone())).await.unwrap();
+        let full = eth_api.fee_history(3, 2u64.into(), Some(percentiles)).await.unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("number,base_fee_per_gas,gas_used_ratio,reward_12.5,reward_50")
+        );
+        let rows = lines
+            .map(|line| line.split(',').map(str::to_string).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), full.gas_used_ratio.len());
+        for (index, row) in rows.iter().enumerate() {
+            assert_eq!(row[0].parse::<u64>().unwrap(), index as u64);
+            assert_eq!(row[1].parse::<U256>().unwrap(), full.base_fee_per_gas[index]);
+            assert_eq!(row[2].parse::<f64>().unwrap(), full.gas_used_ratio[index]);
+            let rewards = row[3..].iter().map(|r| r.parse::<U256>().unwrap()).collect::<Vec<_>>();
+            assert_eq!(rewards, full.reward.as_ref().unwrap()[index]);
+        }
+
+        // without percentiles there are no reward columns
+        let csv = eth_api.fee_history_csv(3, 2u64.into(), None).await.unwrap();
+        assert_eq!(csv.lines().next(), Some("number,base_fee_per_gas,gas_used_ratio"));
+        assert!(csv.lines().skip(1).all(|line| line.split(',').count() == 3));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_summary() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 7, &[1]);
+        mock_provider.add_block_with_tips(1, 10, &[3, 1, 2]);
+        mock_provider.add_block_with_tips(2, 14, &[8, 6]);
+        mock_provider.add_block_with_tips(3, 9, &[]);
+        mock_provider.add_block_with_tips(4, 12, &[5, 4, 9, 7]);
+        let eth_api = eth_api(mock_provider);
+
+        let summary = eth_api.fee_history_summary(4, 4u64.into(), Some(50.0)).await.unwrap();
+        let full = eth_api.fee_history(4, 4u64.into(), Some(vec![50.0])).await.unwrap();
+
+        let block_count = full.gas_used_ratio.len();
+        let base_fees = &full.base_fee_per_gas[..block_count];
+        let mean_base_fee =
+            base_fees.iter().fold(U256::ZERO, |sum, fee| sum + *fee) / U256::from(block_count);
+        let mean_gas_used_ratio = full.gas_used_ratio.iter().sum::<f64>() / block_count as f64;
+        let mut rewards = full.reward.unwrap().iter().map(|r| r[0]).collect::<Vec<_>>();
+        rewards.sort_unstable();
+
+        assert_eq!(summary.block_count, block_count as u64);
+        assert_eq!(summary.min_base_fee_per_gas, *base_fees.iter().min().unwrap());
+        assert_eq!(summary.max_base_fee_per_gas, *base_fees.iter().max().unwrap());
+        assert_eq!(summary.mean_base_fee_per_gas, mean_base_fee);
+        assert!((summary.mean_gas_used_ratio - mean_gas_used_ratio).abs() < 1e-9);
+        assert_eq!(summary.median_reward, Some(rewards[(rewards.len() - 1) / 2]));
+
+        // without a percentile no rewards are computed
+        let summary = eth_api.fee_history_summary(4, 4u64.into(), None).await.unwrap();
+        assert_eq!(summary.median_reward, None);
+        assert_eq!(summary.mean_base_fee_per_gas, mean_base_fee);
+    }
+
+    #[tokio::test]
+    async fn test_average_gas_used_ratio() {
+        let mock_provider = MockFeeProvider::default();
+        for (number, gas_used) in [(0, 10), (1, 20), (2, 30), (3, 60), (4, 150)] {
+            let header = Header { number, gas_used, gas_limit: 100, ..Default::default() };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let average = eth_api.average_gas_used_ratio(3).await.unwrap();
+        assert!((average - 1.9 / 3.0).abs() < 1e-9);
+
+        // overfull blocks count as full
+        let average = eth_api.average_gas_used_ratio(2).await.unwrap();
+        assert!((average - 0.8).abs() < 1e-9);
+
+        let average = eth_api.average_gas_used_ratio(10).await.unwrap();
+        assert!((average - 0.44).abs() < 1e-9);
+
+        assert_eq!(eth_api.average_gas_used_ratio(0).await.unwrap(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_base_fee_history() {
+        let mock_provider = MockFeeProvider::default();
+        for (number, base_fee_per_gas) in [(0, None), (1, Some(1_000_000_000))] {
+            let header = Header {
+                number,
+                gas_used: 10_000_000,
+                gas_limit: 10_000_000,
+                base_fee_per_gas,
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let history = eth_api.base_fee_history(2, 1u64.into()).unwrap();
+        assert_eq!(history.oldest_block, U256::ZERO);
+        assert_eq!(
+            history.base_fee_per_gas,
+            [0u64, 1_000_000_000, 1_125_000_000].map(U256::from).to_vec()
+        );
+
+        // the base fees match the full fee history
+        for block_count in 1..=2 {
+            let history = eth_api.base_fee_history(block_count, 1u64.into()).unwrap();
+            let fee_history = eth_api.fee_history(block_count, 1u64.into(), None).await.unwrap();
+            assert_eq!(history.oldest_block, fee_history.oldest_block);
+            assert_eq!(history.base_fee_per_gas, fee_history.base_fee_per_gas);
+        }
+
+        assert!(matches!(
+            eth_api.base_fee_history(3, 1u64.into()).unwrap_err(),
+            EthApiError::InvalidBlockRange
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_burned_base_fees() {
+        let mock_provider = MockFeeProvider::default();
+        // pre-EIP-1559 blocks don't burn anything
+        let blocks = [
+            (0, None, 15_000_000),
+            (1, Some(1_000_000_000), 10_000_000),
+            (2, Some(1_125_000_000), 0),
+            (3, Some(900_000_000), 29_000_000),
+        ];
+        for (number, base_fee_per_gas, gas_used) in blocks {
+            let header = Header {
+                number,
+                gas_used,
+                gas_limit: 30_000_000,
+                base_fee_per_gas,
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let burned = |blocks: &[(u64, Option<u64>, u64)]| {
+            blocks.iter().fold(U256::ZERO, |burned, (_, base_fee, gas_used)| {
+                burned + U256::from(base_fee.unwrap_or_default()) * U256::from(*gas_used)
+            })
+        };
+        assert_eq!(eth_api.burned_base_fees(4, 3u64.into()).unwrap(), burned(&blocks));
+        assert_eq!(eth_api.burned_base_fees(2, 3u64.into()).unwrap(), burned(&blocks[2..]));
+        assert_eq!(
+            eth_api.burned_base_fees(4, 3u64.into()).unwrap(),
+            U256::from(10_000_000_000_000_000u64 + 26_100_000_000_000_000)
+        );
+        assert_eq!(
+            eth_api.burned_base_fees(2, 1u64.into()).unwrap(),
+            U256::from(10_000_000_000_000_000u64)
+        );
+        assert_eq!(eth_api.burned_base_fees(1, 2u64.into()).unwrap(), U256::ZERO);
+        assert_eq!(eth_api.burned_base_fees(0, 3u64.into()).unwrap(), U256::ZERO);
+
+        assert!(matches!(
+            eth_api.burned_base_fees(5, 3u64.into()).unwrap_err(),
+            EthApiError::InvalidBlockRange
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_full_block_fraction() {
+        let mock_provider = MockFeeProvider::default();
+        let gas_used = [30_000_000, 12_000_000, 29_990_000, 0, 29_000_000, 30_000_000];
+        for (number, gas_used) in gas_used.into_iter().enumerate() {
+            let header = Header {
+                number: number as u64,
+                gas_used,
+                gas_limit: 30_000_000,
+                base_fee_per_gas: Some(1_000_000_000),
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        // only blocks at the gas limit
+        assert_eq!(eth_api.full_block_fraction(6, 5u64.into(), 0).unwrap(), 2.0 / 6.0);
+        // a transfer doesn't fit into block 2 anymore
+        assert_eq!(eth_api.full_block_fraction(6, 5u64.into(), 21_000).unwrap(), 3.0 / 6.0);
+        assert_eq!(eth_api.full_block_fraction(6, 5u64.into(), 1_000_000).unwrap(), 4.0 / 6.0);
+        assert_eq!(eth_api.full_block_fraction(3, 5u64.into(), 21_000).unwrap(), 1.0 / 3.0);
+        assert_eq!(eth_api.full_block_fraction(2, 4u64.into(), 21_000).unwrap(), 0.0);
+        assert_eq!(eth_api.full_block_fraction(1, 0u64.into(), 0).unwrap(), 1.0);
+        assert_eq!(eth_api.full_block_fraction(0, 5u64.into(), 21_000).unwrap(), 0.0);
+
+        assert!(matches!(
+            eth_api.full_block_fraction(7, 5u64.into(), 0).unwrap_err(),
+            EthApiError::InvalidBlockRange
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_for_epoch() {
+        // modeled after the Merge on mainnet: the first post-Merge block was proposed in slot
+        // 4700013, the last pre-Merge block 13 seconds before it
+        let merge_slot = 4_700_013;
+        let merge_block = 4;
+        let missed_slot = 4_700_040;
+        let schedule = SlotSchedule::MAINNET;
+        assert_eq!(schedule.slot_timestamp(merge_slot), 1_663_224_179);
+
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=60u64 {
+            let (timestamp, difficulty) = if number < merge_block {
+                let timestamp = schedule.slot_timestamp(merge_slot) - (merge_block - number) * 13;
+                (timestamp, U256::from(10_000_000_000_000u64))
+            } else {
+                let mut slot = merge_slot + number - merge_block;
+                if slot >= missed_slot {
+                    slot += 1;
+                }
+                (schedule.slot_timestamp(slot), U256::ZERO)
+            };
+            let header = Header {
+                number,
+                timestamp,
+                difficulty,
+                gas_used: number * 100_000,
+                gas_limit: 30_000_000,
+                base_fee_per_gas: Some(1_000_000_000 + number),
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        // epoch 146876 covers slots 4700032..4700064, which are blocks 23..=53 with the missed
+        // slot
+        assert_eq!(schedule.epoch_slots(146_876), 4_700_032..4_700_064);
+        let fee_history = eth_api.fee_history_for_epoch(146_876, &schedule, None).await.unwrap();
+        assert_eq!(fee_history.oldest_block, U256::from(23));
+        assert_eq!(fee_history.gas_used_ratio.len(), 31);
+        assert_eq!(fee_history, eth_api.fee_history(31, 53u64.into(), None).await.unwrap());
+
+        // the slots can be arbitrary
+        let fee_history = eth_api
+            .fee_history_for_slots(missed_slot - 1..missed_slot + 2, &schedule, None)
+            .await
+            .unwrap();
+        assert_eq!(fee_history.oldest_block, U256::from(30));
+        assert_eq!(fee_history.gas_used_ratio.len(), 2);
+        let fee_history =
+            eth_api.fee_history_for_slots(missed_slot..missed_slot + 1, &schedule, None).await;
+        assert_eq!(fee_history.unwrap(), FeeHistory::default());
+
+        // the current epoch is in progress, the next one didn't start yet
+        let fee_history = eth_api.fee_history_for_epoch(146_877, &schedule, None).await.unwrap();
+        assert_eq!(fee_history.oldest_block, U256::from(54));
+        assert_eq!(fee_history.gas_used_ratio.len(), 7);
+        let fee_history = eth_api.fee_history_for_epoch(146_878, &schedule, None).await.unwrap();
+        assert_eq!(fee_history, FeeHistory::default());
+
+        // the epoch of the Merge starts with pre-Merge blocks
+        assert!(matches!(
+            eth_api.fee_history_for_epoch(146_875, &schedule, None).await.unwrap_err(),
+            EthApiError::InvalidBlockRange
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_block_range() {
+        let schedule = SlotSchedule { genesis_time: 0, seconds_per_slot: 12, slots_per_epoch: 4 };
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..10u64 {
+            let header = Header {
+                number,
+                timestamp: schedule.slot_timestamp(number),
+                gas_used: number * 1_000,
+                gas_limit: 1_000_000,
+                ..Default::default()
+            };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+        let ratio = |number: u64| number as f64 * 1_000.0 / 1_000_000.0;
+
+        // the epoch of slots 8..12 is trimmed to the blocks that exist
+        let fee_history = eth_api.fee_history_for_epoch(2, &schedule, None).await.unwrap();
+        assert_eq!(fee_history.block_range(), Some(8..=9));
+        assert_eq!(fee_history.gas_used_ratio, [8, 9].map(ratio));
+
+        let options =
+            FeeHistoryOptions { order: FeeHistoryOrder::Descending, ..Default::default() };
+        let fee_history =
+            eth_api.fee_history_with_options(4, 9u64.into(), None, options).await.unwrap();
+        let range = fee_history.block_range().unwrap();
+        assert_eq!(range, 6..=9);
+        assert_eq!(fee_history.gas_used_ratio, range.rev().map(ratio).collect::<Vec<_>>());
+
+        let fee_history = eth_api.fee_history_for_epoch(3, &schedule, None).await.unwrap();
+        assert_eq!(fee_history.block_range(), None);
+        assert_eq!(eth_api.fee_history(0, 9u64.into(), None).await.unwrap().block_range(), None);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_from_genesis() {
+        let mock_provider = MockFeeProvider::default();
+        // a pre-EIP-1559 genesis without transactions
+        let genesis = Header { number: 0, gas_limit: 30_000_000, ..Default::default() };
+        mock_provider.add_block(genesis, vec![], vec![]);
+        mock_provider.add_block_with_tips(1, 10, &[1, 2]);
+        let eth_api = eth_api(mock_provider);
+
+        let fee_history = eth_api.fee_history(2, 1u64.into(), Some(vec![50.0])).await.unwrap();
+        assert_eq!(fee_history.oldest_block, U256::ZERO);
+        assert_eq!(fee_history.base_fee_per_gas[0], U256::ZERO);
+        assert_eq!(fee_history.gas_used_ratio[0], 0.0);
+        assert_eq!(fee_history.reward.unwrap()[0], vec![U256::ZERO]);
+
+        // ranges ending at genesis cover at most the genesis block
+        assert!(eth_api
+            .fee_history(0, 0u64.into(), None)
+            .await
+            .unwrap()
+            .base_fee_per_gas
+            .is_empty());
+        let fee_history = eth_api.fee_history(1, 0u64.into(), None).await.unwrap();
+        assert_eq!(fee_history.oldest_block, U256::ZERO);
+        assert_eq!(fee_history.gas_used_ratio.len(), 1);
+        assert!(matches!(
+            eth_api.fee_history(2, 0u64.into(), None).await,
+            Err(EthApiError::InvalidBlockRange)
+        ));
+
+        // ranges reaching below genesis are rejected rather than wrapping around
+        for block_count in [3, 1024, u64::MAX] {
+            assert!(matches!(
+                eth_api.fee_history(block_count, 1u64.into(), Some(vec![50.0])).await,
+                Err(EthApiError::InvalidBlockRange)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_below_pruning_horizon() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 5..=10 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let err = eth_api.fee_history(9, 10u64.into(), None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::BlockPruned { requested: 2, earliest: 5 }));
+        let err = eth_api.base_fee_history(9, 10u64.into()).unwrap_err();
+        assert!(matches!(err, EthApiError::BlockPruned { requested: 2, earliest: 5 }));
+
+        assert!(eth_api.fee_history(6, 10u64.into(), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cached_fee_history_item() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        let eth_api = eth_api(mock_provider.clone());
+
+        let item = FeeHistoryCacheItem {
+            hash: None,
+            base_fee_per_gas: U256::from(7),
+            next_base_fee_per_gas: U256::from(7),
+            gas_used_ratio: 0.5,
+            gas_limit: 30_000_000,
+            timestamp: 1_700_000_000,
+            transaction_count: None,
+            median_reward: None,
+            reward: Some(vec![U256::from(3)]),
+            reward_percentiles: Some(vec![50.0]),
+            reward_mode: RewardMode::Standard,
+            approximate_reward: false,
+            popular_rewards: Vec::new(),
+            cached_at: 0,
+        };
+        eth_api.fee_history_cache.0.lock().await.put(5, item);
+
+        let cached = eth_api.cached_fee_history_item(5).await.unwrap().unwrap();
+        assert_eq!(cached.base_fee_per_gas, U256::from(7));
+        assert_eq!(cached.gas_used_ratio, 0.5);
+        assert_eq!(cached.reward, Some(vec![U256::from(3)]));
+        assert_eq!(cached.reward_percentiles, Some(vec![50.0]));
+
+        // uncached blocks are not fetched, even if they exist
+        assert!(eth_api.cached_fee_history_item(0).await.unwrap().is_none());
+        assert!(eth_api.cached_fee_history_item(6).await.unwrap().is_none());
+        assert_eq!(mock_provider.headers_read(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_compact_fee_history_cache() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=20 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let eth_api = eth_api(mock_provider.clone());
+
+        // scattered queries of old blocks, followed by the most recent blocks
+        for newest in [2u64, 7, 12] {
+            eth_api.fee_history(2, newest.into(), None).await.unwrap();
+        }
+        eth_api.fee_history(5, 20u64.into(), None).await.unwrap();
+        assert_eq!(eth_api.fee_history_cache.0.lock().await.len(), 11);
+
+        assert_eq!(eth_api.compact_fee_history_cache(5).await.unwrap(), 6);
+        {
+            let fee_history_cache = eth_api.fee_history_cache.0.lock().await;
+            let mut retained =
+                fee_history_cache.iter().map(|(block, _)| *block).collect::<Vec<_>>();
+            retained.sort_unstable();
+            assert_eq!(retained, (16..=20).collect::<Vec<_>>());
+        }
+
+        // the window follows the chain tip
+        mock_provider.add_block_with_tips(21, 10, &[]);
+        assert_eq!(eth_api.compact_fee_history_cache(5).await.unwrap(), 1);
+        assert!(!eth_api.fee_history_cache.0.lock().await.contains(&16));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_cache_compaction_task() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=10 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let (signal, shutdown) = reth_tasks::shutdown::signal();
+        let eth_api = eth_api(mock_provider).with_shutdown_signal(shutdown);
+        eth_api.fee_history(11, 10u64.into(), None).await.unwrap();
+
+        let config = FeeHistoryCompactionConfig { interval: Duration::from_millis(10), window: 3 };
+        let handle = eth_api
+            .spawn_fee_history_cache_compaction(config, reth_tasks::TokioTaskExecutor::default());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(eth_api.fee_history_cache.0.lock().await.len(), 3);
+
+        // the task stops on shutdown
+        signal.fire();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_prune_fee_history_cache() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=10 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let eth_api = eth_api(mock_provider.clone());
+
+        eth_api.fee_history(10, 9u64.into(), None).await.unwrap();
+        assert_eq!(eth_api.fee_history_cache.0.lock().await.len(), 10);
+        // nothing is pruned yet
+        assert_eq!(eth_api.prune_fee_history_cache().await.unwrap(), 0);
+
+        mock_provider.prune_below(4);
+        assert_eq!(eth_api.prune_fee_history_cache().await.unwrap(), 4);
+        {
+            let fee_history_cache = eth_api.fee_history_cache.0.lock().await;
+            assert_eq!(fee_history_cache.len(), 6);
+            assert!((0..4).all(|block| !fee_history_cache.contains(&block)));
+            assert!((4..=9).all(|block| fee_history_cache.contains(&block)));
+        }
+
+        // the horizon advances again
+        mock_provider.prune_below(7);
+        assert_eq!(eth_api.prune_fee_history_cache().await.unwrap(), 3);
+        assert_eq!(eth_api.fee_history_cache.0.lock().await.len(), 3);
+        assert!(eth_api.fee_history(4, 10u64.into(), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_prune_fee_history_cache_of_database() {
+        let db = create_test_db::<WriteMap>(EnvKind::RW);
+        db.update(|tx| {
+            for number in 0..=10 {
+                let header = Header {
+                    number,
+                    gas_limit: 30_000_000,
+                    base_fee_per_gas: Some(10),
+                    ..Default::default()
+                };
+                tx.put::<tables::CanonicalHeaders>(number, H256::random())?;
+                tx.put::<tables::Headers>(number, header)?;
+                tx.put::<tables::BlockBodies>(number, StoredBlockBody::default())?;
+            }
+            Ok::<_, reth_interfaces::db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+        let chain_spec = Arc::new(ChainSpecBuilder::mainnet().build());
+        let eth_api = EthApi::new(
+            ShareableDatabase::new(db.clone(), chain_spec),
+            testing_pool(),
+            NoopNetwork::default(),
+            EthStateCache::spawn(NoopProvider::default(), Default::default()),
+        );
+
+        eth_api.fee_history(10, 9u64.into(), None).await.unwrap();
+        assert_eq!(eth_api.prune_fee_history_cache().await.unwrap(), 0);
+
+        // the database drops the blocks below 4
+        db.update(|tx| {
+            for number in 0..4 {
+                tx.delete::<tables::Headers>(number, None)?;
+                tx.delete::<tables::BlockBodies>(number, None)?;
+            }
+            Ok::<_, reth_interfaces::db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(eth_api.prune_fee_history_cache().await.unwrap(), 4);
+        assert!(matches!(
+            eth_api.fee_history(10, 9u64.into(), None).await,
+            Err(EthApiError::BlockPruned { requested: 0, earliest: 4 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_circuit_breaker_serves_cache_only() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=10 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let eth_api =
+            eth_api(mock_provider.clone()).with_fee_history_circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 2,
+                ..Default::default()
+            });
+
+        let cached = eth_api.fee_history(6, 10u64.into(), None).await.unwrap();
+
+        mock_provider.set_failing(true);
+        for _ in 0..2 {
+            let err = eth_api.fee_history(9, 10u64.into(), None).await.unwrap_err();
+            assert!(matches!(err, EthApiError::Internal(_)));
+        }
+        assert!(eth_api.fee_history_circuit_open());
+
+        // the database isn't queried until the cooldown passed, even if it recovered
+        mock_provider.set_failing(false);
+        let err = eth_api.fee_history(9, 10u64.into(), None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::ServiceUnavailable));
+        let served = eth_api.fee_history(6, 10u64.into(), None).await.unwrap();
+        assert_eq!(served.base_fee_per_gas, cached.base_fee_per_gas);
+        assert_eq!(served.gas_used_ratio, cached.gas_used_ratio);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_circuit_breaker_disabled_by_default() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        let eth_api = eth_api(mock_provider.clone());
+
+        mock_provider.set_failing(true);
+        for _ in 0..CircuitBreakerConfig::default().failure_threshold {
+            let err = eth_api.fee_history(1, 0u64.into(), None).await.unwrap_err();
+            assert!(matches!(err, EthApiError::Internal(_)));
+        }
+        mock_provider.set_failing(false);
+        eth_api.fee_history(1, 0u64.into(), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_circuit_breaker_trips_on_slow_queries() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=3 {
+            mock_provider.add_block_with_tips(number, 10, &[]);
+        }
+        let eth_api =
+            eth_api(mock_provider.clone()).with_fee_history_circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 2,
+                slow_query_threshold: Some(Duration::ZERO),
+                ..Default::default()
+            });
+
+        // every database query is slower than the threshold, although all of them succeed
+        eth_api.fee_history(1, 0u64.into(), None).await.unwrap();
+        assert!(!eth_api.fee_history_circuit_open());
+        eth_api.fee_history(1, 1u64.into(), None).await.unwrap();
+        assert!(eth_api.fee_history_circuit_open());
+
+        let err = eth_api.fee_history(1, 2u64.into(), None).await.unwrap_err();
+        assert!(matches!(err, EthApiError::ServiceUnavailable));
+        eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_response_cache() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=3 {
+            mock_provider.add_block_with_tips(number, 10, &[1, 2, 3]);
+        }
+        let eth_api = eth_api(mock_provider.clone())
+            .with_fee_history_response_cache(FeeHistoryResponseCacheConfig::default());
+
+        let response = eth_api.fee_history(3, 3u64.into(), Some(vec![50.0])).await.unwrap();
+
+        // identical requests don't touch the database
+        mock_provider.set_failing(true);
+        let cached = eth_api.fee_history(3, 3u64.into(), Some(vec![50.0])).await.unwrap();
+        assert_eq!(cached.reward, response.reward);
+        assert!(eth_api.fee_history(3, 3u64.into(), Some(vec![60.0])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_serves_popular_percentiles_from_cache() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[number as u128, 5, 9, 2]);
+        }
+        let popular = vec![25.0, 50.0, 75.0];
+        let fresh = eth_api(mock_provider.clone())
+            .fee_history(5, 4u64.into(), Some(popular.clone()))
+            .await
+            .unwrap();
+
+        let eth_api = eth_api(mock_provider.clone()).with_popular_reward_percentiles(Some(1));
+        for _ in 0..2 {
+            eth_api.fee_history(2, 4u64.into(), Some(popular.clone())).await.unwrap();
+        }
+        // caches all blocks for other percentiles, along with the rewards of the popular ones
+        eth_api.fee_history(5, 4u64.into(), Some(vec![10.0, 90.0])).await.unwrap();
+        let cached = eth_api.fee_history_cache.0.lock().await.peek(&2).unwrap().clone();
+        assert_eq!(cached.reward_percentiles, Some(vec![10.0, 90.0]));
+        assert_eq!(cached.popular_rewards.len(), 1);
+        assert_eq!(cached.popular_rewards[0].percentiles, popular);
+
+        mock_provider.set_failing(true);
+        let served = eth_api.fee_history(5, 4u64.into(), Some(popular)).await.unwrap();
+        assert_eq!(served.reward, fresh.reward);
+        assert_eq!(served.base_fee_per_gas, fresh.base_fee_per_gas);
+        assert_eq!(served.gas_used_ratio, fresh.gas_used_ratio);
+
+        // rewards of percentiles that weren't popular still require a database query
+        assert!(eth_api.fee_history(5, 4u64.into(), Some(vec![50.0])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_precomputes_no_popular_percentiles_by_default() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[number as u128, 5, 9, 2]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        for _ in 0..2 {
+            eth_api.fee_history(2, 4u64.into(), Some(vec![50.0])).await.unwrap();
+        }
+        eth_api.fee_history(5, 4u64.into(), Some(vec![10.0, 90.0])).await.unwrap();
+        let cached = eth_api.fee_history_cache.0.lock().await.peek(&4).unwrap().clone();
+        assert!(cached.popular_rewards.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_merges_cached_popular_rewards() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=4u64 {
+            mock_provider.add_block_with_tips(number, 10, &[number as u128, 5, 9, 2]);
+        }
+        let (first, second, other) = (vec![25.0, 75.0], vec![50.0], vec![10.0, 90.0]);
+        let fresh = eth_api(mock_provider.clone())
+            .fee_history(5, 4u64.into(), Some(first.clone()))
+            .await
+            .unwrap();
+
+        let eth_api = eth_api(mock_provider.clone()).with_popular_reward_percentiles(Some(1));
+        for _ in 0..2 {
+            eth_api.fee_history(5, 4u64.into(), Some(first.clone())).await.unwrap();
+        }
+        // caches the rewards of the first popular set along
+        eth_api.fee_history(5, 4u64.into(), Some(second.clone())).await.unwrap();
+        for _ in 0..2 {
+            eth_api.fee_history(5, 4u64.into(), Some(second.clone())).await.unwrap();
+        }
+
+        // the second set is most popular now, re-querying the blocks keeps the first one's rewards
+        eth_api.fee_history(5, 4u64.into(), Some(other)).await.unwrap();
+        let cached = eth_api.fee_history_cache.0.lock().await.peek(&2).unwrap().clone();
+        let cached_percentiles = cached
+            .popular_rewards
+            .iter()
+            .map(|cached| cached.percentiles.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(cached_percentiles, vec![second.clone(), first.clone()]);
+
+        mock_provider.set_failing(true);
+        let served = eth_api.fee_history(5, 4u64.into(), Some(first)).await.unwrap();
+        assert_eq!(served.reward, fresh.reward);
+        eth_api.fee_history(5, 4u64.into(), Some(second)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_cancelled_on_shutdown() {
+        let mock_provider = MockFeeProvider::default();
+        for number in 0..=3 {
+            mock_provider.add_block_with_tips(number, 10, &[1, 2, 3]);
+        }
+        let (signal, shutdown) = reth_tasks::shutdown::signal();
+        let eth_api = eth_api(mock_provider).with_shutdown_signal(shutdown);
+
+        // a concurrent query holds the cache
+        let fee_history_cache = eth_api.fee_history_cache.0.lock().await;
+        let query = eth_api.fee_history(3, 3u64.into(), Some(vec![50.0]));
+        signal.fire();
+
+        // the query doesn't wait for the cache once the node is shutting down
+        let err = query.now_or_never().unwrap().unwrap_err();
+        assert!(matches!(err, EthApiError::Cancelled));
+
+        // nor does it start querying the database
+        drop(fee_history_cache);
+        let err = eth_api.fee_history(3, 3u64.into(), Some(vec![50.0])).await.unwrap_err();
+        assert!(matches!(err, EthApiError::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_zero_gas_limit() {
+        let mock_provider = MockFeeProvider::default();
+        for (number, gas_used) in [(0, 0), (1, 21000)] {
+            let header = Header { number, gas_used, gas_limit: 0, ..Default::default() };
+            mock_provider.add_block(header, vec![], vec![]);
+        }
+        let eth_api = eth_api(mock_provider);
+
+        let fee_history = eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        assert_eq!(fee_history.gas_used_ratio, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_reward_mode_of_options() {
+        assert_eq!(FeeHistoryOptions::default().reward_mode(), RewardMode::Standard);
+        let options = FeeHistoryOptions { exclude_zero_tips: true, ..Default::default() };
+        assert_eq!(options.reward_mode(), RewardMode::ZeroTipsExcluded);
+        let options =
+            FeeHistoryOptions { weight: PercentileWeight::TransactionCount, ..Default::default() };
+        assert_eq!(
+            options.reward_mode(),
+            RewardMode::Custom {
+                interpolation: PercentileInterpolation::Step,
+                weight: PercentileWeight::TransactionCount,
+                exclude_zero_tips: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_interpolated_reward_percentiles() {
+        let sorted = [
+            TxGasAndReward { gas_used: 21000, reward: 1 },
+            TxGasAndReward { gas_used: 42000, reward: 5 },
+            TxGasAndReward { gas_used: 21000, reward: 9 },
+        ];
+        let percentiles = [0.0, 25.0, 50.0, 75.0, 90.0, 100.0];
+
+        let stepped = compute_reward_percentiles(&sorted, 84000, &percentiles);
+        let interpolated = compute_interpolated_reward_percentiles(&sorted, 84000, &percentiles);
+        assert_eq!(stepped, [1u64, 1, 5, 5, 9, 9].map(U256::from).to_vec());
+        // 50% is halfway between the first two transactions, 90% is 60% of the way from the
+        // second to the third transaction, rounded down
+        assert_eq!(interpolated, [1u64, 1, 3, 5, 7, 9].map(U256::from).to_vec());
+        assert!(interpolated
+            .iter()
+            .zip(&stepped)
+            .all(|(interpolated, stepped)| interpolated <= stepped));
+
+        // thresholds beyond the transactions' gas used stick to the last reward
+        assert_eq!(
+            compute_interpolated_reward_percentiles(&sorted, 100000, &[100.0]),
+            vec![U256::from(9)]
+        );
+        assert_eq!(compute_interpolated_reward_percentiles(&[], 0, &[50.0]), vec![U256::ZERO]);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_interpolated_rewards() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        mock_provider.add_block_with_tips(1, 10, &[1, 5, 9, 13]);
+        let eth_api = eth_api(mock_provider);
+        let percentiles = vec![10.0, 50.0, 90.0];
+        let linear = FeeHistoryOptions {
+            interpolation: PercentileInterpolation::Linear,
+            ..Default::default()
+        };
+
+        let stepped = eth_api.fee_history(2, 1u64.into(), Some(percentiles.clone())).await.unwrap();
+        let interpolated = eth_api
+            .fee_history_with_options(2, 1u64.into(), Some(percentiles.clone()), linear)
+            .await
+            .unwrap();
+
+        assert_eq!(stepped.reward.unwrap()[1], [1u64, 5, 13].map(U256::from).to_vec());
+        // the four transactions end at 25%, 50%, 75% and 100% of the gas used
+        assert_eq!(interpolated.reward.unwrap()[1], [1u64, 5, 11].map(U256::from).to_vec());
+
+        // the modes are cached separately
+        let stepped = eth_api.fee_history(2, 1u64.into(), Some(percentiles)).await.unwrap();
+        assert_eq!(stepped.reward.unwrap()[1], [1u64, 5, 13].map(U256::from).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_median_reward() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        // four cheap transfers and an expensive transaction using more gas than all of them
+        let header = Header {
+            number: 1,
+            gas_used: 184_000,
+            gas_limit: 30_000_000,
+            base_fee_per_gas: Some(10),
+            ..Default::default()
+        };
+        let body = (1..=5)
+            .map(|tip| {
+                signed(Transaction::Legacy(TxLegacy { gas_price: 10 + tip, ..Default::default() }))
+            })
+            .collect();
+        let receipts = [21_000, 42_000, 63_000, 84_000, 184_000].map(receipt).to_vec();
+        mock_provider.add_block(header, body, receipts);
+        let eth_api = eth_api(mock_provider);
+        let options = FeeHistoryOptions { include_median_reward: true, ..Default::default() };
+
+        assert_eq!(eth_api.fee_history(2, 1u64.into(), None).await.unwrap().median_reward, None);
+
+        // the median by count would be the tip of 3, but the last transaction uses more than half
+        // of the gas
+        let fee_history =
+            eth_api.fee_history_with_options(2, 1u64.into(), None, options).await.unwrap();
+        assert_eq!(fee_history.median_reward.unwrap(), [0u64, 5].map(U256::from).to_vec());
+        assert_eq!(fee_history.reward, None);
+
+        // along with rewards
+        let fee_history = eth_api
+            .fee_history_with_options(2, 1u64.into(), Some(vec![25.0, 50.0]), options)
+            .await
+            .unwrap();
+        assert_eq!(fee_history.median_reward.unwrap(), [0u64, 5].map(U256::from).to_vec());
+        assert_eq!(fee_history.reward.unwrap()[1], [3u64, 5].map(U256::from).to_vec());
+
+        let descending = FeeHistoryOptions { order: FeeHistoryOrder::Descending, ..options };
+        let fee_history =
+            eth_api.fee_history_with_options(2, 1u64.into(), None, descending).await.unwrap();
+        assert_eq!(fee_history.median_reward.unwrap(), [5u64, 0].map(U256::from).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_count_weighted_rewards() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        // four cheap transfers and an expensive transaction using more gas than all of them
+        let header = Header {
+            number: 1,
+            gas_used: 184_000,
+            gas_limit: 30_000_000,
+            base_fee_per_gas: Some(10),
+            ..Default::default()
+        };
+        let body = (1..=5)
+            .map(|tip| {
+                signed(Transaction::Legacy(TxLegacy { gas_price: 10 + tip, ..Default::default() }))
+            })
+            .collect();
+        let receipts = [21_000, 42_000, 63_000, 84_000, 184_000].map(receipt).to_vec();
+        mock_provider.add_block(header, body, receipts);
+        let eth_api = eth_api(mock_provider);
+        let percentiles = vec![25.0, 50.0, 100.0];
+        let by_count =
+            FeeHistoryOptions { weight: PercentileWeight::TransactionCount, ..Default::default() };
+
+        // the last transaction uses more than half of the gas
+        let by_gas = eth_api.fee_history(2, 1u64.into(), Some(percentiles.clone())).await.unwrap();
+        assert_eq!(by_gas.reward.unwrap()[1], [3u64, 5, 5].map(U256::from).to_vec());
+
+        // but is only one of five transactions, the third is the median one
+        let fee_history = eth_api
+            .fee_history_with_options(2, 1u64.into(), Some(percentiles.clone()), by_count)
+            .await
+            .unwrap();
+        assert_eq!(fee_history.reward.unwrap()[1], [2u64, 3, 5].map(U256::from).to_vec());
+
+        // the modes are cached separately
+        let by_gas = eth_api.fee_history(2, 1u64.into(), Some(percentiles)).await.unwrap();
+        assert_eq!(by_gas.reward.unwrap()[1], [3u64, 5, 5].map(U256::from).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_without_receipts() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 10, &[]);
+        // four cheap transfers and an expensive transaction using more gas than all of them
+        let header = Header {
+            number: 1,
+            gas_used: 184_000,
+            gas_limit: 30_000_000,
+            base_fee_per_gas: Some(10),
+            ..Default::default()
+        };
+        let body = (1..=5)
+            .map(|tip| {
+                signed(Transaction::Legacy(TxLegacy { gas_price: 10 + tip, ..Default::default() }))
+            })
+            .collect();
+        let receipts = [21_000, 42_000, 63_000, 84_000, 184_000].map(receipt).to_vec();
+        mock_provider.add_block(header, body, receipts);
+        let options = FeeHistoryOptions { include_median_reward: true, ..Default::default() };
+        let percentiles = Some(vec![25.0, 50.0]);
+
+        let exact = eth_api(mock_provider.clone())
+            .fee_history_with_options(2, 1u64.into(), percentiles.clone(), options)
+            .await
+            .unwrap();
+        assert!(!exact.approximate_reward);
+        assert_eq!(exact.reward.unwrap()[1], [3u64, 5].map(U256::from).to_vec());
+        assert_eq!(exact.median_reward.unwrap()[1], U256::from(5));
+
+        // without receipts, every transaction is weighted equally instead of failing
+        mock_provider.prune_receipts_below(2);
+        let eth_api = eth_api(mock_provider);
+        let approximate =
+            eth_api.fee_history_with_options(2, 1u64.into(), percentiles, options).await.unwrap();
+        assert!(approximate.approximate_reward);
+        assert_eq!(
+            approximate.reward.unwrap(),
+            vec![vec![U256::ZERO; 2], vec![U256::from(2), U256::from(3)]]
+        );
+        assert_eq!(approximate.median_reward.unwrap()[1], U256::from(3));
+        assert_eq!(approximate.gas_used_ratio, exact.gas_used_ratio);
+
+        // the flag is kept for cached blocks, but only set if rewards are requested
+        let cached = eth_api.fee_history_with_options(2, 1u64.into(), None, options).await.unwrap();
+        assert!(cached.approximate_reward);
+        let fee_history = eth_api.fee_history(2, 1u64.into(), None).await.unwrap();
+        assert!(!fee_history.approximate_reward);
+    }
+
+    #[test]
+    fn test_evenly_weighted_tx_rewards() {
+        let header = Header { gas_used: 100, base_fee_per_gas: Some(10), ..Default::default() };
+        let body = [13, 11, 12]
+            .map(|gas_price| {
+                signed(Transaction::Legacy(TxLegacy { gas_price, ..Default::default() }))
+            })
+            .to_vec();
+        let sorted = evenly_weighted_tx_rewards(&header, &body).unwrap();
+        let sorted = sorted.iter().map(|tx| (tx.gas_used, tx.reward)).collect::<Vec<_>>();
+        assert_eq!(sorted, [(34, 1), (33, 2), (33, 3)]);
+        assert!(evenly_weighted_tx_rewards(&header, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_gas_weighted_median_reward() {
+        let tx = |gas_used, reward| TxGasAndReward { gas_used, reward };
+        assert_eq!(gas_weighted_median_reward(&[]), U256::ZERO);
+        assert_eq!(gas_weighted_median_reward(&[tx(21_000, 7)]), U256::from(7));
+        // exactly half of the gas is reached with the first transaction
+        assert_eq!(gas_weighted_median_reward(&[tx(21_000, 1), tx(21_000, 9)]), U256::from(1));
+        assert_eq!(gas_weighted_median_reward(&[tx(21_000, 1), tx(21_001, 9)]), U256::from(9));
+        // no overflow for extreme gas used
+        assert_eq!(
+            gas_weighted_median_reward(&[tx(u64::MAX, 1), tx(u64::MAX, 2), tx(1, 3)]),
+            U256::from(2)
+        );
+    }
+
+    #[test]
+    fn test_reward_percentiles_with_extreme_values() {
+        let sorted = [
+            TxGasAndReward { gas_used: 1, reward: 0 },
+            TxGasAndReward { gas_used: u64::MAX, reward: u128::MAX - 1 },
+            TxGasAndReward { gas_used: u64::MAX, reward: u128::MAX },
+        ];
+        let rewards = compute_reward_percentiles(&sorted, u64::MAX, &[0.0, 50.0, 100.0]);
+        // the cumulative gas used saturates before the last transaction is reached
+        assert_eq!(rewards, vec![U256::ZERO, U256::from(u128::MAX - 1), U256::from(u128::MAX - 1)]);
+
+        let fractions = gas_used_fraction_above(&sorted, &[U256::from(1u64)], U256::from(1u64));
+        assert_eq!(fractions, vec![1.0]);
+    }
+
+    #[test]
+    fn test_compute_reward_percentiles() {
+        let sorted = [
+            TxGasAndReward { gas_used: 21000, reward: 1 },
+            TxGasAndReward { gas_used: 29000, reward: 3 },
+            TxGasAndReward { gas_used: 50000, reward: 5 },
+        ];
+        let rewards =
+            compute_reward_percentiles(&sorted, 100000, &[0.0, 21.0, 22.0, 50.0, 51.0, 100.0]);
+        assert_eq!(rewards, [1u64, 1, 3, 3, 5, 5].map(U256::from).to_vec());
+
+        assert_eq!(compute_reward_percentiles(&[], 0, &[50.0]), vec![U256::ZERO]);
+    }
+
+    #[test]
+    fn test_compute_reward_percentiles_edge_cases() {
+        let percentiles = [0.0, 10.0, 50.0, 99.9, 100.0];
+
+        // empty blocks, and blocks whose transactions were all filtered out
+        assert_eq!(compute_reward_percentiles(&[], 0, &percentiles), vec![U256::ZERO; 5]);
+        assert_eq!(compute_reward_percentiles(&[], 100000, &percentiles), vec![U256::ZERO; 5]);
+        assert!(compute_reward_percentiles(&[], 0, &[]).is_empty());
+
+        // a single transaction pays all rewards, whether it covers the block's gas used or not
+        let single = [TxGasAndReward { gas_used: 21000, reward: 3 }];
+        for block_gas_used in [0, 21000, 100000] {
+            assert_eq!(
+                compute_reward_percentiles(&single, block_gas_used, &percentiles),
+                vec![U256::from(3); 5]
+            );
+        }
+
+        // equal rewards are returned for all percentiles, regardless of the gas distribution
+        let equal = [1000, 21000, 50000].map(|gas_used| TxGasAndReward { gas_used, reward: 9 });
+        assert_eq!(compute_reward_percentiles(&equal, 72000, &percentiles), vec![U256::from(9); 5]);
+    }
+
+    #[test]
+    fn test_compute_reward_percentiles_reference_vector() {
+        // each transaction uses a quarter of the block's gas
+        let sorted =
+            (1..=4).map(|reward| TxGasAndReward { gas_used: 25, reward }).collect::<Vec<_>>();
+        // (percentile, reward)
+        let cases = [
+            (0.0, 1),
+            (24.0, 1),
+            (25.0, 1),
+            (26.0, 2),
+            (50.0, 2),
+            (51.0, 3),
+            (75.0, 3),
+            (76.0, 4),
+            (100.0, 4),
+        ];
+        let percentiles = cases.map(|(percentile, _)| percentile);
+        let expected = cases.map(|(_, reward)| U256::from(reward as u64)).to_vec();
+        assert_eq!(compute_reward_percentiles(&sorted, 100, &percentiles), expected);
+    }
+
+    #[test]
+    fn test_inclusion_tip_on_idle_chain() {
+        // empty blocks have zero rewards, any tip gets included
+        let mut thresholds = vec![U256::ZERO; 20];
+        assert_eq!(inclusion_tip(&mut thresholds, 1, 0.99), U256::ZERO);
+        assert_eq!(inclusion_tip(&mut [], 1, 0.99), U256::ZERO);
+    }
+
+    #[test]
+    fn test_inclusion_tip_on_congested_chain() {
+        let mut thresholds = (1..=20u64).rev().map(U256::from).collect::<Vec<_>>();
+
+        // meeting the threshold of half the blocks gives a 50% chance for the next block
+        assert_eq!(inclusion_tip(&mut thresholds, 1, 0.5), U256::from(10));
+        // 1 - 0.1^(1/3) ~= 53.6% of the blocks are needed for 90% within 3 blocks
+        assert_eq!(inclusion_tip(&mut thresholds, 3, 0.9), U256::from(11));
+        // higher confidence needs higher tips, more blocks lower tips
+        assert_eq!(inclusion_tip(&mut thresholds, 1, 0.99), U256::from(20));
+        assert_eq!(inclusion_tip(&mut thresholds, 100, 0.9), U256::from(1));
+    }
+
+    #[test]
+    fn test_max_base_fee_within() {
+        assert_eq!(max_base_fee_within(U256::from(800), 1), U256::from(800));
+        assert_eq!(max_base_fee_within(U256::from(800), 3), U256::from(1012));
+        assert_eq!(max_base_fee_within(U256::from(7), 10), U256::from(7));
+        assert_eq!(max_base_fee_within(U256::MAX / U256::from(2), u64::MAX), U256::MAX);
+    }
+
+    #[test]
+    fn test_high_percentiles_of_small_block() {
+        // the block's gas used isn't covered by its only transaction
+        let sorted = [TxGasAndReward { gas_used: 21000, reward: 7 }];
+        let rewards = compute_reward_percentiles(&sorted, 100000, &[0.0, 99.0, 100.0, 100.0]);
+        assert_eq!(rewards, [7u64; 4].map(U256::from).to_vec());
+    }
+
+    #[test]
+    fn test_gas_used_fraction_of_empty_block() {
+        let thresholds = [U256::ZERO, U256::from(1)];
+        assert_eq!(gas_used_fraction_above(&[], &thresholds, U256::from(1u64)), vec![0.0, 0.0]);
+    }
+}
@@ -3,28 +3,53 @@
 //! The entire implementation of the namespace is quite large, hence it is divided across several
 //! files.
 
-use crate::eth::{cache::EthStateCache, signer::EthSigner};
+use crate::eth::{
+    cache::EthStateCache,
+    circuit_breaker::{CircuitBreaker, CircuitBreakerConfig},
+    fee_history_response_cache::{FeeHistoryResponseCache, FeeHistoryResponseCacheConfig},
+    popular_percentiles::PopularPercentiles,
+    signer::EthSigner,
+};
 use async_trait::async_trait;
+use futures::FutureExt;
 use reth_interfaces::Result;
 use reth_network_api::NetworkInfo;
 use reth_primitives::{Address, BlockId, BlockNumberOrTag, ChainInfo, H256, U256, U64};
 use reth_provider::{BlockProvider, EvmEnvProvider, StateProviderBox, StateProviderFactory};
 use reth_rpc_types::{FeeHistoryCache, SyncInfo, SyncStatus};
+use reth_tasks::shutdown::Shutdown;
 use reth_transaction_pool::TransactionPool;
 use std::{num::NonZeroUsize, sync::Arc};
 
 mod block;
 mod call;
+mod fees;
+#[cfg(feature = "arrow")]
+mod record_batch;
 mod server;
 mod sign;
 mod state;
 mod transactions;
 use crate::eth::error::{EthApiError, EthResult};
+pub(crate) use fees::{
+    fee_history_in_wei, fee_in_wei, single_block_fee_history, sorted_tx_gas_and_rewards,
+    validate_reward_percentiles,
+};
+pub use fees::{
+    FeeHistoryCompactionConfig, FeeHistoryOptions, FeeHistoryOrder, FeeHistoryRequest,
+    FeeHistoryReward, SlotSchedule, TipBucketConfig,
+};
 pub use transactions::{EthTransactions, TransactionSource};
 
 /// Cache limit of block-level fee history for `eth_feeHistory` RPC method.
 const FEE_HISTORY_CACHE_LIMIT: usize = 2048;
 
+/// Default limit of the number of rewards (blocks × percentiles) in an `eth_feeHistory` response.
+pub const DEFAULT_MAX_FEE_HISTORY_REWARDS: u64 = 1024 * 100;
+
+/// Default limit of the number of reward percentiles of an `eth_feeHistory` request.
+pub const DEFAULT_MAX_REWARD_PERCENTILES: usize = 100;
+
 /// `Eth` API trait.
 ///
 /// Defines core functionality of the `eth` API implementation.
@@ -47,6 +72,10 @@ pub trait EthApiSpec: EthTransactions + Send + Sync {
 
     /// Returns the [SyncStatus] of the network
     fn sync_status(&self) -> Result<SyncStatus>;
+
+    /// Returns the number of wei in one unit of the chain's fees, the fees of all RPC responses
+    /// are converted to wei with it.
+    fn wei_per_fee_unit(&self) -> U256;
 }
 
 /// `Eth` API implementation.
@@ -62,6 +91,25 @@ pub struct EthApi<Client, Pool, Network> {
     /// All nested fields bundled together.
     inner: Arc<EthApiInner<Client, Pool, Network>>,
     fee_history_cache: FeeHistoryCache,
+    /// Stops `eth_feeHistory` database queries while the provider is degraded.
+    fee_history_circuit_breaker: Option<CircuitBreaker>,
+    /// Caches complete `eth_feeHistory` responses, if enabled.
+    fee_history_response_cache: Option<FeeHistoryResponseCache>,
+    /// The reward percentiles whose rewards are precomputed for cached blocks, if enabled.
+    popular_percentiles: Option<PopularPercentiles>,
+    /// Aborts in-flight queries once the node shuts down, if set.
+    shutdown: Option<Shutdown>,
+    /// Maximum number of blocks in an `eth_feeHistory` response, if limited.
+    max_fee_history_blocks: Option<u64>,
+    /// Maximum number of rewards in an `eth_feeHistory` response, if limited.
+    max_fee_history_rewards: Option<u64>,
+    /// Maximum number of reward percentiles of an `eth_feeHistory` request, if limited.
+    max_reward_percentiles: Option<usize>,
+    /// Maximum number of blocks the chain may have advanced since a fee history cache entry was
+    /// queried, if limited.
+    max_fee_history_cache_age: Option<u64>,
+    /// The number of wei in one unit of the chain's fees.
+    wei_per_fee_unit: U256,
 }
 
 impl<Client, Pool, Network> EthApi<Client, Pool, Network> {
@@ -73,9 +121,120 @@ impl<Client, Pool, Network> EthApi<Client, Pool, Network> {
             fee_history_cache: FeeHistoryCache::new(
                 NonZeroUsize::new(FEE_HISTORY_CACHE_LIMIT).unwrap(),
             ),
+            fee_history_circuit_breaker: None,
+            fee_history_response_cache: None,
+            popular_percentiles: None,
+            shutdown: None,
+            max_fee_history_blocks: None,
+            max_fee_history_rewards: Some(DEFAULT_MAX_FEE_HISTORY_REWARDS),
+            max_reward_percentiles: Some(DEFAULT_MAX_REWARD_PERCENTILES),
+            max_fee_history_cache_age: None,
+            wei_per_fee_unit: U256::from(1u64),
         }
     }
 
+    /// Sets the maximum number of blocks in an `eth_feeHistory` response.
+    ///
+    /// Every block is read from the cache or the database, requests exceeding the limit are
+    /// rejected before any blocks are queried. This bounds the database reads of a single request,
+    /// which the rewards limit only does for requests with reward percentiles.
+    ///
+    /// Disabled by default.
+    pub fn with_max_fee_history_blocks(mut self, max: Option<u64>) -> Self {
+        self.max_fee_history_blocks = max;
+        self
+    }
+
+    /// Sets the maximum number of rewards in an `eth_feeHistory` response, i.e. the number of
+    /// blocks times the number of requested percentiles.
+    ///
+    /// Requests exceeding the limit are rejected, rather than allocating a huge response. `None`
+    /// disables the limit.
+    ///
+    /// Default is [DEFAULT_MAX_FEE_HISTORY_REWARDS]
+    pub fn with_max_fee_history_rewards(mut self, max: Option<u64>) -> Self {
+        self.max_fee_history_rewards = max;
+        self
+    }
+
+    /// Sets the maximum number of reward percentiles of an `eth_feeHistory` request.
+    ///
+    /// Every percentile is computed for every block of the range, requests exceeding the limit
+    /// are rejected before any blocks are queried. `None` disables the limit.
+    ///
+    /// Default is [DEFAULT_MAX_REWARD_PERCENTILES]
+    pub fn with_max_reward_percentiles(mut self, max: Option<usize>) -> Self {
+        self.max_reward_percentiles = max;
+        self
+    }
+
+    /// Sets the maximum number of blocks the chain may advance after a block's fees were cached
+    /// before they're queried from the database again.
+    ///
+    /// Cached fees don't change without a reorg, so they're served regardless of their age by
+    /// default. With a bound, responses are guaranteed to be no older than `max_age` blocks, at
+    /// the cost of querying the database more often. `None` disables the bound.
+    pub fn with_max_fee_history_cache_age(mut self, max_age: Option<u64>) -> Self {
+        self.max_fee_history_cache_age = max_age;
+        self
+    }
+
+    /// Enables the circuit breaker guarding the database queries of `eth_feeHistory`.
+    ///
+    /// Once the provider failed repeatedly, only fully cached ranges are served until the cooldown
+    /// passed. Disabled by default.
+    pub fn with_fee_history_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.fee_history_circuit_breaker = Some(CircuitBreaker::new(config));
+        self
+    }
+
+    /// Enables caching of complete `eth_feeHistory` responses.
+    pub fn with_fee_history_response_cache(
+        mut self,
+        config: FeeHistoryResponseCacheConfig,
+    ) -> Self {
+        self.fee_history_response_cache = Some(FeeHistoryResponseCache::new(config));
+        self
+    }
+
+    /// Sets the number of most requested reward percentile sets whose rewards are precomputed for
+    /// every block added to the fee history cache, so repeated `eth_feeHistory` requests for them
+    /// are served from the cache, e.g.
+    /// [DEFAULT_POPULAR_PERCENTILE_SETS](crate::eth::DEFAULT_POPULAR_PERCENTILE_SETS).
+    ///
+    /// The precomputation costs every database query with reward percentiles the rewards of the
+    /// additional sets, so it's disabled by default.
+    pub fn with_popular_reward_percentiles(mut self, max_sets: Option<usize>) -> Self {
+        self.popular_percentiles = max_sets.map(PopularPercentiles::new);
+        self
+    }
+
+    /// Sets the number of wei in one unit of the chain's base fees and gas prices, for chains that
+    /// denominate their fees differently.
+    ///
+    /// All fees returned by the RPC are converted from the chain's unit to wei, see
+    /// [EthApiSpec::wei_per_fee_unit]. Default is `1`, as on Ethereum.
+    pub fn with_wei_per_fee_unit(mut self, wei_per_fee_unit: u64) -> Self {
+        self.wei_per_fee_unit = U256::from(wei_per_fee_unit);
+        self
+    }
+
+    /// Sets the signal that cancels long running queries, so they don't delay a shutdown.
+    pub fn with_shutdown_signal(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Returns [EthApiError::Cancelled] if the shutdown signal was fired.
+    pub(crate) fn ensure_not_shutting_down(&self) -> EthResult<()> {
+        if let Some(shutdown) = &self.shutdown {
+            if shutdown.clone().now_or_never().is_some() {
+                return Err(EthApiError::Cancelled)
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the state cache frontend
     pub(crate) fn cache(&self) -> &EthStateCache {
         &self.inner.eth_cache
@@ -219,6 +378,10 @@ where
         };
         Ok(status)
     }
+
+    fn wei_per_fee_unit(&self) -> U256 {
+        self.wei_per_fee_unit
+    }
 }
 
 /// Container type `EthApi`
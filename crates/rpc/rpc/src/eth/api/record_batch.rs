@@ -0,0 +1,176 @@
+//! Fee history as Arrow record batches, for analytics pipelines colocated with the node.
+
+use crate::eth::{error::EthResult, EthApi};
+use arrow::{
+    array::{ArrayRef, Decimal256Array, Float64Array, UInt64Array},
+    datatypes::{i256, DataType, Field, Schema, DECIMAL256_MAX_PRECISION},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use reth_primitives::{BlockId, U256};
+use reth_provider::{BlockProvider, EvmEnvProvider, StateProviderFactory};
+use reth_rpc_types::FeeHistory;
+use std::sync::Arc;
+
+/// The type of the fee columns: the widest decimals supported by arrow, which hold fees below
+/// `10^76` wei.
+const FEE_TYPE: DataType = DataType::Decimal256(DECIMAL256_MAX_PRECISION, 0);
+
+impl<Client, Pool, Network> EthApi<Client, Pool, Network>
+where
+    Client: BlockProvider + StateProviderFactory + EvmEnvProvider + 'static,
+{
+    /// Returns the fee history of the given range as an Arrow [RecordBatch], so colocated
+    /// consumers don't have to parse JSON.
+    ///
+    /// The batch has one row per block of the range in ascending order, with the non-nullable
+    /// columns:
+    ///
+    /// | column                | type                | value                                 |
+    /// |-----------------------|---------------------|---------------------------------------|
+    /// | `number`              | `UInt64`            | block number                          |
+    /// | `base_fee_per_gas`    | `Decimal256(76, 0)` | base fee per gas in wei               |
+    /// | `gas_used_ratio`      | `Float64`           | gas used divided by the gas limit     |
+    /// | `reward_<percentile>` | `Decimal256(76, 0)` | reward in wei, one per percentile     |
+    ///
+    /// The columns match the ones of [EthApi::fee_history_csv]. The range is resolved like
+    /// [EthApi::fee_history], whose response is materialized.
+    pub async fn fee_history_record_batch(
+        &self,
+        block_count: u64,
+        newest_block: BlockId,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> EthResult<RecordBatch> {
+        let percentiles = reward_percentiles.clone().unwrap_or_default();
+        let fee_history = self.fee_history(block_count, newest_block, reward_percentiles).await?;
+        Ok(fee_history_record_batch(&fee_history, &percentiles)?)
+    }
+}
+
+/// Materializes the fee history as a record batch, with a reward column for each of the
+/// `percentiles`.
+fn fee_history_record_batch(
+    fee_history: &FeeHistory,
+    percentiles: &[f64],
+) -> Result<RecordBatch, ArrowError> {
+    let block_count = fee_history.gas_used_ratio.len();
+    let oldest_block = fee_history.oldest_block.to::<u64>();
+
+    let mut fields = vec![
+        Field::new("number", DataType::UInt64, false),
+        Field::new("base_fee_per_gas", FEE_TYPE, false),
+        Field::new("gas_used_ratio", DataType::Float64, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(oldest_block..oldest_block + block_count as u64)),
+        fee_column(
+            (0..block_count)
+                .map(|index| fee_history.base_fee_per_gas.get(index).copied().unwrap_or_default()),
+        )?,
+        Arc::new(Float64Array::from(fee_history.gas_used_ratio.clone())),
+    ];
+
+    let rewards = fee_history.reward.as_deref().unwrap_or_default();
+    for (column, percentile) in percentiles.iter().enumerate() {
+        fields.push(Field::new(format!("reward_{percentile}"), FEE_TYPE, false));
+        columns.push(fee_column((0..block_count).map(|index| {
+            rewards.get(index).and_then(|rewards| rewards.get(column)).copied().unwrap_or_default()
+        }))?);
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+/// Returns a fee column with the given fees.
+fn fee_column(fees: impl Iterator<Item = U256>) -> Result<ArrayRef, ArrowError> {
+    let max = U256::from(10u64).pow(U256::from(DECIMAL256_MAX_PRECISION));
+    let fees = fees
+        .map(|fee| {
+            if fee >= max {
+                return Err(ArrowError::InvalidArgumentError(format!(
+                    "fee {fee} exceeds the precision of {DECIMAL256_MAX_PRECISION} digits"
+                )))
+            }
+            Ok(i256::from_be_bytes(fee.to_be_bytes::<32>()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let column = Decimal256Array::from_iter_values(fees)
+        .with_precision_and_scale(DECIMAL256_MAX_PRECISION, 0)?;
+    Ok(Arc::new(column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::cache::EthStateCache;
+    use arrow::array::Array;
+    use reth_network_api::test_utils::NoopNetwork;
+    use reth_provider::test_utils::{MockFeeProvider, NoopProvider};
+    use reth_transaction_pool::test_utils::{testing_pool, TestPool};
+
+    fn eth_api(provider: MockFeeProvider) -> EthApi<MockFeeProvider, TestPool, NoopNetwork> {
+        EthApi::new(
+            provider,
+            testing_pool(),
+            NoopNetwork::default(),
+            EthStateCache::spawn(NoopProvider::default(), Default::default()),
+        )
+    }
+
+    /// Reads the fees of a decimal column back.
+    fn fees(batch: &RecordBatch, name: &str) -> Vec<U256> {
+        let column = batch.column(batch.schema().index_of(name).unwrap());
+        let column = column.as_any().downcast_ref::<Decimal256Array>().unwrap();
+        assert_eq!(column.null_count(), 0);
+        column.values().iter().map(|fee| U256::from_be_bytes(fee.to_be_bytes())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_record_batch() {
+        let mock_provider = MockFeeProvider::default();
+        mock_provider.add_block_with_tips(0, 7, &[1]);
+        mock_provider.add_block_with_tips(1, 10, &[3, 1, 2]);
+        mock_provider.add_block_with_tips(2, 14, &[]);
+        let eth_api = eth_api(mock_provider);
+        let percentiles = vec![12.5, 50.0];
+
+        let batch = eth_api
+            .fee_history_record_batch(3, 2u64.into(), Some(percentiles.clone()))
+            .await
+            .unwrap();
+        let full = eth_api.fee_history(3, 2u64.into(), Some(percentiles)).await.unwrap();
+
+        let schema = batch.schema();
+        let names = schema.fields().iter().map(|field| field.name().as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["number", "base_fee_per_gas", "gas_used_ratio", "reward_12.5", "reward_50"]
+        );
+        assert!(schema.fields().iter().all(|field| !field.is_nullable()));
+        assert_eq!(batch.num_rows(), full.gas_used_ratio.len());
+
+        let numbers = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(numbers.values().to_vec(), vec![0, 1, 2]);
+        assert_eq!(fees(&batch, "base_fee_per_gas"), full.base_fee_per_gas[..batch.num_rows()]);
+        let ratios = batch.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(ratios.values().to_vec(), full.gas_used_ratio);
+
+        let rewards = full.reward.unwrap();
+        for (column, name) in ["reward_12.5", "reward_50"].into_iter().enumerate() {
+            let expected = rewards.iter().map(|rewards| rewards[column]).collect::<Vec<_>>();
+            assert_eq!(fees(&batch, name), expected);
+        }
+
+        // without percentiles there are no reward columns
+        let batch = eth_api.fee_history_record_batch(3, 2u64.into(), None).await.unwrap();
+        assert_eq!(batch.num_columns(), 3);
+    }
+
+    #[test]
+    fn test_fee_column_precision() {
+        let max = U256::from(10u64).pow(U256::from(76u64));
+        assert!(fee_column([max - U256::from(1u64)].into_iter()).is_ok());
+        assert!(fee_column([max].into_iter()).is_err());
+        assert!(fee_column([U256::MAX].into_iter()).is_err());
+    }
+}
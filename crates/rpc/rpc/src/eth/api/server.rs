@@ -12,20 +12,18 @@ use crate::{
 use jsonrpsee::core::RpcResult as Result;
 use reth_primitives::{
     serde_helper::JsonStorageKey, AccessListWithGasUsed, Address, BlockId, BlockNumberOrTag, Bytes,
-    Header, H256, H64, U256, U64,
+    H256, H64, U256, U64,
 };
 use reth_provider::{BlockProvider, EvmEnvProvider, HeaderProvider, StateProviderFactory};
 use reth_rpc_api::EthApiServer;
 use reth_rpc_types::{
-    state::StateOverride, CallRequest, EIP1186AccountProofResponse, FeeHistory,
-    FeeHistoryCacheItem, Index, RichBlock, SyncStatus, TransactionReceipt, TransactionRequest,
-    Work,
+    state::StateOverride, CallRequest, EIP1186AccountProofResponse, FeeHistory, Index, RichBlock,
+    SyncStatus, TransactionReceipt, TransactionRequest, Work,
 };
 use reth_transaction_pool::TransactionPool;
 
 use reth_network_api::NetworkInfo;
 use serde_json::Value;
-use std::collections::BTreeMap;
 use tracing::trace;
 
 #[async_trait::async_trait]
@@ -254,14 +252,6 @@ where
         Err(internal_rpc_err("unimplemented"))
     }
 
-    // FeeHistory is calculated based on lazy evaluation of fees for historical blocks, and further
-    // caching of it in the LRU cache.
-    // When new RPC call is executed, the cache gets locked, we check it for the historical fees
-    // according to the requested block range, and fill any cache misses (in both RPC response
-    // and cache itself) with the actual data queried from the database.
-    // To minimize the number of database seeks required to query the missing data, we calculate the
-    // first non-cached block number and last non-cached block number. After that, we query this
-    // range of consecutive blocks from the database.
     /// Handler for: `eth_feeHistory`
     async fn fee_history(
         &self,
@@ -270,94 +260,8 @@ where
         reward_percentiles: Option<Vec<f64>>,
     ) -> Result<FeeHistory> {
         trace!(target: "rpc::eth", ?block_count, ?newest_block, ?reward_percentiles, "Serving eth_feeHistory");
-        let block_count = block_count.as_u64();
-
-        if block_count == 0 {
-            return Ok(FeeHistory::default())
-        }
-
-        let Some(end_block) = self.inner.client.block_number_for_id(newest_block).to_rpc_result()? else { return Err(EthApiError::UnknownBlockNumber.into())};
-
-        if end_block < block_count {
-            return Err(EthApiError::InvalidBlockRange.into())
-        }
-
-        let start_block = end_block - block_count;
-
-        let mut fee_history_cache = self.fee_history_cache.0.lock().await;
-
-        // Sorted map that's populated in two rounds:
-        // 1. Cache entries until first non-cached block
-        // 2. Database query from the first non-cached block
-        let mut fee_history_cache_items = BTreeMap::new();
-
-        let mut first_non_cached_block = None;
-        let mut last_non_cached_block = None;
-        for block in start_block..=end_block {
-            // Check if block exists in cache, and move it to the head of the list if so
-            if let Some(fee_history_cache_item) = fee_history_cache.get(&block) {
-                fee_history_cache_items.insert(block, fee_history_cache_item.clone());
-            } else {
-                // If block doesn't exist in cache, set it as a first non-cached block to query it
-                // from the database
-                first_non_cached_block.get_or_insert(block);
-                // And last non-cached block, so we could query the database until we reach it
-                last_non_cached_block = Some(block);
-            }
-        }
-
-        // If we had any cache misses, query the database starting with the first non-cached block
-        // and ending with the last
-        if let (Some(start_block), Some(end_block)) =
-            (first_non_cached_block, last_non_cached_block)
-        {
-            let headers: Vec<Header> =
-                self.inner.client.headers_range(start_block..=end_block).to_rpc_result()?;
-
-            // We should receive exactly the amount of blocks missing from the cache
-            if headers.len() != (end_block - start_block + 1) as usize {
-                return Err(EthApiError::InvalidBlockRange.into())
-            }
-
-            for header in headers {
-                let base_fee_per_gas = header.base_fee_per_gas.
-                        unwrap_or_default(). // Zero for pre-EIP-1559 blocks
-                        try_into().unwrap(); // u64 -> U256 won't fail
-                let gas_used_ratio = header.gas_used as f64 / header.gas_limit as f64;
-
-                let fee_history_cache_item = FeeHistoryCacheItem {
-                    hash: None,
-                    base_fee_per_gas,
-                    gas_used_ratio,
-                    reward: None, // TODO: calculate rewards per transaction
-                };
-
-                // Insert missing cache entries in the map for further response composition from it
-                fee_history_cache_items.insert(header.number, fee_history_cache_item.clone());
-                // And populate the cache with new entries
-                fee_history_cache.push(header.number, fee_history_cache_item);
-            }
-        }
-
-        let oldest_block_hash = self.inner.client.block_hash(start_block).to_rpc_result()?.unwrap();
-
-        fee_history_cache_items.get_mut(&start_block).unwrap().hash = Some(oldest_block_hash);
-        fee_history_cache.get_mut(&start_block).unwrap().hash = Some(oldest_block_hash);
-
-        // `fee_history_cache_items` now contains full requested block range (populated from both
-        // cache and database), so we can iterate over it in order and populate the response fields
-        Ok(FeeHistory {
-            base_fee_per_gas: fee_history_cache_items
-                .values()
-                .map(|item| item.base_fee_per_gas)
-                .collect(),
-            gas_used_ratio: fee_history_cache_items
-                .values()
-                .map(|item| item.gas_used_ratio)
-                .collect(),
-            oldest_block: U256::from_be_bytes(oldest_block_hash.0),
-            reward: None,
-        })
+        Ok(EthApi::fee_history(self, block_count.as_u64(), newest_block, reward_percentiles)
+            .await?)
     }
 
     /// Handler for: `eth_maxPriorityFeePerGas`
@@ -447,9 +351,10 @@ mod tests {
     };
     use rand::random;
     use reth_network_api::test_utils::NoopNetwork;
-    use reth_primitives::{Block, BlockNumberOrTag, Header, H256, U256};
+    use reth_primitives::{basefee::next_base_fee, Block, BlockNumberOrTag, Header, H256, U256};
     use reth_provider::test_utils::{MockEthProvider, NoopProvider};
     use reth_rpc_api::EthApiServer;
+    use reth_rpc_types::{FeeHistoryCacheItem, RewardMode};
     use reth_transaction_pool::test_utils::testing_pool;
 
     #[tokio::test]
@@ -462,7 +367,9 @@ mod tests {
             EthStateCache::spawn(NoopProvider::default(), Default::default()),
         );
 
-        let response = eth_api.fee_history(1.into(), BlockNumberOrTag::Latest.into(), None).await;
+        let response =
+            EthApiServer::fee_history(&eth_api, 2.into(), BlockNumberOrTag::Latest.into(), None)
+                .await;
         assert!(matches!(response, RpcResult::Err(RpcError::Call(CallError::Custom(_)))));
         let Err(RpcError::Call(CallError::Custom(error_object))) = response else { unreachable!() };
         assert_eq!(error_object.code(), INVALID_PARAMS_CODE);
@@ -470,17 +377,18 @@ mod tests {
         let block_count = 10;
         let newest_block = 1337;
 
-        let mut oldest_block = None;
         let mut gas_used_ratios = Vec::new();
         let mut base_fees_per_gas = Vec::new();
+        let mut next_base_fee_per_gas = U256::ZERO;
 
         let mock_provider = MockEthProvider::default();
 
-        for i in (0..=block_count).rev() {
+        for i in (0..block_count).rev() {
             let hash = H256::random();
-            let gas_limit: u64 = random();
-            let gas_used: u64 = random();
-            let base_fee_per_gas: Option<u64> = random::<bool>().then(random);
+            // valid blocks have a gas limit and never use more gas than it
+            let gas_limit = random::<u32>() as u64 + 1;
+            let gas_used = random::<u64>() % (gas_limit + 1);
+            let base_fee_per_gas: Option<u64> = random::<bool>().then(|| random::<u32>() as u64);
 
             let header = Header {
                 number: newest_block - i,
@@ -489,15 +397,16 @@ mod tests {
                 base_fee_per_gas,
                 ..Default::default()
             };
+            next_base_fee_per_gas = U256::from(next_base_fee(&header));
 
             mock_provider.add_block(hash, Block { header: header.clone(), ..Default::default() });
             mock_provider.add_header(hash, header);
 
-            oldest_block.get_or_insert(hash);
             gas_used_ratios.push(gas_used as f64 / gas_limit as f64);
             base_fees_per_gas
                 .push(base_fee_per_gas.map(|fee| U256::try_from(fee).unwrap()).unwrap_or_default());
         }
+        base_fees_per_gas.push(next_base_fee_per_gas);
 
         let eth_api = EthApi::new(
             mock_provider,
@@ -506,17 +415,84 @@ mod tests {
             EthStateCache::spawn(NoopProvider::default(), Default::default()),
         );
 
-        let response =
-            eth_api.fee_history((newest_block + 1).into(), newest_block.into(), None).await;
+        let response = EthApiServer::fee_history(
+            &eth_api,
+            (newest_block + 2).into(),
+            newest_block.into(),
+            None,
+        )
+        .await;
         assert!(matches!(response, RpcResult::Err(RpcError::Call(CallError::Custom(_)))));
         let Err(RpcError::Call(CallError::Custom(error_object))) = response else { unreachable!() };
         assert_eq!(error_object.code(), INVALID_PARAMS_CODE);
 
         let fee_history =
-            eth_api.fee_history(block_count.into(), newest_block.into(), None).await.unwrap();
+            EthApiServer::fee_history(&eth_api, block_count.into(), newest_block.into(), None)
+                .await
+                .unwrap();
 
         assert_eq!(fee_history.base_fee_per_gas, base_fees_per_gas);
         assert_eq!(fee_history.gas_used_ratio, gas_used_ratios);
-        assert_eq!(fee_history.oldest_block, U256::from_be_bytes(oldest_block.unwrap().0));
+        assert_eq!(fee_history.oldest_block, U256::from(newest_block + 1 - block_count));
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_prefers_database_over_stale_cache() {
+        let newest_block = 10;
+        let block_count: u64 = 10;
+
+        let mock_provider = MockEthProvider::default();
+        for number in 0..=newest_block {
+            let hash = H256::random();
+            let header = Header {
+                number,
+                gas_limit: 100,
+                gas_used: 50,
+                base_fee_per_gas: Some(7),
+                ..Default::default()
+            };
+            mock_provider.add_block(hash, Block { header: header.clone(), ..Default::default() });
+            mock_provider.add_header(hash, header);
+        }
+
+        let eth_api = EthApi::new(
+            mock_provider,
+            testing_pool(),
+            NoopNetwork::default(),
+            EthStateCache::spawn(NoopProvider::default(), Default::default()),
+        );
+
+        // a stale entry in between non-cached blocks is refetched from the database
+        eth_api.fee_history_cache.0.lock().await.push(
+            5,
+            FeeHistoryCacheItem {
+                hash: None,
+                base_fee_per_gas: U256::from(1337),
+                next_base_fee_per_gas: U256::from(1337),
+                gas_used_ratio: 1.0,
+                gas_limit: 100,
+                timestamp: 0,
+                transaction_count: None,
+                median_reward: None,
+                reward: None,
+                reward_percentiles: None,
+                reward_mode: RewardMode::Standard,
+                approximate_reward: false,
+                popular_rewards: Vec::new(),
+                cached_at: 0,
+            },
+        );
+
+        let fee_history =
+            EthApiServer::fee_history(&eth_api, block_count.into(), newest_block.into(), None)
+                .await
+                .unwrap();
+
+        assert!(fee_history.base_fee_per_gas.iter().all(|fee| *fee == U256::from(7)));
+        assert!(fee_history.gas_used_ratio.iter().all(|ratio| *ratio == 0.5));
+        assert_eq!(
+            eth_api.fee_history_cache.0.lock().await.get(&5).unwrap().base_fee_per_gas,
+            U256::from(7)
+        );
     }
 }
@@ -0,0 +1,200 @@
+//! A circuit breaker that stops issuing queries against a degraded provider.
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Settings of a [CircuitBreaker].
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures after which the breaker opens.
+    pub failure_threshold: u32,
+    /// Failures are only considered consecutive if they happen within this window of the first
+    /// one.
+    pub failure_window: Duration,
+    /// How long the breaker stays open before queries are attempted again.
+    pub cooldown: Duration,
+    /// Successful queries that take longer than this are counted as failures, if set.
+    ///
+    /// Slow queries don't necessarily mean the provider is degraded, e.g. large ranges take long
+    /// on a healthy database as well, so only provider errors count by default.
+    pub slow_query_threshold: Option<Duration>,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            failure_window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            slow_query_threshold: None,
+        }
+    }
+}
+
+/// Tracks consecutive provider failures and rejects queries for a cooldown period once too many
+/// of them happened within the configured window.
+///
+/// This is cheap to clone, all clones share the same state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<CircuitBreakerState>>,
+}
+
+// === impl CircuitBreaker ===
+
+impl CircuitBreaker {
+    /// Creates a new, closed circuit breaker.
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self { config, state: Default::default() }
+    }
+
+    /// Returns the configuration of the breaker.
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    /// Returns `true` if the breaker is open and queries should not be issued.
+    pub fn is_open(&self) -> bool {
+        self.is_open_at(Instant::now())
+    }
+
+    /// Records the outcome of a query that took `elapsed`.
+    ///
+    /// Queries slower than [CircuitBreakerConfig::slow_query_threshold] count as failures.
+    pub fn record(&self, success: bool, elapsed: Duration) {
+        let slow = self.config.slow_query_threshold.map_or(false, |threshold| elapsed > threshold);
+        if success && !slow {
+            self.record_success()
+        } else {
+            self.record_failure()
+        }
+    }
+
+    /// Resets the consecutive failure count.
+    pub fn record_success(&self) {
+        let mut state = self.lock();
+        state.consecutive_failures = 0;
+        state.first_failure = None;
+    }
+
+    /// Records a failure, opening the breaker if the threshold is reached.
+    pub fn record_failure(&self) {
+        self.record_failure_at(Instant::now())
+    }
+
+    fn is_open_at(&self, now: Instant) -> bool {
+        self.lock().open_until.map_or(false, |open_until| now < open_until)
+    }
+
+    fn record_failure_at(&self, now: Instant) {
+        let mut state = self.lock();
+
+        // failures outside of the window start a new streak
+        let within_window = state
+            .first_failure
+            .map_or(false, |first| now.duration_since(first) <= self.config.failure_window);
+        if !within_window {
+            state.consecutive_failures = 0;
+            state.first_failure = Some(now);
+        }
+
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.config.failure_threshold {
+            state.open_until = Some(now + self.config.cooldown);
+            state.consecutive_failures = 0;
+            state.first_failure = None;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CircuitBreakerState> {
+        // the state is always left consistent, so a poisoned lock can safely be reused
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(CircuitBreakerConfig::default())
+    }
+}
+
+#[derive(Debug, Default)]
+struct CircuitBreakerState {
+    consecutive_failures: u32,
+    first_failure: Option<Instant>,
+    open_until: Option<Instant>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 3,
+            failure_window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            slow_query_threshold: Some(Duration::from_secs(1)),
+        })
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_failure_at(now);
+        breaker.record_failure_at(now);
+        assert!(!breaker.is_open_at(now));
+
+        breaker.record_failure_at(now);
+        assert!(breaker.is_open_at(now));
+        assert!(breaker.is_open_at(now + Duration::from_secs(29)));
+        assert!(!breaker.is_open_at(now + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_failure_at(now);
+        breaker.record_failure_at(now);
+        breaker.record_success();
+        breaker.record_failure_at(now);
+        assert!(!breaker.is_open_at(now));
+    }
+
+    #[test]
+    fn failures_outside_window_start_new_streak() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_failure_at(now);
+        breaker.record_failure_at(now);
+        let later = now + Duration::from_secs(11);
+        breaker.record_failure_at(later);
+        assert!(!breaker.is_open_at(later));
+    }
+
+    #[test]
+    fn slow_queries_count_as_failures() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record(true, Duration::from_secs(2));
+        }
+        assert!(breaker.is_open());
+    }
+
+    #[test]
+    fn slow_queries_are_successes_by_default() {
+        let breaker = CircuitBreaker::default();
+        for _ in 0..breaker.config().failure_threshold {
+            breaker.record(true, Duration::from_secs(60));
+        }
+        assert!(!breaker.is_open());
+    }
+}
@@ -29,6 +29,50 @@ pub enum EthApiError {
     UnknownBlockOrTxIndex,
     #[error("Invalid block range")]
     InvalidBlockRange,
+    /// Thrown when the requested block range reaches below the earliest block that wasn't pruned
+    #[error("block {requested} is pruned, earliest available block is {earliest}")]
+    BlockPruned { requested: u64, earliest: u64 },
+    /// Thrown when the reward percentiles of `eth_feeHistory` are out of range or not
+    /// monotonically increasing
+    #[error("Invalid reward percentiles")]
+    InvalidRewardPercentiles,
+    /// Thrown when an `eth_feeHistory` response would contain more blocks than allowed
+    #[error("too many fee history blocks: {requested} > {max}")]
+    TooManyFeeHistoryBlocks { requested: u64, max: u64 },
+    /// Thrown when an `eth_feeHistory` response would contain more rewards than allowed
+    #[error("too many fee history rewards: {requested} > {max}, use fewer blocks or percentiles")]
+    TooManyFeeHistoryRewards { requested: u64, max: u64 },
+    /// Thrown when more reward percentiles are requested from `eth_feeHistory` than allowed
+    #[error("too many reward percentiles: {requested} > {max}")]
+    TooManyRewardPercentiles { requested: usize, max: usize },
+    /// Thrown when a fee estimate is requested for zero blocks or a confidence outside of `(0, 1)`
+    #[error("Invalid inclusion target")]
+    InvalidInclusionTarget,
+    /// Thrown when a fee spike is checked over zero blocks or with a non positive multiplier
+    #[error("Invalid fee spike window or multiplier")]
+    InvalidFeeSpikeParams,
+    /// Thrown when fees are suggested with a base fee multiplier below one or not finite
+    #[error("Invalid base fee multiplier")]
+    InvalidBaseFeeMultiplier,
+    /// Thrown when a moving average of rewards is requested with a decay outside of `[0, 1)`
+    #[error("Invalid reward decay")]
+    InvalidRewardDecay,
+    /// Thrown when a base fee threshold subscription has neither a lower nor an upper threshold
+    #[error("Missing base fee threshold")]
+    MissingBaseFeeThreshold,
+    /// Thrown when the header of a block that was resolved for the request is missing, e.g.
+    /// because it was pruned in between
+    #[error("header of block {0} not found, retry with a lower newest block")]
+    HeaderNotFound(u64),
+    /// Thrown when the provider returned headers that don't match the requested block range
+    #[error("provider returned block {actual} in place of block {expected}")]
+    ProviderRangeMismatch { expected: u64, actual: u64 },
+    /// Thrown when a query was aborted because the node is shutting down
+    #[error("request cancelled, node is shutting down")]
+    Cancelled,
+    /// Thrown when the provider is degraded and queries are rejected until it recovers
+    #[error("service temporarily unavailable")]
+    ServiceUnavailable,
     /// An internal error where prevrandao is not set in the evm's environment
     #[error("Prevrandao not in th EVM's environment after merge")]
     PrevrandaoNotSet,
@@ -60,6 +104,10 @@ pub enum EthApiError {
     /// When tracer config does not match the tracer
     #[error("invalid tracer config")]
     InvalidTracerConfig,
+    /// Thrown when the fee history couldn't be exported as a record batch
+    #[cfg(feature = "arrow")]
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
 }
 
 impl From<EthApiError> for RpcError {
@@ -69,6 +117,15 @@ impl From<EthApiError> for RpcError {
             EthApiError::InvalidTransactionSignature |
             EthApiError::EmptyRawTransactionData |
             EthApiError::InvalidBlockRange |
+            EthApiError::InvalidRewardPercentiles |
+            EthApiError::InvalidInclusionTarget |
+            EthApiError::InvalidFeeSpikeParams |
+            EthApiError::InvalidBaseFeeMultiplier |
+            EthApiError::InvalidRewardDecay |
+            EthApiError::MissingBaseFeeThreshold |
+            EthApiError::TooManyFeeHistoryBlocks { .. } |
+            EthApiError::TooManyFeeHistoryRewards { .. } |
+            EthApiError::TooManyRewardPercentiles { .. } |
             EthApiError::ConflictingFeeFieldsInRequest |
             EthApiError::Signing(_) |
             EthApiError::BothStateAndStateDiffInOverride(_) |
@@ -78,11 +135,19 @@ impl From<EthApiError> for RpcError {
             EthApiError::PrevrandaoNotSet |
             EthApiError::InvalidBlockData(_) |
             EthApiError::Internal(_) |
+            EthApiError::ServiceUnavailable |
+            EthApiError::ProviderRangeMismatch { .. } |
+            EthApiError::Cancelled |
             EthApiError::TransactionNotFound => internal_rpc_err(error.to_string()),
-            EthApiError::UnknownBlockNumber | EthApiError::UnknownBlockOrTxIndex => {
+            EthApiError::UnknownBlockNumber |
+            EthApiError::UnknownBlockOrTxIndex |
+            EthApiError::HeaderNotFound(_) |
+            EthApiError::BlockPruned { .. } => {
                 rpc_error_with_code(EthRpcErrorCode::ResourceNotFound.code(), error.to_string())
             }
             EthApiError::Unsupported(msg) => internal_rpc_err(msg),
+            #[cfg(feature = "arrow")]
+            EthApiError::Arrow(_) => internal_rpc_err(error.to_string()),
         }
     }
 }
@@ -0,0 +1,191 @@
+//! Short-lived cache of complete `eth_feeHistory` responses.
+
+use crate::eth::FeeHistoryOptions;
+use metrics::Counter;
+use reth_metrics_derive::Metrics;
+use reth_primitives::BlockNumber;
+use reth_rpc_types::FeeHistory;
+use schnellru::{ByLength, LruMap};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Settings for the [FeeHistoryResponseCache]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeHistoryResponseCacheConfig {
+    /// Max number of cached responses.
+    ///
+    /// Default is 256
+    pub max_responses: u32,
+    /// How long a response is served from the cache.
+    ///
+    /// Default is 2 seconds
+    pub ttl: Duration,
+}
+
+impl Default for FeeHistoryResponseCacheConfig {
+    fn default() -> Self {
+        Self { max_responses: 256, ttl: Duration::from_secs(2) }
+    }
+}
+
+/// Identifies a fee history response by its normalized request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct FeeHistoryResponseKey {
+    start_block: BlockNumber,
+    end_block: BlockNumber,
+    /// Bit patterns of the requested percentiles, so the key is hashable
+    reward_percentiles: Option<Vec<u64>>,
+    options: FeeHistoryOptions,
+}
+
+impl FeeHistoryResponseKey {
+    pub(crate) fn new(
+        start_block: BlockNumber,
+        end_block: BlockNumber,
+        reward_percentiles: &Option<Vec<f64>>,
+        options: FeeHistoryOptions,
+    ) -> Self {
+        Self {
+            start_block,
+            end_block,
+            reward_percentiles: reward_percentiles
+                .as_ref()
+                .map(|percentiles| percentiles.iter().map(|p| p.to_bits()).collect()),
+            options,
+        }
+    }
+}
+
+/// Caches complete fee history responses for a short time, so identical polling requests don't
+/// recompute the reward percentiles.
+///
+/// All entries are dropped once the chain advances.
+#[derive(Clone)]
+pub(crate) struct FeeHistoryResponseCache {
+    ttl: Duration,
+    inner: Arc<Mutex<ResponseCacheInner>>,
+}
+
+// === impl FeeHistoryResponseCache ===
+
+impl FeeHistoryResponseCache {
+    pub(crate) fn new(config: FeeHistoryResponseCacheConfig) -> Self {
+        let inner = ResponseCacheInner {
+            responses: LruMap::new(ByLength::new(config.max_responses)),
+            best_block: None,
+            metrics: Default::default(),
+        };
+        Self { ttl: config.ttl, inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    /// Returns the cached response for the key, if it's fresh and the chain didn't advance since.
+    pub(crate) fn get(
+        &self,
+        key: &FeeHistoryResponseKey,
+        best_block: BlockNumber,
+    ) -> Option<FeeHistory> {
+        let mut inner = self.lock();
+        inner.on_best_block(best_block);
+
+        let hit = match inner.responses.get(key) {
+            Some((inserted, response)) if inserted.elapsed() < self.ttl => Some(response.clone()),
+            _ => None,
+        };
+
+        if hit.is_some() {
+            inner.metrics.hits.increment(1);
+        } else {
+            inner.metrics.misses.increment(1);
+        }
+        hit
+    }
+
+    /// Caches the response computed at the given best block.
+    pub(crate) fn insert(
+        &self,
+        key: FeeHistoryResponseKey,
+        best_block: BlockNumber,
+        response: FeeHistory,
+    ) {
+        let mut inner = self.lock();
+        inner.on_best_block(best_block);
+        inner.responses.insert(key, (Instant::now(), response));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ResponseCacheInner> {
+        // entries are inserted atomically, so a poisoned lock can safely be reused
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for FeeHistoryResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeeHistoryResponseCache")
+            .field("ttl", &self.ttl)
+            .field("cached", &self.lock().responses.len())
+            .finish()
+    }
+}
+
+struct ResponseCacheInner {
+    responses: LruMap<FeeHistoryResponseKey, (Instant, FeeHistory), ByLength>,
+    /// The best block the cached responses were computed at
+    best_block: Option<BlockNumber>,
+    metrics: FeeHistoryResponseCacheMetrics,
+}
+
+impl ResponseCacheInner {
+    /// Drops all responses if the chain advanced, since they may not reflect the new head.
+    fn on_best_block(&mut self, best_block: BlockNumber) {
+        if self.best_block != Some(best_block) {
+            self.responses.clear();
+            self.best_block = Some(best_block);
+        }
+    }
+}
+
+/// Fee history response cache metrics
+#[derive(Metrics)]
+#[metrics(scope = "rpc.eth.fee_history_response_cache")]
+struct FeeHistoryResponseCacheMetrics {
+    /// Number of requests served from the cache
+    hits: Counter,
+    /// Number of requests that weren't cached
+    misses: Counter,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(end_block: BlockNumber, percentiles: Option<Vec<f64>>) -> FeeHistoryResponseKey {
+        FeeHistoryResponseKey::new(0, end_block, &percentiles, Default::default())
+    }
+
+    #[test]
+    fn serves_until_chain_advances() {
+        let cache = FeeHistoryResponseCache::new(Default::default());
+        let response = FeeHistory { gas_used_ratio: vec![0.5], ..Default::default() };
+
+        cache.insert(key(1, Some(vec![50.0])), 1, response);
+        assert!(cache.get(&key(1, Some(vec![50.0])), 1).is_some());
+        assert!(cache.get(&key(1, Some(vec![60.0])), 1).is_none());
+        assert!(cache.get(&key(1, None), 1).is_none());
+
+        assert!(cache.get(&key(1, Some(vec![50.0])), 2).is_none());
+    }
+
+    #[test]
+    fn expires_after_ttl() {
+        let cache = FeeHistoryResponseCache::new(FeeHistoryResponseCacheConfig {
+            ttl: Duration::ZERO,
+            ..Default::default()
+        });
+
+        cache.insert(key(1, None), 1, FeeHistory::default());
+        assert!(cache.get(&key(1, None), 1).is_none());
+    }
+}
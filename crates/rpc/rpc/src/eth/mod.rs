@@ -2,16 +2,29 @@
 
 mod api;
 pub mod cache;
+pub mod circuit_breaker;
 pub mod error;
+pub mod fee_history_response_cache;
 mod filter;
 mod id_provider;
 mod logs_utils;
+mod popular_percentiles;
 mod pubsub;
 pub(crate) mod revm_utils;
 mod signer;
+pub mod units;
 pub(crate) mod utils;
 
-pub use api::{EthApi, EthApiSpec, EthTransactions, TransactionSource};
+pub(crate) use api::{
+    fee_history_in_wei, fee_in_wei, single_block_fee_history, sorted_tx_gas_and_rewards,
+    validate_reward_percentiles,
+};
+pub use api::{
+    EthApi, EthApiSpec, EthTransactions, FeeHistoryCompactionConfig, FeeHistoryOptions,
+    FeeHistoryOrder, FeeHistoryRequest, FeeHistoryReward, SlotSchedule, TipBucketConfig,
+    TransactionSource, DEFAULT_MAX_FEE_HISTORY_REWARDS, DEFAULT_MAX_REWARD_PERCENTILES,
+};
 pub use filter::EthFilter;
 pub use id_provider::EthSubscriptionIdProvider;
-pub use pubsub::EthPubSub;
+pub use popular_percentiles::DEFAULT_POPULAR_PERCENTILE_SETS;
+pub use pubsub::{EthPubSub, DEFAULT_MAX_FEE_HISTORY_SUBSCRIPTIONS};
@@ -0,0 +1,205 @@
+//! Tracks which reward percentiles `eth_feeHistory` is queried for most often.
+
+use metrics::Counter;
+use reth_metrics_derive::Metrics;
+use schnellru::{ByLength, LruMap};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Suggested number of percentile sets whose rewards are precomputed for every cached block, see
+/// [EthApi::with_popular_reward_percentiles](crate::EthApi::with_popular_reward_percentiles).
+pub const DEFAULT_POPULAR_PERCENTILE_SETS: usize = 3;
+
+/// Max number of percentile sets whose rewards are cached per block, unless more sets are
+/// precomputed.
+///
+/// Re-querying a block for other percentiles merges the rewards cached for it before with the
+/// freshly precomputed ones, the oldest are dropped once there are more.
+const MAX_CACHED_REWARD_SETS: usize = 8;
+
+/// Max number of distinct percentile sets whose request count is tracked.
+const TRACKED_PERCENTILE_SETS: u32 = 64;
+
+/// All request counts are halved after this many requests, so the popular sets follow changes in
+/// the client mix.
+const DECAY_INTERVAL: u64 = 1024;
+
+/// A requested set of reward percentiles.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PercentileSet {
+    /// Bit patterns of the percentiles, so the set is hashable
+    percentiles: Vec<u64>,
+    exclude_zero_tips: bool,
+}
+
+// === impl PercentileSet ===
+
+impl PercentileSet {
+    pub(crate) fn new(percentiles: &[f64], exclude_zero_tips: bool) -> Self {
+        Self { percentiles: percentiles.iter().map(|p| p.to_bits()).collect(), exclude_zero_tips }
+    }
+
+    /// Returns the percentiles of the set.
+    pub(crate) fn percentiles(&self) -> Vec<f64> {
+        self.percentiles.iter().map(|p| f64::from_bits(*p)).collect()
+    }
+
+    /// Returns `true` if transactions without an effective priority fee are excluded.
+    pub(crate) fn exclude_zero_tips(&self) -> bool {
+        self.exclude_zero_tips
+    }
+}
+
+/// Counts the requested reward percentile sets, to precompute the rewards of the most popular
+/// ones for every block added to the fee history cache.
+///
+/// Clients tend to poll with the same few percentile sets, which are then served from the cache
+/// even if the cached blocks were queried for different percentiles.
+#[derive(Clone)]
+pub(crate) struct PopularPercentiles {
+    /// Number of most requested sets that are reported as popular
+    max_sets: usize,
+    inner: Arc<Mutex<PopularPercentilesInner>>,
+}
+
+// === impl PopularPercentiles ===
+
+impl PopularPercentiles {
+    /// Creates a tracker reporting the `max_sets` most requested sets, `0` disables tracking.
+    pub(crate) fn new(max_sets: usize) -> Self {
+        let inner = PopularPercentilesInner {
+            requests: LruMap::new(ByLength::new(TRACKED_PERCENTILE_SETS)),
+            recorded: 0,
+            metrics: Default::default(),
+        };
+        Self { max_sets, inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    /// Returns the max number of percentile sets whose rewards are cached per block.
+    pub(crate) fn max_cached_sets(&self) -> usize {
+        self.max_sets.max(MAX_CACHED_REWARD_SETS)
+    }
+
+    /// Records a request for the set.
+    pub(crate) fn record(&self, set: &PercentileSet) {
+        if self.max_sets == 0 {
+            return
+        }
+
+        let mut inner = self.lock();
+        inner.recorded += 1;
+        if inner.recorded % DECAY_INTERVAL == 0 {
+            for (_, count) in inner.requests.iter_mut() {
+                *count /= 2;
+            }
+        }
+        match inner.requests.get(set) {
+            Some(count) => *count += 1,
+            None => {
+                inner.requests.insert(set.clone(), 1);
+            }
+        }
+    }
+
+    /// Returns the most requested sets, most popular first.
+    pub(crate) fn popular(&self) -> Vec<PercentileSet> {
+        if self.max_sets == 0 {
+            return Vec::new()
+        }
+
+        let inner = self.lock();
+        let mut sets: Vec<_> = inner.requests.iter().filter(|(_, count)| **count > 0).collect();
+        sets.sort_by(|(_, a), (_, b)| b.cmp(a));
+        sets.into_iter().take(self.max_sets).map(|(set, _)| set.clone()).collect()
+    }
+
+    /// Records whether the rewards for the requested percentiles of a cached block were cached.
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        let inner = self.lock();
+        if hit {
+            inner.metrics.hits.increment(1);
+        } else {
+            inner.metrics.misses.increment(1);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PopularPercentilesInner> {
+        // counts are only ever updated in place, so a poisoned lock can safely be reused
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for PopularPercentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PopularPercentiles")
+            .field("max_sets", &self.max_sets)
+            .field("tracked", &self.lock().requests.len())
+            .finish()
+    }
+}
+
+struct PopularPercentilesInner {
+    /// Request count per set
+    requests: LruMap<PercentileSet, u64, ByLength>,
+    /// Total number of recorded requests
+    recorded: u64,
+    metrics: RewardPercentileCacheMetrics,
+}
+
+/// Metrics of the rewards cached per block in the fee history cache
+#[derive(Metrics)]
+#[metrics(scope = "rpc.eth.fee_history_reward_cache")]
+struct RewardPercentileCacheMetrics {
+    /// Number of cached blocks whose rewards for the requested percentiles were cached
+    hits: Counter,
+    /// Number of cached blocks whose rewards had to be recomputed for the requested percentiles
+    misses: Counter,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(percentiles: &[f64]) -> PercentileSet {
+        PercentileSet::new(percentiles, false)
+    }
+
+    #[test]
+    fn reports_most_requested_sets() {
+        let tracker = PopularPercentiles::new(2);
+        for _ in 0..3 {
+            tracker.record(&set(&[25.0, 50.0, 75.0]));
+        }
+        tracker.record(&set(&[10.0]));
+        tracker.record(&set(&[50.0]));
+        tracker.record(&set(&[50.0]));
+        tracker.record(&PercentileSet::new(&[10.0], true));
+
+        assert_eq!(tracker.popular(), vec![set(&[25.0, 50.0, 75.0]), set(&[50.0])]);
+        assert_eq!(tracker.popular()[0].percentiles(), vec![25.0, 50.0, 75.0]);
+    }
+
+    #[test]
+    fn decays_request_counts() {
+        let tracker = PopularPercentiles::new(1);
+        for _ in 0..DECAY_INTERVAL - 1 {
+            tracker.record(&set(&[50.0]));
+        }
+        assert_eq!(tracker.popular(), vec![set(&[50.0])]);
+
+        // the old favourite's count is halved, so a new set can overtake it
+        for _ in 0..DECAY_INTERVAL / 2 + 1 {
+            tracker.record(&set(&[90.0]));
+        }
+        assert_eq!(tracker.popular(), vec![set(&[90.0])]);
+    }
+
+    #[test]
+    fn disabled_tracker_reports_nothing() {
+        let tracker = PopularPercentiles::new(0);
+        tracker.record(&set(&[50.0]));
+        assert!(tracker.popular().is_empty());
+    }
+}
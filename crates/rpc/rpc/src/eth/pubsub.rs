@@ -1,26 +1,40 @@
 //! `eth_` PubSub RPC handler implementation
 
-use crate::eth::{cache::EthStateCache, logs_utils};
+use crate::eth::{
+    cache::EthStateCache, error::EthApiError, fee_history_in_wei, logs_utils,
+    single_block_fee_history, validate_reward_percentiles, EthApiSpec,
+};
 use futures::StreamExt;
-use jsonrpsee::{types::SubscriptionResult, SubscriptionSink};
+use jsonrpsee::{core::Error as RpcError, types::SubscriptionResult, SubscriptionSink};
 use reth_interfaces::events::{ChainEventSubscriptions, NewBlockNotification};
 use reth_network_api::NetworkInfo;
-use reth_primitives::{filter::FilteredParams, Receipt, TransactionSigned, TxHash};
+use reth_primitives::{filter::FilteredParams, Receipt, TransactionSigned, TxHash, U256};
 use reth_provider::{BlockProvider, EvmEnvProvider};
 use reth_rpc_api::EthPubSubApiServer;
 use reth_rpc_types::{
     pubsub::{
+        BaseFeeThresholdCrossing, BaseFeeThresholdParams, CrossingDirection, FeeHistoryParams,
         Params, PubSubSyncStatus, SubscriptionKind, SubscriptionResult as EthSubscriptionResult,
         SyncStatusMetadata,
     },
-    Header, Log,
+    FeeHistory, Header, Log,
 };
 use reth_tasks::{TaskSpawner, TokioTaskExecutor};
 use reth_transaction_pool::TransactionPool;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tokio_stream::{
-    wrappers::{BroadcastStream, ReceiverStream},
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, ReceiverStream},
     Stream,
 };
+use tracing::debug;
+
+/// The default maximum number of concurrent `feeHistory` subscriptions.
+pub const DEFAULT_MAX_FEE_HISTORY_SUBSCRIPTIONS: usize = 128;
+
+/// Number of consecutive blocks the base fee must stay across a threshold of a `baseFeeThreshold`
+/// subscription before the crossing is reported.
+const BASE_FEE_THRESHOLD_CONFIRMATIONS: u64 = 2;
 
 /// `Eth` pubsub RPC implementation.
 ///
@@ -65,9 +79,36 @@ impl<Client, Pool, Events, Network> EthPubSub<Client, Pool, Events, Network> {
         eth_cache: EthStateCache,
         subscription_task_spawner: Box<dyn TaskSpawner>,
     ) -> Self {
-        let inner = EthPubSubInner { client, pool, chain_events, network, eth_cache };
+        let inner = EthPubSubInner {
+            client,
+            pool,
+            chain_events,
+            network,
+            eth_cache,
+            fee_history_subscriptions: Arc::new(Semaphore::new(
+                DEFAULT_MAX_FEE_HISTORY_SUBSCRIPTIONS,
+            )),
+            wei_per_fee_unit: U256::from(1u64),
+        };
         Self { inner, subscription_task_spawner }
     }
+
+    /// Sets the maximum number of concurrent `feeHistory` subscriptions.
+    ///
+    /// Default is [DEFAULT_MAX_FEE_HISTORY_SUBSCRIPTIONS]
+    pub fn with_max_fee_history_subscriptions(mut self, max: usize) -> Self {
+        self.inner.fee_history_subscriptions = Arc::new(Semaphore::new(max));
+        self
+    }
+
+    /// Converts the fees of `feeHistory` subscriptions to wei like the given `eth` API does, see
+    /// [EthApiSpec::wei_per_fee_unit].
+    ///
+    /// Default is wei, as on Ethereum.
+    pub fn with_fee_unit_of(mut self, eth_api: &impl EthApiSpec) -> Self {
+        self.inner.wei_per_fee_unit = eth_api.wei_per_fee_unit();
+        self
+    }
 }
 
 impl<Client, Pool, Events, Network> EthPubSubApiServer for EthPubSub<Client, Pool, Events, Network>
@@ -157,6 +198,44 @@ async fn handle_accepted<Client, Pool, Events, Network>(
                 }
             }));
         }
+        SubscriptionKind::FeeHistory => {
+            let percentiles = match params {
+                Some(Params::FeeHistory(FeeHistoryParams { percentiles })) => percentiles,
+                _ => None,
+            };
+            if let Some(percentiles) = &percentiles {
+                if let Err(err) = validate_reward_percentiles(percentiles) {
+                    accepted_sink.close(RpcError::from(err));
+                    return
+                }
+            }
+
+            // the slot is held until the subscription ends
+            let Ok(_slot) = pubsub.fee_history_subscriptions.clone().try_acquire_owned() else {
+                accepted_sink.close(RpcError::from(EthApiError::ServiceUnavailable));
+                return
+            };
+
+            let stream = pubsub
+                .into_fee_history_stream(percentiles)
+                .map(|fee_history| EthSubscriptionResult::FeeHistory(Box::new(fee_history)));
+            accepted_sink.pipe_from_stream(stream).await;
+        }
+        SubscriptionKind::BaseFeeThreshold => {
+            let thresholds = match params {
+                Some(Params::BaseFeeThreshold(thresholds)) => thresholds,
+                _ => BaseFeeThresholdParams::default(),
+            };
+            if thresholds.below.is_none() && thresholds.above.is_none() {
+                accepted_sink.close(RpcError::from(EthApiError::MissingBaseFeeThreshold));
+                return
+            }
+
+            let stream = pubsub
+                .into_base_fee_threshold_stream(thresholds)
+                .map(EthSubscriptionResult::BaseFeeThreshold);
+            accepted_sink.pipe_from_stream(stream).await;
+        }
     }
 }
 
@@ -179,6 +258,10 @@ struct EthPubSubInner<Client, Pool, Events, Network> {
     network: Network,
     /// The async cache frontend for eth related data
     eth_cache: EthStateCache,
+    /// Limits the number of concurrent `feeHistory` subscriptions.
+    fee_history_subscriptions: Arc<Semaphore>,
+    /// The number of wei in one unit of the chain's fees.
+    wei_per_fee_unit: U256,
 }
 
 // == impl EthPubSubInner ===
@@ -247,6 +330,154 @@ where
                 futures::stream::iter(all_logs)
             })
     }
+
+    /// Returns a stream that yields the fee history of every new block.
+    fn into_fee_history_stream(
+        self,
+        percentiles: Option<Vec<f64>>,
+    ) -> impl Stream<Item = FeeHistory> {
+        fee_history_stream(
+            self.eth_cache,
+            BroadcastStream::new(self.chain_events.subscribe_new_blocks()),
+            percentiles,
+            self.wei_per_fee_unit,
+        )
+    }
+
+    /// Returns a stream that yields the crossings of the base fee thresholds by the new blocks.
+    fn into_base_fee_threshold_stream(
+        self,
+        thresholds: BaseFeeThresholdParams,
+    ) -> impl Stream<Item = BaseFeeThresholdCrossing> {
+        base_fee_threshold_stream(
+            BroadcastStream::new(self.chain_events.subscribe_new_blocks()),
+            thresholds,
+        )
+    }
+}
+
+/// Maps the new blocks to their fee history, with rewards at the given percentiles and fees
+/// converted to wei.
+///
+/// The stream ends as soon as it falls behind the new blocks, so a slow subscriber is dropped
+/// rather than being served an ever growing backlog of outdated fees.
+fn fee_history_stream(
+    eth_cache: EthStateCache,
+    new_blocks: impl Stream<Item = Result<NewBlockNotification, BroadcastStreamRecvError>>,
+    percentiles: Option<Vec<f64>>,
+    wei_per_fee_unit: U256,
+) -> impl Stream<Item = FeeHistory> {
+    new_blocks
+        .take_while(|new_block| {
+            if let Err(BroadcastStreamRecvError::Lagged(skipped)) = new_block {
+                debug!(target: "rpc::eth", skipped, "Dropping lagging feeHistory subscription");
+            }
+            futures::future::ready(new_block.is_ok())
+        })
+        .filter_map(move |new_block| {
+            let eth_cache = eth_cache.clone();
+            let percentiles = percentiles.clone();
+            Box::pin(async move {
+                let new_block = new_block.ok()?;
+                let Some(percentiles) = percentiles else {
+                    // base fees and gas used are known from the header alone
+                    return single_block_fee_history(&new_block.header, &[], &[], None).ok()
+                };
+                let (new_block, transactions, receipts) =
+                    get_block_receipts(eth_cache, Some(new_block)).await?;
+                single_block_fee_history(
+                    &new_block.header,
+                    &transactions,
+                    &receipts,
+                    Some(&percentiles),
+                )
+                .ok()
+            })
+        })
+        .map(move |fee_history| fee_history_in_wei(fee_history, wei_per_fee_unit))
+}
+
+/// Maps the new blocks to the crossings of the base fee thresholds.
+///
+/// The first block only establishes on which side of each threshold the base fee is. Afterwards a
+/// crossing is reported once the base fee stayed across the threshold for
+/// [BASE_FEE_THRESHOLD_CONFIRMATIONS] consecutive blocks, and the threshold is rearmed once it
+/// stayed back for as long. Only the latest base fee matters, so lagging behind the new blocks
+/// skips the missed blocks instead of dropping the subscriber.
+fn base_fee_threshold_stream(
+    new_blocks: impl Stream<Item = Result<NewBlockNotification, BroadcastStreamRecvError>>,
+    thresholds: BaseFeeThresholdParams,
+) -> impl Stream<Item = BaseFeeThresholdCrossing> {
+    let mut watches = [
+        thresholds.below.map(|threshold| ThresholdWatch::new(CrossingDirection::Below, threshold)),
+        thresholds.above.map(|threshold| ThresholdWatch::new(CrossingDirection::Above, threshold)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    new_blocks.filter_map(|new_block| futures::future::ready(new_block.ok())).flat_map(
+        move |new_block| {
+            let base_fee_per_gas =
+                U256::from(new_block.header.base_fee_per_gas.unwrap_or_default());
+            let crossings = watches
+                .iter_mut()
+                .filter(|watch| watch.on_base_fee(base_fee_per_gas))
+                .map(|watch| BaseFeeThresholdCrossing {
+                    direction: watch.direction,
+                    threshold: watch.threshold,
+                    base_fee_per_gas,
+                    block_number: U256::from(new_block.header.number),
+                    block_hash: new_block.hash,
+                })
+                .collect::<Vec<_>>();
+            futures::stream::iter(crossings)
+        },
+    )
+}
+
+/// Tracks on which side of a single threshold the base fee is.
+#[derive(Debug)]
+struct ThresholdWatch {
+    direction: CrossingDirection,
+    threshold: U256,
+    /// Whether the base fee is confirmed to be across the threshold, `None` before the first block
+    across: Option<bool>,
+    /// Number of consecutive blocks on the other side of the threshold than `across`
+    pending: u64,
+}
+
+// === impl ThresholdWatch ===
+
+impl ThresholdWatch {
+    fn new(direction: CrossingDirection, threshold: U256) -> Self {
+        Self { direction, threshold, across: None, pending: 0 }
+    }
+
+    /// Records the base fee of a new block, returning `true` if it confirms a crossing.
+    fn on_base_fee(&mut self, base_fee_per_gas: U256) -> bool {
+        let is_across = match self.direction {
+            CrossingDirection::Below => base_fee_per_gas < self.threshold,
+            CrossingDirection::Above => base_fee_per_gas > self.threshold,
+        };
+        let Some(across) = self.across else {
+            self.across = Some(is_across);
+            return false
+        };
+
+        if is_across == across {
+            self.pending = 0;
+            return false
+        }
+        self.pending += 1;
+        if self.pending < BASE_FEE_THRESHOLD_CONFIRMATIONS {
+            return false
+        }
+
+        self.across = Some(is_across);
+        self.pending = 0;
+        is_across
+    }
 }
 
 /// Helper function for getting block receipts and transactions
@@ -264,3 +495,125 @@ async fn get_block_receipts(
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_primitives::{constants::GWEI_TO_WEI, SealedHeader, H256, U256};
+    use reth_provider::{test_utils::MockFeeProvider, HeaderProvider};
+
+    fn new_block(provider: &MockFeeProvider, hash: H256) -> NewBlockNotification {
+        let header = provider.header(&hash).unwrap().unwrap();
+        Arc::new(SealedHeader { header, hash })
+    }
+
+    #[tokio::test]
+    async fn fee_history_of_new_blocks() {
+        let provider = MockFeeProvider::default();
+        let first = provider.add_block_with_tips(1, 10, &[3, 1, 2]);
+        let second = provider.add_block_with_tips(2, 20, &[]);
+        let third = provider.add_block_with_tips(3, 30, &[5]);
+        let eth_cache = EthStateCache::spawn(provider.clone(), Default::default());
+
+        let new_blocks = futures::stream::iter(vec![
+            Ok(new_block(&provider, first)),
+            Ok(new_block(&provider, second)),
+            // the subscriber fell behind, so it's dropped
+            Err(BroadcastStreamRecvError::Lagged(1)),
+            Ok(new_block(&provider, third)),
+        ]);
+        let fee_histories =
+            fee_history_stream(eth_cache, new_blocks, Some(vec![0.0, 100.0]), U256::from(1u64))
+                .collect::<Vec<_>>()
+                .await;
+
+        assert_eq!(fee_histories.len(), 2);
+        assert_eq!(fee_histories[0].oldest_block, U256::from(1));
+        assert_eq!(fee_histories[0].base_fee_per_gas[0], U256::from(10));
+        assert_eq!(fee_histories[0].reward, Some(vec![vec![U256::from(1), U256::from(3)]]));
+        assert_eq!(fee_histories[1].oldest_block, U256::from(2));
+        assert_eq!(fee_histories[1].reward, Some(vec![vec![U256::ZERO, U256::ZERO]]));
+    }
+
+    #[tokio::test]
+    async fn base_fee_threshold_crossings() {
+        let provider = MockFeeProvider::default();
+        let base_fees = [30, 19, 30, 18, 17, 16, 60, 40, 61, 62, 30, 30];
+        let new_blocks = base_fees
+            .into_iter()
+            .enumerate()
+            .map(|(number, base_fee)| {
+                let hash = provider.add_block_with_tips(number as u64, base_fee, &[]);
+                Ok(new_block(&provider, hash))
+            })
+            .collect::<Vec<_>>();
+
+        let thresholds =
+            BaseFeeThresholdParams { below: Some(U256::from(20)), above: Some(U256::from(50)) };
+        let crossings = base_fee_threshold_stream(futures::stream::iter(new_blocks), thresholds)
+            .collect::<Vec<_>>()
+            .await;
+
+        // single blocks across a threshold are ignored, staying back above rearms the lower one
+        let reported = crossings
+            .iter()
+            .map(|crossing| (crossing.direction, crossing.block_number, crossing.base_fee_per_gas))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reported,
+            vec![
+                (CrossingDirection::Below, U256::from(4), U256::from(17)),
+                (CrossingDirection::Above, U256::from(9), U256::from(62)),
+            ]
+        );
+        assert_eq!(crossings[0].threshold, U256::from(20));
+        assert_eq!(crossings[1].threshold, U256::from(50));
+    }
+
+    #[tokio::test]
+    async fn fee_history_without_percentiles() {
+        let provider = MockFeeProvider::default();
+        let hash = provider.add_block_with_tips(1, 10, &[1]);
+        let eth_cache = EthStateCache::spawn(provider.clone(), Default::default());
+
+        let new_blocks = futures::stream::iter(vec![Ok(new_block(&provider, hash))]);
+        let fee_histories = fee_history_stream(eth_cache, new_blocks, None, U256::from(1u64))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(fee_histories.len(), 1);
+        assert_eq!(fee_histories[0].gas_used_ratio.len(), 1);
+        assert_eq!(fee_histories[0].base_fee_per_gas.len(), 2);
+        assert!(fee_histories[0].reward.is_none());
+    }
+
+    #[tokio::test]
+    async fn fee_history_of_new_blocks_in_wei() {
+        let provider = MockFeeProvider::default();
+        let hash = provider.add_block_with_tips(1, 10, &[3, 1]);
+        let eth_cache = EthStateCache::spawn(provider.clone(), Default::default());
+        let wei_per_fee_unit = U256::from(GWEI_TO_WEI);
+
+        let new_blocks = futures::stream::iter(vec![Ok(new_block(&provider, hash))]);
+        let fee_histories =
+            fee_history_stream(eth_cache, new_blocks, Some(vec![0.0, 100.0]), wei_per_fee_unit)
+                .collect::<Vec<_>>()
+                .await;
+
+        let header = provider.header(&hash).unwrap().unwrap();
+        let expected = single_block_fee_history(&header, &[], &[], None).unwrap();
+        assert_eq!(fee_histories.len(), 1);
+        assert_eq!(
+            fee_histories[0].base_fee_per_gas,
+            expected
+                .base_fee_per_gas
+                .iter()
+                .map(|base_fee| *base_fee * wei_per_fee_unit)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            fee_histories[0].reward,
+            Some(vec![vec![wei_per_fee_unit, U256::from(3) * wei_per_fee_unit]])
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Conversions of fee values between wei and gwei, and into other tokens.
+//!
+//! All fees are computed and stored in wei, these helpers are only meant for reporting values to
+//! humans.
+
+use reth_primitives::{
+    constants::{ETH_TO_WEI, GWEI_TO_WEI},
+    U256,
+};
+
+/// The unit a fee value is reported in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FeeUnit {
+    /// The smallest denomination, which all fees are computed in.
+    #[default]
+    Wei,
+    /// 10^9 wei.
+    Gwei,
+}
+
+/// How a value that isn't a whole multiple of the target unit is rounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    /// Round towards zero.
+    #[default]
+    Down,
+    /// Round away from zero.
+    Up,
+    /// Round to the nearest whole unit, halfway values are rounded up.
+    Nearest,
+}
+
+// === impl FeeUnit ===
+
+impl FeeUnit {
+    /// Returns the number of wei in one unit.
+    pub fn wei_per_unit(&self) -> U256 {
+        match self {
+            FeeUnit::Wei => U256::from(1u64),
+            FeeUnit::Gwei => U256::from(GWEI_TO_WEI),
+        }
+    }
+
+    /// Converts a value in wei to this unit, using the given rounding.
+    ///
+    /// This can't overflow, since the result is never larger than `wei`.
+    pub fn from_wei(&self, wei: U256, rounding: Rounding) -> U256 {
+        div_rounded(wei, self.wei_per_unit(), rounding)
+    }
+
+    /// Converts a value in this unit to wei.
+    ///
+    /// Returns `None` if the result doesn't fit into a [U256].
+    pub fn to_wei(&self, value: U256) -> Option<U256> {
+        value.checked_mul(self.wei_per_unit())
+    }
+}
+
+/// The price of one ether in the smallest unit of another token, e.g. in cents for USD.
+///
+/// The price is supplied by the caller, it's never fetched. Fees are still computed in wei and
+/// only converted for reporting, see [EthPrice::from_wei].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EthPrice {
+    units_per_eth: U256,
+}
+
+// === impl EthPrice ===
+
+impl EthPrice {
+    /// Creates the price from the number of token units one ether is worth.
+    ///
+    /// Returns `None` for a zero price.
+    pub fn new(units_per_eth: U256) -> Option<Self> {
+        (units_per_eth > U256::ZERO).then_some(Self { units_per_eth })
+    }
+
+    /// Creates the price from a decimal price of one ether in whole tokens, e.g. `1834.56` USD,
+    /// for a token with the given number of decimals, e.g. 2 for cents.
+    ///
+    /// The price is rounded to the nearest token unit. Returns `None` if it's not finite, not
+    /// positive, rounds to zero units or doesn't fit into 128 bits.
+    pub fn from_f64(price: f64, decimals: u8) -> Option<Self> {
+        let units = (price * 10f64.powi(decimals as i32)).round();
+        // also rejects NaN
+        if !(units >= 1.0 && units < u128::MAX as f64) {
+            return None
+        }
+        Self::new(U256::from(units as u128))
+    }
+
+    /// Returns the number of token units one ether is worth.
+    pub fn units_per_eth(&self) -> U256 {
+        self.units_per_eth
+    }
+
+    /// Converts a value in wei to token units, using the given rounding.
+    ///
+    /// Saturates at [U256::MAX] if the value times the price doesn't fit into a [U256], which is
+    /// far beyond any realistic fee.
+    pub fn from_wei(&self, wei: U256, rounding: Rounding) -> U256 {
+        match wei.checked_mul(self.units_per_eth) {
+            Some(value) => div_rounded(value, U256::from(ETH_TO_WEI), rounding),
+            None => U256::MAX,
+        }
+    }
+}
+
+/// Divides the value by the non-zero divisor, using the given rounding.
+fn div_rounded(value: U256, divisor: U256, rounding: Rounding) -> U256 {
+    let quotient = value / divisor;
+    let remainder = value % divisor;
+
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => remainder > U256::ZERO,
+        // `divisor - remainder <= remainder` avoids the overflow of `2 * remainder`
+        Rounding::Nearest => remainder > U256::ZERO && divisor - remainder <= remainder,
+    };
+
+    // the quotient is at most `U256::MAX / divisor`, so adding one can only overflow for a divisor
+    // of one, which leaves no remainder to round up
+    if round_up {
+        quotient + U256::from(1u64)
+    } else {
+        quotient
+    }
+}
+
+/// Converts wei to gwei, using the given rounding.
+pub fn wei_to_gwei(wei: U256, rounding: Rounding) -> U256 {
+    FeeUnit::Gwei.from_wei(wei, rounding)
+}
+
+/// Converts gwei to wei, returning `None` on overflow.
+pub fn gwei_to_wei(gwei: U256) -> Option<U256> {
+    FeeUnit::Gwei.to_wei(gwei)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gwei(value: u64) -> U256 {
+        U256::from(value)
+    }
+
+    #[test]
+    fn rounds_wei_to_gwei() {
+        let half = GWEI_TO_WEI / 2;
+        let cases = [
+            // (wei, down, up, nearest)
+            (0, 0, 0, 0),
+            (1, 0, 1, 0),
+            (half - 1, 0, 1, 0),
+            (half, 0, 1, 1),
+            (GWEI_TO_WEI - 1, 0, 1, 1),
+            (GWEI_TO_WEI, 1, 1, 1),
+            (GWEI_TO_WEI + 1, 1, 2, 1),
+            (GWEI_TO_WEI + half, 1, 2, 2),
+        ];
+
+        for (wei, down, up, nearest) in cases {
+            let wei = U256::from(wei);
+            assert_eq!(wei_to_gwei(wei, Rounding::Down), gwei(down), "{wei} down");
+            assert_eq!(wei_to_gwei(wei, Rounding::Up), gwei(up), "{wei} up");
+            assert_eq!(wei_to_gwei(wei, Rounding::Nearest), gwei(nearest), "{wei} nearest");
+        }
+    }
+
+    #[test]
+    fn rounds_max_wei() {
+        let max = U256::MAX / U256::from(GWEI_TO_WEI);
+        assert_eq!(wei_to_gwei(U256::MAX, Rounding::Down), max);
+        assert_eq!(wei_to_gwei(U256::MAX, Rounding::Up), max + U256::from(1u64));
+
+        for rounding in [Rounding::Down, Rounding::Up, Rounding::Nearest] {
+            assert_eq!(FeeUnit::Wei.from_wei(U256::MAX, rounding), U256::MAX);
+        }
+    }
+
+    #[test]
+    fn checks_gwei_to_wei_overflow() {
+        assert_eq!(gwei_to_wei(gwei(2)), Some(U256::from(2 * GWEI_TO_WEI)));
+
+        let max = U256::MAX / U256::from(GWEI_TO_WEI);
+        assert!(gwei_to_wei(max).is_some());
+        assert_eq!(gwei_to_wei(max + U256::from(1u64)), None);
+        assert_eq!(FeeUnit::Wei.to_wei(U256::MAX), Some(U256::MAX));
+    }
+
+    #[test]
+    fn converts_wei_with_eth_price() {
+        // 1834.56 USD in cents
+        let price = EthPrice::from_f64(1834.56, 2).unwrap();
+        assert_eq!(price.units_per_eth(), U256::from(183_456u64));
+        assert_eq!(price, EthPrice::new(U256::from(183_456u64)).unwrap());
+
+        let eth = U256::from(ETH_TO_WEI);
+        assert_eq!(price.from_wei(eth, Rounding::Down), U256::from(183_456u64));
+        // 21000 gas at 30 gwei cost 0.00063 ether, i.e. 115.57728 cents
+        let fee = U256::from(21_000 * 30 * GWEI_TO_WEI);
+        assert_eq!(price.from_wei(fee, Rounding::Down), U256::from(115u64));
+        assert_eq!(price.from_wei(fee, Rounding::Up), U256::from(116u64));
+        assert_eq!(price.from_wei(fee, Rounding::Nearest), U256::from(116u64));
+        assert_eq!(price.from_wei(U256::ZERO, Rounding::Up), U256::ZERO);
+        assert_eq!(price.from_wei(U256::MAX, Rounding::Down), U256::MAX);
+    }
+
+    #[test]
+    fn rejects_invalid_eth_prices() {
+        assert_eq!(EthPrice::new(U256::ZERO), None);
+        for price in [0.0, -1834.56, 0.004, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(EthPrice::from_f64(price, 2), None, "{price}");
+        }
+        assert_eq!(EthPrice::from_f64(1834.56, 40), None);
+        assert!(EthPrice::from_f64(0.005, 2).is_some());
+    }
+
+    #[test]
+    fn round_trips_whole_gwei() {
+        let wei = gwei_to_wei(gwei(42)).unwrap();
+        for rounding in [Rounding::Down, Rounding::Up, Rounding::Nearest] {
+            assert_eq!(wei_to_gwei(wei, rounding), gwei(42));
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Checks `eth_feeHistory` against the output of geth for a recorded mainnet block range.
+//!
+//! The fixture is not part of the repository, it is read from `testdata/fee_history_mainnet.json`,
+//! and the test only runs with the `mainnet-fixture` feature:
+//!
+//! ```sh
+//! cargo test -p reth-rpc --features mainnet-fixture --test fee_history_mainnet
+//! ```
+//!
+//! # Generating the fixture
+//!
+//! The fixture is recorded from a synced geth node with the JSON-RPC API enabled. Pick a range of
+//! post-London, pre-Cancun blocks, blob transactions are not supported. With `RPC`, `FIRST` and
+//! `LAST` set to the node's URL and the first and last block of the range (inclusive, as decimal
+//! numbers) and the percentiles of interest in `PERCENTILES`:
+//!
+//! ```sh
+//! rpc() {
+//!     curl -s -X POST -H 'Content-Type: application/json' "$RPC" \
+//!         --data "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"$1\",\"params\":$2}" | jq '.result'
+//! }
+//! PERCENTILES='[0,10,25,50,75,90,100]'
+//! for n in $(seq "$FIRST" "$LAST"); do
+//!     block=$(printf '"0x%x"' "$n")
+//!     rpc eth_getBlockByNumber "[$block,true]" > "block-$n.json"
+//!     rpc eth_getBlockReceipts "[$block]" > "receipts-$n.json"
+//! done
+//! rpc eth_feeHistory "[$((LAST - FIRST + 1)),$(printf '"0x%x"' "$LAST"),$PERCENTILES]" \
+//!     > fee-history.json
+//! jq -n --argjson percentiles "$PERCENTILES" \
+//!     --slurpfile feeHistory fee-history.json \
+//!     '{percentiles: $percentiles, blocks: [], receipts: [], feeHistory: $feeHistory[0]}' \
+//!     > fixture.json
+//! for n in $(seq "$FIRST" "$LAST"); do
+//!     jq --slurpfile block "block-$n.json" --slurpfile receipts "receipts-$n.json" \
+//!         '.blocks += $block | .receipts += $receipts' fixture.json > fixture.tmp.json
+//!     mv fixture.tmp.json fixture.json
+//! done
+//! mv fixture.json crates/rpc/rpc/testdata/fee_history_mainnet.json
+//! ```
+//!
+//! Only the fields the fee history is derived from are read, everything else in the recorded
+//! responses is ignored.
+
+use reth_network_api::test_utils::NoopNetwork;
+use reth_primitives::{
+    BlockId, Header, Receipt, Transaction, TransactionSigned, TxEip1559, TxEip2930, TxLegacy,
+    TxType, H256, U256, U64,
+};
+use reth_provider::test_utils::{MockFeeProvider, NoopProvider};
+use reth_rpc::{eth::cache::EthStateCache, EthApi};
+use reth_rpc_types::FeeHistory;
+use reth_transaction_pool::test_utils::{testing_pool, TestPool};
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+
+/// Blocks, receipts and the geth `eth_feeHistory` response recorded for them.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fixture {
+    /// The reward percentiles geth was queried with.
+    percentiles: Vec<f64>,
+    /// The blocks of the range in ascending order, with full transactions.
+    blocks: Vec<FixtureBlock>,
+    /// The receipts of each block, in the same order as `blocks`.
+    receipts: Vec<Vec<FixtureReceipt>>,
+    /// The response of geth for the whole range.
+    fee_history: FeeHistory,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FixtureBlock {
+    number: U64,
+    base_fee_per_gas: U256,
+    gas_used: U64,
+    gas_limit: U64,
+    timestamp: U64,
+    transactions: Vec<FixtureTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FixtureTransaction {
+    hash: H256,
+    #[serde(rename = "type")]
+    tx_type: U64,
+    gas: U64,
+    gas_price: U256,
+    max_fee_per_gas: Option<U256>,
+    max_priority_fee_per_gas: Option<U256>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FixtureReceipt {
+    #[serde(rename = "type")]
+    tx_type: U64,
+    status: U64,
+    cumulative_gas_used: U64,
+}
+
+fn tx_type(tx_type: U64) -> TxType {
+    match tx_type.as_u64() {
+        0 => TxType::Legacy,
+        1 => TxType::EIP2930,
+        2 => TxType::EIP1559,
+        other => panic!("unsupported transaction type {other}"),
+    }
+}
+
+impl FixtureTransaction {
+    fn into_signed(self) -> TransactionSigned {
+        let gas_limit = self.gas.as_u64();
+        let gas_price = self.gas_price.to::<u128>();
+        let transaction = match tx_type(self.tx_type) {
+            TxType::Legacy => {
+                Transaction::Legacy(TxLegacy { gas_limit, gas_price, ..Default::default() })
+            }
+            TxType::EIP2930 => {
+                Transaction::Eip2930(TxEip2930 { gas_limit, gas_price, ..Default::default() })
+            }
+            TxType::EIP1559 => Transaction::Eip1559(TxEip1559 {
+                gas_limit,
+                max_fee_per_gas: self.max_fee_per_gas.expect("dynamic fee transaction").to(),
+                max_priority_fee_per_gas: self
+                    .max_priority_fee_per_gas
+                    .expect("dynamic fee transaction")
+                    .to(),
+                ..Default::default()
+            }),
+        };
+        TransactionSigned { hash: self.hash, signature: Default::default(), transaction }
+    }
+}
+
+impl FixtureReceipt {
+    fn into_receipt(self) -> Receipt {
+        Receipt {
+            tx_type: tx_type(self.tx_type),
+            success: self.status.as_u64() == 1,
+            cumulative_gas_used: self.cumulative_gas_used.as_u64(),
+            logs: vec![],
+        }
+    }
+}
+
+fn load_fixture() -> Fixture {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/fee_history_mainnet.json");
+    let data = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!("Unable to read fixture {}, see the module docs to record it: {err}", path.display())
+    });
+    serde_json::from_str(&data).expect("Invalid fixture")
+}
+
+fn eth_api(provider: MockFeeProvider) -> EthApi<MockFeeProvider, TestPool, NoopNetwork> {
+    EthApi::new(
+        provider,
+        testing_pool(),
+        NoopNetwork::default(),
+        EthStateCache::spawn(NoopProvider::default(), Default::default()),
+    )
+}
+
+#[tokio::test]
+async fn fee_history_matches_geth() {
+    let Fixture { percentiles, blocks, receipts, fee_history: expected } = load_fixture();
+    assert_eq!(blocks.len(), receipts.len(), "every block needs its receipts");
+    assert!(blocks.len() > 1, "the fixture needs at least two blocks");
+
+    let provider = MockFeeProvider::default();
+    for (block, receipts) in blocks.into_iter().zip(receipts) {
+        let header = Header {
+            number: block.number.as_u64(),
+            base_fee_per_gas: Some(block.base_fee_per_gas.to()),
+            gas_used: block.gas_used.as_u64(),
+            gas_limit: block.gas_limit.as_u64(),
+            timestamp: block.timestamp.as_u64(),
+            ..Default::default()
+        };
+        let body = block.transactions.into_iter().map(FixtureTransaction::into_signed).collect();
+        let receipts = receipts.into_iter().map(FixtureReceipt::into_receipt).collect();
+        provider.add_block(header, body, receipts);
+    }
+
+    // the same request geth answered
+    let block_count = expected.gas_used_ratio.len() as u64;
+    let oldest_block = expected.oldest_block.to::<u64>();
+    let newest_block: BlockId = (oldest_block + block_count - 1).into();
+    let fee_history =
+        eth_api(provider).fee_history(block_count, newest_block, Some(percentiles)).await.unwrap();
+
+    assert_eq!(fee_history.oldest_block, expected.oldest_block);
+    // including the base fee of the block after the range
+    assert_eq!(fee_history.base_fee_per_gas, expected.base_fee_per_gas);
+    assert_eq!(fee_history.gas_used_ratio, expected.gas_used_ratio);
+    assert_eq!(fee_history.reward, expected.reward);
+}
@@ -124,6 +124,21 @@ impl<DB: Database> BlockIdProvider for ShareableDatabase<DB> {
     fn block_number(&self, hash: H256) -> Result<Option<BlockNumber>> {
         self.db.view(|tx| tx.get::<tables::HeaderNumbers>(hash))?.map_err(Into::into)
     }
+
+    fn earliest_block_number(&self) -> Result<BlockNumber> {
+        // A block is only available with both its header and its body, so the earliest one is the
+        // higher of the lowest stored header and the lowest stored body
+        self.db
+            .view(|tx| {
+                let first_header = tx.cursor_read::<tables::Headers>()?.first()?;
+                let first_body = tx.cursor_read::<tables::BlockBodies>()?.first()?;
+                let earliest = first_header
+                    .map(|(number, _)| number)
+                    .max(first_body.map(|(number, _)| number));
+                Ok::<_, reth_interfaces::db::Error>(earliest.unwrap_or_default())
+            })?
+            .map_err(Into::into)
+    }
 }
 
 impl<DB: Database> BlockProvider for ShareableDatabase<DB> {
@@ -276,6 +291,18 @@ impl<DB: Database> TransactionsProvider for ShareableDatabase<DB> {
         }
         Ok(results)
     }
+
+    fn transaction_counts_by_block_range(
+        &self,
+        range: impl RangeBounds<BlockNumber>,
+    ) -> Result<Vec<u64>> {
+        let tx = self.db.tx()?;
+        let mut body_cursor = tx.cursor_read::<tables::BlockBodies>()?;
+        body_cursor
+            .walk_range(range)?
+            .map(|entry| entry.map(|(_, body)| body.tx_count).map_err(Into::into))
+            .collect()
+    }
 }
 
 impl<DB: Database> ReceiptProvider for ShareableDatabase<DB> {
@@ -454,8 +481,14 @@ mod tests {
 
     use super::ShareableDatabase;
     use crate::{BlockIdProvider, StateProviderFactory};
-    use reth_db::mdbx::{test_utils::create_test_db, EnvKind, WriteMap};
-    use reth_primitives::{ChainSpecBuilder, H256};
+    use reth_db::{
+        database::Database,
+        mdbx::{test_utils::create_test_db, EnvKind, WriteMap},
+        models::StoredBlockBody,
+        tables,
+        transaction::DbTxMut,
+    };
+    use reth_primitives::{ChainSpecBuilder, Header, H256};
 
     #[test]
     fn common_history_provider() {
@@ -477,4 +510,38 @@ mod tests {
         assert_eq!(chain_info.last_finalized, None);
         assert_eq!(chain_info.safe_finalized, None);
     }
+
+    #[test]
+    fn earliest_block_number() {
+        let chain_spec = ChainSpecBuilder::mainnet().build();
+        let db = create_test_db::<WriteMap>(EnvKind::RW);
+        let provider = ShareableDatabase::new(db.clone(), Arc::new(chain_spec));
+        assert_eq!(provider.earliest_block_number().unwrap(), 0);
+
+        db.update(|tx| {
+            for number in 3..=10 {
+                tx.put::<tables::Headers>(number, Header { number, ..Default::default() })?;
+            }
+            for number in 5..=10 {
+                tx.put::<tables::BlockBodies>(number, StoredBlockBody::default())?;
+            }
+            Ok::<_, reth_interfaces::db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+        // the bodies of blocks 3 and 4 are missing
+        assert_eq!(provider.earliest_block_number().unwrap(), 5);
+
+        // blocks are pruned from the bottom
+        db.update(|tx| {
+            for number in 5..8 {
+                tx.delete::<tables::Headers>(number, None)?;
+                tx.delete::<tables::BlockBodies>(number, None)?;
+            }
+            Ok::<_, reth_interfaces::db::Error>(())
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(provider.earliest_block_number().unwrap(), 8);
+    }
 }
@@ -0,0 +1,375 @@
+use crate::{
+    test_utils::MockEthProvider, traits::ReceiptProvider, BlockHashProvider, BlockIdProvider,
+    BlockProvider, EvmEnvProvider, HeaderProvider, PostStateDataProvider, StateProviderBox,
+    StateProviderFactory, TransactionsProvider,
+};
+use parking_lot::Mutex;
+use reth_interfaces::Result;
+use reth_primitives::{
+    Block, BlockHash, BlockId, BlockNumber, ChainInfo, Header, Receipt, Transaction,
+    TransactionMeta, TransactionSigned, TxHash, TxLegacy, TxNumber, TxType, H256, U256,
+};
+use revm_primitives::{BlockEnv, CfgEnv};
+use std::{
+    ops::RangeBounds,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Gas used by every transaction added with [MockFeeProvider::add_block_with_tips].
+pub const MOCK_TX_GAS_USED: u64 = 21_000;
+
+/// A mock provider for fee related queries.
+///
+/// Blocks are stored together with matching receipts, so fee history rewards can be derived from
+/// them. All block data reads can be made to fail, to simulate a degraded database.
+#[derive(Debug, Clone, Default)]
+pub struct MockFeeProvider {
+    inner: MockEthProvider,
+    failing: Arc<AtomicBool>,
+    /// Number of headers returned by range queries
+    headers_read: Arc<AtomicUsize>,
+    /// Number of transactions returned by all queries
+    transactions_read: Arc<AtomicUsize>,
+    /// Number of receipts returned by all queries
+    receipts_read: Arc<AtomicUsize>,
+    /// Whether range queries return headers in descending order
+    headers_reversed: Arc<AtomicBool>,
+    /// Whether block hash lookups fail, independently of the other reads
+    block_hashes_failing: Arc<AtomicBool>,
+    /// Range queries omit the headers from this block on, if set
+    headers_hidden_from: Arc<Mutex<Option<BlockNumber>>>,
+}
+
+impl MockFeeProvider {
+    /// Adds a block with the given transactions and receipts, returning its hash.
+    pub fn add_block(
+        &self,
+        header: Header,
+        body: Vec<TransactionSigned>,
+        receipts: Vec<Receipt>,
+    ) -> H256 {
+        let hash = H256::random();
+        self.inner.add_block(hash, Block { header, body, ..Default::default() });
+        self.inner.add_receipts(hash, receipts);
+        hash
+    }
+
+    /// Adds a block with a legacy transaction for each of the given `tips`, each using
+    /// [MOCK_TX_GAS_USED] gas, returning its hash.
+    pub fn add_block_with_tips(&self, number: BlockNumber, base_fee: u64, tips: &[u128]) -> H256 {
+        let header = Header {
+            number,
+            gas_used: MOCK_TX_GAS_USED * tips.len() as u64,
+            gas_limit: 30_000_000,
+            base_fee_per_gas: Some(base_fee),
+            ..Default::default()
+        };
+        let body = tips
+            .iter()
+            .map(|tip| TransactionSigned {
+                hash: H256::random(),
+                signature: Default::default(),
+                transaction: Transaction::Legacy(TxLegacy {
+                    gas_price: base_fee as u128 + tip,
+                    gas_limit: MOCK_TX_GAS_USED,
+                    ..Default::default()
+                }),
+            })
+            .collect();
+        let receipts = (1..=tips.len() as u64)
+            .map(|i| Receipt {
+                tx_type: TxType::Legacy,
+                success: true,
+                cumulative_gas_used: MOCK_TX_GAS_USED * i,
+                logs: vec![],
+            })
+            .collect();
+        self.add_block(header, body, receipts)
+    }
+
+    /// Removes all blocks below the given number, as a pruning node would.
+    pub fn prune_below(&self, number: BlockNumber) {
+        let mut headers = self.inner.headers.lock();
+        let pruned = headers
+            .iter()
+            .filter(|(_, header)| header.number < number)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        for hash in pruned {
+            headers.remove(&hash);
+            self.inner.blocks.lock().remove(&hash);
+            self.inner.receipts.lock().remove(&hash);
+        }
+    }
+
+    /// Removes the receipts of all blocks below the given number, keeping the blocks, as a node
+    /// pruning only receipts would.
+    pub fn prune_receipts_below(&self, number: BlockNumber) {
+        let headers = self.inner.headers.lock();
+        let mut receipts = self.inner.receipts.lock();
+        for (hash, _) in headers.iter().filter(|(_, header)| header.number < number) {
+            receipts.remove(hash);
+        }
+    }
+
+    /// Makes all subsequent block data reads fail, or succeed again.
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::Relaxed);
+    }
+
+    /// Makes range queries return headers in descending order, or in ascending order again.
+    pub fn set_headers_reversed(&self, reversed: bool) {
+        self.headers_reversed.store(reversed, Ordering::Relaxed);
+    }
+
+    /// Makes all subsequent block hash lookups fail, or succeed again.
+    ///
+    /// Unlike [MockFeeProvider::set_failing], this only affects block hashes, which allows failing
+    /// a request after its block data was read.
+    pub fn set_block_hashes_failing(&self, failing: bool) {
+        self.block_hashes_failing.store(failing, Ordering::Relaxed);
+    }
+
+    /// Makes range queries omit the headers from the given block on, or return all headers again.
+    ///
+    /// The chain info still reports the hidden blocks, which simulates headers that disappear
+    /// between resolving a block number and reading its header, e.g. due to pruning.
+    pub fn hide_headers_from(&self, number: Option<BlockNumber>) {
+        *self.headers_hidden_from.lock() = number;
+    }
+
+    /// Returns the number of headers returned by range queries so far.
+    pub fn headers_read(&self) -> usize {
+        self.headers_read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of transactions returned by all queries so far.
+    pub fn transactions_read(&self) -> usize {
+        self.transactions_read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of receipts returned by all queries so far.
+    pub fn receipts_read(&self) -> usize {
+        self.receipts_read.load(Ordering::Relaxed)
+    }
+
+    /// Records the number of transactions returned by a query.
+    fn count_transactions(&self, count: usize) {
+        self.transactions_read.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records the number of receipts returned by a query.
+    fn count_receipts(&self, count: usize) {
+        self.receipts_read.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns an error if reads are set to fail.
+    fn ensure_available(&self) -> Result<()> {
+        if self.failing.load(Ordering::Relaxed) {
+            return Err(reth_interfaces::db::Error::Read(-1).into())
+        }
+        Ok(())
+    }
+}
+
+impl HeaderProvider for MockFeeProvider {
+    fn header(&self, block_hash: &BlockHash) -> Result<Option<Header>> {
+        self.ensure_available()?;
+        self.inner.header(block_hash)
+    }
+
+    fn header_by_number(&self, num: u64) -> Result<Option<Header>> {
+        self.ensure_available()?;
+        self.inner.header_by_number(num)
+    }
+
+    fn header_td(&self, hash: &BlockHash) -> Result<Option<U256>> {
+        self.ensure_available()?;
+        self.inner.header_td(hash)
+    }
+
+    fn header_td_by_number(&self, number: BlockNumber) -> Result<Option<U256>> {
+        self.ensure_available()?;
+        self.inner.header_td_by_number(number)
+    }
+
+    fn headers_range(&self, range: impl RangeBounds<BlockNumber>) -> Result<Vec<Header>> {
+        self.ensure_available()?;
+        let mut headers = self.inner.headers_range(range)?;
+        if let Some(hidden_from) = *self.headers_hidden_from.lock() {
+            headers.retain(|header| header.number < hidden_from);
+        }
+        self.headers_read.fetch_add(headers.len(), Ordering::Relaxed);
+        if self.headers_reversed.load(Ordering::Relaxed) {
+            headers.reverse();
+        }
+        Ok(headers)
+    }
+}
+
+impl TransactionsProvider for MockFeeProvider {
+    fn transaction_by_id(&self, id: TxNumber) -> Result<Option<TransactionSigned>> {
+        self.ensure_available()?;
+        let transaction = self.inner.transaction_by_id(id)?;
+        self.count_transactions(transaction.iter().len());
+        Ok(transaction)
+    }
+
+    fn transaction_by_hash(&self, hash: TxHash) -> Result<Option<TransactionSigned>> {
+        self.ensure_available()?;
+        let transaction = self.inner.transaction_by_hash(hash)?;
+        self.count_transactions(transaction.iter().len());
+        Ok(transaction)
+    }
+
+    fn transaction_by_hash_with_meta(
+        &self,
+        hash: TxHash,
+    ) -> Result<Option<(TransactionSigned, TransactionMeta)>> {
+        self.ensure_available()?;
+        let transaction = self.inner.transaction_by_hash_with_meta(hash)?;
+        self.count_transactions(transaction.iter().len());
+        Ok(transaction)
+    }
+
+    fn transaction_block(&self, id: TxNumber) -> Result<Option<BlockNumber>> {
+        self.ensure_available()?;
+        self.inner.transaction_block(id)
+    }
+
+    fn transactions_by_block(&self, id: BlockId) -> Result<Option<Vec<TransactionSigned>>> {
+        self.ensure_available()?;
+        let transactions = self.inner.transactions_by_block(id)?;
+        self.count_transactions(transactions.iter().map(Vec::len).sum());
+        Ok(transactions)
+    }
+
+    fn transactions_by_block_range(
+        &self,
+        range: impl RangeBounds<BlockNumber>,
+    ) -> Result<Vec<Vec<TransactionSigned>>> {
+        self.ensure_available()?;
+        let transactions = self.inner.transactions_by_block_range(range)?;
+        self.count_transactions(transactions.iter().map(Vec::len).sum());
+        Ok(transactions)
+    }
+}
+
+impl ReceiptProvider for MockFeeProvider {
+    fn receipt(&self, id: TxNumber) -> Result<Option<Receipt>> {
+        self.ensure_available()?;
+        let receipt = self.inner.receipt(id)?;
+        self.count_receipts(receipt.iter().len());
+        Ok(receipt)
+    }
+
+    fn receipt_by_hash(&self, hash: TxHash) -> Result<Option<Receipt>> {
+        self.ensure_available()?;
+        let receipt = self.inner.receipt_by_hash(hash)?;
+        self.count_receipts(receipt.iter().len());
+        Ok(receipt)
+    }
+
+    fn receipts_by_block(&self, block: BlockId) -> Result<Option<Vec<Receipt>>> {
+        self.ensure_available()?;
+        let receipts = self.inner.receipts_by_block(block)?;
+        self.count_receipts(receipts.iter().map(Vec::len).sum());
+        Ok(receipts)
+    }
+}
+
+impl BlockHashProvider for MockFeeProvider {
+    fn block_hash(&self, number: u64) -> Result<Option<H256>> {
+        if self.block_hashes_failing.load(Ordering::Relaxed) {
+            return Err(reth_interfaces::db::Error::Read(-1).into())
+        }
+        self.inner.block_hash(number)
+    }
+
+    fn canonical_hashes_range(&self, start: BlockNumber, end: BlockNumber) -> Result<Vec<H256>> {
+        self.inner.canonical_hashes_range(start, end)
+    }
+}
+
+impl BlockIdProvider for MockFeeProvider {
+    fn chain_info(&self) -> Result<ChainInfo> {
+        self.inner.chain_info()
+    }
+
+    fn block_number(&self, hash: H256) -> Result<Option<BlockNumber>> {
+        self.inner.block_number(hash)
+    }
+
+    fn earliest_block_number(&self) -> Result<BlockNumber> {
+        self.inner.earliest_block_number()
+    }
+}
+
+impl BlockProvider for MockFeeProvider {
+    fn block(&self, id: BlockId) -> Result<Option<Block>> {
+        self.ensure_available()?;
+        let block = self.inner.block(id)?;
+        self.count_transactions(block.iter().map(|block| block.body.len()).sum());
+        Ok(block)
+    }
+
+    fn ommers(&self, id: BlockId) -> Result<Option<Vec<Header>>> {
+        self.ensure_available()?;
+        self.inner.ommers(id)
+    }
+}
+
+impl EvmEnvProvider for MockFeeProvider {
+    fn fill_env_at(&self, cfg: &mut CfgEnv, block_env: &mut BlockEnv, at: BlockId) -> Result<()> {
+        self.inner.fill_env_at(cfg, block_env, at)
+    }
+
+    fn fill_env_with_header(
+        &self,
+        cfg: &mut CfgEnv,
+        block_env: &mut BlockEnv,
+        header: &Header,
+    ) -> Result<()> {
+        self.inner.fill_env_with_header(cfg, block_env, header)
+    }
+
+    fn fill_block_env_at(&self, block_env: &mut BlockEnv, at: BlockId) -> Result<()> {
+        self.inner.fill_block_env_at(block_env, at)
+    }
+
+    fn fill_block_env_with_header(&self, block_env: &mut BlockEnv, header: &Header) -> Result<()> {
+        self.inner.fill_block_env_with_header(block_env, header)
+    }
+
+    fn fill_cfg_env_at(&self, cfg: &mut CfgEnv, at: BlockId) -> Result<()> {
+        self.inner.fill_cfg_env_at(cfg, at)
+    }
+
+    fn fill_cfg_env_with_header(&self, cfg: &mut CfgEnv, header: &Header) -> Result<()> {
+        self.inner.fill_cfg_env_with_header(cfg, header)
+    }
+}
+
+impl StateProviderFactory for MockFeeProvider {
+    fn latest(&self) -> Result<StateProviderBox<'_>> {
+        self.inner.latest()
+    }
+
+    fn history_by_block_number(&self, block: BlockNumber) -> Result<StateProviderBox<'_>> {
+        self.inner.history_by_block_number(block)
+    }
+
+    fn history_by_block_hash(&self, block: BlockHash) -> Result<StateProviderBox<'_>> {
+        self.inner.history_by_block_hash(block)
+    }
+
+    fn pending<'a>(
+        &'a self,
+        post_state_data: Box<dyn PostStateDataProvider + 'a>,
+    ) -> Result<StateProviderBox<'a>> {
+        self.inner.pending(post_state_data)
+    }
+}
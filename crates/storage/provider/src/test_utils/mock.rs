@@ -26,6 +26,8 @@ pub struct MockEthProvider {
     pub headers: Arc<Mutex<HashMap<H256, Header>>>,
     /// Local account store
     pub accounts: Arc<Mutex<HashMap<Address, ExtendedAccount>>>,
+    /// Local receipt store indexed by block hash
+    pub receipts: Arc<Mutex<HashMap<H256, Vec<Receipt>>>>,
 }
 
 /// An extended account for local store
@@ -82,6 +84,11 @@ impl MockEthProvider {
         }
     }
 
+    /// Add the receipts of the block with the given hash to local receipt store
+    pub fn add_receipts(&self, hash: H256, receipts: Vec<Receipt>) {
+        self.receipts.lock().insert(hash, receipts);
+    }
+
     /// Add account to local account store
     pub fn add_account(&self, address: Address, account: ExtendedAccount) {
         self.accounts.lock().insert(address, account);
@@ -194,8 +201,9 @@ impl ReceiptProvider for MockEthProvider {
         Ok(None)
     }
 
-    fn receipts_by_block(&self, _block: BlockId) -> Result<Option<Vec<Receipt>>> {
-        Ok(None)
+    fn receipts_by_block(&self, block: BlockId) -> Result<Option<Vec<Receipt>>> {
+        let Some(hash) = self.block_hash_for_id(block)? else { return Ok(None) };
+        Ok(self.receipts.lock().get(&hash).cloned())
     }
 }
 
@@ -239,6 +247,11 @@ impl BlockIdProvider for MockEthProvider {
         let num = lock.iter().find_map(|(h, b)| (*h == hash).then_some(b.number));
         Ok(num)
     }
+
+    fn earliest_block_number(&self) -> Result<reth_primitives::BlockNumber> {
+        let lock = self.headers.lock();
+        Ok(lock.values().map(|header| header.number).min().unwrap_or_default())
+    }
 }
 
 impl BlockProvider for MockEthProvider {
@@ -1,6 +1,8 @@
 pub mod blocks;
+mod fee;
 mod mock;
 mod noop;
 
+pub use fee::{MockFeeProvider, MOCK_TX_GAS_USED};
 pub use mock::{ExtendedAccount, MockEthProvider};
 pub use noop::NoopProvider;
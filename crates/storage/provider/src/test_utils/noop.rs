@@ -36,6 +36,10 @@ impl BlockIdProvider for NoopProvider {
     fn block_number(&self, _hash: H256) -> Result<Option<BlockNumber>> {
         Ok(None)
     }
+
+    fn earliest_block_number(&self) -> Result<BlockNumber> {
+        Ok(0)
+    }
 }
 
 impl BlockProvider for NoopProvider {
@@ -53,4 +53,8 @@ pub trait BlockIdProvider: BlockHashProvider + Send + Sync {
 
     /// Gets the `Block` for the given hash. Returns `None` if no block with this hash exists.
     fn block_number(&self, hash: H256) -> Result<Option<reth_primitives::BlockNumber>>;
+
+    /// Returns the number of the earliest block whose data is still available, i.e. that hasn't
+    /// been pruned.
+    fn earliest_block_number(&self) -> Result<reth_primitives::BlockNumber>;
 }
@@ -26,8 +26,26 @@ pub trait TransactionsProvider: BlockIdProvider + Send + Sync {
     fn transactions_by_block(&self, block: BlockId) -> Result<Option<Vec<TransactionSigned>>>;
 
     /// Get transactions by block range.
+    ///
+    /// The transactions are grouped per block, in ascending block order: the returned vector has
+    /// one entry per block of the range, which is empty for blocks without transactions.
     fn transactions_by_block_range(
         &self,
         range: impl RangeBounds<BlockNumber>,
     ) -> Result<Vec<Vec<TransactionSigned>>>;
+
+    /// Get the number of transactions of each block of the range, in ascending block order.
+    ///
+    /// Implementations should override this if the counts are available without fetching the
+    /// transactions.
+    fn transaction_counts_by_block_range(
+        &self,
+        range: impl RangeBounds<BlockNumber>,
+    ) -> Result<Vec<u64>> {
+        Ok(self
+            .transactions_by_block_range(range)?
+            .iter()
+            .map(|transactions| transactions.len() as u64)
+            .collect())
+    }
 }